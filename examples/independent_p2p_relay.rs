@@ -52,6 +52,27 @@ async fn main() {
             ReceiveMessage::StableResult(..) => {}
             ReceiveMessage::Delivery(..) => {}
             ReceiveMessage::NetworkLost => {}
+            ReceiveMessage::Pong(..) => {}
+            ReceiveMessage::PeerJoin(..) => {}
+            ReceiveMessage::PeerLeave(..) => {}
+            ReceiveMessage::DialFailure(..) => {}
+            ReceiveMessage::StableRestored(..) => {}
+            ReceiveMessage::ProtocolData(..) => {}
+            ReceiveMessage::NetworkReady => {}
+            ReceiveMessage::GroupJoin(..) => {}
+            ReceiveMessage::GroupLeave(..) => {}
+            ReceiveMessage::GroupData(..) => {}
+            ReceiveMessage::Services(..) => {}
+            ReceiveMessage::Mail(..) => {}
+            ReceiveMessage::PathData(..) => {}
+            ReceiveMessage::Probe(..) => {}
+            ReceiveMessage::DeliveryMulti(..) => {}
+            ReceiveMessage::PeerBanned(..) => {}
+            ReceiveMessage::Heartbeat(..) => {}
+            ReceiveMessage::SessionStats(..) => {}
+            ReceiveMessage::NetworkRecovery(..) => {}
+            ReceiveMessage::Bandwidth(..) => {}
+            ReceiveMessage::PeersExported(..) => {}
         }
     }
 }
@@ -63,6 +63,27 @@ async fn main() {
             ReceiveMessage::NetworkLost => {
                 println!("No peers conneced.")
             }
+            ReceiveMessage::Pong(..) => {}
+            ReceiveMessage::PeerJoin(..) => {}
+            ReceiveMessage::PeerLeave(..) => {}
+            ReceiveMessage::DialFailure(..) => {}
+            ReceiveMessage::StableRestored(..) => {}
+            ReceiveMessage::ProtocolData(..) => {}
+            ReceiveMessage::NetworkReady => {}
+            ReceiveMessage::GroupJoin(..) => {}
+            ReceiveMessage::GroupLeave(..) => {}
+            ReceiveMessage::GroupData(..) => {}
+            ReceiveMessage::Services(..) => {}
+            ReceiveMessage::Mail(..) => {}
+            ReceiveMessage::PathData(..) => {}
+            ReceiveMessage::Probe(..) => {}
+            ReceiveMessage::DeliveryMulti(..) => {}
+            ReceiveMessage::PeerBanned(..) => {}
+            ReceiveMessage::Heartbeat(..) => {}
+            ReceiveMessage::SessionStats(..) => {}
+            ReceiveMessage::NetworkRecovery(..) => {}
+            ReceiveMessage::Bandwidth(..) => {}
+            ReceiveMessage::PeersExported(..) => {}
         }
     }
 }
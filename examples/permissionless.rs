@@ -46,7 +46,7 @@ async fn main() {
         fn mod_reduce(mut i: u32) -> u8 {
             loop {
                 if i > 255 {
-                    i = i - 255
+                    i -= 255
                 } else {
                     break;
                 }
@@ -61,7 +61,7 @@ async fn main() {
 
         println!("Will send bytes: {}-{:?}", bytes.len(), &bytes);
         let _ = send
-            .send(SendMessage::Broadcast(Broadcast::Gossip, bytes))
+            .send(SendMessage::Broadcast(Broadcast::Gossip, bytes.into()))
             .await;
     }
 
@@ -113,11 +113,32 @@ async fn main() {
                 println!("Recv Result Connect {:?}", from);
             }
             ReceiveMessage::Delivery(t, tid, had, _data) => {
-                println!("Recv {:?} Delivery: {} {}", t, tid, had);
+                println!("Recv {:?} Delivery: {} {:?}", t, tid, had);
             }
             ReceiveMessage::NetworkLost => {
                 println!("No peers conneced.")
             }
+            ReceiveMessage::Pong(..) => {}
+            ReceiveMessage::PeerJoin(..) => {}
+            ReceiveMessage::PeerLeave(..) => {}
+            ReceiveMessage::DialFailure(..) => {}
+            ReceiveMessage::StableRestored(..) => {}
+            ReceiveMessage::ProtocolData(..) => {}
+            ReceiveMessage::NetworkReady => {}
+            ReceiveMessage::GroupJoin(..) => {}
+            ReceiveMessage::GroupLeave(..) => {}
+            ReceiveMessage::GroupData(..) => {}
+            ReceiveMessage::Services(..) => {}
+            ReceiveMessage::Mail(..) => {}
+            ReceiveMessage::PathData(..) => {}
+            ReceiveMessage::Probe(..) => {}
+            ReceiveMessage::DeliveryMulti(..) => {}
+            ReceiveMessage::PeerBanned(..) => {}
+            ReceiveMessage::Heartbeat(..) => {}
+            ReceiveMessage::SessionStats(..) => {}
+            ReceiveMessage::NetworkRecovery(..) => {}
+            ReceiveMessage::Bandwidth(..) => {}
+            ReceiveMessage::PeersExported(..) => {}
         }
     }
 }
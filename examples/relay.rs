@@ -122,11 +122,32 @@ async fn main() {
                 println!("Recv Result Connect {:?}", from);
             }
             ReceiveMessage::Delivery(t, tid, had, _data) => {
-                println!("======== ===== Recv {:?} Delivery: {} {}", t, tid, had);
+                println!("======== ===== Recv {:?} Delivery: {} {:?}", t, tid, had);
             }
             ReceiveMessage::NetworkLost => {
                 println!("No peers conneced.")
             }
+            ReceiveMessage::Pong(..) => {}
+            ReceiveMessage::PeerJoin(..) => {}
+            ReceiveMessage::PeerLeave(..) => {}
+            ReceiveMessage::DialFailure(..) => {}
+            ReceiveMessage::StableRestored(..) => {}
+            ReceiveMessage::ProtocolData(..) => {}
+            ReceiveMessage::NetworkReady => {}
+            ReceiveMessage::GroupJoin(..) => {}
+            ReceiveMessage::GroupLeave(..) => {}
+            ReceiveMessage::GroupData(..) => {}
+            ReceiveMessage::Services(..) => {}
+            ReceiveMessage::Mail(..) => {}
+            ReceiveMessage::PathData(..) => {}
+            ReceiveMessage::Probe(..) => {}
+            ReceiveMessage::DeliveryMulti(..) => {}
+            ReceiveMessage::PeerBanned(..) => {}
+            ReceiveMessage::Heartbeat(..) => {}
+            ReceiveMessage::SessionStats(..) => {}
+            ReceiveMessage::NetworkRecovery(..) => {}
+            ReceiveMessage::Bandwidth(..) => {}
+            ReceiveMessage::PeersExported(..) => {}
         }
     }
 }
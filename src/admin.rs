@@ -0,0 +1,268 @@
+use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, Result},
+    net::{TcpListener, TcpStream},
+    select,
+    time::timeout,
+};
+
+use chamomile_types::PeerId;
+
+use crate::global::Global;
+use crate::session::SessionMessage;
+
+/// max bytes of the request head.
+const ADMIN_REQUEST_MAX: usize = 8192;
+
+/// max time of reading the request head.
+const ADMIN_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const ADMIN_INDEX: &str = "GET  /routes
+GET  /stables
+GET  /buffer
+GET  /bandwidth
+POST /block/<peer_id | ip>
+POST /unblock/<peer_id | ip>
+";
+
+/// the admin endpoint's bind address and the bearer token of the writes.
+struct Admin {
+    addr: SocketAddr,
+    token: Option<String>,
+}
+
+impl Admin {
+    /// the `Host` must be the bind address (or localhost with the port when bind
+    /// to loopback), against DNS rebinding from the browser.
+    fn is_host(&self, host: &str) -> bool {
+        host == self.addr.to_string()
+            || (self.addr.ip().is_loopback() && host == format!("localhost:{}", self.addr.port()))
+    }
+
+    /// check the `Authorization: Bearer <token>`, in constant time.
+    fn is_auth(&self, auth: Option<&str>) -> bool {
+        match (&self.token, auth.and_then(|a| a.strip_prefix("Bearer "))) {
+            (Some(token), Some(given)) => {
+                token.len() == given.len()
+                    && token
+                        .bytes()
+                        .zip(given.bytes())
+                        .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+                        == 0
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Embedded HTTP debug/admin endpoint (plain text), when enable `admin` feature.
+/// the reads have no auth, so bind it to localhost or private network, the block
+/// & unblock need the bearer token. it stops when the server stopped.
+pub(crate) async fn listen(
+    addr: SocketAddr,
+    token: Option<String>,
+    global: Arc<Global>,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("admin endpoint listening: {}", addr);
+    let admin = Arc::new(Admin { addr, token });
+    tokio::spawn(async move {
+        loop {
+            let accepted = select! {
+                v = listener.accept() => v,
+                _ = global.out_sender.closed() => break,
+            };
+            match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle(stream, admin.clone(), global.clone()));
+                }
+                Err(e) => {
+                    warn!("admin endpoint accept failure: {:?}", e);
+                    break;
+                }
+            }
+        }
+        info!("admin endpoint stopped: {}", addr);
+    });
+    Ok(())
+}
+
+async fn handle(mut stream: TcpStream, admin: Arc<Admin>, global: Arc<Global>) {
+    let mut buf = vec![0u8; ADMIN_REQUEST_MAX];
+    let mut len = 0;
+    let read = timeout(ADMIN_REQUEST_TIMEOUT, async {
+        while len < buf.len() {
+            match stream.read(&mut buf[len..]).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => len += n,
+            }
+            if buf[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+                break;
+            }
+        }
+    })
+    .await;
+    if read.is_err() {
+        debug!("admin endpoint request timeout.");
+        return;
+    }
+
+    let head = String::from_utf8_lossy(&buf[..len]);
+    let mut lines = head.lines();
+    let mut line = lines.next().unwrap_or("").split_whitespace();
+    let (mut host, mut auth) = (None, None);
+    for header in lines {
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("host") {
+                host = Some(value.trim());
+            } else if name.eq_ignore_ascii_case("authorization") {
+                auth = Some(value.trim());
+            }
+        }
+    }
+    let (code, body) = match (line.next(), line.next()) {
+        _ if !host.map(|h| admin.is_host(h)).unwrap_or(false) => (403, "invalid host\n".to_owned()),
+        (Some("POST"), Some(_)) if !admin.is_auth(auth) => (401, "unauthorized\n".to_owned()),
+        (Some(method), Some(path)) => route(&global, method, path).await,
+        _ => (400, "bad request\n".to_owned()),
+    };
+
+    let reason = match code {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        _ => "Method Not Allowed",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+async fn route(global: &Global, method: &str, path: &str) -> (u16, String) {
+    let mut parts = path.trim_matches('/').splitn(2, '/');
+    match (method, parts.next().unwrap_or(""), parts.next()) {
+        ("GET", "", None) => (200, ADMIN_INDEX.to_owned()),
+        ("GET", "routes", None) => (200, routes(global).await),
+        ("GET", "stables", None) => (200, stables(global).await),
+        ("GET", "buffer", None) => (200, buffer(global).await),
+        ("GET", "bandwidth", None) => (200, bandwidth(global).await),
+        ("POST", "block", Some(target)) => block(global, target, true).await,
+        ("POST", "unblock", Some(target)) => block(global, target, false).await,
+        (_, "block", _) | (_, "unblock", _) => (405, "method not allowed\n".to_owned()),
+        _ => (404, "not found\n".to_owned()),
+    }
+}
+
+async fn routes(global: &Global) -> String {
+    let peer_list = global.peer_list.read().await;
+    let latencies = global.latencies.read().await;
+    let mut body = String::new();
+    let dhts = peer_list.dht_keys();
+    let _ = writeln!(body, "dht peers: {}", dhts.len());
+    for id in dhts {
+        let rtt = latencies
+            .get(&id)
+            .map(|r| r.to_string())
+            .unwrap_or("-".to_owned());
+        let _ = writeln!(body, "{} rtt {}ms", id.to_hex(), rtt);
+    }
+    body
+}
+
+async fn stables(global: &Global) -> String {
+    let peer_list = global.peer_list.read().await;
+    let latencies = global.latencies.read().await;
+    let mut body = String::new();
+    let stables = peer_list.stable_all();
+    let _ = writeln!(body, "stable peers: {}", stables.len());
+    for (id, (_, is_direct)) in stables {
+        let rtt = latencies
+            .get(&id)
+            .map(|r| r.to_string())
+            .unwrap_or("-".to_owned());
+        let way = if is_direct { "direct" } else { "relay" };
+        let _ = writeln!(body, "{} {} rtt {}ms", id.to_hex(), way, rtt);
+    }
+    body
+}
+
+async fn buffer(global: &Global) -> String {
+    let (tmps, connects, results) = global.buffer.pending().await;
+    let mut body = String::new();
+    let _ = writeln!(body, "tmp sessions: {}", tmps.len());
+    for (id, is_direct, waited) in tmps {
+        let way = if is_direct { "direct" } else { "relay" };
+        let _ = writeln!(body, "{} {} {}ms", id.to_hex(), way, waited.as_millis());
+    }
+    for (name, entries) in [("stable connects", connects), ("stable results", results)] {
+        let _ = writeln!(body, "{}: {}", name, entries.len());
+        for (id, tid, waited) in entries {
+            let _ = writeln!(body, "{} {} {}ms", id.to_hex(), tid, waited.as_millis());
+        }
+    }
+    body
+}
+
+async fn bandwidth(global: &Global) -> String {
+    let mut body = String::new();
+    for (t, b) in global.transport_bandwidths.iter() {
+        let (sent, received) = b.get();
+        let _ = writeln!(body, "{} sent {} received {}", t.to_str(), sent, received);
+    }
+    for (id, b) in global.bandwidths.read().await.iter() {
+        let (sent, received) = b.get();
+        let _ = writeln!(body, "{} sent {} received {}", id.to_hex(), sent, received);
+    }
+    body
+}
+
+/// block or unblock the peer or ip, blocked peer's (or ip's) sessions will be closed.
+async fn block(global: &Global, target: &str, is_block: bool) -> (u16, String) {
+    if let Ok(ip) = target.parse::<IpAddr>() {
+        let addr = SocketAddr::new(ip, 0);
+        if is_block {
+            let closes: Vec<_> = {
+                let mut peer_list = global.peer_list.write().await;
+                peer_list._add_block_addr(addr);
+                peer_list
+                    .all_peers()
+                    .into_iter()
+                    .filter(|(p, _)| p.socket.ip() == ip)
+                    .map(|(_, sender)| sender.clone())
+                    .collect()
+            };
+            for sender in closes {
+                let _ = sender.send(SessionMessage::Close).await;
+            }
+        } else {
+            global.peer_list.write().await._remove_block_addr(&addr);
+        }
+    } else if let Ok(peer_id) = PeerId::from_hex(target) {
+        if is_block {
+            global.peer_list.write().await._add_block_peer(peer_id);
+            global.reconnects.write().await.remove(&peer_id);
+            if let Some(sender) = global.routes.get(&peer_id) {
+                let _ = sender.send(SessionMessage::Close).await;
+            }
+        } else {
+            global.peer_list.write().await._remove_block_peer(&peer_id);
+        }
+    } else {
+        return (400, "invalid peer id or ip\n".to_owned());
+    }
+
+    let action = if is_block { "blocked" } else { "unblocked" };
+    info!("admin endpoint: {} {}", action, target);
+    (200, format!("{} {}\n", action, target))
+}
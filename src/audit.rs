@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+/// max buffered audit events, when full, drop the new events.
+const AUDIT_CHANNEL_SIZE: usize = 1024;
+
+/// keep the rotated audit log files: audit.log.1 ... audit.log.N.
+const AUDIT_ROTATE_FILES: usize = 5;
+
+/// Append-only connection audit log in storage directory.
+/// every line is: unix secs + event + params, e.g.
+/// `1700000000 handshake_accepted <peer_id> <socket>`.
+/// the dropped events when full are counted, and written as
+/// `1700000000 audit_dropped <count>` before the next event.
+#[derive(Clone)]
+pub(crate) struct Audit(Option<(Sender<String>, Arc<AtomicU64>)>);
+
+impl Audit {
+    /// start the writer task, `max_size` is the max bytes of a file before rotate,
+    /// 0 is disable the audit log.
+    pub fn start(path: PathBuf, max_size: u64) -> Self {
+        if max_size == 0 {
+            return Audit(None);
+        }
+        let (sender, receiver) = channel(AUDIT_CHANNEL_SIZE);
+        let dropped = Arc::new(AtomicU64::new(0));
+        tokio::spawn(write_loop(path, max_size, receiver, dropped.clone()));
+        Audit(Some((sender, dropped)))
+    }
+
+    /// add an event (with params) to the audit log, not waiting it written.
+    pub fn log(&self, event: String) {
+        if let Some((sender, dropped)) = &self.0 {
+            let line = format!("{} {}\n", now_secs(), event);
+            if sender.try_send(line).is_err() {
+                dropped.fetch_add(1, Ordering::Relaxed);
+                warn!("CHAMOMILE: AUDIT LOG IS FULL, DROP EVENT.");
+            }
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// write the events to the kept opened file, reopen it after rotated or failure.
+async fn write_loop(
+    path: PathBuf,
+    max_size: u64,
+    mut receiver: Receiver<String>,
+    dropped: Arc<AtomicU64>,
+) {
+    let mut size = fs::metadata(&path).await.map(|m| m.len()).unwrap_or(0);
+    let mut file: Option<File> = None;
+    while let Some(mut line) = receiver.recv().await {
+        let count = dropped.swap(0, Ordering::Relaxed);
+        if count > 0 {
+            line = format!("{} audit_dropped {}\n{}", now_secs(), count, line);
+        }
+        if size + line.len() as u64 > max_size && size > 0 {
+            file = None;
+            rotate(&path).await;
+            size = 0;
+        }
+        if file.is_none() {
+            match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await
+            {
+                Ok(f) => file = Some(f),
+                Err(e) => {
+                    warn!("CHAMOMILE: AUDIT LOG WRITE FAILURE: {:?}", e);
+                    dropped.fetch_add(count + 1, Ordering::Relaxed);
+                    continue;
+                }
+            }
+        }
+        if let Some(f) = file.as_mut() {
+            let written = async {
+                f.write_all(line.as_bytes()).await?;
+                f.flush().await
+            };
+            if written.await.is_ok() {
+                size += line.len() as u64;
+            } else {
+                dropped.fetch_add(count + 1, Ordering::Relaxed);
+                file = None;
+            }
+        }
+    }
+}
+
+/// audit.log => audit.log.1 => ... => audit.log.N (dropped).
+async fn rotate(path: &Path) {
+    let rotated = |i: usize| {
+        let mut p = path.to_path_buf().into_os_string();
+        p.push(format!(".{}", i));
+        PathBuf::from(p)
+    };
+    let _ = fs::remove_file(rotated(AUDIT_ROTATE_FILES)).await;
+    for i in (1..AUDIT_ROTATE_FILES).rev() {
+        let _ = fs::rename(rotated(i), rotated(i + 1)).await;
+    }
+    let _ = fs::rename(path, rotated(1)).await;
+}
@@ -0,0 +1,381 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chamomile_types::{
+    message::Misbehavior,
+    types::{BlockTarget, PEER_ID_LENGTH},
+    PeerId,
+};
+
+use crate::config::PeerIdHash;
+use crate::keys::Keypair;
+use crate::token::now_secs;
+
+/// the score of misbehavior, an invalid frame may be a bug, but a bad
+/// signature or flooding is always an attack.
+fn penalty(misbehavior: Misbehavior) -> u32 {
+    match misbehavior {
+        Misbehavior::InvalidFrame => 10,
+        Misbehavior::BadSignature => 25,
+        Misbehavior::RateViolation => 20,
+    }
+}
+
+/// max scored PeerIds and ips, when full, the decayed and the oldest are removed.
+const BAN_SCORES_MAX: usize = 4096;
+
+/// key => score and last update time.
+type Scores<K> = HashMap<K, (u32, Instant)>;
+
+/// add the penalty to the decayed (one per second) score, return the new score.
+fn add_score<K: Hash + Eq + Clone>(scores: &mut Scores<K>, key: K, penalty: u32) -> u32 {
+    let now = Instant::now();
+    if scores.len() >= BAN_SCORES_MAX && !scores.contains_key(&key) {
+        scores.retain(|_, (score, last)| now.duration_since(*last).as_secs() < *score as u64);
+        if scores.len() >= BAN_SCORES_MAX {
+            let oldest = scores
+                .iter()
+                .min_by_key(|(_, (_, last))| *last)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                scores.remove(&oldest);
+            }
+        }
+    }
+    let (score, last) = scores.entry(key).or_insert((0, now));
+    let decay = now.duration_since(*last).as_secs() as u32;
+    *score = score.saturating_sub(decay) + penalty;
+    *last = now;
+    *score
+}
+
+/// check the key is in the bans, remove it if expired.
+fn is_banned<K: Hash + Eq>(bans: &Mutex<HashMap<K, Instant>>, key: &K) -> bool {
+    let mut bans = bans.lock().unwrap();
+    match bans.get(key) {
+        Some(until) if *until > Instant::now() => true,
+        Some(_) => {
+            bans.remove(key);
+            false
+        }
+        None => false,
+    }
+}
+
+/// Misbehavior scores of peers, when a score is over the threshold, the peer
+/// (PeerId if handshaked, or ip) is temp-banned, the banned peers cannot handshake
+/// again until expired. the bans are not persisted, unlike the blocklist.
+pub(crate) struct Bans {
+    /// 0 is disable.
+    threshold: u32,
+    time: Duration,
+    /// also ban the ip of the banned handshaked peer.
+    ban_ip: bool,
+    /// scores of handshaked peers.
+    peer_scores: Mutex<Scores<PeerId>>,
+    /// scores of not handshaked ips.
+    addr_scores: Mutex<Scores<IpAddr>>,
+    /// banned PeerId & ip => expire time.
+    peers: Mutex<HashMap<PeerId, Instant>>,
+    addrs: Mutex<HashMap<IpAddr, Instant>>,
+}
+
+impl Bans {
+    pub fn new(threshold: u32, time: Duration, ban_ip: bool) -> Self {
+        Bans {
+            threshold,
+            time,
+            ban_ip,
+            peer_scores: Mutex::new(HashMap::new()),
+            addr_scores: Mutex::new(HashMap::new()),
+            peers: Mutex::new(HashMap::new()),
+            addrs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enable(&self) -> bool {
+        self.threshold > 0
+    }
+
+    /// add the misbehavior's penalty, scored and banned by PeerId if it is
+    /// authenticated, or by ip. return true if it is banned now.
+    pub fn penalize(
+        &self,
+        peer_id: Option<PeerId>,
+        ip: Option<IpAddr>,
+        misbehavior: Misbehavior,
+    ) -> bool {
+        if !self.is_enable() {
+            return false;
+        }
+        let score = match (peer_id, ip) {
+            (Some(peer_id), _) => add_score(
+                &mut self.peer_scores.lock().unwrap(),
+                peer_id,
+                penalty(misbehavior),
+            ),
+            (None, Some(ip)) => add_score(
+                &mut self.addr_scores.lock().unwrap(),
+                ip,
+                penalty(misbehavior),
+            ),
+            (None, None) => return false,
+        };
+        if score < self.threshold {
+            return false;
+        }
+
+        // the authenticated peer is banned by PeerId, and by ip if `ban_ip`, or it
+        // can come back with a new PeerId (other peers may share its ip by NAT).
+        let until = Instant::now() + self.time;
+        if let Some(peer_id) = peer_id {
+            self.peer_scores.lock().unwrap().remove(&peer_id);
+            self.peers.lock().unwrap().insert(peer_id, until);
+        }
+        if let Some(ip) = ip {
+            if peer_id.is_none() || self.ban_ip {
+                self.addr_scores.lock().unwrap().remove(&ip);
+                self.addrs.lock().unwrap().insert(ip, until);
+            }
+        }
+        true
+    }
+
+    pub fn is_banned_peer(&self, peer_id: &PeerId) -> bool {
+        self.is_enable() && is_banned(&self.peers, peer_id)
+    }
+
+    pub fn is_banned_addr(&self, ip: &IpAddr) -> bool {
+        self.is_enable() && is_banned(&self.addrs, ip)
+    }
+
+    /// remove the expired bans and decayed scores.
+    pub fn clear(&self) {
+        let now = Instant::now();
+        self.peers.lock().unwrap().retain(|_, until| *until > now);
+        self.addrs.lock().unwrap().retain(|_, until| *until > now);
+        let is_alive =
+            |(score, last): &(u32, Instant)| now.duration_since(*last).as_secs() < *score as u64;
+        self.peer_scores.lock().unwrap().retain(|_, v| is_alive(v));
+        self.addr_scores.lock().unwrap().retain(|_, v| is_alive(v));
+    }
+}
+
+/// Shared blocks, the entries are signed by the trusted peers (allowlist) with
+/// expiry, and propagated between them. the local overrides are never blocked
+/// by them. they are not persisted, like the bans.
+pub(crate) struct SharedBans {
+    /// max accepted block time of the shared entries, zero is not accept.
+    max: Duration,
+    /// blocked target => expire time.
+    targets: Mutex<HashMap<BlockTarget, Instant>>,
+    /// the targets which ignore shared entries.
+    overrides: Mutex<HashSet<BlockTarget>>,
+}
+
+impl SharedBans {
+    pub fn new(max: Duration) -> Self {
+        SharedBans {
+            max,
+            targets: Mutex::new(HashMap::new()),
+            overrides: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// accept the shared entries from trusted peers.
+    pub fn is_enable(&self) -> bool {
+        self.max > Duration::ZERO
+    }
+
+    /// add the block, the self's (`is_local`) is not limited by max time and
+    /// cancels the override. return true if it is new or extended.
+    pub fn add(&self, target: BlockTarget, time: Duration, is_local: bool) -> bool {
+        let time = if is_local {
+            self.overrides.lock().unwrap().remove(&target);
+            time
+        } else if self.overrides.lock().unwrap().contains(&target) {
+            return false;
+        } else {
+            time.min(self.max)
+        };
+        let until = Instant::now() + time;
+        let mut targets = self.targets.lock().unwrap();
+        match targets.get(&target) {
+            Some(t) if *t >= until => false,
+            _ => {
+                targets.insert(target, until);
+                true
+            }
+        }
+    }
+
+    /// set or cancel the local override of the target.
+    pub fn set_override(&self, target: BlockTarget, is_override: bool) {
+        if is_override {
+            self.targets.lock().unwrap().remove(&target);
+            self.overrides.lock().unwrap().insert(target);
+        } else {
+            self.overrides.lock().unwrap().remove(&target);
+        }
+    }
+
+    pub fn is_banned_peer(&self, peer_id: &PeerId) -> bool {
+        is_banned(&self.targets, &BlockTarget::Peer(*peer_id))
+    }
+
+    pub fn is_banned_addr(&self, ip: &IpAddr) -> bool {
+        is_banned(&self.targets, &BlockTarget::Ip(*ip))
+    }
+
+    /// remove the expired blocks.
+    pub fn clear(&self) {
+        let now = Instant::now();
+        self.targets.lock().unwrap().retain(|_, until| *until > now);
+    }
+}
+
+/// the domain tag of shared block entry's signature.
+const BLOCK_DOMAIN: &[u8] = b"chamomile-block-share-v1";
+
+/// seal the shared block entry by self's keypair,
+/// entry is: expire unix secs(8) + kind(1, 0 PeerId, 4 ipv4, 6 ipv6) + target.
+pub(crate) fn seal_block(key: &Keypair, target: &BlockTarget, time: Duration) -> Option<Vec<u8>> {
+    let mut msg = (now_secs() + time.as_secs()).to_le_bytes().to_vec();
+    match target {
+        BlockTarget::Peer(peer_id) => {
+            msg.push(0);
+            msg.extend(peer_id.as_bytes());
+        }
+        BlockTarget::Ip(IpAddr::V4(ip)) => {
+            msg.push(4);
+            msg.extend(ip.octets());
+        }
+        BlockTarget::Ip(IpAddr::V6(ip)) => {
+            msg.push(6);
+            msg.extend(ip.octets());
+        }
+    }
+    key.seal(BLOCK_DOMAIN, &msg).ok()
+}
+
+/// open the shared block entry, return the issuer, the target and the remain
+/// block time, None if it is invalid or expired.
+pub(crate) fn open_block(
+    sealed: &[u8],
+    hash: &PeerIdHash,
+) -> Option<(PeerId, BlockTarget, Duration)> {
+    let (issuer, msg) = Keypair::open(sealed, BLOCK_DOMAIN, hash)?;
+    if msg.len() < 9 {
+        return None;
+    }
+    let mut expire_bytes = [0u8; 8];
+    expire_bytes.copy_from_slice(&msg[..8]);
+    let remain = u64::from_le_bytes(expire_bytes)
+        .checked_sub(now_secs())
+        .filter(|r| *r > 0)?;
+    let target = match (msg[8], &msg[9..]) {
+        (0, bytes) if bytes.len() == PEER_ID_LENGTH => {
+            BlockTarget::Peer(PeerId::from_bytes(bytes).ok()?)
+        }
+        (4, bytes) if bytes.len() == 4 => BlockTarget::Ip(IpAddr::V4(Ipv4Addr::new(
+            bytes[0], bytes[1], bytes[2], bytes[3],
+        ))),
+        (6, bytes) if bytes.len() == 16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(bytes);
+            BlockTarget::Ip(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => return None,
+    };
+    Some((issuer, target, Duration::from_secs(remain)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::KeyType;
+
+    const TIME: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn ban_by_score() {
+        let bans = Bans::new(50, TIME, true);
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        assert!(!bans.penalize(None, Some(ip), Misbehavior::BadSignature));
+        assert!(!bans.is_banned_addr(&ip));
+        assert!(bans.penalize(None, Some(ip), Misbehavior::BadSignature));
+        assert!(bans.is_banned_addr(&ip));
+
+        // the authenticated peer is banned by PeerId, and by ip with `ban_ip`.
+        let peer = PeerId([1u8; PEER_ID_LENGTH]);
+        let peer_ip: IpAddr = "5.6.7.8".parse().unwrap();
+        bans.penalize(Some(peer), Some(peer_ip), Misbehavior::BadSignature);
+        assert!(bans.penalize(Some(peer), Some(peer_ip), Misbehavior::BadSignature));
+        assert!(bans.is_banned_peer(&peer));
+        assert!(bans.is_banned_addr(&peer_ip));
+    }
+
+    #[test]
+    fn ban_ip_disabled() {
+        let bans = Bans::new(25, TIME, false);
+        let peer = PeerId([1u8; PEER_ID_LENGTH]);
+        let ip: IpAddr = "5.6.7.8".parse().unwrap();
+        assert!(bans.penalize(Some(peer), Some(ip), Misbehavior::BadSignature));
+        assert!(bans.is_banned_peer(&peer));
+        assert!(!bans.is_banned_addr(&ip));
+
+        let disabled = Bans::new(0, TIME, true);
+        assert!(!disabled.penalize(None, Some(ip), Misbehavior::BadSignature));
+        assert!(!disabled.is_banned_addr(&ip));
+    }
+
+    #[test]
+    fn scores_capped() {
+        let mut scores: Scores<u32> = HashMap::new();
+        for i in 0..BAN_SCORES_MAX as u32 + 10 {
+            add_score(&mut scores, i, 10);
+        }
+        assert_eq!(scores.len(), BAN_SCORES_MAX);
+    }
+
+    #[test]
+    fn shared_block() {
+        let hash = PeerIdHash::default();
+        let key = KeyType::Ed25519.generate_kepair();
+        let targets = [
+            BlockTarget::Peer(PeerId([1u8; PEER_ID_LENGTH])),
+            BlockTarget::Ip("1.2.3.4".parse().unwrap()),
+            BlockTarget::Ip("2001:db8::1".parse().unwrap()),
+        ];
+        for target in targets {
+            let sealed = seal_block(&key, &target, TIME).unwrap();
+            let (issuer, opened, remain) = open_block(&sealed, &hash).unwrap();
+            assert_eq!(issuer, key.peer_id(&hash));
+            assert_eq!(opened, target);
+            assert!(remain <= TIME && remain > Duration::ZERO);
+        }
+        // expired.
+        let sealed = seal_block(&key, &targets[0], Duration::ZERO).unwrap();
+        assert!(open_block(&sealed, &hash).is_none());
+    }
+
+    #[test]
+    fn shared_override() {
+        let shared = SharedBans::new(TIME);
+        let ip: IpAddr = "1.2.3.4".parse().unwrap();
+        let target = BlockTarget::Ip(ip);
+        assert!(shared.add(target, TIME, false));
+        assert!(shared.is_banned_addr(&ip));
+
+        // the local override ignores the shared entries.
+        shared.set_override(target, true);
+        assert!(!shared.is_banned_addr(&ip));
+        assert!(!shared.add(target, TIME, false));
+        // the local block cancels the override.
+        assert!(shared.add(target, TIME, true));
+        assert!(shared.is_banned_addr(&ip));
+    }
+}
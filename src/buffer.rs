@@ -1,27 +1,227 @@
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use tokio::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+use tokio::sync::{
+    mpsc::{self, Receiver, Sender},
+    oneshot,
+};
 
-use chamomile_types::{Peer, PeerId};
+use chamomile_types::{message::DeliveryType, Peer, PeerId};
 
+use crate::config::BufferOverflow;
 use crate::kad::KadValue;
 use crate::session::SessionMessage;
 use crate::transports::EndpointMessage;
 
+/// buffered message, (delivery_id, data, deadline).
+type Entry = (u64, Vec<u8>, Instant);
+
+/// buffered messages, (delivery_id, data).
+type Entries = Vec<(u64, Vec<u8>)>;
+
+/// messages dropped or expired, need delivery failure to outside.
+type Failures = Vec<(DeliveryType, u64, Vec<u8>)>;
+
+/// pending tmp sessions (peer, is_d, age), and buffered connects & results (peer, tid, age).
+type Pending = (
+    Vec<(PeerId, bool, Duration)>,
+    Vec<(PeerId, u64, Duration)>,
+    Vec<(PeerId, u64, Duration)>,
+);
+
+/// push result, Ok((is_processing, dropped)), if full, return back the data and dropped.
+type Push = std::result::Result<(bool, Failures), (Vec<u8>, Failures)>;
+
+/// the requests to the buffer task.
+enum BufferMessage {
+    /// make room & add stable connect to buffer.
+    Connect(PeerId, u64, Vec<u8>, oneshot::Sender<Push>),
+    /// make room & add stable result to buffer.
+    Result(PeerId, u64, Vec<u8>, oneshot::Sender<Push>),
+    RemoveConnect(PeerId, oneshot::Sender<Entries>),
+    /// remove one stable connect, return it's data and if no more connects to the peer.
+    CancelConnect(PeerId, u64, oneshot::Sender<Option<(Vec<u8>, bool)>>),
+    /// add tmp session, if need, take out the buffered connects and results.
+    AddTmp(
+        PeerId,
+        KadValue,
+        bool,
+        Option<(bool, oneshot::Sender<(Entries, Entries)>)>,
+    ),
+    TmpSession(PeerId, oneshot::Sender<Option<Sender<SessionMessage>>>),
+    TmpStream(PeerId, oneshot::Sender<Option<Sender<EndpointMessage>>>),
+    UpdatePeer(PeerId, Peer),
+    RemoveTmp(PeerId, Option<oneshot::Sender<Option<(KadValue, bool)>>>),
+    /// remove tmp and all buffered connects & results.
+    RemoveStable(PeerId),
+    /// timer clear, return expired, closed tmp sessions and buffer length.
+    Clear(oneshot::Sender<(Failures, Vec<Sender<SessionMessage>>, usize)>),
+    Pending(oneshot::Sender<Pending>),
+}
+
+/// the buffer handle, the buffer is owned by a task, all requests by channel,
+/// so no lock waiting on the connect path, and clear is in order with adds.
+#[derive(Clone)]
 pub(crate) struct Buffer {
+    sender: Sender<BufferMessage>,
+}
+
+struct State {
+    /// buffered message's live time, expired will delivery failure.
+    ttl: Duration,
+    /// max number of buffered stable connects & results, and of tmp sessions.
+    max_entries: usize,
+    /// max bytes of buffered stable connects & results.
+    max_bytes: usize,
+    overflow: BufferOverflow,
+    /// current bytes of buffered stable connects & results.
+    bytes: usize,
     /// queue for connect to ip addr. if has one, not send aggin.
     dhts: HashMap<SocketAddr, bool>,
     /// queue for stable connect to peer id. if has one, add to queue buffer.
-    connects: HashMap<PeerId, (bool, Vec<(u64, Vec<u8>)>)>,
+    connects: HashMap<PeerId, Vec<Entry>>,
     /// queue for stable result to peer id. if has one, add to queue buffer.
-    results: HashMap<PeerId, (bool, Vec<(u64, Vec<u8>)>)>,
+    results: HashMap<PeerId, Vec<Entry>>,
     /// tmp stable waiting outside to stable result. 60s if no-ok, close it.
-    tmps: HashMap<PeerId, (bool, KadValue, bool)>,
+    tmps: HashMap<PeerId, (bool, KadValue, bool, Instant)>,
 }
 
 impl Buffer {
-    pub fn init() -> Self {
-        Buffer {
+    /// start the buffer task.
+    pub fn init(
+        ttl: Duration,
+        max_entries: usize,
+        max_bytes: usize,
+        overflow: BufferOverflow,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(128);
+        let state = State::new(ttl, max_entries, max_bytes, overflow);
+        tokio::spawn(state.run(receiver));
+        Buffer { sender }
+    }
+
+    async fn request<T: Default>(&self, f: impl FnOnce(oneshot::Sender<T>) -> BufferMessage) -> T {
+        let (tx, rx) = oneshot::channel();
+        if self.sender.send(f(tx)).await.is_err() {
+            return T::default();
+        }
+        rx.await.unwrap_or_default()
+    }
+
+    /// add stable connect to buffer, Ok((is_processing, dropped)), Err((data, dropped)) if full.
+    pub async fn add_connect(&self, peer_id: PeerId, tid: u64, data: Vec<u8>) -> Push {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(BufferMessage::Connect(peer_id, tid, data, tx))
+            .await
+            .map_err(|e| match e.0 {
+                BufferMessage::Connect(_, _, data, _) => (data, vec![]),
+                _ => (vec![], vec![]),
+            })?;
+        rx.await.map_err(|_| (vec![], vec![]))?
+    }
+
+    /// add stable result to buffer, Ok((is_processing, dropped)), Err((data, dropped)) if full.
+    pub async fn add_result(&self, peer_id: PeerId, tid: u64, data: Vec<u8>) -> Push {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(BufferMessage::Result(peer_id, tid, data, tx))
+            .await
+            .map_err(|e| match e.0 {
+                BufferMessage::Result(_, _, data, _) => (data, vec![]),
+                _ => (vec![], vec![]),
+            })?;
+        rx.await.map_err(|_| (vec![], vec![]))?
+    }
+
+    pub async fn remove_connect(&self, peer_id: PeerId) -> Entries {
+        self.request(|tx| BufferMessage::RemoveConnect(peer_id, tx))
+            .await
+    }
+
+    /// remove the stable connect of tid, return it's data and if no more connects to the peer.
+    pub async fn cancel_connect(&self, peer_id: PeerId, tid: u64) -> Option<(Vec<u8>, bool)> {
+        self.request(|tx| BufferMessage::CancelConnect(peer_id, tid, tx))
+            .await
+    }
+
+    pub async fn add_tmp(&self, peer_id: PeerId, value: KadValue, is_d: bool) {
+        let _ = self
+            .sender
+            .send(BufferMessage::AddTmp(peer_id, value, is_d, None))
+            .await;
+    }
+
+    /// add tmp session, and take out the buffered connects (and results).
+    pub async fn take_tmp(
+        &self,
+        peer_id: PeerId,
+        value: KadValue,
+        is_d: bool,
+        with_results: bool,
+    ) -> (Entries, Entries) {
+        self.request(|tx| BufferMessage::AddTmp(peer_id, value, is_d, Some((with_results, tx))))
+            .await
+    }
+
+    pub async fn get_tmp_session(&self, peer_id: PeerId) -> Option<Sender<SessionMessage>> {
+        self.request(|tx| BufferMessage::TmpSession(peer_id, tx))
+            .await
+    }
+
+    pub async fn get_tmp_stream(&self, peer_id: PeerId) -> Option<Sender<EndpointMessage>> {
+        self.request(|tx| BufferMessage::TmpStream(peer_id, tx))
+            .await
+    }
+
+    pub async fn update_peer(&self, peer_id: PeerId, peer: Peer) {
+        let _ = self
+            .sender
+            .send(BufferMessage::UpdatePeer(peer_id, peer))
+            .await;
+    }
+
+    /// remove tmp session, not wait the result.
+    pub async fn remove_tmp(&self, peer_id: PeerId) {
+        let _ = self
+            .sender
+            .send(BufferMessage::RemoveTmp(peer_id, None))
+            .await;
+    }
+
+    pub async fn take_out_tmp(&self, peer_id: PeerId) -> Option<(KadValue, bool)> {
+        self.request(|tx| BufferMessage::RemoveTmp(peer_id, Some(tx)))
+            .await
+    }
+
+    pub async fn remove_stable(&self, peer_id: PeerId) {
+        let _ = self.sender.send(BufferMessage::RemoveStable(peer_id)).await;
+    }
+
+    /// clear the expired buffers, return the expired stable connects and results,
+    /// and the buffer length after clear.
+    pub async fn timer_clear(&self) -> (Failures, usize) {
+        let (expired, closes, len) = self.request(BufferMessage::Clear).await;
+        for ss in closes {
+            let _ = ss.send(SessionMessage::Close).await;
+        }
+        (expired, len)
+    }
+
+    /// the waiting tmp sessions and buffered stable connects & results.
+    pub async fn pending(&self) -> Pending {
+        self.request(BufferMessage::Pending).await
+    }
+}
+
+impl State {
+    fn new(ttl: Duration, max_entries: usize, max_bytes: usize, overflow: BufferOverflow) -> Self {
+        State {
+            ttl,
+            max_entries,
+            max_bytes,
+            overflow,
+            bytes: 0,
             dhts: HashMap::new(),
             connects: HashMap::new(),
             results: HashMap::new(),
@@ -29,6 +229,65 @@ impl Buffer {
         }
     }
 
+    async fn run(mut self, mut receiver: Receiver<BufferMessage>) {
+        while let Some(msg) = receiver.recv().await {
+            match msg {
+                BufferMessage::Connect(peer_id, tid, data, tx) => {
+                    let _ = tx.send(self.push(true, peer_id, tid, data));
+                }
+                BufferMessage::Result(peer_id, tid, data, tx) => {
+                    let _ = tx.send(self.push(false, peer_id, tid, data));
+                }
+                BufferMessage::RemoveConnect(peer_id, tx) => {
+                    let _ = tx.send(self.remove_connect(&peer_id));
+                }
+                BufferMessage::CancelConnect(peer_id, tid, tx) => {
+                    let _ = tx.send(self.cancel_connect(&peer_id, tid));
+                }
+                BufferMessage::AddTmp(peer_id, value, is_d, take) => {
+                    if let Some((with_results, tx)) = take {
+                        let connects = self.remove_connect(&peer_id);
+                        let results = if with_results {
+                            self.remove_result(&peer_id)
+                        } else {
+                            vec![]
+                        };
+                        let _ = tx.send((connects, results));
+                    }
+                    if let Some(ss) = self.add_tmp(peer_id, value, is_d) {
+                        tokio::spawn(async move {
+                            let _ = ss.send(SessionMessage::Close).await;
+                        });
+                    }
+                }
+                BufferMessage::TmpSession(peer_id, tx) => {
+                    let _ = tx.send(self.get_tmp_session(&peer_id).cloned());
+                }
+                BufferMessage::TmpStream(peer_id, tx) => {
+                    let _ = tx.send(self.get_tmp_stream(&peer_id).cloned());
+                }
+                BufferMessage::UpdatePeer(peer_id, peer) => self.update_peer(&peer_id, peer),
+                BufferMessage::RemoveTmp(peer_id, tx) => {
+                    let v = self.remove_tmp(&peer_id);
+                    if let Some(tx) = tx {
+                        let _ = tx.send(v);
+                    }
+                }
+                BufferMessage::RemoveStable(peer_id) => {
+                    self.remove_tmp(&peer_id);
+                    self.remove_stable(&peer_id);
+                }
+                BufferMessage::Clear(tx) => {
+                    let (expired, closes) = self.timer_clear();
+                    let _ = tx.send((expired, closes, self.len()));
+                }
+                BufferMessage::Pending(tx) => {
+                    let _ = tx.send(self.pending());
+                }
+            }
+        }
+    }
+
     pub fn _add_dht(&mut self, ip: &SocketAddr) -> bool {
         if self.dhts.contains_key(ip) {
             false
@@ -42,60 +301,217 @@ impl Buffer {
         self.dhts.remove(ip);
     }
 
-    pub fn add_connect(&mut self, peer_id: PeerId, tid: u64, data: Vec<u8>) -> bool {
+    fn entries_len(&self) -> usize {
+        self.connects.values().map(|v| v.len()).sum::<usize>()
+            + self.results.values().map(|v| v.len()).sum::<usize>()
+    }
+
+    /// check buffer has room for a new message of `size` bytes.
+    /// if full and overflow policy is drop oldest, the dropped messages will return.
+    /// if full and cannot store, return error with the dropped messages. the message
+    /// larger than the whole buffer is rejected before drop any.
+    fn make_room(&mut self, size: usize) -> std::result::Result<Failures, Failures> {
+        if size > self.max_bytes || self.max_entries == 0 {
+            return Err(vec![]);
+        }
+        let mut dropped = vec![];
+        let mut entries = self.entries_len();
+        while entries + 1 > self.max_entries || self.bytes + size > self.max_bytes {
+            if self.overflow == BufferOverflow::RejectNew {
+                return Err(dropped);
+            }
+
+            // find the oldest one.
+            let mut oldest: Option<(bool, PeerId, usize, Instant)> = None;
+            for (is_connect, queues) in [(true, &self.connects), (false, &self.results)] {
+                for (id, v) in queues.iter() {
+                    for (i, (_, _, deadline)) in v.iter().enumerate() {
+                        if oldest.map(|o| *deadline < o.3).unwrap_or(true) {
+                            oldest = Some((is_connect, *id, i, *deadline));
+                        }
+                    }
+                }
+            }
+
+            let (is_connect, id, i, _) = match oldest {
+                Some(oldest) => oldest,
+                None => return Err(dropped), // empty, but still cannot store.
+            };
+            let (t, queues) = if is_connect {
+                (DeliveryType::StableConnect, &mut self.connects)
+            } else {
+                (DeliveryType::StableResult, &mut self.results)
+            };
+            if let Some(v) = queues.get_mut(&id) {
+                let (tid, data, _) = v.remove(i);
+                if v.is_empty() {
+                    queues.remove(&id);
+                }
+                self.bytes -= data.len();
+                dropped.push((t, tid, data));
+            }
+            entries -= 1;
+        }
+
+        Ok(dropped)
+    }
+
+    /// make room & add to connects or results.
+    fn push(&mut self, is_connect: bool, peer_id: PeerId, tid: u64, data: Vec<u8>) -> Push {
+        let dropped = match self.make_room(data.len()) {
+            Ok(dropped) => dropped,
+            Err(dropped) => return Err((data, dropped)),
+        };
+        let is_processing = if is_connect {
+            self.add_connect(peer_id, tid, data)
+        } else {
+            self.add_result(peer_id, tid, data)
+        };
+        Ok((is_processing, dropped))
+    }
+
+    fn add_connect(&mut self, peer_id: PeerId, tid: u64, data: Vec<u8>) -> bool {
+        self.bytes += data.len();
+        let deadline = Instant::now() + self.ttl;
         if let Some(v) = self.connects.get_mut(&peer_id) {
-            v.1.push((tid, data));
+            v.push((tid, data, deadline));
             true
         } else {
-            self.connects.insert(peer_id, (false, vec![(tid, data)]));
+            self.connects.insert(peer_id, vec![(tid, data, deadline)]);
             false
         }
     }
 
-    pub fn remove_connect(&mut self, peer_id: &PeerId) -> Vec<(u64, Vec<u8>)> {
-        self.connects.remove(peer_id).map(|v| v.1).unwrap_or(vec![])
+    fn remove_connect(&mut self, peer_id: &PeerId) -> Entries {
+        let entries = self.connects.remove(peer_id).unwrap_or(vec![]);
+        self.remove_entries(entries)
+    }
+
+    fn cancel_connect(&mut self, peer_id: &PeerId, tid: u64) -> Option<(Vec<u8>, bool)> {
+        let v = self.connects.get_mut(peer_id)?;
+        let i = v.iter().position(|(t, _, _)| *t == tid)?;
+        let (_, data, _) = v.remove(i);
+        let is_empty = v.is_empty();
+        if is_empty {
+            self.connects.remove(peer_id);
+        }
+        self.bytes -= data.len();
+        Some((data, is_empty))
     }
 
-    pub fn add_result(&mut self, peer_id: PeerId, tid: u64, data: Vec<u8>) -> bool {
+    fn add_result(&mut self, peer_id: PeerId, tid: u64, data: Vec<u8>) -> bool {
+        self.bytes += data.len();
+        let deadline = Instant::now() + self.ttl;
         if let Some(v) = self.results.get_mut(&peer_id) {
-            v.1.push((tid, data));
+            v.push((tid, data, deadline));
             true
         } else {
-            self.results.insert(peer_id, (false, vec![(tid, data)]));
+            self.results.insert(peer_id, vec![(tid, data, deadline)]);
             false
         }
     }
 
-    pub fn remove_result(&mut self, peer_id: &PeerId) -> Vec<(u64, Vec<u8>)> {
-        self.results.remove(peer_id).map(|v| v.1).unwrap_or(vec![])
+    fn remove_result(&mut self, peer_id: &PeerId) -> Entries {
+        let entries = self.results.remove(peer_id).unwrap_or(vec![]);
+        self.remove_entries(entries)
+    }
+
+    fn remove_entries(&mut self, entries: Vec<Entry>) -> Entries {
+        entries
+            .into_iter()
+            .map(|(tid, data, _)| {
+                self.bytes -= data.len();
+                (tid, data)
+            })
+            .collect()
     }
 
-    pub fn remove_stable(&mut self, peer_id: &PeerId) {
-        self.connects.remove(peer_id);
-        self.results.remove(peer_id);
+    fn remove_stable(&mut self, peer_id: &PeerId) {
+        self.remove_connect(peer_id);
+        self.remove_result(peer_id);
     }
 
-    pub fn get_tmp_session(&self, peer_id: &PeerId) -> Option<&Sender<SessionMessage>> {
-        self.tmps.get(peer_id).map(|(_, v, _)| &v.0)
+    fn get_tmp_session(&self, peer_id: &PeerId) -> Option<&Sender<SessionMessage>> {
+        self.tmps.get(peer_id).map(|(_, v, _, _)| &v.0)
     }
 
-    pub fn get_tmp_stream(&self, peer_id: &PeerId) -> Option<&Sender<EndpointMessage>> {
-        self.tmps.get(peer_id).map(|(_, v, _)| &v.1)
+    fn get_tmp_stream(&self, peer_id: &PeerId) -> Option<&Sender<EndpointMessage>> {
+        self.tmps.get(peer_id).map(|(_, v, _, _)| &v.1)
+    }
+
+    /// add tmp session, if full, by the overflow policy, reject the new one or drop
+    /// the oldest one, return the rejected or dropped session need close.
+    fn add_tmp(
+        &mut self,
+        peer_id: PeerId,
+        value: KadValue,
+        is_d: bool,
+    ) -> Option<Sender<SessionMessage>> {
+        let mut dropped = None;
+        if !self.tmps.contains_key(&peer_id) && self.tmps.len() >= self.max_entries {
+            if self.overflow == BufferOverflow::RejectNew {
+                debug!(
+                    "Buffer tmp sessions is full, reject {}.",
+                    peer_id.short_show()
+                );
+                return Some(value.0);
+            }
+
+            let oldest = self
+                .tmps
+                .iter()
+                .min_by_key(|(_, (_, _, _, start))| *start)
+                .map(|(id, _)| *id);
+            if let Some(id) = oldest {
+                debug!("Buffer tmp sessions is full, drop {}.", id.short_show());
+                dropped = self.tmps.remove(&id).map(|(_, v, _, _)| v.0);
+            }
+        }
+        self.tmps
+            .insert(peer_id, (false, value, is_d, Instant::now()));
+        dropped
+    }
+
+    fn update_peer(&mut self, peer_id: &PeerId, peer: Peer) {
+        if let Some((_, v, _, _)) = self.tmps.get_mut(peer_id) {
+            v.2 = peer;
+        }
     }
 
-    pub fn add_tmp(&mut self, peer_id: PeerId, value: KadValue, is_d: bool) {
-        self.tmps.insert(peer_id, (false, value, is_d));
+    fn remove_tmp(&mut self, peer_id: &PeerId) -> Option<(KadValue, bool)> {
+        self.tmps.remove(peer_id).map(|(_, v, is_d, _)| (v, is_d))
     }
 
-    pub fn update_peer(&mut self, peer_id: &PeerId, peer: Peer) {
-        self.tmps.get_mut(peer_id).map(|(_, v, _)| v.2 = peer);
+    fn pending(&self) -> Pending {
+        let now = Instant::now();
+        let tmps = self
+            .tmps
+            .iter()
+            .map(|(id, (_, _, is_d, start))| (*id, *is_d, now.duration_since(*start)))
+            .collect();
+        // the entry's deadline is added time + ttl.
+        let ages = |queues: &HashMap<PeerId, Vec<Entry>>| {
+            queues
+                .iter()
+                .flat_map(|(id, v)| {
+                    v.iter().map(move |(tid, _, deadline)| {
+                        let left = deadline.saturating_duration_since(now);
+                        (*id, *tid, self.ttl.saturating_sub(left))
+                    })
+                })
+                .collect()
+        };
+        (tmps, ages(&self.connects), ages(&self.results))
     }
 
-    pub fn remove_tmp(&mut self, peer_id: &PeerId) -> Option<(KadValue, bool)> {
-        self.tmps.remove(peer_id).map(|(_, v, is_d)| (v, is_d))
+    /// all waiting entries number in buffer.
+    fn len(&self) -> usize {
+        self.dhts.len() + self.tmps.len() + self.entries_len()
     }
 
-    pub async fn timer_clear(&mut self) {
+    /// clear the expired buffers, return the expired stable connects and results,
+    /// need delivery failure to outside, and the expired tmp sessions need close.
+    fn timer_clear(&mut self) -> (Failures, Vec<Sender<SessionMessage>>) {
         let mut dht_deletes = vec![];
         for (ip, t) in self.dhts.iter_mut() {
             if *t {
@@ -108,34 +524,33 @@ impl Buffer {
             self.dhts.remove(&ip);
         }
 
-        let mut connect_deletes = vec![];
-        for (id, (t, _)) in self.connects.iter_mut() {
-            if *t {
-                connect_deletes.push(*id);
-            } else {
-                *t = true; // checked.
+        let now = Instant::now();
+        let mut expired = vec![];
+        for (t, queues) in [
+            (DeliveryType::StableConnect, &mut self.connects),
+            (DeliveryType::StableResult, &mut self.results),
+        ] {
+            for entries in queues.values_mut() {
+                entries.retain(|(tid, data, deadline)| {
+                    if *deadline <= now {
+                        expired.push((t.clone(), *tid, data.clone()));
+                        false
+                    } else {
+                        true
+                    }
+                });
             }
+            queues.retain(|_, entries| !entries.is_empty());
         }
-        for id in connect_deletes {
-            self.connects.remove(&id);
-        }
-
-        let mut result_deletes = vec![];
-        for (id, (t, _)) in self.results.iter_mut() {
-            if *t {
-                result_deletes.push(*id);
-            } else {
-                *t = true; // checked.
-            }
-        }
-        for id in result_deletes {
-            self.results.remove(&id);
+        for (_, _, data) in &expired {
+            self.bytes -= data.len();
         }
 
         let mut tmp_deletes = vec![];
-        for (id, (t, KadValue(ss, _, _), _)) in self.tmps.iter_mut() {
+        let mut closes = vec![];
+        for (id, (t, KadValue(ss, _, _), _, _)) in self.tmps.iter_mut() {
             if *t {
-                let _ = ss.send(SessionMessage::Close).await;
+                closes.push(ss.clone());
                 tmp_deletes.push(*id);
             } else {
                 *t = true; // checked.
@@ -144,5 +559,68 @@ impl Buffer {
         for id in tmp_deletes {
             self.tmps.remove(&id);
         }
+
+        (expired, closes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chamomile_types::types::PEER_ID_LENGTH;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn peer_id(b: u8) -> PeerId {
+        PeerId([b; PEER_ID_LENGTH])
+    }
+
+    fn tids(failures: &Failures) -> Vec<u64> {
+        failures.iter().map(|(_, tid, _)| *tid).collect()
+    }
+
+    #[test]
+    fn reject_new() {
+        let mut state = State::new(TTL, 2, 1024, BufferOverflow::RejectNew);
+        // the first one of the peer starts processing.
+        let (is_processing, dropped) = state.push(true, peer_id(1), 1, vec![1]).unwrap();
+        assert!(!is_processing && dropped.is_empty());
+        let (is_processing, dropped) = state.push(true, peer_id(1), 2, vec![2]).unwrap();
+        assert!(is_processing && dropped.is_empty());
+        let (data, dropped) = state.push(false, peer_id(2), 3, vec![3]).unwrap_err();
+        assert_eq!((data, dropped.len()), (vec![3], 0));
+        assert_eq!(state.entries_len(), 2);
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let mut state = State::new(TTL, 2, 1024, BufferOverflow::DropOldest);
+        state.push(true, peer_id(1), 1, vec![1]).unwrap();
+        state.push(false, peer_id(2), 2, vec![2]).unwrap();
+        let (_, dropped) = state.push(true, peer_id(3), 3, vec![3]).unwrap();
+        assert_eq!(tids(&dropped), vec![1]);
+        assert!(matches!(dropped[0].0, DeliveryType::StableConnect));
+        assert_eq!(state.entries_len(), 2);
+        assert_eq!(state.bytes, 2);
+    }
+
+    #[test]
+    fn max_bytes() {
+        let mut state = State::new(TTL, 16, 10, BufferOverflow::DropOldest);
+        state.push(true, peer_id(1), 1, vec![0; 4]).unwrap();
+        state.push(true, peer_id(1), 2, vec![0; 4]).unwrap();
+        // drop the oldest ones until it is stored.
+        let (_, dropped) = state.push(true, peer_id(1), 3, vec![0; 8]).unwrap();
+        assert_eq!(tids(&dropped), vec![1, 2]);
+        assert_eq!(state.bytes, 8);
+
+        // larger than the whole buffer, rejected before drop any.
+        let (data, dropped) = state.push(true, peer_id(1), 4, vec![0; 11]).unwrap_err();
+        assert_eq!((data.len(), dropped.len()), (11, 0));
+        assert_eq!(state.entries_len(), 1);
+
+        // no entries room.
+        let mut state = State::new(TTL, 0, 10, BufferOverflow::DropOldest);
+        assert!(state.push(true, peer_id(1), 1, vec![1]).is_err());
     }
 }
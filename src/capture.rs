@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::{
+    fs::OpenOptions,
+    io::AsyncWriteExt,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use chamomile_types::PeerId;
+
+/// max buffered capture records, when full, drop the new records.
+const CAPTURE_CHANNEL_SIZE: usize = 4096;
+
+/// Wire capture for debugging (`capture` feature), record the traffic of the
+/// selected peers to `db_dir/capture.jsonl`, every line is a JSON object:
+/// `{"ts":<unix ms>,"peer":"<hex>","dir":"in|out","layer":"endpoint|core","type":<u8>,"bytes":"<hex>"}`.
+/// `endpoint` records are the transport messages (without encrypted payload),
+/// `core` records are the decrypted session frames, can be decoded again.
+pub(crate) struct Capture {
+    peers: RwLock<HashSet<PeerId>>,
+    /// None is ephemeral, nothing will be written.
+    sender: Option<Sender<String>>,
+}
+
+impl Capture {
+    pub fn start(path: Option<PathBuf>) -> Self {
+        let sender = path.map(|path| {
+            let (sender, receiver) = channel(CAPTURE_CHANNEL_SIZE);
+            tokio::spawn(write_loop(path, receiver));
+            sender
+        });
+        Capture {
+            peers: RwLock::new(HashSet::new()),
+            sender,
+        }
+    }
+
+    /// start or stop capture the peer's traffic.
+    pub fn set(&self, peer_id: PeerId, enable: bool) {
+        if self.sender.is_none() {
+            warn!("CHAMOMILE: CAPTURE IS DISABLED IN EPHEMERAL MODE.");
+            return;
+        }
+        let mut peers = self.peers.write().unwrap();
+        if enable {
+            peers.insert(peer_id);
+        } else {
+            peers.remove(&peer_id);
+        }
+    }
+
+    pub fn is_capture(&self, peer_id: &PeerId) -> bool {
+        let peers = self.peers.read().unwrap();
+        !peers.is_empty() && peers.contains(peer_id)
+    }
+
+    /// add a record, not waiting it written.
+    pub fn record(&self, peer_id: &PeerId, is_in: bool, is_core: bool, t: u8, bytes: &[u8]) {
+        if !self.is_capture(peer_id) {
+            return;
+        }
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let mut hex = String::with_capacity(bytes.len() * 2);
+        hex.extend(bytes.iter().map(|b| format!("{:02x}", b)));
+        let line = format!(
+            "{{\"ts\":{},\"peer\":\"{}\",\"dir\":\"{}\",\"layer\":\"{}\",\"type\":{},\"bytes\":\"{}\"}}\n",
+            ts,
+            peer_id.to_hex(),
+            if is_in { "in" } else { "out" },
+            if is_core { "core" } else { "endpoint" },
+            t,
+            hex
+        );
+        if let Some(Err(_)) = self.sender.as_ref().map(|s| s.try_send(line)) {
+            warn!("CHAMOMILE: CAPTURE IS FULL, DROP RECORD.");
+        }
+    }
+}
+
+async fn write_loop(path: PathBuf, mut receiver: Receiver<String>) {
+    while let Some(line) = receiver.recv().await {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await;
+        match file {
+            Ok(mut file) => {
+                let _ = file.write_all(line.as_bytes()).await;
+            }
+            Err(e) => warn!("CHAMOMILE: CAPTURE WRITE FAILURE: {:?}", e),
+        }
+    }
+}
@@ -1,18 +1,297 @@
-use std::net::IpAddr;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-use chamomile_types::{Peer, PeerId};
+use chamomile_types::{
+    types::{PowerProfile, TransportType},
+    Multiaddr, Peer, PeerId,
+};
+
+use crate::transports::Emulation;
+
+/// handshake check function, params is remote's `PeerId` and payload.
+type CheckFn = dyn Fn(&PeerId, &[u8]) -> bool + Send + Sync;
+
+/// Check the remote's handshake payload before the session accepted,
+/// return false will reject it.
+#[derive(Clone)]
+pub struct HandshakeCheck(pub Arc<CheckFn>);
+
+impl HandshakeCheck {
+    pub fn new(f: impl Fn(&PeerId, &[u8]) -> bool + Send + Sync + 'static) -> Self {
+        HandshakeCheck(Arc::new(f))
+    }
+}
+
+impl Debug for HandshakeCheck {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "HandshakeCheck")
+    }
+}
+
+/// Custom connection policies (e.g. geo restrictions, corporate allow rules),
+/// checked with the block list. all default is allow.
+pub trait ConnectionGater: Debug + Send + Sync {
+    /// the remote addr of inbound or outbound connection, before accept the handshake.
+    fn allow_addr(&self, _addr: &SocketAddr) -> bool {
+        true
+    }
+
+    /// the remote peer (with `PeerId` and NAT addr) after the handshake,
+    /// include the relay connections.
+    fn allow_peer(&self, _peer: &Peer) -> bool {
+        true
+    }
+
+    /// before dial to the addr.
+    fn allow_dial(&self, _transport: &TransportType, _addr: &SocketAddr) -> bool {
+        true
+    }
+}
+
+/// Relay accounting hooks (e.g. token incentives, quota trading), called on the
+/// relay path with the peers and bytes. all default is allow and do nothing.
+pub trait RelayAccounting: Debug + Send + Sync {
+    /// before forward `from`'s relay data to `to`, return false will drop it.
+    fn allow_forward(&self, _from: &PeerId, _to: &PeerId, _bytes: usize) -> bool {
+        true
+    }
+
+    /// the relay data had forwarded to the next hop `next`.
+    fn forwarded(&self, _from: &PeerId, _to: &PeerId, _next: &PeerId, _bytes: usize) {}
+
+    /// before forward `from`'s relay connect to `to`, return false will refuse it.
+    fn allow_circuit(&self, _from: &PeerId, _to: &PeerId) -> bool {
+        true
+    }
+
+    /// self's data to `to` had sent via the relay peer.
+    fn relay_used(&self, _relay: &PeerId, _to: &PeerId, _bytes: usize) {}
+
+    /// `from`'s relay data to `to` had dropped, the downstream is busy and the
+    /// circuit's queue is full (`Config::relay_queue_size`).
+    fn dropped(&self, _from: &PeerId, _to: &PeerId, _bytes: usize) {}
+}
+
+/// The decision of `StableCheck` on remote's stable connect.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StableDecision {
+    /// accept it, build the stable connection.
+    Accept,
+    /// reject the stable connection, but keep the peer in DHT (it can relay).
+    RelayOnly,
+    /// reject it and close the connection.
+    Reject,
+    /// send `ReceiveMessage::StableConnect` to outside, waiting outside's `StableResult`.
+    Outside,
+}
+
+/// Max stable connections of every peer class, enforced when accept remote's
+/// stable connect, so strangers cannot crowd out the trusted peers.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct StableQuotas {
+    /// peers in allowlist.
+    pub allowlisted: usize,
+    /// peers with a valid join token.
+    pub verified: usize,
+    /// other peers.
+    pub anonymous: usize,
+}
+
+impl Default for StableQuotas {
+    fn default() -> Self {
+        StableQuotas {
+            allowlisted: usize::MAX,
+            verified: usize::MAX,
+            anonymous: usize::MAX,
+        }
+    }
+}
+
+/// PeerId derivation function, params is the public key bytes.
+type IdHashFn = dyn Fn(&[u8]) -> [u8; 32] + Send + Sync;
+
+/// Derive the `PeerId` from the public key, all peers of the network must use
+/// the same one. default is blake3 hash. identity systems can use `derive` to
+/// verify the binding between the network id (`PeerId::to_did`) and the key
+/// in their identity documents.
+#[derive(Clone)]
+pub struct PeerIdHash(pub Arc<IdHashFn>);
+
+impl PeerIdHash {
+    pub fn new(f: impl Fn(&[u8]) -> [u8; 32] + Send + Sync + 'static) -> Self {
+        PeerIdHash(Arc::new(f))
+    }
+
+    /// the PeerId of the public key.
+    pub fn derive(&self, pk: &[u8]) -> PeerId {
+        PeerId((self.0)(pk))
+    }
+}
+
+impl Default for PeerIdHash {
+    fn default() -> Self {
+        PeerIdHash::new(|pk| *blake3::hash(pk).as_bytes())
+    }
+}
+
+impl Debug for PeerIdHash {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "PeerIdHash")
+    }
+}
+
+/// stable check function, params is the remote peer and the connect info.
+type StableCheckFn = dyn Fn(&Peer, &[u8]) -> StableDecision + Send + Sync;
+
+/// Decide the remote's stable connect in chamomile, not round-trip through
+/// the outside every time.
+#[derive(Clone)]
+pub struct StableCheck(pub Arc<StableCheckFn>);
+
+impl StableCheck {
+    pub fn new(f: impl Fn(&Peer, &[u8]) -> StableDecision + Send + Sync + 'static) -> Self {
+        StableCheck(Arc::new(f))
+    }
+}
+
+impl Debug for StableCheck {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "StableCheck")
+    }
+}
+
+/// The purpose of the peer selection.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SelectPurpose {
+    /// the peers sent to a new connected peer to help its DHT.
+    DhtHelp,
+    /// the relay of a stable connection which cannot connect directly.
+    Relay,
+    /// the peers which gossip broadcast is sent to.
+    Gossip,
+}
+
+/// peer score function, params is the purpose, target and candidate.
+type PeerScoreFn = dyn Fn(SelectPurpose, Option<&Peer>, &Peer) -> i32 + Send + Sync;
+
+/// Influence which peers are chosen, e.g. prefer same-region peers by a GeoIP
+/// lookup of the sockets. params is the purpose, the target (the helped peer,
+/// the relay's destination, None for gossip) and the candidate, return the
+/// candidate's score, higher is preferred, negative is excluded.
+#[derive(Clone)]
+pub struct PeerSelector(pub Arc<PeerScoreFn>);
+
+impl PeerSelector {
+    pub fn new(
+        f: impl Fn(SelectPurpose, Option<&Peer>, &Peer) -> i32 + Send + Sync + 'static,
+    ) -> Self {
+        PeerSelector(Arc::new(f))
+    }
+
+    /// sort the candidates by score (higher first), and remove the excluded.
+    pub fn select<T>(
+        &self,
+        purpose: SelectPurpose,
+        target: Option<&Peer>,
+        candidates: Vec<T>,
+        peer: impl Fn(&T) -> &Peer,
+    ) -> Vec<T> {
+        let mut scored: Vec<(i32, T)> = candidates
+            .into_iter()
+            .map(|c| ((self.0)(purpose, target, peer(&c)), c))
+            .filter(|(score, _)| *score >= 0)
+            .collect();
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        scored.into_iter().map(|(_, c)| c).collect()
+    }
+}
+
+impl Debug for PeerSelector {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "PeerSelector")
+    }
+}
+
+/// delivery function, params is the sent data, return the delivery feedback bytes.
+type DeliveryFn = dyn Fn(&[u8]) -> Vec<u8> + Send + Sync;
+
+/// How to compute the data echoed back in `ReceiveMessage::Delivery`.
+#[derive(Clone)]
+pub enum DeliveryPolicy {
+    /// not echo data, return empty.
+    None,
+    /// the first length bytes of data.
+    Prefix(usize),
+    /// the blake3 hash (32 bytes) of data.
+    Hash,
+    /// the full data.
+    Full,
+    /// computed by application's function.
+    Custom(Arc<DeliveryFn>),
+}
+
+impl DeliveryPolicy {
+    pub fn custom(f: impl Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static) -> Self {
+        DeliveryPolicy::Custom(Arc::new(f))
+    }
+
+    /// compute the delivery feedback bytes of data.
+    pub fn delivery(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            DeliveryPolicy::None => vec![],
+            DeliveryPolicy::Prefix(length) => data[..(*length).min(data.len())].to_vec(),
+            DeliveryPolicy::Hash => blake3::hash(data).as_bytes().to_vec(),
+            DeliveryPolicy::Full => data.to_vec(),
+            DeliveryPolicy::Custom(f) => f(data),
+        }
+    }
+}
+
+impl Debug for DeliveryPolicy {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            DeliveryPolicy::None => write!(f, "None"),
+            DeliveryPolicy::Prefix(length) => write!(f, "Prefix({})", length),
+            DeliveryPolicy::Hash => write!(f, "Hash"),
+            DeliveryPolicy::Full => write!(f, "Full"),
+            DeliveryPolicy::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// When buffer is full, how to handle the new buffered message.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BufferOverflow {
+    /// reject the new message, return failure `Delivery`.
+    RejectNew,
+    /// drop the oldest messages to store the new one, dropped return failure `Delivery`.
+    DropOldest,
+}
 
 /// Chammomile Configs.
 #[derive(Debug, Clone)]
 pub struct Config {
-    /// Default Data saved directory.
+    /// Default Data saved directory (keypair & peers), multiple instances in one
+    /// process must use different directories.
     pub db_dir: PathBuf,
+    /// Ephemeral mode, keep the keypair and peer list in memory only, nothing
+    /// is read from or written to `db_dir` (a new PeerId every start), and the
+    /// outbox, buffer journal, audit log and capture are not persisted.
+    pub ephemeral: bool,
     /// Default binding multiaddr string.
-    /// Example: "/ip4/0.0.0.0/quic/7364"
+    /// Example: "/ip4/0.0.0.0/udp/7364/quic"
     pub peer: Peer,
     /// Allowed MultiAddr style peer list.
     pub allowlist: Vec<Peer>,
+    /// Import the peers file (exported by `SendMessage::ExportPeers`) when start,
+    /// merge its peers and tags into the saved peer list. `None` is not import.
+    pub import_peers: Option<PathBuf>,
+    /// Allowed multiaddr seeds, support dns name, it will resolved when dial.
+    /// Example: "/dns4/node.example.com/tcp/443"
+    pub seeds: Vec<Multiaddr>,
     /// Blocked Ip's list.
     pub blocklist: Vec<IpAddr>,
     /// Allowed peer's `PeerId` list.
@@ -33,27 +312,373 @@ pub struct Config {
     /// For example. set `delivery_length = 8`,
     /// and when a `Data(1u64, PeerId, vec![1u8, 2u8, ..., 100u8]),
     /// if send success, will return:
-    /// `Delivery(DeliveryType::Data, 1u64, true, vec![1u8, 2u8, ..., 8u8])`
+    /// `Delivery(DeliveryType::Data, 1u64, Ok(()), vec![1u8, 2u8, ..., 8u8])`
     /// if send failure, will return:
-    /// `Delivery(DeliveryType::Data, 1u64, false, vec![1u8, 2u8, ..., 8u8])`
+    /// `Delivery(DeliveryType::Data, 1u64, Err(reason), vec![1u8, 2u8, ..., 8u8])`
     pub delivery_length: usize,
+    /// How to compute the delivery feedback data, `None` will use `delivery_length`'s prefix.
+    pub delivery_policy: Option<DeliveryPolicy>,
+    /// If `delivery_echo` is true, keep the sent data (with delivery_id) in memory,
+    /// and return the complete data in `Delivery`, not the delivery data, for exact
+    /// correlation of small messages. the delivery data is always computed by self.
+    pub delivery_echo: bool,
+    /// When connected peers less than `bootstrap_min_peers`, will retry connect
+    /// to bootstrap seeds. set 0 to disable retry.
+    pub bootstrap_min_peers: usize,
+    /// First retry waiting time, it will double every retry (with random jitter).
+    pub bootstrap_retry_min: Duration,
+    /// Max retry waiting time.
+    pub bootstrap_retry_max: Duration,
+    /// Interval of reconnecting the lost pinned peers (configured allowlist and seeds),
+    /// pinned peers are never evicted from DHT or idle closed. it is the seeds' health
+    /// check too (`StateRequest::Seed`), dead seeds are tried last. set zero to disable.
+    pub pinned_reconnect: Duration,
+    /// Max concurrent dials when bootstrap, it will send `NetworkReady` when the
+    /// first peer connected.
+    pub bootstrap_concurrency: usize,
+    /// Max concurrent outbound dials of all (bootstrap, DHT, stable connect, probe),
+    /// the more dials are queued, and the failed targets are backed off.
+    pub dial_concurrency: usize,
+    /// If `dht_events` is true, will send `PeerJoin` and `PeerLeave` to outside
+    /// when DHT (not stable) peers connected or disconnected.
+    pub dht_events: bool,
+    /// Interval of sending `SessionStats` of every stable session to outside,
+    /// checked with keepalive, so it is not less than the keepalive interval.
+    /// set zero to disable.
+    pub session_stats: Duration,
+    /// Interval of sending the `Bandwidth` summary (sessions and every transport's
+    /// frame bytes) to outside. set zero to disable, then it is only logged every 60s.
+    pub bandwidth_summary: Duration,
+    /// Max queued data messages to every offline stable peer, when the peer
+    /// connected again, will send them. it is saved every `clear_interval`.
+    /// set 0 to disable the outbox.
+    pub outbox_size: usize,
+    /// Max queued time of outbox message, expired will return failure `Delivery`.
+    pub outbox_age: Duration,
+    /// If `dial_on_demand` is true, data to a not connected peer, whose address is
+    /// known (saved stable peers & allowlist), will dial it and queue the data until
+    /// connected, instead of failure `Delivery` immediately.
+    pub dial_on_demand: bool,
+    /// If `ordered_delivery` is true, stable session's data will deliver to remote
+    /// outside in sending order (if remote support it). set false for latency-critical
+    /// data, it will deliver as soon as received.
+    pub ordered_delivery: bool,
+    /// Max waiting time of buffered stable connect & result when connecting,
+    /// expired will return failure `Delivery`.
+    pub buffer_ttl: Duration,
+    /// Max number of buffered stable connects & results, and also max number of
+    /// the connecting sessions waiting to be stable or DHT.
+    pub buffer_max_entries: usize,
+    /// Max total bytes of buffered stable connects & results.
+    pub buffer_max_bytes: usize,
+    /// When buffer is full, reject new message or drop the oldest. the waiting
+    /// sessions use same policy, the rejected or dropped one is closed.
+    pub buffer_overflow: BufferOverflow,
+    /// If `buffer_persist` is true, buffered stable connects & results (with
+    /// delivery_id) are saved in `db_dir`, so they will send again when restart
+    /// after crash, not dropped silently. (outbox is always saved).
+    pub buffer_persist: bool,
+    /// Interval of checking network is lost. set zero to disable.
+    pub check_interval: Duration,
+    /// Recovery rounds of the network watchdog when network lost, every round
+    /// retry seeds, retry stable peers and rebind sockets, with progress events
+    /// (`NetworkRecovery`). set 0 to disable (only `NetworkLost` every check).
+    pub watchdog_rounds: usize,
+    /// Waiting time after every recovery stage of the watchdog.
+    pub watchdog_wait: Duration,
+    /// Interval of clearing the expired buffers. set zero to disable.
+    pub clear_interval: Duration,
+    /// Interval of refreshing DHT buckets (remove closed sessions). set zero to disable.
+    pub refresh_interval: Duration,
+    /// Interval of session's keepalive (not in low power profile), the remote is
+    /// checked alive by it. min is 100ms.
+    pub keepalive_interval: Duration,
+    /// Max continuous keepalives without answer, more is the session dead (closed,
+    /// and `StableLeave` if stable). so a dead session is detected in about
+    /// `keepalive_interval * (keepalive_misses + 1)`, less for interactive apps,
+    /// more for high-latency links.
+    pub keepalive_misses: u32,
+    /// Close DHT (not stable) sessions which had no data traffic (keepalive is not
+    /// traffic) in this time. set zero to disable.
+    pub idle_timeout: Duration,
+    /// When a stable connection dropped (not closed by outside or remote), will try
+    /// to reconnect it with exponential backoff, and send `StableRestored` when ok.
+    /// it is the max retry times, set 0 to disable.
+    pub stable_reconnect_times: usize,
+    /// First reconnect waiting time, it will double every retry (with random jitter).
+    pub stable_reconnect_min: Duration,
+    /// Max reconnect waiting time.
+    pub stable_reconnect_max: Duration,
+    /// When stable connected, will give remote a resumption ticket, if the connection
+    /// dropped and reconnect in this time, it will resume the stable connection without
+    /// `StableConnect` to outside again (the join token, gater, stable check and quotas
+    /// are still checked). the ticket is revoked when the stable connection is closed
+    /// on purpose (disconnect, unstable or ban). set zero to disable.
+    pub resume_window: Duration,
+    /// Emulate the network condition (latency, jitter, loss and bandwidth) when
+    /// send to remote, only for testing. `None` is disable.
+    pub emulation: Option<Emulation>,
+    /// Application's payload attach to the handshake, e.g. app version or auth token.
+    pub handshake_payload: Vec<u8>,
+    /// Check remote's handshake payload, `None` is accept all.
+    pub handshake_check: Option<HandshakeCheck>,
+    /// Derive the PeerId from public key, default is blake3.
+    pub peer_id_hash: PeerIdHash,
+    /// Custom inbound, handshaked and outbound connection policies. `None` is allow all.
+    pub gater: Option<Arc<dyn ConnectionGater>>,
+    /// Relay forwarding & using hooks, for settlement of relaying. `None` is disable.
+    pub relay_accounting: Option<Arc<dyn RelayAccounting>>,
+    /// Relayed stable connections keep the relay, when the relay's connection
+    /// dropped, re-dial it and wait it back in this time, data is pending, not
+    /// `StableLeave`. set zero to disable (close when the relay lost).
+    pub relay_sticky: Duration,
+    /// Max helpers (the relay & the closest peers of remote) to coordinate the hole
+    /// punching at same time, when a stable connection is relayed, it will try to
+    /// upgrade to direct by the first punched path. set 0 to not start punching.
+    #[cfg(feature = "hole-punching")]
+    pub punch_helpers: usize,
+    /// Max queued bytes of every relay circuit (from, to), when the downstream
+    /// session is busy. set 0 to not queue, drop when busy.
+    #[cfg(feature = "relay")]
+    pub relay_queue_size: usize,
+    /// When the relay circuit's queue is full, drop the new data or the oldest.
+    #[cfg(feature = "relay")]
+    pub relay_queue_overflow: BufferOverflow,
+    /// Decide remote's stable connect (e.g. permissioned joins), accepted peers are in
+    /// `StateRequest::Stable`. `None` is send all to outside.
+    pub stable_check: Option<StableCheck>,
+    /// Choose the peers of DHT help, relay and gossip fanout by the scores.
+    /// `None` is the default (DHT help & gossip to all, relay by the closest).
+    pub peer_selector: Option<PeerSelector>,
+    /// Max stable connections of allowlisted, verified (join token) and anonymous peers.
+    /// default is unlimited.
+    pub stable_quotas: StableQuotas,
+    /// Trusted join token issuers, when not empty, remote's `StableConnect` must have
+    /// a valid token (not expired, for self and the remote) issued by one of them,
+    /// or will be rejected. empty is disable.
+    pub join_issuers: Vec<PeerId>,
+    /// If `data_path` is true, will send `PathData` (with direct transport or relay peer)
+    /// to outside instead of `Data`.
+    pub data_path: bool,
+    /// Max bytes of a message's data. send larger one will return failure `Delivery`,
+    /// receive larger frame from remote will close the connection.
+    pub max_message_size: usize,
+    /// Small data messages to the same session will wait this time, and send
+    /// together in one frame (if remote support it). set zero to disable.
+    pub batch_delay: Duration,
+    /// Max bytes of one batch, when full, send it immediately.
+    pub batch_size: usize,
+    /// Pad stable session's frames to fixed size buckets (bytes), e.g. `[256, 1024, 4096]`,
+    /// larger one pad to multiple of the largest bucket (if remote support it). empty is disable.
+    pub padding_buckets: Vec<usize>,
+    /// Send cover frames on stable sessions about every this time (random jitter),
+    /// only when padding enabled. zero is disable.
+    pub cover_traffic: Duration,
+    /// If `outside` is false, will not send any message to outside (e.g. public
+    /// bootstrap node), the outside receiver needn't be consumed.
+    pub outside: bool,
+    /// Pre-shared key of private network, peers without the same psk cannot
+    /// confirm it in the handshake, they are rejected before join the DHT, even
+    /// in permissionless mode. `None` is disable.
+    pub psk: Option<[u8; 32]>,
+    /// Act as a mailbox, store max this number of mails for every offline peer,
+    /// until it online and fetch them. set 0 to disable the mailbox role.
+    pub mailbox_size: usize,
+    /// Max stored time of mailbox's mail.
+    pub mailbox_age: Duration,
+    /// The mailbox role stores mails from any connected peer, if false, only from
+    /// stable and allowlisted peers. every sender can store max 4 MB mails.
+    pub mailbox_open: bool,
+    /// Group members publish their membership to this number of the closest DHT
+    /// peers of the group's topic (hash of the name), and query them for the members
+    /// not connected yet, refreshed with `refresh_interval`. The registered services'
+    /// provider records are published the same. set 0 to disable.
+    pub topic_replicas: usize,
+    /// Gossip broadcast larger than it is distributed by swarm: split to chunks,
+    /// announced by the signed manifest of chunks' hashes, and peers fetch the
+    /// missing chunks from any neighbor, not pushed to every peer by self. set 0
+    /// to disable (push the full data).
+    pub swarm_threshold: usize,
+    /// Max bytes of the swarms fetching & serving for others (kept for 10 min),
+    /// the larger is ignored. set 0 to disable join other's swarms.
+    pub swarm_cache: usize,
+    /// Act as a transport bridge, listen both QUIC & TCP at the same port and relay
+    /// between them, advertised in handshake, so peers on different transports
+    /// prefer it to relay. it will relay even if `permission` is true.
+    pub bridge: bool,
+    /// Keepalive & maintenance profile, `LowPower` is for mobile. it can be changed
+    /// at runtime by `SendMessage::PowerProfile`.
+    pub power_profile: PowerProfile,
+    /// Uplink bytes per second shared by stable sessions, by their classes
+    /// (`SendMessage::PeerQos`), so background sessions not starve interactive.
+    /// set 0 to disable.
+    pub uplink: u64,
+    /// Max pending inbound QUIC handshakes, when more than it, new connections must
+    /// pass a stateless retry (address validation) before allocating state and DH.
+    /// (TCP is validated by its own handshake). set 0 to disable.
+    pub handshake_retry: usize,
+    /// Trusted proxies' source addresses (the node is behind load balancers), inbound
+    /// TCP connections from them start with a HAProxy PROXY protocol v2 header, the
+    /// client address in it is used as the remote address (block/allow, NAT), the
+    /// connections without it are closed. the header is not parsed from other
+    /// addresses, it is an invalid frame. empty is disable.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Misbehavior score (invalid frames, bad signatures, rate violations) of
+    /// a peer to disconnect and temp-ban it (PeerId if handshaked, or ip), the
+    /// score decays one per second. the pinned peers (allowlist and seeds) are
+    /// never banned. set 0 to disable (default).
+    pub ban_threshold: u32,
+    /// Temp-ban time (seconds) of the misbehaving peers.
+    pub ban_time: u64,
+    /// Also temp-ban the ip of the banned handshaked peer, so it cannot come back
+    /// with a new PeerId. set false when many peers share an ip (NAT).
+    pub ban_ip: bool,
+    /// Max inbound frames per second of a direct session, more than it is
+    /// a rate violation. set 0 to disable.
+    pub ban_rate: u32,
+    /// Max block time of the shared block entries accepted from the trusted peers
+    /// (allowlist), they are applied and forwarded to other trusted peers. the
+    /// entries targeting self or the allowlisted peers (PeerId or ip) are ignored.
+    /// set zero to not accept (self can still share by `SendMessage::ShareBlock`).
+    pub block_share: Duration,
+    /// Write the connection audit log (handshake, blocked, stable, relay) to
+    /// `db_dir/audit.log`, rotated when over this bytes. set 0 to disable.
+    pub audit_log_size: u64,
+    /// Serve the HTTP debug/admin endpoint (routes, stables, buffer, bandwidth,
+    /// block & unblock) at this address, the reads have no auth, bind to localhost.
+    /// requests whose `Host` is not this address are rejected. `None` is disable.
+    #[cfg(feature = "admin")]
+    pub admin_addr: Option<SocketAddr>,
+    /// The bearer token (`Authorization: Bearer <token>`) of the admin endpoint's
+    /// block & unblock. `None` is disable them.
+    #[cfg(feature = "admin")]
+    pub admin_token: Option<String>,
+    /// Publish & lookup self in BitTorrent mainline DHT with this network key
+    /// (e.g. "my-app-mainnet"), as out-of-band bootstrap when all seeds are down.
+    /// `None` is disable.
+    #[cfg(feature = "mainline")]
+    pub mainline_key: Option<String>,
 }
 
 impl Config {
     pub fn default(peer: Peer) -> Self {
         Self {
             db_dir: PathBuf::from("./"),
-            peer: peer,
+            ephemeral: false,
+            import_peers: None,
+            peer,
             allowlist: vec![],
+            seeds: vec![],
             blocklist: vec![],
             allow_peer_list: vec![],
             block_peer_list: vec![],
             permission: false,
             only_stable_data: false,
             delivery_length: 0,
+            delivery_echo: false,
+            delivery_policy: None,
+            bootstrap_min_peers: 1,
+            bootstrap_retry_min: Duration::from_secs(5),
+            bootstrap_retry_max: Duration::from_secs(300),
+            pinned_reconnect: Duration::from_secs(30),
+            bootstrap_concurrency: 16,
+            dial_concurrency: 32,
+            dht_events: false,
+            session_stats: Duration::ZERO,
+            bandwidth_summary: Duration::ZERO,
+            outbox_size: 0,
+            outbox_age: Duration::from_secs(86400),
+            dial_on_demand: true,
+            ordered_delivery: true,
+            buffer_ttl: Duration::from_secs(20),
+            buffer_max_entries: 1024,
+            buffer_max_bytes: 67108864, // 64 MB
+            buffer_overflow: BufferOverflow::RejectNew,
+            buffer_persist: false,
+            check_interval: Duration::from_secs(10),
+            watchdog_rounds: 3,
+            watchdog_wait: Duration::from_secs(10),
+            clear_interval: Duration::from_secs(10),
+            refresh_interval: Duration::from_secs(60),
+            keepalive_interval: Duration::from_secs(2),
+            keepalive_misses: 3,
+            idle_timeout: Duration::ZERO,
+            stable_reconnect_times: 0,
+            stable_reconnect_min: Duration::from_secs(2),
+            stable_reconnect_max: Duration::from_secs(60),
+            resume_window: Duration::from_secs(60),
+            emulation: None,
+            handshake_payload: vec![],
+            handshake_check: None,
+            peer_id_hash: PeerIdHash::default(),
+            gater: None,
+            relay_accounting: None,
+            relay_sticky: Duration::from_secs(10),
+            #[cfg(feature = "hole-punching")]
+            punch_helpers: 3,
+            #[cfg(feature = "relay")]
+            relay_queue_size: 1024 * 1024,
+            #[cfg(feature = "relay")]
+            relay_queue_overflow: BufferOverflow::DropOldest,
+            stable_check: None,
+            peer_selector: None,
+            join_issuers: vec![],
+            stable_quotas: StableQuotas::default(),
+            data_path: false,
+            max_message_size: 67108864, // 64 MB
+            batch_delay: Duration::ZERO,
+            batch_size: 16384, // 16 KB
+            padding_buckets: vec![],
+            cover_traffic: Duration::ZERO,
+            outside: true,
+            psk: None,
+            mailbox_size: 0,
+            mailbox_age: Duration::from_secs(604800), // 7 days
+            mailbox_open: false,
+            topic_replicas: 3,
+            swarm_threshold: 4194304, // 4 MB
+            swarm_cache: 134217728,   // 128 MB
+            bridge: false,
+            power_profile: PowerProfile::Normal,
+            uplink: 0,
+            handshake_retry: 64,
+            trusted_proxies: vec![],
+            ban_threshold: 0,
+            ban_time: 600,
+            ban_ip: true,
+            block_share: Duration::ZERO,
+            ban_rate: 5000,
+            audit_log_size: 0,
+            #[cfg(feature = "admin")]
+            admin_addr: None,
+            #[cfg(feature = "admin")]
+            admin_token: None,
+            #[cfg(feature = "mainline")]
+            mainline_key: None,
         }
     }
 
+    /// Preset profile for public infrastructure node (bootstrap/supernode),
+    /// permissionless (relay & DHT serving), more connections and buffers,
+    /// and no application data to outside.
+    pub fn bootstrap_node(peer: Peer) -> Self {
+        Self {
+            permission: false,
+            only_stable_data: true,
+            bootstrap_concurrency: 64,
+            dial_concurrency: 64,
+            buffer_ttl: Duration::from_secs(10),
+            buffer_max_entries: 8192,
+            buffer_max_bytes: 268435456, // 256 MB
+            buffer_overflow: BufferOverflow::DropOldest,
+            idle_timeout: Duration::from_secs(600),
+            resume_window: Duration::ZERO,
+            outside: false,
+            ..Self::default(peer)
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         db_dir: PathBuf,
         peer: Peer,
@@ -67,7 +692,6 @@ impl Config {
     ) -> Self {
         Self {
             db_dir,
-            peer,
             allowlist,
             blocklist,
             allow_peer_list,
@@ -75,6 +699,7 @@ impl Config {
             permission,
             only_stable_data,
             delivery_length,
+            ..Self::default(peer)
         }
     }
 }
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{
+    mpsc::{channel, Receiver, Sender},
+    oneshot, Semaphore,
+};
+
+use chamomile_types::Peer;
+
+use crate::global::Global;
+use crate::keys::SessionKey;
+use crate::primitives::DIAL_TIMEOUT;
+use crate::transports::{EndpointMessage, RemotePublic, TransportSendMessage};
+
+/// max queued dials, when full, the new dials are refused.
+const DIAL_QUEUE_SIZE: usize = 1024;
+
+/// the first backoff time after a dial failure, doubled every failure.
+const DIAL_BACKOFF_MIN: Duration = Duration::from_secs(1);
+
+/// the max backoff time of a target.
+const DIAL_BACKOFF_MAX: Duration = Duration::from_secs(300);
+
+/// the kind of outbound connection attempt.
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum DialKind {
+    /// DHT connect (bootstrap, DHT help, outside `Connect`), reuse the in-flight dial.
+    Connect,
+    /// probe the address with the probe's handshake info, always dial.
+    Probe(SessionKey, RemotePublic),
+    /// stable connect with the session's channels and handshake info, always dial.
+    Stable(
+        Sender<EndpointMessage>,
+        Receiver<EndpointMessage>,
+        RemotePublic,
+    ),
+}
+
+pub(crate) struct DialJob {
+    peer: Peer,
+    kind: DialKind,
+    notify: Option<oneshot::Sender<()>>,
+}
+
+/// the target's failures and the time it can dial again.
+type Backoff = (u32, Instant);
+
+/// Dialer, all outbound connection attempts are queued to one task, which
+/// limits the in-flight dials, and backs off the failed targets.
+pub(crate) struct Dialer {
+    sender: Sender<DialJob>,
+    backoffs: Mutex<HashMap<SocketAddr, Backoff>>,
+}
+
+impl Dialer {
+    pub fn new() -> (Self, Receiver<DialJob>) {
+        let (sender, receiver) = channel(DIAL_QUEUE_SIZE);
+        let dialer = Dialer {
+            sender,
+            backoffs: Mutex::new(HashMap::new()),
+        };
+        (dialer, receiver)
+    }
+
+    /// queue a dial, false if the target is backing off or the queue is full.
+    /// the `notify` will send when the dial finished.
+    pub fn dial(&self, peer: Peer, kind: DialKind, notify: Option<oneshot::Sender<()>>) -> bool {
+        if let Some((_, until)) = self.backoffs.lock().unwrap().get(&peer.socket) {
+            if *until > Instant::now() {
+                debug!("Dial to {} is backing off.", peer.socket);
+                return false;
+            }
+        }
+        let job = DialJob { peer, kind, notify };
+        if self.sender.try_send(job).is_err() {
+            warn!("CHAMOMILE: DIALER QUEUE IS FULL.");
+            return false;
+        }
+        true
+    }
+
+    /// the dial to socket failure, backoff it.
+    pub fn failure(&self, socket: SocketAddr) {
+        let mut backoffs = self.backoffs.lock().unwrap();
+        let backoff = backoffs.entry(socket).or_insert((0, Instant::now()));
+        let delay = DIAL_BACKOFF_MIN
+            .saturating_mul(2u32.saturating_pow(backoff.0))
+            .min(DIAL_BACKOFF_MAX);
+        *backoff = (backoff.0.saturating_add(1), Instant::now() + delay);
+    }
+
+    /// the dial to socket is connected, clear its backoff.
+    pub fn success(&self, socket: &SocketAddr) {
+        self.backoffs.lock().unwrap().remove(socket);
+    }
+
+    /// remove the expired backoffs.
+    pub fn clear(&self) {
+        let now = Instant::now();
+        self.backoffs
+            .lock()
+            .unwrap()
+            .retain(|_, (_, until)| *until + DIAL_BACKOFF_MAX > now);
+    }
+}
+
+/// run the queued dials, at most `concurrency` dials in-flight, a dial is
+/// finished when connected, failure or timeout.
+pub(crate) async fn dialer_loop(
+    global: Arc<Global>,
+    mut receiver: Receiver<DialJob>,
+    concurrency: usize,
+) {
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    while let Some(DialJob { peer, kind, notify }) = receiver.recv().await {
+        let permit = match semaphore.clone().acquire_owned().await {
+            Ok(permit) => permit,
+            Err(_) => break,
+        };
+
+        let is_new = global.dial_start(peer.socket, peer.id, notify).await;
+        let msg = match kind {
+            DialKind::Connect if !is_new => {
+                debug!("Dial to {} is in-flight, reuse it.", peer.socket);
+                continue;
+            }
+            DialKind::Connect => {
+                let (session_key, remote_pk) = global.generate_remote();
+                TransportSendMessage::Connect(peer.socket, remote_pk, session_key)
+            }
+            DialKind::Probe(session_key, remote_pk) => {
+                TransportSendMessage::Connect(peer.socket, remote_pk, session_key)
+            }
+            DialKind::Stable(stream_sender, endpoint_receiver, remote_pk) => {
+                TransportSendMessage::StableConnect(
+                    stream_sender,
+                    endpoint_receiver,
+                    peer.socket,
+                    remote_pk,
+                )
+            }
+        };
+        // the stable session is waiting the handshake, close it when dial failure.
+        let stable_sender = match &msg {
+            TransportSendMessage::StableConnect(sender, ..) => Some(sender.clone()),
+            _ => None,
+        };
+
+        if global.trans_send(&peer.transport, msg).await.is_err() {
+            global.dial_done(&peer.socket).await;
+            if let Some(sender) = stable_sender {
+                let _ = sender.send(EndpointMessage::Close).await;
+            }
+            continue;
+        }
+
+        if let Some(rx) = global.dial_wait(peer.socket, peer.id).await {
+            tokio::spawn(async move {
+                let _ = tokio::time::timeout(DIAL_TIMEOUT, rx).await;
+                drop(permit);
+            });
+        }
+    }
+}
+
+/// release the in-flight dial to the socket when dropped, so the aborted waiting
+/// task (e.g. by `StableCancel`) not keep it.
+pub(crate) struct DialGuard {
+    global: Arc<Global>,
+    socket: Option<SocketAddr>,
+}
+
+impl DialGuard {
+    pub fn new(global: Arc<Global>, socket: SocketAddr) -> Self {
+        DialGuard {
+            global,
+            socket: Some(socket),
+        }
+    }
+
+    /// the dial is finished, release it now.
+    pub async fn done(mut self) {
+        if let Some(socket) = self.socket.take() {
+            self.global.dial_done(&socket).await;
+        }
+    }
+}
+
+impl Drop for DialGuard {
+    fn drop(&mut self) {
+        if let Some(socket) = self.socket.take() {
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                let global = self.global.clone();
+                handle.spawn(async move { global.dial_done(&socket).await });
+            }
+        }
+    }
+}
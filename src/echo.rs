@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chamomile_types::message::SendMessage;
+
+use crate::config::DeliveryPolicy;
+
+/// Local payload of `Delivery`, keep the sent data's delivery data (computed by
+/// self's `delivery_policy`, or the full data if `delivery_echo`) by delivery_id,
+/// and return it in `Delivery`, not the data computed by remote when success.
+pub(crate) struct Echoes {
+    /// max kept time, the not returned data are dropped.
+    ttl: Duration,
+    /// keep the full data, not the delivery data.
+    is_full: bool,
+    /// delivery_id => full data and kept time.
+    datas: Mutex<HashMap<u64, (Vec<u8>, Instant)>>,
+}
+
+impl Echoes {
+    pub fn new(ttl: Duration, is_full: bool) -> Self {
+        Echoes {
+            ttl,
+            is_full,
+            datas: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// keep the delivery data of outside's message which has delivery_id.
+    pub fn keep(&self, msg: &SendMessage, policy: &DeliveryPolicy) {
+        let (tid, data) = match msg {
+            SendMessage::Data(tid, _, data)
+            | SendMessage::StableConnect(tid, _, data)
+            | SendMessage::StableResult(tid, _, _, _, data)
+            | SendMessage::ProtocolData(tid, _, _, data)
+            | SendMessage::MailboxSend(tid, _, data) => (*tid, data),
+            SendMessage::Deadline(_, msg) => return self.keep(msg, policy),
+            _ => return,
+        };
+        if tid != 0 {
+            let data = if self.is_full {
+                data.clone()
+            } else {
+                policy.delivery(data)
+            };
+            self.datas
+                .lock()
+                .unwrap()
+                .insert(tid, (data, Instant::now()));
+        }
+    }
+
+    /// take the kept delivery data.
+    pub fn take(&self, tid: u64) -> Option<Vec<u8>> {
+        self.datas
+            .lock()
+            .unwrap()
+            .remove(&tid)
+            .map(|(data, _)| data)
+    }
+
+    /// remove the expired data.
+    pub fn clear(&self) {
+        let now = Instant::now();
+        let ttl = self.ttl;
+        self.datas
+            .lock()
+            .unwrap()
+            .retain(|_, (_, t)| now.duration_since(*t) < ttl);
+    }
+}
@@ -1,21 +1,77 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::{
     io::Result,
-    sync::{mpsc::Sender, RwLock},
+    sync::{mpsc::Sender, oneshot, RwLock},
+    task::AbortHandle,
 };
 
 use chamomile_types::{
-    message::ReceiveMessage,
-    types::{new_io_error, TransportType},
-    Peer, PeerId,
+    message::{
+        DeliveryFailure, DeliveryType, DialReason, EventFilter, Misbehavior, ReceiveMessage,
+    },
+    types::{new_io_error, BlockTarget, DataPath, PeerPolicy, PowerProfile, TransportType},
+    Multiaddr, Peer, PeerId,
 };
 
+use crate::audit::Audit;
+use crate::ban::{Bans, SharedBans};
 use crate::buffer::Buffer;
+use crate::config::{
+    ConnectionGater, DeliveryPolicy, HandshakeCheck, PeerIdHash, PeerSelector, RelayAccounting,
+    SelectPurpose, StableCheck, StableQuotas,
+};
+use crate::dialer::{DialKind, Dialer};
+use crate::echo::Echoes;
+use crate::group::Groups;
+#[cfg(feature = "hole-punching")]
+use crate::hole_punching::Punches;
+use crate::journal::Journal;
 use crate::kad::KadValue;
-use crate::keys::{Keypair, SessionKey};
-use crate::peer_list::PeerList;
-use crate::transports::{start, RemotePublic, TransportRecvMessage, TransportSendMessage};
+use crate::keys::{psk_confirm, psk_verify, Cipher, Keypair, SessionKey};
+use crate::mailbox::{Mailbox, PeerKeys};
+use crate::multi::Multis;
+use crate::outbox::Outbox;
+use crate::peer_list::{PeerList, Routes};
+use crate::primitives::{
+    DIAL_TIMEOUT, LOW_POWER_KEEPALIVE_INTERVAL, LOW_POWER_SCALE, MAX_DIAL_DATA,
+};
+use crate::qos::Qos;
+#[cfg(feature = "relay")]
+use crate::relay_queue::RelayQueues;
+use crate::seeds::SeedHealth;
+use crate::service::{service_key, Services};
+use crate::session::{Resumption, SessionMessage};
+use crate::stats::{self, Bandwidth};
+use crate::swarm::{SwarmId, Swarms};
+use crate::topic::{topic_key, Topics};
+use crate::transports::{
+    negotiate_version, start, Emulation, FrameCounter, RemotePublic, TransportRecvMessage,
+    TransportSendMessage, BRIDGE_FLAG, PROTOCOL_VERSION, PUNCH_FLAG,
+};
+use crate::watchdog::Watchdog;
+
+/// in-flight dial, start time and the waiting notifies.
+pub(crate) type Dial = (Instant, Vec<oneshot::Sender<()>>);
+
+/// dial on demand, the dialing socket, start time and the queued data (tid, data).
+pub(crate) type DialData = (SocketAddr, Instant, Vec<(u64, Vec<u8>)>);
+
+/// in-flight probe, the probe_id, peer, start time and the dial's session key bytes.
+pub(crate) type Probe = (u64, Peer, Instant, Vec<u8>);
+
+/// the message's delivery type, peer and deadline.
+pub(crate) type Deadline = (DeliveryType, PeerId, Instant);
+
+/// in-flight stable connecting, the tid, delivery and the task.
+pub(crate) type Connecting = (u64, Vec<u8>, AbortHandle);
+
+/// filtered subscriber, filter and receiver channel's sender.
+pub(crate) type Subscriber = (EventFilter, Sender<ReceiveMessage>);
 
 pub(crate) struct Global {
     pub peer: Peer,
@@ -23,10 +79,161 @@ pub(crate) struct Global {
     pub trans: Sender<TransportRecvMessage>,
     pub transports: Arc<RwLock<HashMap<TransportType, Sender<TransportSendMessage>>>>,
     pub out_sender: Sender<ReceiveMessage>,
+    /// filtered subscribers, matched message will send to it, not the out_sender.
+    pub subscribers: Arc<RwLock<Vec<Subscriber>>>,
     pub peer_list: Arc<RwLock<PeerList>>,
-    pub buffer: Arc<RwLock<Buffer>>,
+    /// connected sessions index, lookup without peer_list's lock.
+    pub routes: Arc<Routes>,
+    pub buffer: Buffer,
+    /// queued data to offline stable peers.
+    pub outbox: Arc<RwLock<Outbox>>,
+    /// dial the known but not connected peer when send data to it.
+    pub dial_on_demand: bool,
+    /// queued data to the dialing peers, peer_id => dial.
+    pub dial_datas: Arc<RwLock<HashMap<PeerId, DialData>>>,
+    /// saved buffered stable connects & results.
+    pub journal: Arc<RwLock<Journal>>,
+    /// PeerId => last keepalive round-trip time (ms).
+    pub latencies: Arc<RwLock<HashMap<PeerId, u32>>>,
+    /// PeerId => estimated remote clock minus self's clock (ms), by keepalive.
+    pub clock_offsets: Arc<RwLock<HashMap<PeerId, i64>>>,
+    /// PeerId => session's bytes counter.
+    pub bandwidths: Arc<RwLock<HashMap<PeerId, Arc<Bandwidth>>>>,
+    /// relay-connected PeerId => the relay peer.
+    pub relay_paths: Arc<RwLock<HashMap<PeerId, PeerId>>>,
+    /// TransportType => transport's total frame bytes counter.
+    pub transport_bandwidths: HashMap<TransportType, Arc<FrameCounter>>,
+    #[cfg(feature = "relay")]
     pub is_relay_data: bool,
-    pub delivery_length: usize,
+    /// in-flight parallel hole punches.
+    #[cfg(feature = "hole-punching")]
+    pub punches: Punches,
+    /// relay circuits' queues of the busy downstream sessions.
+    #[cfg(feature = "relay")]
+    pub relay_queues: RelayQueues,
+    pub delivery: DeliveryPolicy,
+    /// the delivery data (or full data) of sent messages, returned in `Delivery`.
+    pub echoes: Echoes,
+    pub dht_events: bool,
+    /// interval of stable sessions' stats events, zero is disable.
+    pub session_stats: Duration,
+    pub ordered_delivery: bool,
+    /// session's keepalive interval in normal profile.
+    pub keepalive_interval: Duration,
+    /// the network emulation of sessions' direct sending, only for testing.
+    pub emulation: Option<Emulation>,
+    /// max keepalives without answer before the session is dead.
+    pub keepalive_misses: u32,
+    pub idle_timeout: Duration,
+    /// max bytes of a message's data.
+    pub max_message_size: usize,
+    /// max concurrent bootstrap dials.
+    pub bootstrap_concurrency: usize,
+    /// queued outbound dials and the targets' backoff.
+    pub dialer: Dialer,
+    /// in-flight dials, (socket, peer_id) => (start time, finished notifies).
+    pub dials: Arc<RwLock<HashMap<(SocketAddr, PeerId), Dial>>>,
+    /// in-flight probes, socket => probe (with the probe dial's session key bytes).
+    pub probes: Arc<RwLock<HashMap<SocketAddr, Probe>>>,
+    /// had connected peers, false when network lost.
+    pub online: AtomicBool,
+    /// network lost recovery.
+    pub watchdog: Watchdog,
+    /// is low power profile, can changed at runtime.
+    pub low_power: AtomicBool,
+    /// stable sessions' uplink scheduler, None is disable.
+    pub qos: Option<Qos>,
+    /// PeerId => data permissions, not in it is the default policy.
+    pub policies: Arc<RwLock<HashMap<PeerId, PeerPolicy>>>,
+    /// wire capture of the selected peers.
+    #[cfg(feature = "capture")]
+    pub capture: crate::capture::Capture,
+    /// session event recording of the selected peers.
+    #[cfg(feature = "capture")]
+    pub recorder: crate::record::Recorder,
+    /// small data batch delay & max bytes.
+    pub batch: (Duration, usize),
+    /// stable session's frame padding buckets, empty is disable.
+    pub padding_buckets: Vec<usize>,
+    /// stable session's cover frames interval, zero is disable.
+    pub cover_traffic: Duration,
+    /// if false, not send any message to outside.
+    pub outside: bool,
+    /// pre-shared key of private network, confirmed in handshake and mixed in
+    /// session key derivation.
+    pub psk: Option<[u8; 32]>,
+    /// registered services and the connected providers.
+    pub services: Arc<RwLock<Services>>,
+    /// stored mails (mailbox role), connected mailboxes and received mails.
+    pub mailbox: Arc<RwLock<Mailbox>>,
+    /// the handshaked peers' DH public keys, the mails are encrypted to them.
+    pub peer_keys: Mutex<PeerKeys>,
+    /// the closest peers number of publish & query the group's topic, 0 is disable.
+    pub topic_replicas: usize,
+    /// the stored topic records for other peers.
+    pub topics: Arc<RwLock<Topics>>,
+    /// gossip larger than it is distributed by swarm, 0 is disable.
+    pub swarm_threshold: usize,
+    /// fetching & serving swarms of large broadcasts.
+    pub swarms: Arc<RwLock<Swarms>>,
+    /// connection audit log.
+    pub audit: Audit,
+    /// misbehavior scores and temp-banned peers.
+    pub bans: Bans,
+    /// shared blocks from trusted peers.
+    pub shared_bans: SharedBans,
+    /// max inbound frames per second of a direct session, 0 is disable.
+    pub ban_rate: u32,
+    /// joined groups and known members.
+    pub groups: Arc<RwLock<Groups>>,
+    /// in-flight multi-recipient data.
+    pub multis: Arc<RwLock<Multis>>,
+    /// delivery_id => the message's deadline.
+    pub deadlines: Arc<RwLock<HashMap<u64, Deadline>>>,
+    /// PeerId => in-flight stable connecting task.
+    pub connectings: Arc<RwLock<HashMap<PeerId, Connecting>>>,
+    /// reconnecting dropped stable peers.
+    pub reconnects: Arc<RwLock<HashSet<PeerId>>>,
+    /// stable reconnect max times, min & max waiting time.
+    pub reconnect: (usize, Duration, Duration),
+    /// PeerId => stable session's resumption tickets.
+    pub resumptions: Arc<RwLock<HashMap<PeerId, Resumption>>>,
+    pub resume_window: Duration,
+    /// multiaddr seeds, resolved when bootstrap.
+    pub seeds: Vec<Multiaddr>,
+    /// health of the configured seeds.
+    pub seed_health: SeedHealth,
+    pub handshake_payload: Vec<u8>,
+    pub handshake_check: Option<HandshakeCheck>,
+    pub peer_id_hash: PeerIdHash,
+    pub gater: Option<Arc<dyn ConnectionGater>>,
+    pub relay_accounting: Option<Arc<dyn RelayAccounting>>,
+    /// relayed stable sessions wait the lost relay back in it, zero is disable.
+    pub relay_sticky: Duration,
+    pub stable_check: Option<StableCheck>,
+    pub peer_selector: Option<PeerSelector>,
+    pub join_issuers: Vec<PeerId>,
+    pub stable_quotas: StableQuotas,
+    /// stable peers' class, for the quotas.
+    pub stable_classes: Arc<RwLock<HashMap<PeerId, PeerClass>>>,
+    /// send `PathData` instead of `Data`.
+    pub data_path: bool,
+    /// the configured allowlist peers.
+    pub allowlisted: HashSet<PeerId>,
+    /// self is a transport bridge.
+    pub bridge: bool,
+    /// connected peers which are transport bridges.
+    pub bridges: Arc<RwLock<HashSet<PeerId>>>,
+    /// application heartbeat payload, sent with keepalive on stable sessions.
+    pub heartbeat: Arc<RwLock<Vec<u8>>>,
+}
+
+/// the class of stable peer, for `StableQuotas`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum PeerClass {
+    Allowlisted,
+    Verified,
+    Anonymous,
 }
 
 impl Global {
@@ -40,31 +247,456 @@ impl Global {
         // random gennerate, so must return. no keep-loop.
         loop {
             if let Ok(session_key) = self.key.generate_session_key() {
+                let dh_bytes = session_key.out_bytes();
+                let confirm = self
+                    .psk
+                    .as_ref()
+                    .map(|psk| psk_confirm(psk, &dh_bytes, &[]))
+                    .unwrap_or_default();
                 let remote_pk = RemotePublic(
                     self.key.public(),
-                    self.peer.clone(),
-                    session_key.out_bytes(),
+                    self.peer,
+                    dh_bytes,
+                    PROTOCOL_VERSION | self.flags(),
+                    self.handshake_payload.clone(),
+                    confirm,
                 );
                 return (session_key, remote_pk);
             }
         }
     }
 
+    /// as the handshake's responder, check the remote's psk confirmation and
+    /// complete the session key. None if the remote not has the same psk.
     #[inline]
     pub fn complete_remote(
         &self,
         remote_key: &Keypair,
         dh_bytes: Vec<u8>,
+        confirm: &[u8],
+        cipher: Cipher,
     ) -> Option<(SessionKey, RemotePublic)> {
-        if let Some(session_key) = self.key.complete_session_key(remote_key, dh_bytes) {
-            let remote_pk = RemotePublic(
-                self.key.public(),
-                self.peer.clone(),
-                session_key.out_bytes(),
-            );
-            Some((session_key, remote_pk))
+        if let Some(psk) = &self.psk {
+            if !psk_verify(psk, &dh_bytes, &[], confirm) {
+                return None;
+            }
+        }
+        let remote_dh = dh_bytes.clone();
+        let session_key =
+            self.key
+                .complete_session_key(remote_key, dh_bytes, cipher, self.psk.as_ref())?;
+        self.remember_key(remote_key);
+        let self_dh = session_key.out_bytes();
+        let confirm = self
+            .psk
+            .as_ref()
+            .map(|psk| psk_confirm(psk, &self_dh, &remote_dh))
+            .unwrap_or_default();
+        let remote_pk = RemotePublic(
+            self.key.public(),
+            self.peer,
+            self_dh,
+            PROTOCOL_VERSION | self.flags(),
+            self.handshake_payload.clone(),
+            confirm,
+        );
+        Some((session_key, remote_pk))
+    }
+
+    /// as the handshake's initiator, check the remote's psk confirmation (bound to
+    /// self's handshake) and complete the session key.
+    #[inline]
+    pub fn complete_local(
+        &self,
+        session_key: &mut SessionKey,
+        remote_key: &Keypair,
+        dh_bytes: Vec<u8>,
+        confirm: &[u8],
+        cipher: Cipher,
+    ) -> bool {
+        if let Some(psk) = &self.psk {
+            if !psk_verify(psk, &dh_bytes, &session_key.out_bytes(), confirm) {
+                return false;
+            }
+        }
+        let ok = session_key.complete(&remote_key.pk, dh_bytes, cipher, self.psk.as_ref());
+        if ok {
+            self.remember_key(remote_key);
+        }
+        ok
+    }
+
+    /// remember the handshaked peer's DH public key, for encrypt mails to it.
+    fn remember_key(&self, remote_key: &Keypair) {
+        if let Some(dh) = remote_key.dh_public() {
+            let peer_id = remote_key.peer_id(&self.peer_id_hash);
+            if let Ok(mut keys) = self.peer_keys.lock() {
+                keys.add(peer_id, dh);
+            }
+        }
+    }
+
+    /// change the keepalive & maintenance profile.
+    pub fn set_power(&self, profile: PowerProfile) {
+        let is_low = profile == PowerProfile::LowPower;
+        self.low_power.store(is_low, Ordering::Relaxed);
+    }
+
+    /// the maintenance intervals' scale, and whether batch the close jobs.
+    pub fn power_scale(&self) -> (u32, bool) {
+        if self.low_power.load(Ordering::Relaxed) {
+            (LOW_POWER_SCALE, true)
+        } else {
+            (1, false)
+        }
+    }
+
+    /// the next keepalive time of session. in low power profile, it is aligned to
+    /// the wall clock, so all sessions wake up together.
+    pub fn keepalive_at(&self) -> Instant {
+        if self.low_power.load(Ordering::Relaxed) {
+            let interval = LOW_POWER_KEEPALIVE_INTERVAL.as_millis() as u64;
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0);
+            Instant::now() + Duration::from_millis(interval - now % interval)
         } else {
-            None
+            Instant::now() + self.keepalive_interval
+        }
+    }
+
+    /// self's bridge capability flag, send to remote in handshake.
+    /// self's capability flags in the handshake version.
+    fn flags(&self) -> u8 {
+        let mut flags = Cipher::flags();
+        if self.bridge {
+            flags |= BRIDGE_FLAG;
+        }
+        if cfg!(feature = "hole-punching") {
+            flags |= PUNCH_FLAG;
+        }
+        flags
+    }
+
+    /// save the direct connected remote if it is a transport bridge.
+    pub async fn bridge_join(&self, peer_id: PeerId, remote_version: u8) {
+        if remote_version & BRIDGE_FLAG != 0 {
+            self.bridges.write().await.insert(peer_id);
+        }
+    }
+
+    /// a connected bridge's session to relay, when the peer's transport is not self's.
+    pub async fn bridge_relay(&self, to: &Peer) -> Option<Sender<SessionMessage>> {
+        if to.transport == self.peer.transport {
+            return None;
+        }
+        let bridges = self.bridges.read().await;
+        bridges
+            .iter()
+            .filter(|id| **id != to.id)
+            .find_map(|id| self.routes.get(id))
+    }
+
+    /// the best relay to the peer by the peer selector, None is use the closest.
+    pub async fn select_relay(&self, to: &Peer) -> Option<Sender<SessionMessage>> {
+        let selector = self.peer_selector.as_ref()?;
+        let peer_list = self.peer_list.read().await;
+        let candidates = peer_list
+            .all_peers()
+            .into_iter()
+            .filter(|(p, _)| p.id != to.id)
+            .collect();
+        selector
+            .select(SelectPurpose::Relay, Some(to), candidates, |(p, _)| p)
+            .first()
+            .map(|(_, sender)| (*sender).clone())
+    }
+
+    /// check remote's handshake payload, return the negotiated protocol version
+    /// if accepted.
+    pub fn check_handshake(&self, remote_id: &PeerId, version: u8, payload: &[u8]) -> Option<u8> {
+        match &self.handshake_check {
+            Some(check) if !(check.0)(remote_id, payload) => None,
+            _ => Some(negotiate_version(version)),
+        }
+    }
+
+    /// check the remote addr by the gater.
+    pub fn gate_addr(&self, addr: &SocketAddr) -> bool {
+        match &self.gater {
+            Some(gater) => gater.allow_addr(addr),
+            None => true,
+        }
+    }
+
+    /// check the handshaked remote peer by the gater.
+    pub fn gate_peer(&self, peer: &Peer) -> bool {
+        match &self.gater {
+            Some(gater) => gater.allow_peer(peer),
+            None => true,
+        }
+    }
+
+    /// update the peer's clock offset with new sample, smoothed like rtt (1/8 gain).
+    pub async fn clock_offset(&self, peer_id: PeerId, sample: i64) {
+        let mut offsets = self.clock_offsets.write().await;
+        let offset = offsets.entry(peer_id).or_insert(sample);
+        *offset += (sample - *offset) / 8;
+    }
+
+    /// persist the outbox's changes in batch, the file is written without the lock.
+    pub async fn outbox_flush(&self) {
+        let flushed = self.outbox.write().await.flush();
+        if let Some((path, bytes)) = flushed {
+            let _ = tokio::fs::write(path, bytes).await;
+        }
+    }
+
+    /// the median of connected peers' clock offsets (ms), 0 if no peers.
+    pub async fn clock_median(&self) -> i64 {
+        let mut offsets: Vec<i64> = self.clock_offsets.read().await.values().copied().collect();
+        if offsets.is_empty() {
+            return 0;
+        }
+        offsets.sort_unstable();
+        offsets[offsets.len() / 2]
+    }
+
+    /// a member left the encrypted group, send new sender key to the remaining
+    /// admitted members.
+    pub async fn group_rekey(&self, group: &str) {
+        let mut groups = self.groups.write().await;
+        let (epoch, key) = match groups.rotate(group) {
+            Some(v) => v,
+            None => return,
+        };
+        let members = groups.admitted(group);
+        drop(groups);
+        for peer_id in members {
+            if let Some(sender) = self.routes.get(&peer_id) {
+                let msg = SessionMessage::GroupKey(group.to_owned(), epoch, key);
+                let _ = sender.send(msg).await;
+            }
+        }
+    }
+
+    /// the gossip broadcast's peers (not the excepted), chosen by the peer selector.
+    pub async fn gossip_peers(&self, except: &[PeerId]) -> Vec<Sender<SessionMessage>> {
+        let peer_list = self.peer_list.read().await;
+        let mut peers = peer_list.all_peers();
+        peers.retain(|(p, _)| !except.contains(&p.id));
+        if let Some(selector) = &self.peer_selector {
+            peers = selector.select(SelectPurpose::Gossip, None, peers, |(p, _)| p);
+        }
+        peers.into_iter().map(|(_, s)| s.clone()).collect()
+    }
+
+    /// announce the swarm's manifest to gossip peers (not the excepted), if self is
+    /// the origin, give the full data to the peers not support swarm.
+    pub async fn swarm_announce(&self, sealed: Bytes, data: Option<Bytes>, except: &[PeerId]) {
+        for sender in self.gossip_peers(except).await {
+            let msg = SessionMessage::SwarmManifest(sealed.clone(), data.clone());
+            let _ = sender.send(msg).await;
+        }
+    }
+
+    /// request the swarm's missing chunks from the providers.
+    pub async fn swarm_fetch(&self, id: &SwarmId) {
+        let requests = self.swarms.write().await.requests(id);
+        for (provider, index) in requests {
+            // not wait, the provider maybe the caller session.
+            let is_sent = self
+                .routes
+                .get(&provider)
+                .map(|s| s.try_send(SessionMessage::SwarmWant(*id, index)).is_ok())
+                .unwrap_or(false);
+            if !is_sent {
+                self.swarms.write().await.miss(id, index, &provider);
+            }
+        }
+    }
+
+    /// publish (or unpublish) self's membership of the group to the closest peers
+    /// of its topic, they reply the other members when publish.
+    pub async fn topic_sync(&self, group: &str, is_join: bool) {
+        self.record_publish(topic_key(group), is_join).await;
+    }
+
+    /// publish (or unpublish) self's provider record of the service to the closest
+    /// peers of its key, they reply it when the other peers find the service.
+    pub async fn service_sync(&self, name: &str, is_register: bool) {
+        self.record_publish(service_key(name), is_register).await;
+    }
+
+    /// publish (or unpublish) self's record of the DHT key to its closest peers.
+    async fn record_publish(&self, key: PeerId, is_join: bool) {
+        if self.topic_replicas == 0 {
+            return;
+        }
+        let peers = self
+            .peer_list
+            .read()
+            .await
+            .closest(&key, self.topic_replicas);
+        for (_, sender) in peers {
+            let _ = sender.send(SessionMessage::TopicJoin(key, is_join)).await;
+        }
+    }
+
+    /// connect the found members of topic (and the closer peers of it).
+    pub async fn topic_connect(&self, peers: Vec<Peer>) {
+        let peer_list = self.peer_list.read().await;
+        let peers: Vec<Peer> = peers
+            .into_iter()
+            .filter(|p| &p.id != self.peer_id() && peer_list.get(&p.id).is_none())
+            .collect();
+        drop(peer_list);
+        for peer in peers {
+            debug!("Topic connect to {}.", peer.id.short_show());
+            self.dialer.dial(peer, DialKind::Connect, None);
+        }
+    }
+
+    /// the peer's data permissions.
+    pub async fn policy(&self, peer_id: &PeerId) -> PeerPolicy {
+        self.policies
+            .read()
+            .await
+            .get(peer_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// the peer or ip is temp-banned.
+    pub fn is_banned(&self, peer_id: Option<&PeerId>, addr: &SocketAddr) -> bool {
+        self.bans.is_banned_addr(&addr.ip())
+            || self.shared_bans.is_banned_addr(&addr.ip())
+            || peer_id
+                .map(|p| self.bans.is_banned_peer(p) || self.shared_bans.is_banned_peer(p))
+                .unwrap_or(false)
+    }
+
+    /// block the target by the shared entry, close its sessions, and forward the
+    /// entry to the connected trusted peers (not the issuer and sender).
+    pub async fn share_block(
+        &self,
+        sealed: Vec<u8>,
+        target: BlockTarget,
+        time: Duration,
+        except: &[PeerId],
+    ) {
+        let is_local = except.is_empty();
+        if !is_local && self.is_share_protected(&target).await {
+            debug!("Shared block entry targets self or allowlisted, ignore it.");
+            return;
+        }
+        if !self.shared_bans.add(target, time, is_local) {
+            return;
+        }
+        self.audit
+            .log(format!("shared_block {:?} {}s", target, time.as_secs()));
+
+        let (mut closes, mut forwards) = (vec![], vec![]);
+        for (peer, sender) in self.peer_list.read().await.all_peers() {
+            let is_target = match target {
+                BlockTarget::Peer(peer_id) => peer.id == peer_id,
+                BlockTarget::Ip(ip) => peer.socket.ip() == ip,
+            };
+            if is_target {
+                closes.push(sender.clone());
+            } else if self.allowlisted.contains(&peer.id) && !except.contains(&peer.id) {
+                forwards.push(sender.clone());
+            }
+        }
+        for sender in closes {
+            let _ = sender.send(SessionMessage::Close).await;
+        }
+        for sender in forwards {
+            let _ = sender
+                .send(SessionMessage::BlockShare(sealed.clone()))
+                .await;
+        }
+    }
+
+    /// the target is self or allowlisted peers (by PeerId, or ip of the connected
+    /// and configured allowlisted), only the local `ShareBlock` can block it.
+    async fn is_share_protected(&self, target: &BlockTarget) -> bool {
+        match target {
+            BlockTarget::Peer(peer_id) => {
+                peer_id == self.peer_id() || self.allowlisted.contains(peer_id)
+            }
+            BlockTarget::Ip(ip) => {
+                if ip == &self.peer.socket.ip() {
+                    return true;
+                }
+                let peer_list = self.peer_list.read().await;
+                peer_list.all_peers().iter().any(|(p, _)| {
+                    &p.socket.ip() == ip
+                        && (self.allowlisted.contains(&p.id) || peer_list.is_pinned(p))
+                }) || peer_list.pinned_ips().contains(ip)
+            }
+        }
+    }
+
+    /// score the peer's misbehavior, by PeerId if it is authenticated (handshaked),
+    /// or only by ip. return true if it is banned now, and tell outside.
+    pub async fn misbehave(&self, peer: &Peer, is_auth: bool, misbehavior: Misbehavior) -> bool {
+        // the allowlisted and seeds are never banned.
+        if (is_auth && self.allowlisted.contains(&peer.id))
+            || self.peer_list.read().await.is_pinned(peer)
+        {
+            return false;
+        }
+        let peer_id = if is_auth { Some(peer.id) } else { None };
+        if !self
+            .bans
+            .penalize(peer_id, Some(peer.socket.ip()), misbehavior)
+        {
+            return false;
+        }
+        warn!(
+            "CHAMOMILE: BAN MISBEHAVING PEER {} {}: {:?}.",
+            peer.id.short_show(),
+            peer.socket,
+            misbehavior
+        );
+        stats::counter(stats::METRIC_BANNED, 1);
+        if is_auth {
+            self.resumptions.write().await.remove(&peer.id);
+        }
+        self.audit.log(format!(
+            "banned {} {} {:?}",
+            peer.id.to_hex(),
+            peer.socket,
+            misbehavior
+        ));
+        let _ = self
+            .out_send(ReceiveMessage::PeerBanned(*peer, misbehavior))
+            .await;
+        true
+    }
+
+    /// the peer is the relay of self's relayed sessions, keep its session alive.
+    pub async fn is_circuit_relay(&self, peer_id: &PeerId) -> bool {
+        self.relay_paths.read().await.values().any(|r| r == peer_id)
+    }
+
+    /// check forward the relay data by the relay accounting.
+    #[cfg(feature = "relay")]
+    pub fn relay_allow_forward(&self, from: &PeerId, to: &PeerId, bytes: usize) -> bool {
+        match &self.relay_accounting {
+            Some(accounting) => accounting.allow_forward(from, to, bytes),
+            None => true,
+        }
+    }
+
+    /// check forward the relay connect by the relay accounting.
+    #[cfg(feature = "relay")]
+    pub fn relay_allow_circuit(&self, from: &PeerId, to: &PeerId) -> bool {
+        match &self.relay_accounting {
+            Some(accounting) => accounting.allow_circuit(from, to),
+            None => true,
         }
     }
 
@@ -74,6 +706,23 @@ impl Global {
         trans_type: &TransportType,
         msg: TransportSendMessage,
     ) -> Result<()> {
+        if let Some(gater) = &self.gater {
+            let addr = match &msg {
+                TransportSendMessage::Connect(addr, ..) => Some(addr),
+                TransportSendMessage::StableConnect(_, _, addr, _) => Some(addr),
+                TransportSendMessage::Rebind => None,
+            };
+            if let Some(addr) = addr.filter(|addr| !gater.allow_dial(trans_type, addr)) {
+                debug!("Dial to {} is not allowed by gater.", addr);
+                let mut peer = Peer::socket(*addr);
+                peer.transport = *trans_type;
+                self.dial_done(addr).await;
+                let _ = self
+                    .out_send(ReceiveMessage::DialFailure(peer, DialReason::Blocked))
+                    .await;
+                return Err(new_io_error("dial is not allowed"));
+            }
+        }
         let trans_lock = self.transports.read().await;
         if let Some(sender) = trans_lock.get(trans_type) {
             sender
@@ -85,11 +734,20 @@ impl Global {
             // start new transport to send it.
             // Only TCP & QUIC
             let main_send = self.trans.clone();
-            let mut new_peer = self.peer.clone();
+            let mut new_peer = self.peer;
             new_peer.transport = *trans_type;
             new_peer.zero_port();
 
-            let (_, trans_send, _, _) = start(&new_peer, Some(main_send)).await?;
+            let counter = self.transport_bandwidths[trans_type].clone();
+            let (_, trans_send, _, _) = start(
+                &new_peer,
+                Some(main_send),
+                self.max_message_size,
+                0,
+                vec![],
+                counter,
+            )
+            .await?;
             trans_send
                 .send(msg)
                 .await
@@ -102,20 +760,363 @@ impl Global {
         }
     }
 
+    /// start a dial to socket & peer, dedup the dials to the same one: if had a
+    /// in-flight one, the DHT connect reuses it, and the stable connect waits it
+    /// by `dial_wait` first, not dial again. the `notify` will send when the dial
+    /// finished.
+    pub async fn dial_start(
+        &self,
+        socket: SocketAddr,
+        peer_id: PeerId,
+        notify: Option<oneshot::Sender<()>>,
+    ) -> bool {
+        let mut dials = self.dials.write().await;
+        let dial = dials
+            .entry((socket, peer_id))
+            .or_insert_with(|| (Instant::now() - DIAL_TIMEOUT, vec![]));
+        let is_new = dial.0.elapsed() >= DIAL_TIMEOUT;
+        if is_new {
+            *dial = (Instant::now(), vec![]);
+        }
+        if let Some(notify) = notify {
+            dial.1.push(notify);
+        }
+        is_new
+    }
+
+    /// waiting the in-flight dial to socket & peer, None if not dialing.
+    pub async fn dial_wait(
+        &self,
+        socket: SocketAddr,
+        peer_id: PeerId,
+    ) -> Option<oneshot::Receiver<()>> {
+        let mut dials = self.dials.write().await;
+        match dials.get_mut(&(socket, peer_id)) {
+            Some((t, notifies)) if t.elapsed() < DIAL_TIMEOUT => {
+                let (tx, rx) = oneshot::channel();
+                notifies.push(tx);
+                Some(rx)
+            }
+            _ => None,
+        }
+    }
+
+    /// the dials to socket are finished (connected or failure).
+    pub async fn dial_done(&self, socket: &SocketAddr) {
+        self.dials.write().await.retain(|(s, _), (t, notifies)| {
+            if s == socket {
+                for notify in notifies.drain(..) {
+                    let _ = notify.send(());
+                }
+                false
+            } else {
+                t.elapsed() < DIAL_TIMEOUT
+            }
+        });
+    }
+
+    /// the data's deadline is expired and it is still waiting in the session,
+    /// remove it, the caller drops the data and sends the timeout failure.
+    pub async fn deadline_expired(&self, tid: u64) -> bool {
+        if tid == 0 || self.deadlines.read().await.is_empty() {
+            return false;
+        }
+        let mut deadlines = self.deadlines.write().await;
+        match deadlines.get(&tid) {
+            Some((.., deadline)) if *deadline <= Instant::now() => {
+                deadlines.remove(&tid);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// start a probe to the peer's socket with the dial's session key bytes,
+    /// false if it is probing.
+    pub async fn probe_start(&self, tid: u64, peer: Peer, dial: Vec<u8>) -> bool {
+        let mut probes = self.probes.write().await;
+        probes.retain(|_, (_, _, t, _)| t.elapsed() < DIAL_TIMEOUT);
+        if probes.contains_key(&peer.socket) {
+            return false;
+        }
+        probes.insert(peer.socket, (tid, peer, Instant::now(), dial));
+        true
+    }
+
+    /// the probe's dial is finished (handshaked or failure), matched by the dial's
+    /// session key, so other dials to the same socket not finish the probe.
+    pub async fn probe_done(&self, socket: &SocketAddr, dial: &[u8]) -> Option<Probe> {
+        let mut probes = self.probes.write().await;
+        match probes.get(socket) {
+            Some((.., key)) if key == dial => probes.remove(socket),
+            _ => None,
+        }
+    }
+
+    /// the probe of tid is finished without its dial (not dialed or timeout).
+    pub async fn probe_cancel(&self, socket: &SocketAddr, tid: u64) -> bool {
+        let mut probes = self.probes.write().await;
+        match probes.get(socket) {
+            Some((t, ..)) if *t == tid => probes.remove(socket).is_some(),
+            _ => false,
+        }
+    }
+
+    /// the known address of the not connected peer, to dial on demand. allowlist
+    /// peers are saved to outbox when it is enabled, not dial them.
+    pub async fn dial_target(&self, peer_id: &PeerId) -> Option<Peer> {
+        if !self.dial_on_demand {
+            return None;
+        }
+        let peer_list = self.peer_list.read().await;
+        if matches!(peer_list.get(peer_id), Some((_, _, true))) {
+            return None;
+        }
+        if self.outbox.read().await.is_enable() && peer_list.is_allow_peer(peer_id) {
+            return None;
+        }
+        peer_list.known_addr(peer_id)
+    }
+
+    /// queue the data to the peer, and dial it if not dialing. failure `Delivery`
+    /// when the queue is full or the dial refused, and when the dial timeout.
+    pub async fn dial_data(self: &Arc<Self>, peer: Peer, tid: u64, data: Vec<u8>) {
+        let mut datas = self.dial_datas.write().await;
+        if let Some((_, _, queue)) = datas.get_mut(&peer.id) {
+            if queue.len() < MAX_DIAL_DATA {
+                queue.push((tid, data));
+                return;
+            }
+            drop(datas);
+            warn!("CHAMOMILE: DIAL DATA QUEUE IS FULL.");
+            self.delivery_failures(
+                vec![(DeliveryType::Data, tid, data)],
+                DeliveryFailure::BufferFull,
+            )
+            .await;
+            return;
+        }
+
+        if !self.dialer.dial(peer, DialKind::Connect, None) {
+            drop(datas);
+            self.delivery_failures(
+                vec![(DeliveryType::Data, tid, data)],
+                DeliveryFailure::PeerOffline,
+            )
+            .await;
+            return;
+        }
+        debug!("Data to not connected peer, dial {}.", peer.socket);
+        datas.insert(peer.id, (peer.socket, Instant::now(), vec![(tid, data)]));
+        drop(datas);
+
+        let global = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(DIAL_TIMEOUT * 2).await;
+            let mut datas = global.dial_datas.write().await;
+            let expired = match datas.get(&peer.id) {
+                Some((_, t, _)) if t.elapsed() >= DIAL_TIMEOUT * 2 => datas.remove(&peer.id),
+                _ => None,
+            };
+            drop(datas);
+            if let Some((_, _, queue)) = expired {
+                warn!("CHAMOMILE: DIAL ON DEMAND TIMEOUT.");
+                global.dial_datas_failure(queue).await;
+            }
+        });
+    }
+
+    /// the peer connected, take the queued data to send.
+    pub async fn dial_datas_take(&self, peer_id: &PeerId) -> Vec<(u64, Vec<u8>)> {
+        self.dial_datas
+            .write()
+            .await
+            .remove(peer_id)
+            .map(|(_, _, queue)| queue)
+            .unwrap_or_default()
+    }
+
+    /// the dial to socket failure, failure the queued data to it.
+    pub async fn dial_datas_failed(&self, socket: &SocketAddr) {
+        let mut failures = vec![];
+        self.dial_datas.write().await.retain(|_, (s, _, queue)| {
+            if s == socket {
+                failures.append(queue);
+                false
+            } else {
+                true
+            }
+        });
+        self.dial_datas_failure(failures).await;
+    }
+
+    async fn dial_datas_failure(&self, queue: Vec<(u64, Vec<u8>)>) {
+        let failures = queue
+            .into_iter()
+            .map(|(tid, data)| (DeliveryType::Data, tid, data))
+            .collect();
+        self.delivery_failures(failures, DeliveryFailure::PeerOffline)
+            .await;
+    }
+
+    /// network has the first connected peer, tell outside once after lost.
+    pub async fn network_ready(&self) {
+        if !self.online.swap(true, Ordering::SeqCst) {
+            self.watchdog.reset();
+            let _ = self.out_send(ReceiveMessage::NetworkReady).await;
+        }
+    }
+
+    /// network lost all peers.
+    #[inline]
+    pub fn network_lost(&self) {
+        self.online.store(false, Ordering::SeqCst);
+    }
+
+    /// outside's receiver is full, the application is slow.
     #[inline]
-    pub async fn out_send(&self, msg: ReceiveMessage) -> Result<()> {
+    pub fn is_out_full(&self) -> bool {
+        self.outside && self.out_sender.capacity() == 0
+    }
+
+    #[inline]
+    pub async fn out_send(&self, mut msg: ReceiveMessage) -> Result<()> {
+        // multi-recipient data's delivery, aggregated when all finished.
+        if let ReceiveMessage::Delivery(DeliveryType::Data, tid, result, _) = &msg {
+            if !self.multis.read().await.is_empty() {
+                match self.multis.write().await.done(*tid, result.is_ok()) {
+                    Some(Some(multi)) => msg = multi,
+                    Some(None) => return Ok(()),
+                    None => {}
+                }
+            }
+        }
+
+        if let ReceiveMessage::Delivery(_, tid, _, data) = &mut msg {
+            if let Some(kept) = self.echoes.take(*tid) {
+                *data = kept;
+            }
+        }
+
+        if let ReceiveMessage::Delivery(t, tid, _, _) = &msg {
+            if !matches!(t, DeliveryType::Data) && self.journal.read().await.is_enable() {
+                let is_connect = matches!(t, DeliveryType::StableConnect);
+                self.journal.write().await.remove(is_connect, *tid).await;
+            }
+        }
+
+        if !self.outside {
+            return Ok(());
+        }
+
+        if let ReceiveMessage::Delivery(_, tid, _, _) = &msg {
+            if !self.deadlines.read().await.is_empty() {
+                self.deadlines.write().await.remove(tid);
+            }
+        }
+
+        let subscriber = self
+            .subscribers
+            .read()
+            .await
+            .iter()
+            .find(|(filter, sender)| !sender.is_closed() && filter.is_match(&msg))
+            .map(|(_, sender)| sender.clone());
+
+        if let Some(sender) = subscriber {
+            if let Err(e) = sender.send(msg).await {
+                // subscriber closed now, send to main receiver.
+                return self
+                    .out_sender
+                    .send(e.0)
+                    .await
+                    .map_err(|_e| new_io_error("Outside missing"));
+            }
+            return Ok(());
+        }
+
         self.out_sender
             .send(msg)
             .await
             .map_err(|_e| new_io_error("Outside missing"))
     }
 
+    /// received data message to outside, with the path if enabled.
+    #[inline]
+    pub fn data_message(&self, peer_id: PeerId, path: DataPath, data: Vec<u8>) -> ReceiveMessage {
+        if self.data_path {
+            ReceiveMessage::PathData(peer_id, path, data)
+        } else {
+            ReceiveMessage::Data(peer_id, data)
+        }
+    }
+
+    /// check the stable peers of the class are under the quota, and take a place
+    /// for the peer in the same lock, so the concurrent upgrades cannot exceed it.
+    /// the place is released when the stable session closed.
+    pub async fn stable_quota_take(&self, peer_id: PeerId, class: PeerClass) -> bool {
+        let limit = match class {
+            PeerClass::Allowlisted => self.stable_quotas.allowlisted,
+            PeerClass::Verified => self.stable_quotas.verified,
+            PeerClass::Anonymous => self.stable_quotas.anonymous,
+        };
+        let mut classes = self.stable_classes.write().await;
+        if limit != usize::MAX {
+            let used = classes
+                .iter()
+                .filter(|(p, c)| **c == class && **p != peer_id)
+                .count();
+            if used >= limit {
+                return false;
+            }
+        }
+        classes.insert(peer_id, class);
+        true
+    }
+
+    /// the delivery feedback bytes of data.
+    #[inline]
+    pub fn delivery(&self, data: &[u8]) -> Vec<u8> {
+        self.delivery.delivery(data)
+    }
+
+    /// send failure delivery of these messages to outside.
+    pub async fn delivery_failures(
+        &self,
+        failures: Vec<(DeliveryType, u64, Vec<u8>)>,
+        reason: DeliveryFailure,
+    ) {
+        for (t, tid, data) in failures {
+            if tid != 0 {
+                let _ = self
+                    .out_send(ReceiveMessage::Delivery(
+                        t,
+                        tid,
+                        Err(reason),
+                        self.delivery(&data),
+                    ))
+                    .await;
+            }
+        }
+    }
+
+    /// data is larger than `max_message_size`, not send it, delivery failure to outside.
+    pub async fn oversize(&self, t: DeliveryType, tid: u64, data: Vec<u8>) {
+        warn!("CHAMOMILE: DATA IS TOO LARGE: {} bytes.", data.len());
+        self.delivery_failures(vec![(t, tid, data)], DeliveryFailure::TooLarge)
+            .await;
+    }
+
+    /// send DHT peer's join/leave event to outside, if enabled.
+    #[inline]
+    pub async fn dht_event(&self, msg: ReceiveMessage) {
+        if self.dht_events {
+            let _ = self.out_send(msg).await;
+        }
+    }
+
     pub async fn add_tmp(&self, p: PeerId, k: KadValue, d: bool) -> Vec<(u64, Vec<u8>)> {
-        let mut buffer_lock = self.buffer.write().await;
-        let stables = buffer_lock.remove_connect(&p);
-        buffer_lock.add_tmp(p, k, d);
-        drop(buffer_lock);
-        stables
+        self.buffer.take_tmp(p, k, d, false).await.0
     }
 
     pub async fn add_all_tmp(
@@ -124,30 +1125,38 @@ impl Global {
         kv: KadValue,
         is_direct: bool,
     ) -> (Vec<(u64, Vec<u8>)>, Vec<(u64, Vec<u8>)>) {
-        let mut buffer_lock = self.buffer.write().await;
-        let connects = buffer_lock.remove_connect(&peer_id);
-        let results = buffer_lock.remove_result(&peer_id);
-        buffer_lock.add_tmp(peer_id, kv, is_direct);
-        drop(buffer_lock);
-
-        (connects, results)
+        self.buffer.take_tmp(peer_id, kv, is_direct, true).await
     }
 
     pub async fn upgrade(&self, peer_id: &PeerId) -> Result<()> {
-        let v_some = self.buffer.write().await.remove_tmp(peer_id);
+        let v_some = self.buffer.take_out_tmp(*peer_id).await;
         if let Some((v, is_d)) = v_some {
-            self.peer_list.write().await.add_stable(*peer_id, v, is_d);
+            self.peer_list
+                .write()
+                .await
+                .add_stable(*peer_id, v, is_d)
+                .await;
+            let way = if is_d { "direct" } else { "relay" };
+            self.audit
+                .log(format!("stable_established {} {}", peer_id.to_hex(), way));
+            self.network_ready().await;
             Ok(())
         } else {
-            self.peer_list.write().await.dht_to_stable(peer_id)
+            self.peer_list.write().await.dht_to_stable(peer_id).await?;
+            self.audit
+                .log(format!("stable_established {} direct", peer_id.to_hex()));
+            self.dht_event(ReceiveMessage::PeerLeave(*peer_id)).await;
+            Ok(())
         }
     }
 
     pub async fn tmp_to_dht(&self, peer_id: &PeerId) -> Result<()> {
-        let v_some = self.buffer.write().await.remove_tmp(peer_id);
+        let v_some = self.buffer.take_out_tmp(*peer_id).await;
         if let Some((v, is_d)) = v_some {
             if is_d {
+                let peer = v.2;
                 if self.peer_list.write().await.add_dht(v).await {
+                    self.dht_event(ReceiveMessage::PeerJoin(peer)).await;
                     return Ok(());
                 }
             }
@@ -157,11 +1166,8 @@ impl Global {
 
     #[inline]
     pub async fn stable_to_dht(&self, peer_id: &PeerId) -> Result<()> {
-        let mut buffer_lock = self.buffer.write().await;
-        buffer_lock.remove_tmp(peer_id);
-        buffer_lock.remove_stable(peer_id);
-        drop(buffer_lock);
+        self.buffer.remove_stable(*peer_id).await;
 
-        self.peer_list.write().await.stable_to_dht(peer_id)
+        self.peer_list.write().await.stable_to_dht(peer_id).await
     }
 }
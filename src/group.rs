@@ -0,0 +1,331 @@
+use chacha20poly1305::{
+    aead::{Aead, NewAead, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+
+use chamomile_types::PeerId;
+
+/// max bytes of group's name.
+pub(crate) const GROUP_NAME_MAX: usize = 255;
+
+/// max groups of a peer recorded in the groups self not joined, the joined
+/// groups' members are always recorded.
+const GROUP_PEER_MAX: usize = 64;
+
+/// keep the sender's previous keys, the in-flight data of old epoch can be opened.
+const SENDER_KEY_EPOCHS: usize = 2;
+
+/// sealed group data's header: epoch(4) + counter(8).
+const SEALED_HEAD: usize = 12;
+
+/// (group, sender) => the sender's recent (epoch, key).
+type SenderKeys = HashMap<(String, PeerId), Vec<(u32, [u8; 32])>>;
+
+/// self's sender key of an encrypted group.
+struct SenderKey {
+    epoch: u32,
+    key: [u8; 32],
+    /// nonce counter in this epoch.
+    counter: u64,
+}
+
+impl SenderKey {
+    fn new(epoch: u32) -> Self {
+        SenderKey {
+            epoch,
+            key: rand::thread_rng().gen(),
+            counter: 0,
+        }
+    }
+}
+
+fn cipher(key: &[u8; 32]) -> ChaCha20Poly1305 {
+    ChaCha20Poly1305::new(&Key::from(*key))
+}
+
+/// Named groups membership.
+/// self joined groups, and the connected peers joined in every group,
+/// (include the groups self not joined, so when join, members are known,
+/// at most `GROUP_PEER_MAX` of them for a peer).
+///
+/// Encrypted groups' data is sealed by the sender's key, which is only sent to the
+/// members admitted by the application (`SendMessage::GroupAdmit`) or allowlisted.
+/// it protects the data from the peers which only joined the group's name, and from
+/// the relays and gossip peers forwarding it. the left member cannot open the data
+/// after the rekey, but the admitted members can read and forward all data of the
+/// current epoch, and the group's name and members are not hidden.
+#[derive(Default)]
+pub(crate) struct Groups {
+    joined: HashSet<String>,
+    /// group => members.
+    members: HashMap<String, HashSet<PeerId>>,
+    /// encrypted groups (sender keys), group => self's sender key.
+    own_keys: HashMap<String, SenderKey>,
+    /// the members admitted to receive self's sender key, (group, member).
+    admitted: HashSet<(String, PeerId)>,
+    /// members' sender keys.
+    sender_keys: SenderKeys,
+}
+
+impl Groups {
+    /// join a group, return false if had joined.
+    pub fn join(&mut self, group: &str) -> bool {
+        self.joined.insert(group.to_owned())
+    }
+
+    /// leave a group, return false if not joined.
+    pub fn leave(&mut self, group: &str) -> bool {
+        self.own_keys.remove(group);
+        self.sender_keys.retain(|(g, _), _| g != group);
+        self.admitted.retain(|(g, _)| g != group);
+        self.joined.remove(group)
+    }
+
+    /// self's sender key of the group, init it if not had, the group's data is
+    /// encrypted by it. return (epoch, key), it need send to the admitted members.
+    pub fn own_key_or_init(&mut self, group: &str) -> (u32, [u8; 32]) {
+        let own = self
+            .own_keys
+            .entry(group.to_owned())
+            .or_insert_with(|| SenderKey::new(0));
+        (own.epoch, own.key)
+    }
+
+    /// self's sender key of the group, None if not encrypted group.
+    pub fn own_key(&self, group: &str) -> Option<(u32, [u8; 32])> {
+        self.own_keys.get(group).map(|own| (own.epoch, own.key))
+    }
+
+    /// a member left, new sender key (next epoch), so it cannot open the next data.
+    pub fn rotate(&mut self, group: &str) -> Option<(u32, [u8; 32])> {
+        let own = self.own_keys.get_mut(group)?;
+        *own = SenderKey::new(own.epoch.wrapping_add(1));
+        Some((own.epoch, own.key))
+    }
+
+    /// admit the member to receive self's sender key, return false if had admitted.
+    pub fn admit(&mut self, group: &str, peer_id: PeerId) -> bool {
+        self.admitted.insert((group.to_owned(), peer_id))
+    }
+
+    pub fn is_admitted(&self, group: &str, peer_id: &PeerId) -> bool {
+        self.admitted.contains(&(group.to_owned(), *peer_id))
+    }
+
+    /// the admitted members of the group.
+    pub fn admitted(&self, group: &str) -> Vec<PeerId> {
+        self.members(group)
+            .into_iter()
+            .filter(|p| self.is_admitted(group, p))
+            .collect()
+    }
+
+    /// save the member's sender key.
+    pub fn add_sender_key(&mut self, group: &str, peer_id: PeerId, epoch: u32, key: [u8; 32]) {
+        if !self.joined.contains(group) || !self.is_member(group, &peer_id) {
+            return;
+        }
+        let keys = self
+            .sender_keys
+            .entry((group.to_owned(), peer_id))
+            .or_default();
+        keys.retain(|(e, _)| *e != epoch);
+        keys.push((epoch, key));
+        if keys.len() > SENDER_KEY_EPOCHS {
+            keys.remove(0);
+        }
+    }
+
+    /// seal the data by self's sender key: epoch(4) + counter(8) + ciphertext.
+    pub fn seal(&mut self, group: &str, data: &[u8]) -> Option<Vec<u8>> {
+        let own = self.own_keys.get_mut(group)?;
+        own.counter += 1;
+        let mut nonce = [0u8; SEALED_HEAD];
+        nonce[..4].copy_from_slice(&own.epoch.to_le_bytes());
+        nonce[4..].copy_from_slice(&own.counter.to_le_bytes());
+        let payload = Payload {
+            msg: data,
+            aad: group.as_bytes(),
+        };
+        let ciphertext = cipher(&own.key)
+            .encrypt(&Nonce::from(nonce), payload)
+            .ok()?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Some(sealed)
+    }
+
+    /// open the member's sealed data by its sender key.
+    pub fn open(&self, group: &str, peer_id: PeerId, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < SEALED_HEAD {
+            return None;
+        }
+        let mut nonce = [0u8; SEALED_HEAD];
+        nonce.copy_from_slice(&sealed[..SEALED_HEAD]);
+        let mut epoch_bytes = [0u8; 4];
+        epoch_bytes.copy_from_slice(&sealed[..4]);
+        let epoch = u32::from_le_bytes(epoch_bytes);
+        let keys = self.sender_keys.get(&(group.to_owned(), peer_id))?;
+        let (_, key) = keys.iter().find(|(e, _)| *e == epoch)?;
+        let payload = Payload {
+            msg: &sealed[SEALED_HEAD..],
+            aad: group.as_bytes(),
+        };
+        cipher(key).decrypt(&Nonce::from(nonce), payload).ok()
+    }
+
+    pub fn is_joined(&self, group: &str) -> bool {
+        self.joined.contains(group)
+    }
+
+    pub fn joined(&self) -> Vec<String> {
+        self.joined.iter().cloned().collect()
+    }
+
+    /// add a member to group, return false if had it, or the peer is in too many
+    /// groups self not joined.
+    pub fn add_member(&mut self, group: &str, peer_id: PeerId) -> bool {
+        if !self.joined.contains(group) {
+            let others = self
+                .members
+                .iter()
+                .filter(|(g, m)| !self.joined.contains(*g) && m.contains(&peer_id))
+                .count();
+            if others >= GROUP_PEER_MAX {
+                return false;
+            }
+        }
+        self.members
+            .entry(group.to_owned())
+            .or_default()
+            .insert(peer_id)
+    }
+
+    /// remove a member from group, return false if not had it.
+    pub fn remove_member(&mut self, group: &str, peer_id: &PeerId) -> bool {
+        self.sender_keys.remove(&(group.to_owned(), *peer_id));
+        self.admitted.remove(&(group.to_owned(), *peer_id));
+        let removed = match self.members.get_mut(group) {
+            Some(members) => members.remove(peer_id),
+            None => false,
+        };
+        self.members.retain(|_, members| !members.is_empty());
+        removed
+    }
+
+    /// remove the peer from all groups, return the groups it left.
+    pub fn remove_peer(&mut self, peer_id: &PeerId) -> Vec<String> {
+        self.sender_keys.retain(|(_, p), _| p != peer_id);
+        self.admitted.retain(|(_, p)| p != peer_id);
+        let mut groups = vec![];
+        for (group, members) in self.members.iter_mut() {
+            if members.remove(peer_id) {
+                groups.push(group.clone());
+            }
+        }
+        self.members.retain(|_, members| !members.is_empty());
+        groups
+    }
+
+    pub fn is_member(&self, group: &str, peer_id: &PeerId) -> bool {
+        self.members
+            .get(group)
+            .map(|members| members.contains(peer_id))
+            .unwrap_or(false)
+    }
+
+    pub fn members(&self, group: &str) -> Vec<PeerId> {
+        self.members
+            .get(group)
+            .map(|members| members.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// self joined groups and the members.
+    pub fn all(&self) -> Vec<(String, Vec<PeerId>)> {
+        self.joined
+            .iter()
+            .map(|group| (group.clone(), self.members(group)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chamomile_types::types::PEER_ID_LENGTH;
+
+    fn peer_id(b: u8) -> PeerId {
+        PeerId([b; PEER_ID_LENGTH])
+    }
+
+    /// self (sender) and a member joined the group, the member has self's key.
+    fn pair(group: &str) -> (Groups, Groups) {
+        let mut sender = Groups::default();
+        sender.join(group);
+        let (epoch, key) = sender.own_key_or_init(group);
+        let mut member = Groups::default();
+        member.join(group);
+        member.add_member(group, peer_id(1));
+        member.add_sender_key(group, peer_id(1), epoch, key);
+        (sender, member)
+    }
+
+    #[test]
+    fn seal_open() {
+        let (mut sender, member) = pair("g");
+        let sealed = sender.seal("g", b"hello").unwrap();
+        assert_eq!(
+            member.open("g", peer_id(1), &sealed),
+            Some(b"hello".to_vec())
+        );
+        // every data has a new nonce.
+        assert_ne!(sender.seal("g", b"hello").unwrap(), sealed);
+
+        // other sender, truncated or tampered.
+        assert!(member.open("g", peer_id(2), &sealed).is_none());
+        assert!(member
+            .open("g", peer_id(1), &sealed[..SEALED_HEAD])
+            .is_none());
+        let mut tampered = sealed.clone();
+        tampered[SEALED_HEAD] ^= 1;
+        assert!(member.open("g", peer_id(1), &tampered).is_none());
+    }
+
+    #[test]
+    fn rotate_and_non_member() {
+        let (mut sender, mut member) = pair("g");
+        let old = sender.seal("g", b"old").unwrap();
+        let (epoch, key) = sender.rotate("g").unwrap();
+        assert_eq!(epoch, 1);
+        let new = sender.seal("g", b"new").unwrap();
+        // the left member (not had the new key) cannot open the new data.
+        assert!(member.open("g", peer_id(1), &new).is_none());
+        member.add_sender_key("g", peer_id(1), epoch, key);
+        assert_eq!(member.open("g", peer_id(1), &new), Some(b"new".to_vec()));
+        // the previous epoch is kept for the in-flight data.
+        assert_eq!(member.open("g", peer_id(1), &old), Some(b"old".to_vec()));
+
+        // the sender key of the non-member is ignored.
+        let sealed = sender.seal("g", b"hello").unwrap();
+        let mut other = Groups::default();
+        other.join("g");
+        other.add_sender_key("g", peer_id(1), epoch, key);
+        assert!(other.open("g", peer_id(1), &sealed).is_none());
+    }
+
+    #[test]
+    fn peer_groups_capped() {
+        let mut groups = Groups::default();
+        groups.join("joined");
+        for i in 0..GROUP_PEER_MAX {
+            assert!(groups.add_member(&format!("g{}", i), peer_id(1)));
+        }
+        assert!(!groups.add_member("more", peer_id(1)));
+        // the joined groups' members are always recorded.
+        assert!(groups.add_member("joined", peer_id(1)));
+        assert_eq!(groups.remove_peer(&peer_id(1)).len(), GROUP_PEER_MAX + 1);
+    }
+}
@@ -3,9 +3,24 @@ use std::net::SocketAddr;
 
 use chamomile_types::{
     peer::{Peer, PEER_LENGTH},
-    types::{new_io_error, PeerId, TransportType},
+    types::{new_io_error, TransportType},
 };
 
+#[cfg(feature = "hole-punching")]
+use chamomile_types::types::PeerId;
+#[cfg(feature = "hole-punching")]
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::Instant,
+};
+
+#[cfg(feature = "hole-punching")]
+use crate::primitives::DIAL_TIMEOUT;
+#[cfg(feature = "hole-punching")]
+use crate::transports::PUNCH_FLAG;
+
+#[cfg(feature = "hole-punching")]
 use super::peer_list::PeerList;
 
 pub enum Hole {
@@ -14,6 +29,7 @@ pub enum Hole {
     Help,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct DHT(pub Vec<Peer>);
 
 impl Hole {
@@ -44,7 +60,7 @@ impl DHT {
         len_bytes.copy_from_slice(&bytes[0..4]);
         let len = u32::from_le_bytes(len_bytes) as usize;
         let raw_bytes = &bytes[4..];
-        if raw_bytes.len() < len * PEER_LENGTH {
+        if raw_bytes.len() / PEER_LENGTH < len {
             return Err(new_io_error("DHT bytes failure."));
         }
         let mut peers = vec![];
@@ -68,18 +84,16 @@ impl DHT {
 
 pub fn nat(mut remote_addr: SocketAddr, mut local: Peer) -> Peer {
     local.is_pub = remote_addr.port() == local.socket.port();
-    match local.transport {
-        TransportType::TCP => {
-            remote_addr.set_port(local.socket.port()); // TODO TCP hole punching
-        }
-        _ => {}
+    if local.transport == TransportType::TCP {
+        remote_addr.set_port(local.socket.port()); // TODO TCP hole punching
     }
 
     local.socket = remote_addr;
     local
 }
 
-pub(crate) async fn _handle(_remote_peer: &PeerId, hole: Hole, _peers: &PeerList) -> Result<()> {
+#[cfg(feature = "hole-punching")]
+pub(crate) async fn handle(_remote_peer: &PeerId, hole: Hole, _peers: &PeerList) -> Result<()> {
     match hole {
         Hole::StunOne => {
             // first test
@@ -94,3 +108,87 @@ pub(crate) async fn _handle(_remote_peer: &PeerId, hole: Hole, _peers: &PeerList
 
     Ok(())
 }
+
+/// the punch is finished in it, the later punched connections are not upgraded.
+#[cfg(feature = "hole-punching")]
+const PUNCH_TIMEOUT: std::time::Duration = DIAL_TIMEOUT;
+
+/// Parallel hole punching. the relayed stable peers ask several helpers (the
+/// relay & the closest peers of remote) at same time, every helper connected
+/// with both tells them the other's observed address (`HoleConnect`), and both
+/// dial. only the connections dialed by the smaller PeerId are the punched
+/// path (both sides agree), the first one upgrades the relayed session.
+#[cfg(feature = "hole-punching")]
+pub(crate) struct Punches {
+    /// max helpers of a punch, 0 is not start punch (still dial when helped).
+    helpers: usize,
+    /// punching PeerId => (start time, is upgraded).
+    punches: Mutex<HashMap<PeerId, (Instant, bool)>>,
+    /// the direct connected peers advertised `PUNCH_FLAG`, they can help punch
+    /// and know the `HoleConnect` with address.
+    capables: Mutex<HashSet<PeerId>>,
+}
+
+#[cfg(feature = "hole-punching")]
+impl Punches {
+    pub fn new(helpers: usize) -> Self {
+        Punches {
+            helpers,
+            punches: Mutex::new(HashMap::new()),
+            capables: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// the direct connected peer's handshake version (with capability flags).
+    pub fn join(&self, peer_id: PeerId, remote_version: u8) {
+        if remote_version & PUNCH_FLAG != 0 {
+            self.capables.lock().unwrap().insert(peer_id);
+        }
+    }
+
+    pub fn leave(&self, peer_id: &PeerId) {
+        self.capables.lock().unwrap().remove(peer_id);
+    }
+
+    /// the peer advertised `PUNCH_FLAG`.
+    pub fn is_capable(&self, peer_id: &PeerId) -> bool {
+        self.capables.lock().unwrap().contains(peer_id)
+    }
+
+    pub fn helpers(&self) -> usize {
+        self.helpers
+    }
+
+    /// start the punch to the peer, false if it is punching.
+    pub fn start(&self, peer_id: PeerId) -> bool {
+        let mut punches = self.punches.lock().unwrap();
+        punches.retain(|_, (t, _)| t.elapsed() < PUNCH_TIMEOUT);
+        if punches.contains_key(&peer_id) {
+            return false;
+        }
+        punches.insert(peer_id, (Instant::now(), false));
+        true
+    }
+
+    /// check the direct connection with the punching peer. None is not punching,
+    /// true is the first punched path, upgrade by it. false is not the punched
+    /// path or had upgraded, close it.
+    pub fn accept(&self, my_id: &PeerId, remote_id: &PeerId, is_dial: bool) -> Option<bool> {
+        let mut punches = self.punches.lock().unwrap();
+        let (t, is_upgraded) = punches.get_mut(remote_id)?;
+        if t.elapsed() >= PUNCH_TIMEOUT {
+            punches.remove(remote_id);
+            return None;
+        }
+        let is_path = if is_dial {
+            my_id < remote_id
+        } else {
+            remote_id < my_id
+        };
+        if !is_path || *is_upgraded {
+            return Some(false);
+        }
+        *is_upgraded = true;
+        Some(true)
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::fs;
+
+use chamomile_types::{peer::PEER_LENGTH, Peer};
+
+/// journal entry, (is_connect, delivery_id) => (peer, data).
+type Entries = HashMap<(bool, u64), (Peer, Vec<u8>)>;
+
+/// Persisted buffered stable connects & results, so the outside's intents are not
+/// lost when crash or restart, they will be sent again when start.
+/// only the messages with delivery_id are saved, and removed when their delivery
+/// (success or failure) is sent to outside.
+pub(crate) struct Journal {
+    save_path: PathBuf,
+    enable: bool,
+    entries: Entries,
+}
+
+impl Journal {
+    pub fn load(save_path: PathBuf, enable: bool) -> Self {
+        let mut entries = HashMap::new();
+        if enable {
+            if let Ok(bytes) = std::fs::read(&save_path) {
+                Self::from_bytes(&bytes, &mut entries);
+            }
+        }
+
+        Journal {
+            save_path,
+            enable,
+            entries,
+        }
+    }
+
+    pub fn is_enable(&self) -> bool {
+        self.enable
+    }
+
+    /// every entry is: is_connect(1) + delivery_id(8) + peer(PEER_LENGTH) + data_len(4) + data.
+    fn from_bytes(mut bytes: &[u8], entries: &mut Entries) {
+        let head = PEER_LENGTH + 13;
+        while bytes.len() >= head {
+            let is_connect = bytes[0] == 1;
+            let mut tid_bytes = [0u8; 8];
+            tid_bytes.copy_from_slice(&bytes[1..9]);
+            let peer = match Peer::from_bytes(&bytes[9..9 + PEER_LENGTH]) {
+                Ok(peer) => peer,
+                Err(_) => break,
+            };
+            let mut len_bytes = [0u8; 4];
+            len_bytes.copy_from_slice(&bytes[9 + PEER_LENGTH..head]);
+            let len = u32::from_be_bytes(len_bytes) as usize;
+            if bytes.len() < head + len {
+                warn!("CHAMOMILE: BUFFER JOURNAL FILE IS BROKEN.");
+                break;
+            }
+            entries.insert(
+                (is_connect, u64::from_be_bytes(tid_bytes)),
+                (peer, bytes[head..head + len].to_vec()),
+            );
+            bytes = &bytes[head + len..];
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        for ((is_connect, tid), (peer, data)) in &self.entries {
+            bytes.push(if *is_connect { 1u8 } else { 0u8 });
+            bytes.extend(&tid.to_be_bytes()[..]);
+            bytes.extend(peer.to_bytes());
+            bytes.extend(&(data.len() as u32).to_be_bytes()[..]);
+            bytes.extend(data);
+        }
+        bytes
+    }
+
+    async fn save(&self) {
+        let _ = fs::write(&self.save_path, self.to_bytes()).await;
+    }
+
+    /// the saved entries when start, (is_connect, delivery_id, peer, data).
+    pub fn replays(&self) -> Vec<(bool, u64, Peer, Vec<u8>)> {
+        self.entries
+            .iter()
+            .map(|((is_connect, tid), (peer, data))| (*is_connect, *tid, *peer, data.clone()))
+            .collect()
+    }
+
+    /// save the buffered stable connect or result.
+    pub async fn add(&mut self, is_connect: bool, tid: u64, peer: Peer, data: Vec<u8>) {
+        if !self.enable || tid == 0 {
+            return;
+        }
+        self.entries.insert((is_connect, tid), (peer, data));
+        self.save().await;
+    }
+
+    /// the delivery is sent to outside, remove it.
+    pub async fn remove(&mut self, is_connect: bool, tid: u64) {
+        if self.entries.remove(&(is_connect, tid)).is_some() {
+            self.save().await;
+        }
+    }
+}
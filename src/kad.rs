@@ -1,7 +1,7 @@
 use bit_vec::BitVec;
 use core::cmp::Ordering;
 use rand::Rng;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use tokio::sync::mpsc::Sender;
 
@@ -86,17 +86,18 @@ impl DoubleKadTree {
         }
     }
 
-    pub fn add(&mut self, value: KadValue) -> bool {
+    /// add the value, the pinned peers are never evicted by the new peers.
+    pub fn add(&mut self, value: KadValue, pinned: &HashSet<PeerId>) -> bool {
         let mut rng = rand::thread_rng();
         let value_key = rng.gen::<u32>();
         let peer_id = value.2.id;
         let ip_addr = value.2.socket;
-        let (is_ok, removed) = self.peers.add(peer_id, value_key);
+        let (is_ok, removed) = self.peers.add(peer_id, value_key, &|k| pinned.contains(k));
         for i in removed {
             self.values.remove(&i);
         }
         if is_ok {
-            self.ips.add(ip_addr, value_key);
+            self.ips.add(ip_addr, value_key, &|_| false);
             self.values.insert(value_key, value);
             true
         } else {
@@ -104,25 +105,35 @@ impl DoubleKadTree {
         }
     }
 
+    #[cfg(feature = "relay")]
     pub fn id_next_closest(&self, key: &PeerId, prev: &PeerId) -> Option<&KadValue> {
         self.peers
             .next_closest(key, prev)
-            .map(|k| self.values.get(k))
-            .flatten()
+            .and_then(|k| self.values.get(k))
     }
 
+    #[cfg(feature = "relay")]
     pub fn _ip_next_closest(&self, key: &SocketAddr, prev: &SocketAddr) -> Option<&KadValue> {
         self.ips
             .next_closest(key, prev)
-            .map(|k| self.values.get(k))
-            .flatten()
+            .and_then(|k| self.values.get(k))
+    }
+
+    /// the n closest peers of the key (not include it).
+    pub fn id_closest(&self, key: &PeerId, n: usize) -> Vec<&KadValue> {
+        let mut keys = self.keys();
+        keys.retain(|k| k != key);
+        keys.sort_by_cached_key(|k| PeerId::calc_distance(k, key));
+        keys.iter()
+            .filter_map(|k| self.search(k).map(|(v, _)| v))
+            .take(n)
+            .collect()
     }
 
     pub fn search(&self, key: &PeerId) -> Option<(&KadValue, bool)> {
         self.peers
             .search(key)
-            .map(|(_, k, is_it)| self.values.get(k).map(|v| (v, is_it)))
-            .flatten()
+            .and_then(|(_, k, is_it)| self.values.get(k).map(|v| (v, is_it)))
     }
 
     pub fn remove(&mut self, key: &PeerId) -> Option<KadValue> {
@@ -146,6 +157,10 @@ impl DoubleKadTree {
     pub fn is_empty(&self) -> bool {
         self.peers.is_empty()
     }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
 }
 
 impl<K: Key> KadTree<K> {
@@ -157,7 +172,7 @@ impl<K: Key> KadTree<K> {
         }
     }
 
-    fn add(&mut self, key: K, value: u32) -> (bool, Vec<u32>) {
+    fn add(&mut self, key: K, value: u32, is_pinned: &dyn Fn(&K) -> bool) -> (bool, Vec<u32>) {
         let distance = K::calc_distance(&self.root_key, &key);
 
         if distance.get(0) {
@@ -166,7 +181,7 @@ impl<K: Key> KadTree<K> {
             }
             self.right
                 .as_mut()
-                .and_then(|v| Some(v.insert(Cell(key, value, distance), 1, K_BUCKET)))
+                .map(|v| v.insert(Cell(key, value, distance), 1, K_BUCKET, is_pinned))
                 .unwrap() // safe checked.
         } else {
             if self.left.is_none() {
@@ -174,19 +189,19 @@ impl<K: Key> KadTree<K> {
             }
             self.left
                 .as_mut()
-                .and_then(|v| Some(v.insert(Cell(key, value, distance), 1, K_BUCKET)))
+                .map(|v| v.insert(Cell(key, value, distance), 1, K_BUCKET, is_pinned))
                 .unwrap() // safe checked.
         }
     }
 
+    #[cfg(feature = "relay")]
     fn next_closest(&self, key: &K, prev: &K) -> Option<&u32> {
         self.search(key)
-            .map(|v| if v.0 == prev { None } else { Some(v.1) })
-            .flatten()
+            .and_then(|v| if v.0 == prev { None } else { Some(v.1) })
     }
 
     fn search(&self, key: &K) -> Option<(&K, &u32, bool)> {
-        let distance = K::calc_distance(&self.root_key, &key);
+        let distance = K::calc_distance(&self.root_key, key);
 
         if distance.get(0) {
             if self.right.is_none() {
@@ -195,13 +210,13 @@ impl<K: Key> KadTree<K> {
                 } else {
                     self.left
                         .as_ref()
-                        .and_then(|v| Some(v.search(key, &distance, 1)))
+                        .map(|v| v.search(key, &distance, 1))
                         .unwrap() // safe checked.
                 }
             } else {
                 self.right
                     .as_ref()
-                    .and_then(|v| Some(v.search(key, &distance, 1)))
+                    .map(|v| v.search(key, &distance, 1))
                     .unwrap() // safe chekced.
             }
         } else {
@@ -211,20 +226,20 @@ impl<K: Key> KadTree<K> {
                 } else {
                     self.right
                         .as_ref()
-                        .and_then(|v| Some(v.search(key, &distance, 1)))
+                        .map(|v| v.search(key, &distance, 1))
                         .unwrap() // safe checked.
                 }
             } else {
                 self.left
                     .as_ref()
-                    .and_then(|v| Some(v.search(key, &distance, 1)))
+                    .map(|v| v.search(key, &distance, 1))
                     .unwrap() // safe checked.
             }
         }
     }
 
     fn remove(&mut self, key: &K) -> Option<u32> {
-        let distance = K::calc_distance(&self.root_key, &key);
+        let distance = K::calc_distance(&self.root_key, key);
         if distance.get(0) {
             self.right
                 .as_mut()
@@ -235,34 +250,26 @@ impl<K: Key> KadTree<K> {
     }
 
     fn contains(&self, key: &K) -> bool {
-        if let Some((_, _, true)) = self.search(key) {
-            true
-        } else {
-            false
-        }
+        matches!(self.search(key), Some((_, _, true)))
     }
 
     fn keys(&self) -> Vec<K> {
         let mut vec = Vec::new();
-        if self.left.is_some() {
-            self.left.as_ref().unwrap().keys(&mut vec); // safe checked.
+        if let Some(left) = &self.left {
+            left.keys(&mut vec);
         }
-        if self.right.is_some() {
-            self.right.as_ref().unwrap().keys(&mut vec); // safe checked.
+        if let Some(right) = &self.right {
+            right.keys(&mut vec);
         }
         vec
     }
 
     fn is_empty(&self) -> bool {
-        if self.left.is_some() {
-            if !self.left.as_ref().unwrap().is_empty() {
-                return false;
-            }
+        if self.left.is_some() && !self.left.as_ref().unwrap().is_empty() {
+            return false;
         }
-        if self.right.is_some() {
-            if !self.right.as_ref().unwrap().is_empty() {
-                return false;
-            }
+        if self.right.is_some() && !self.right.as_ref().unwrap().is_empty() {
+            return false;
         }
 
         true
@@ -278,7 +285,13 @@ impl<K: Key> Node<K> {
         }
     }
 
-    fn insert(&mut self, mut cell: Cell<K>, index: usize, k_bucket: usize) -> (bool, Vec<u32>) {
+    fn insert(
+        &mut self,
+        mut cell: Cell<K>,
+        index: usize,
+        k_bucket: usize,
+        is_pinned: &dyn Fn(&K) -> bool,
+    ) -> (bool, Vec<u32>) {
         if self.right.is_some() || self.left.is_some() {
             if cell.2.get(index) {
                 if self.right.is_none() {
@@ -286,7 +299,7 @@ impl<K: Key> Node<K> {
                 }
                 self.right
                     .as_mut()
-                    .and_then(|v| Some(v.insert(cell, index + 1, k_bucket)))
+                    .map(|v| v.insert(cell, index + 1, k_bucket, is_pinned))
                     .unwrap() // safe checked.
             } else {
                 if self.left.is_none() {
@@ -294,7 +307,7 @@ impl<K: Key> Node<K> {
                 }
                 self.left
                     .as_mut()
-                    .and_then(|v| Some(v.insert(cell, index + 1, k_bucket)))
+                    .map(|v| v.insert(cell, index + 1, k_bucket, is_pinned))
                     .unwrap() // safe checked.
             }
         } else {
@@ -316,23 +329,31 @@ impl<K: Key> Node<K> {
             } else {
                 if index >= MAX_LEVEL {
                     for v in self.list.iter_mut() {
-                        if v > &mut cell {
+                        if v > &mut cell && !is_pinned(&v.0) {
+                            removed.push(v.1);
+                            *v = cell;
+                            return (true, removed);
+                        }
+                    }
+                    // the pinned peer always replace a not pinned one.
+                    if is_pinned(&cell.0) {
+                        if let Some(v) = self.list.iter_mut().find(|v| !is_pinned(&v.0)) {
                             removed.push(v.1);
                             *v = cell;
                             return (true, removed);
                         }
                     }
-                    return (false, removed);
+                    (false, removed)
                 } else {
                     self.right = Some(Box::new(Node::default()));
                     self.left = Some(Box::new(Node::default()));
 
                     while !self.list.is_empty() {
                         let new_cell = self.list.remove(0);
-                        self.insert(new_cell, index, k_bucket);
+                        self.insert(new_cell, index, k_bucket, is_pinned);
                     }
 
-                    self.insert(cell, index, k_bucket)
+                    self.insert(cell, index, k_bucket, is_pinned)
                 }
             }
         }
@@ -372,7 +393,7 @@ impl<K: Key> Node<K> {
 
         self.list
             .get(closest_index)
-            .and_then(|cell| Some((&cell.0, &cell.1, false)))
+            .map(|cell| (&cell.0, &cell.1, false))
     }
 
     pub fn remove(&mut self, key: &K, distance: &Distance, index: usize) -> Option<u32> {
@@ -1,11 +1,11 @@
-use aes_gcm::aead::{
-    generic_array::{typenum::U12, GenericArray},
-    Aead, NewAead,
-};
+use aes_gcm::aead::{Aead, AeadInPlace, NewAead, Payload};
 use aes_gcm::Aes256Gcm;
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use curve25519_dalek::edwards::CompressedEdwardsY;
 use ed25519_dalek::{
-    Keypair as Ed25519_Keypair, PublicKey as Ed25519_PublicKey, Signature as Ed25519_Signature,
-    Signer, Verifier, KEYPAIR_LENGTH, PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH, SIGNATURE_LENGTH,
+    ExpandedSecretKey, Keypair as Ed25519_Keypair, PublicKey as Ed25519_PublicKey,
+    SecretKey as Ed25519_SecretKey, Signature as Ed25519_Signature, Signer, Verifier,
+    KEYPAIR_LENGTH, PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH, SIGNATURE_LENGTH,
 };
 use rand::Rng;
 use std::convert::TryFrom;
@@ -15,21 +15,114 @@ use zeroize::Zeroize;
 
 use chamomile_types::types::{new_io_error, PeerId};
 
-#[derive(Copy, Clone, Debug, Zeroize)]
-pub enum KeyType {
-    Ed25519, // Ed25519 = 0
-    Lattice, // Lattice-based = 1
-    None,    // None 255
+use crate::config::PeerIdHash;
+
+cpufeatures::new!(cpuid_aes, "aes");
+
+/// capability flag: support ChaCha20-Poly1305.
+pub const CIPHER_CHACHA: u8 = 0b0001_0000;
+/// capability flag: no hardware AES, prefer ChaCha20-Poly1305.
+pub const CIPHER_PREFER_CHACHA: u8 = 0b0010_0000;
+
+/// the length of pre-shared key's confirmation in handshake.
+pub const PSK_CONFIRM_LENGTH: usize = 32;
+
+fn psk_hash(psk: &[u8; 32], dh_bytes: &[u8], remote_dh: &[u8]) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new_keyed(psk);
+    hasher.update(b"chamomile-psk:");
+    hasher.update(dh_bytes);
+    hasher.update(remote_dh);
+    hasher.finalize()
 }
 
-impl Default for KeyType {
-    fn default() -> Self {
-        KeyType::None
+/// confirm self has the pre-shared key, keyed hash of self's dh bytes and the
+/// remote's dh bytes. the initiator not received the remote's, so it is empty,
+/// and the responder's confirmation is bound to the initiator's handshake.
+pub fn psk_confirm(psk: &[u8; 32], dh_bytes: &[u8], remote_dh: &[u8]) -> Vec<u8> {
+    psk_hash(psk, dh_bytes, remote_dh).as_bytes().to_vec()
+}
+
+/// verify the remote's pre-shared key confirmation (constant-time).
+pub fn psk_verify(psk: &[u8; 32], dh_bytes: &[u8], remote_dh: &[u8], confirm: &[u8]) -> bool {
+    match <[u8; PSK_CONFIRM_LENGTH]>::try_from(confirm) {
+        Ok(bytes) => psk_hash(psk, dh_bytes, remote_dh) == blake3::Hash::from(bytes),
+        Err(_) => false,
     }
 }
 
+/// the length of the ephemeral public key at the head of `seal_to`'s bytes.
+pub const SEAL_TO_PK_LENGTH: usize = 32;
+
+/// the key of `seal_to`, derived from the DH shared secret and both public keys.
+fn seal_to_key(shared: &[u8; 32], ephemeral: &[u8], to: &[u8; 32]) -> [u8; 32] {
+    let mut material = shared.to_vec();
+    material.extend(ephemeral);
+    material.extend(to);
+    blake3::derive_key("chamomile 2022 seal-to key", &material)
+}
+
+/// encrypt the message to the owner of the DH public key (see `Keypair::dh_public`),
+/// only it can decrypt by `Keypair::open_to`. the `aad` is authenticated, not
+/// encrypted. bytes is: ephemeral public key(32) + ciphertext.
+pub fn seal_to(to: &[u8; 32], aad: &[u8], msg: &[u8]) -> Option<Vec<u8>> {
+    let ephemeral = Ed25519_DH_Secret::new(rand::thread_rng());
+    let ephemeral_pk = Ed25519_DH_Public::from(&ephemeral);
+    let shared = ephemeral.diffie_hellman(&Ed25519_DH_Public::from(*to));
+    let key = seal_to_key(shared.as_bytes(), ephemeral_pk.as_bytes(), to);
+    // every key is used once, so the zero nonce is safe.
+    let cipher = ChaCha20Poly1305::new(&Key::from(key));
+    let ciphertext = cipher
+        .encrypt(&Nonce::default(), Payload { msg, aad })
+        .ok()?;
+    let mut bytes = ephemeral_pk.as_bytes().to_vec();
+    bytes.extend(ciphertext);
+    Some(bytes)
+}
+
+/// session's AEAD cipher suite, negotiated by handshake capability flags.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Cipher {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    /// self's capability flags, send to remote in handshake.
+    pub fn flags() -> u8 {
+        if cpuid_aes::get() {
+            CIPHER_CHACHA
+        } else {
+            CIPHER_CHACHA | CIPHER_PREFER_CHACHA
+        }
+    }
+
+    /// negotiate with remote's flags. use ChaCha20-Poly1305 only when both support it,
+    /// and someone has no hardware AES. legacy peers always use AES-256-GCM.
+    pub fn negotiate(remote: u8) -> Self {
+        let local = Self::flags();
+        if local & remote & CIPHER_CHACHA != 0 && (local | remote) & CIPHER_PREFER_CHACHA != 0 {
+            Cipher::ChaCha20Poly1305
+        } else {
+            Cipher::Aes256Gcm
+        }
+    }
+}
+
+enum SessionCipher {
+    Aes(Box<Aes256Gcm>),
+    ChaCha(ChaCha20Poly1305),
+}
+
+#[derive(Copy, Clone, Debug, Zeroize, Default)]
+pub enum KeyType {
+    Ed25519, // Ed25519 = 0
+    Lattice, // Lattice-based = 1
+    #[default]
+    None, // None 255
+}
+
 impl KeyType {
-    pub fn to_byte(&self) -> u8 {
+    pub fn to_byte(self) -> u8 {
         match self {
             KeyType::Ed25519 => 1u8,
             KeyType::Lattice => 2u8,
@@ -81,6 +174,11 @@ impl KeyType {
         }
     }
 
+    /// the length of session key's out bytes (dh public key, signature & nonce).
+    pub(crate) fn dh_bytes_len(&self) -> usize {
+        self.dh_pk_len() + self.sign_len() + 12
+    }
+
     pub fn generate_kepair(&self) -> Keypair {
         match self {
             KeyType::Ed25519 => {
@@ -112,12 +210,12 @@ impl KeyType {
     fn verify(&self, pk: &[u8], msg: &[u8], sign: &[u8]) -> Result<bool> {
         match self {
             KeyType::Ed25519 => {
-                let ed_pk = Ed25519_PublicKey::from_bytes(&pk[..])
+                let ed_pk = Ed25519_PublicKey::from_bytes(pk)
                     .map_err(|_e| new_io_error("ed25519 public from bytes failure."))?;
                 Ok(ed_pk
                     .verify(
                         msg,
-                        &Ed25519_Signature::try_from(&sign[..])
+                        &Ed25519_Signature::try_from(sign)
                             .map_err(|_e| new_io_error("ed25519 signaure from bytes failure."))?,
                     )
                     .is_ok())
@@ -129,7 +227,7 @@ impl KeyType {
     pub fn session_key(&self, self_keypair: &Keypair) -> Result<SessionKey> {
         match self {
             KeyType::Ed25519 => {
-                let alice_secret = Ed25519_DH_Secret::new(&mut rand::thread_rng());
+                let alice_secret = Ed25519_DH_Secret::new(rand::thread_rng());
                 let alice_public = Ed25519_DH_Public::from(&alice_secret).as_bytes().to_vec();
 
                 let sign = self_keypair.sign(&alice_public[..])?;
@@ -138,9 +236,9 @@ impl KeyType {
                     key: *self,
                     sk: alice_secret.to_bytes().to_vec(),
                     pk: alice_public,
-                    sign: sign,
+                    sign,
                     is_ok: false,
-                    cipher: Aes256Gcm::new(GenericArray::from_slice(&[0u8; 32])),
+                    cipher: SessionCipher::Aes(Box::new(Aes256Gcm::new(&Key::from([0u8; 32])))),
                     nonce: random_nonce.into(),
                 })
             }
@@ -152,9 +250,9 @@ impl KeyType {
         match self {
             KeyType::Ed25519 => {
                 let mut sk_bytes = [0u8; 32];
-                sk_bytes.copy_from_slice(&sk);
+                sk_bytes.copy_from_slice(sk);
                 let mut pk_bytes = [0u8; 32];
-                pk_bytes.copy_from_slice(&pk);
+                pk_bytes.copy_from_slice(pk);
                 let alice_secret: Ed25519_DH_Secret = sk_bytes.into();
                 let bob_public: Ed25519_DH_Public = pk_bytes.into();
                 Ok(alice_secret.diffie_hellman(&bob_public).as_bytes().to_vec())
@@ -174,7 +272,7 @@ pub struct Keypair {
 impl Keypair {
     /// only key_type and public_key.
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        if bytes.len() < 1 {
+        if bytes.is_empty() {
             return Err(new_io_error("keypair length failure."));
         }
         let key = KeyType::from_byte(bytes[0])?;
@@ -185,11 +283,11 @@ impl Keypair {
         }
         let pk = bytes[1..].to_vec();
 
-        return Ok(Keypair {
+        Ok(Keypair {
             key,
             pk,
             sk: vec![],
-        });
+        })
     }
 
     /// only key_type and public_key.
@@ -211,7 +309,7 @@ impl Keypair {
 
     // TODO add keystore
     pub fn from_db_bytes(bytes: &[u8]) -> Result<Self> {
-        if bytes.len() < 1 {
+        if bytes.is_empty() {
             return Err(new_io_error("keypair from db bytes failure."));
         }
         let key = KeyType::from_byte(bytes[0])?;
@@ -226,10 +324,8 @@ impl Keypair {
         Ok(Self { key, sk, pk })
     }
 
-    pub fn peer_id(&self) -> PeerId {
-        let mut peer_bytes = [0u8; 32];
-        peer_bytes.copy_from_slice(blake3::hash(&self.pk).as_bytes());
-        PeerId(peer_bytes)
+    pub fn peer_id(&self, hash: &PeerIdHash) -> PeerId {
+        hash.derive(&self.pk)
     }
 
     pub fn public(&self) -> Self {
@@ -244,9 +340,15 @@ impl Keypair {
         self.key.session_key(self)
     }
 
-    pub fn complete_session_key(&self, remote: &Keypair, dh_bytes: Vec<u8>) -> Option<SessionKey> {
+    pub fn complete_session_key(
+        &self,
+        remote: &Keypair,
+        dh_bytes: Vec<u8>,
+        cipher: Cipher,
+        psk: Option<&[u8; 32]>,
+    ) -> Option<SessionKey> {
         if let Ok(mut session) = self.generate_session_key() {
-            if session.complete(&remote.pk, dh_bytes) {
+            if session.complete(&remote.pk, dh_bytes, cipher, psk) {
                 return Some(session);
             }
         }
@@ -255,7 +357,7 @@ impl Keypair {
 
     pub fn sign(&self, msg: &[u8]) -> Result<Vec<u8>> {
         self.key
-            .sign(&self, msg)
+            .sign(self, msg)
             .map_err(|_e| new_io_error("keypair sign failure."))
     }
 
@@ -263,6 +365,90 @@ impl Keypair {
         self.key.verify(&self.pk, msg, sign).unwrap_or(false)
     }
 
+    /// signed message: key_type + public_key + signature + message.
+    /// the signature is over domain + message, the domain tag separates the usages.
+    pub fn seal(&self, domain: &[u8], msg: &[u8]) -> Result<Vec<u8>> {
+        let mut signed = domain.to_vec();
+        signed.extend(msg);
+        let mut bytes = self.to_bytes();
+        bytes.extend(self.sign(&signed)?);
+        bytes.extend(msg);
+        Ok(bytes)
+    }
+
+    /// verify the signed message in the domain, return the signer and message.
+    pub fn open<'a>(
+        bytes: &'a [u8],
+        domain: &[u8],
+        hash: &PeerIdHash,
+    ) -> Option<(PeerId, &'a [u8])> {
+        let key = KeyType::from_byte(*bytes.first()?).ok()?;
+        let (pk_len, sign_len) = (key.pk_len(), key.sign_len());
+        if pk_len == 0 || bytes.len() < 1 + pk_len + sign_len {
+            return None;
+        }
+        let signer = Keypair::from_bytes(&bytes[..1 + pk_len]).ok()?;
+        let (sign, msg) = bytes[1 + pk_len..].split_at(sign_len);
+        let mut signed = domain.to_vec();
+        signed.extend(msg);
+        if signer.verify(&signed, sign) {
+            Some((signer.peer_id(hash), msg))
+        } else {
+            None
+        }
+    }
+
+    /// the X25519 public key of the Ed25519 public key, for `seal_to` self.
+    pub fn dh_public(&self) -> Option<[u8; 32]> {
+        match self.key {
+            KeyType::Ed25519 if self.pk.len() == PUBLIC_KEY_LENGTH => {
+                let point = CompressedEdwardsY::from_slice(&self.pk).decompress()?;
+                Some(point.to_montgomery().to_bytes())
+            }
+            _ => None,
+        }
+    }
+
+    /// the X25519 secret key of the Ed25519 secret key (the expanded scalar).
+    fn dh_secret(&self) -> Option<Ed25519_DH_Secret> {
+        match self.key {
+            KeyType::Ed25519 => {
+                let secret = Ed25519_SecretKey::from_bytes(&self.sk).ok()?;
+                let mut expanded = ExpandedSecretKey::from(&secret).to_bytes();
+                let mut scalar = [0u8; 32];
+                scalar.copy_from_slice(&expanded[..32]);
+                expanded.zeroize();
+                Some(Ed25519_DH_Secret::from(scalar))
+            }
+            _ => None,
+        }
+    }
+
+    /// decrypt the bytes of `seal_to` self's DH public key, with the same `aad`.
+    pub fn open_to(&self, aad: &[u8], bytes: &[u8]) -> Option<Vec<u8>> {
+        if bytes.len() < SEAL_TO_PK_LENGTH {
+            return None;
+        }
+        let (ephemeral, ciphertext) = bytes.split_at(SEAL_TO_PK_LENGTH);
+        let mut ephemeral_pk = [0u8; SEAL_TO_PK_LENGTH];
+        ephemeral_pk.copy_from_slice(ephemeral);
+        let to = self.dh_public()?;
+        let shared = self
+            .dh_secret()?
+            .diffie_hellman(&Ed25519_DH_Public::from(ephemeral_pk));
+        let key = seal_to_key(shared.as_bytes(), ephemeral, &to);
+        let cipher = ChaCha20Poly1305::new(&Key::from(key));
+        cipher
+            .decrypt(
+                &Nonce::default(),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .ok()
+    }
+
     pub fn from_pk(key: KeyType, bytes: Vec<u8>) -> Result<Self> {
         if bytes.len() == key.pk_len() {
             Ok(Keypair {
@@ -283,13 +469,13 @@ pub struct SessionKey {
     pk: Vec<u8>,
     sign: Vec<u8>,
     is_ok: bool,
-    /// 256-bit key (random key from DH key)
-    cipher: Aes256Gcm,
+    /// 256-bit key (random key from DH key), AES-256-GCM or ChaCha20-Poly1305.
+    cipher: SessionCipher,
     /// 96-bit nonce (random key, when first handshake. only use this session.)
-    nonce: GenericArray<u8, U12>,
+    nonce: Nonce,
 }
 
-/// Simple DH on 25519 to get AES-256 session key.
+/// Simple DH on 25519 to get AES-256 / ChaCha20 session key.
 /// 1. new a tmp public_key and sign it.
 /// 2. send tmp public key and signature to remote.
 /// 2. receive remote tmp public_key and signature, verify it.
@@ -300,23 +486,38 @@ impl SessionKey {
         self.is_ok
     }
 
-    pub fn complete(&mut self, remote_pk: &[u8], remote_dh: Vec<u8>) -> bool {
-        if self.key.pk_len() != remote_pk.len()
-            || (self.key.dh_pk_len() + self.key.sign_len()) + 12 != remote_dh.len()
-        {
+    /// if has pre-shared key, it is mixed in the key derivation, so peers without
+    /// the same psk will get different session key. the psk is confirmed in the
+    /// handshake before it (see `psk_confirm`).
+    pub fn complete(
+        &mut self,
+        remote_pk: &[u8],
+        remote_dh: Vec<u8>,
+        cipher: Cipher,
+        psk: Option<&[u8; 32]>,
+    ) -> bool {
+        if self.key.pk_len() != remote_pk.len() || self.key.dh_bytes_len() != remote_dh.len() {
             return false;
         }
 
         let (tmp_pk, tmp_sign_nonce) = remote_dh.split_at(self.key.dh_pk_len());
         let (tmp_sign, tmp_nonce) = tmp_sign_nonce.split_at(self.key.sign_len());
 
-        if let Ok(true) = self.key.verify(&remote_pk, tmp_pk, tmp_sign) {
+        if let Ok(true) = self.key.verify(remote_pk, tmp_pk, tmp_sign) {
             self.key
                 .dh(&self.sk, tmp_pk)
                 .map(|session_key| {
-                    self.cipher = Aes256Gcm::new(GenericArray::from_slice(
-                        blake3::hash(&session_key).as_bytes(), // [u8; 32]
-                    ));
+                    let hash = match psk {
+                        Some(psk) => blake3::keyed_hash(psk, &session_key),
+                        None => blake3::hash(&session_key),
+                    }; // [u8; 32]
+                    let key = Key::from(*hash.as_bytes());
+                    self.cipher = match cipher {
+                        Cipher::Aes256Gcm => SessionCipher::Aes(Box::new(Aes256Gcm::new(&key))),
+                        Cipher::ChaCha20Poly1305 => {
+                            SessionCipher::ChaCha(ChaCha20Poly1305::new(&key))
+                        }
+                    };
                     let mut nonce_bytes = [0u8; 12];
                     nonce_bytes.copy_from_slice(tmp_nonce);
                     self.nonce = nonce_bytes.into();
@@ -331,19 +532,28 @@ impl SessionKey {
     pub fn out_bytes(&self) -> Vec<u8> {
         let mut vec = self.pk.clone();
         vec.extend(&self.sign);
-        vec.extend(self.nonce.as_slice());
+        vec.extend(&self.nonce[..]);
         vec
     }
 
-    pub fn encrypt(&self, msg: Vec<u8>) -> Vec<u8> {
-        self.cipher
-            .encrypt(&self.nonce, msg.as_ref())
-            .unwrap_or(vec![])
+    /// encrypt in place, not copy the message.
+    pub fn encrypt(&self, mut msg: Vec<u8>) -> Vec<u8> {
+        let result = match &self.cipher {
+            SessionCipher::Aes(c) => c.encrypt_in_place(&self.nonce, b"", &mut msg),
+            SessionCipher::ChaCha(c) => c.encrypt_in_place(&self.nonce, b"", &mut msg),
+        };
+        if result.is_ok() {
+            msg
+        } else {
+            vec![]
+        }
     }
 
-    pub fn decrypt(&self, msg: Vec<u8>) -> Result<Vec<u8>> {
-        self.cipher
-            .decrypt(&self.nonce, msg.as_ref())
-            .map_err(|_e| new_io_error("decrypt failure."))
+    pub fn decrypt(&self, msg: &[u8]) -> Result<Vec<u8>> {
+        match &self.cipher {
+            SessionCipher::Aes(c) => c.decrypt(&self.nonce, msg.as_ref()),
+            SessionCipher::ChaCha(c) => c.decrypt(&self.nonce, msg.as_ref()),
+        }
+        .map_err(|_e| new_io_error("decrypt failure."))
     }
 }
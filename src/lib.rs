@@ -54,6 +54,27 @@
 //!            ReceiveMessage::StableResult(..) => {}
 //!            ReceiveMessage::Delivery(..) => {}
 //!            ReceiveMessage::NetworkLost => {}
+//!            ReceiveMessage::Pong(..) => {}
+//!            ReceiveMessage::PeerJoin(..) => {}
+//!            ReceiveMessage::PeerLeave(..) => {}
+//!            ReceiveMessage::DialFailure(..) => {}
+//!            ReceiveMessage::StableRestored(..) => {}
+//!            ReceiveMessage::ProtocolData(..) => {}
+//!            ReceiveMessage::NetworkReady => {}
+//!            ReceiveMessage::GroupJoin(..) => {}
+//!            ReceiveMessage::GroupLeave(..) => {}
+//!            ReceiveMessage::GroupData(..) => {}
+//!            ReceiveMessage::Services(..) => {}
+//!            ReceiveMessage::Mail(..) => {}
+//!            ReceiveMessage::PathData(..) => {}
+//!            ReceiveMessage::Probe(..) => {}
+//!            ReceiveMessage::DeliveryMulti(..) => {}
+//!            ReceiveMessage::PeerBanned(..) => {}
+//!            ReceiveMessage::Heartbeat(..) => {}
+//!            ReceiveMessage::SessionStats(..) => {}
+//!            ReceiveMessage::NetworkRecovery(..) => {}
+//!            ReceiveMessage::Bandwidth(..) => {}
+//!            ReceiveMessage::PeersExported(..) => {}
 //!        }
 //!    }
 //! }
@@ -70,33 +91,69 @@
 #[macro_use]
 extern crate log;
 
+#[cfg(feature = "admin")]
+mod admin;
+mod audit;
+mod ban;
 mod buffer;
+#[cfg(feature = "capture")]
+mod capture;
 mod config;
+mod dialer;
+mod echo;
 mod global;
+mod group;
 mod hole_punching;
+mod journal;
 mod kad;
 mod keys;
 mod lan;
+mod mailbox;
+#[cfg(feature = "mainline")]
+mod mainline;
+mod multi;
+mod outbox;
 mod peer_list;
+mod qos;
+#[cfg(feature = "capture")]
+mod record;
+#[cfg(feature = "relay")]
+mod relay_queue;
+mod scheduler;
+mod seeds;
 mod server;
+mod service;
 mod session;
+mod stats;
+mod swarm;
+mod token;
+mod topic;
+mod watchdog;
 
 pub mod primitives;
 pub mod transports;
 
 pub mod prelude {
     pub use chamomile_types::message::{
-        DeliveryType, ReceiveMessage, SendMessage, StateRequest, StateResponse, StreamType,
+        DeliveryFailure, DeliveryType, PeerInfo, ReceiveMessage, RecoveryStage, RelayInfo,
+        SeedInfo, SendMessage, SessionStats, StateRequest, StateResponse, StreamType,
     };
-    pub use chamomile_types::types::{Broadcast, PeerId};
-    pub use chamomile_types::Peer;
+    pub use chamomile_types::types::{
+        BlockTarget, Broadcast, DataPath, PeerId, PeerPolicy, PowerProfile, QosClass,
+    };
+    pub use chamomile_types::{Multiaddr, Peer};
 
     use tokio::{
         io::Result,
         sync::mpsc::{self, Receiver, Sender},
     };
 
-    pub use super::config::Config;
+    pub use super::config::{
+        BufferOverflow, Config, ConnectionGater, DeliveryPolicy, HandshakeCheck, PeerIdHash,
+        PeerSelector, RelayAccounting, SelectPurpose, StableCheck, StableDecision, StableQuotas,
+    };
+    pub use super::token::with_join_token;
+    pub use super::transports::Emulation;
 
     /// new a channel for send message to the chamomile.
     pub fn new_send_channel() -> (Sender<SendMessage>, Receiver<SendMessage>) {
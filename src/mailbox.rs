@@ -0,0 +1,359 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use chamomile_types::{types::PEER_ID_LENGTH, PeerId};
+
+use crate::config::PeerIdHash;
+use crate::keys::{seal_to, Keypair};
+
+/// max stored mails' bytes of the mailbox role.
+const MAILBOX_MAX_BYTES: usize = 67108864; // 64 MB
+
+/// max stored mails' bytes from every sender.
+const MAILBOX_SENDER_MAX_BYTES: usize = 4194304; // 4 MB
+
+/// the domain tag of mail's signature.
+const MAIL_DOMAIN: &[u8] = b"chamomile-mail-v1";
+
+/// one sender's mails to a recipient is at most `1 / MAILBOX_SENDER_SHARE` of
+/// the recipient's mails, so a sender cannot take the place of other senders.
+const MAILBOX_SENDER_SHARE: usize = 4;
+
+/// max remembered received mail ids, drop duplicated from other mailboxes.
+const MAIL_SEEN_MAX: usize = 4096;
+
+/// max remembered DH public keys of the handshaked peers.
+const PEER_KEYS_MAX: usize = 4096;
+
+/// every mail is stored at the closest connected mailboxes.
+pub(crate) const MAILBOX_REPLICAS: usize = 3;
+
+/// queue of (mail id, sender, sealed mail, stored time).
+type Mails = VecDeque<(u64, PeerId, Vec<u8>, Instant)>;
+
+/// the authenticated data of the mail's encryption: id(8) + sender(32) + recipient(32).
+fn mail_aad(id: u64, from: &PeerId, to: &PeerId) -> Vec<u8> {
+    let mut aad = id.to_le_bytes().to_vec();
+    aad.extend(from.as_bytes());
+    aad.extend(to.as_bytes());
+    aad
+}
+
+/// seal a mail, the data is encrypted to the recipient's DH public key, and signed
+/// by sender's keypair, mail is: id(8) + recipient(32) + encrypted data.
+/// only id, sender & recipient are readable by the mailboxes.
+pub(crate) fn seal(
+    key: &Keypair,
+    from: &PeerId,
+    id: u64,
+    to: &PeerId,
+    to_key: &[u8; 32],
+    data: &[u8],
+) -> Option<Vec<u8>> {
+    let encrypted = seal_to(to_key, &mail_aad(id, from, to), data)?;
+    let mut msg = id.to_le_bytes().to_vec();
+    msg.extend(to.as_bytes());
+    msg.extend(encrypted);
+    key.seal(MAIL_DOMAIN, &msg).ok()
+}
+
+/// decrypt the opened mail's data by the recipient's keypair.
+pub(crate) fn decrypt(
+    key: &Keypair,
+    id: u64,
+    from: &PeerId,
+    to: &PeerId,
+    encrypted: &[u8],
+) -> Option<Vec<u8>> {
+    key.open_to(&mail_aad(id, from, to), encrypted)
+}
+
+/// verify the sealed mail, return the (id, sender, recipient, encrypted data).
+pub(crate) fn open(sealed: &[u8], hash: &PeerIdHash) -> Option<(u64, PeerId, PeerId, Vec<u8>)> {
+    let (from, msg) = Keypair::open(sealed, MAIL_DOMAIN, hash)?;
+    if msg.len() < 8 + PEER_ID_LENGTH {
+        return None;
+    }
+    let mut id_bytes = [0u8; 8];
+    id_bytes.copy_from_slice(&msg[..8]);
+    let to = PeerId::from_bytes(&msg[8..8 + PEER_ID_LENGTH]).ok()?;
+    Some((
+        u64::from_le_bytes(id_bytes),
+        from,
+        to,
+        msg[8 + PEER_ID_LENGTH..].to_vec(),
+    ))
+}
+
+/// Offline mailbox.
+/// when enable the mailbox role, store mails for offline peers until they fetch,
+/// and record the connected mailboxes, and the received mails.
+/// the mails are signed by the sender, and encrypted to the recipient, the mailbox
+/// only can read the id, sender and recipient.
+pub(crate) struct Mailbox {
+    /// max stored mails of every recipient. 0 is disable the role.
+    max_size: usize,
+    /// max stored time of every mail.
+    max_age: Duration,
+    /// store mails from any connected peer, or only stable & allowlisted peers.
+    is_open: bool,
+    /// recipient => stored mails.
+    mails: HashMap<PeerId, Mails>,
+    /// total bytes of stored mails.
+    bytes: usize,
+    /// sender => bytes of its stored mails.
+    senders: HashMap<PeerId, usize>,
+    /// connected peers which are mailboxes.
+    peers: HashSet<PeerId>,
+    /// recent received mail ids.
+    seen: (HashSet<u64>, VecDeque<u64>),
+}
+
+impl Mailbox {
+    pub fn new(max_size: usize, max_age: Duration, is_open: bool) -> Self {
+        Mailbox {
+            max_size,
+            max_age,
+            is_open,
+            mails: HashMap::new(),
+            bytes: 0,
+            senders: HashMap::new(),
+            peers: HashSet::new(),
+            seen: (HashSet::new(), VecDeque::new()),
+        }
+    }
+
+    pub fn is_enable(&self) -> bool {
+        self.max_size > 0
+    }
+
+    /// accept the mails from the sender, `is_trusted` is it is stable or allowlisted.
+    pub fn is_accept(&self, is_trusted: bool) -> bool {
+        self.is_enable() && (self.is_open || is_trusted)
+    }
+
+    /// store a sealed mail for the recipient, return false if full or disabled, or
+    /// the sender's quota is full.
+    pub fn store(&mut self, from: PeerId, to: PeerId, id: u64, sealed: Vec<u8>) -> bool {
+        if !self.is_enable() {
+            return false;
+        }
+        self.clear_expired();
+        let sender_bytes = self.senders.get(&from).copied().unwrap_or(0);
+        if self.bytes + sealed.len() > MAILBOX_MAX_BYTES
+            || sender_bytes + sealed.len() > MAILBOX_SENDER_MAX_BYTES
+        {
+            return false;
+        }
+
+        let len = sealed.len();
+        let share = (self.max_size / MAILBOX_SENDER_SHARE).max(1);
+        let mails = self.mails.entry(to).or_default();
+        if mails.iter().any(|(i, ..)| *i == id) {
+            return true;
+        }
+        // only drop the sender's own oldest mail, never other senders'.
+        let own = mails.iter().filter(|(_, f, ..)| f == &from).count();
+        let dropped = if own >= share || mails.len() >= self.max_size {
+            match mails.iter().position(|(_, f, ..)| f == &from) {
+                Some(i) => mails.remove(i),
+                None => return false,
+            }
+        } else {
+            None
+        };
+        mails.push_back((id, from, sealed, Instant::now()));
+        if let Some((_, sender, dropped, _)) = dropped {
+            self.release(&sender, dropped.len());
+        }
+        self.bytes += len;
+        *self.senders.entry(from).or_default() += len;
+        true
+    }
+
+    /// the stored mail is removed, release its bytes.
+    fn release(&mut self, from: &PeerId, len: usize) {
+        self.bytes -= len;
+        if let Some(bytes) = self.senders.get_mut(from) {
+            *bytes = bytes.saturating_sub(len);
+            if *bytes == 0 {
+                self.senders.remove(from);
+            }
+        }
+    }
+
+    /// take out the recipient's stored sealed mails.
+    pub fn take(&mut self, to: &PeerId) -> Vec<Vec<u8>> {
+        self.clear_expired();
+        let mails = self.mails.remove(to).unwrap_or_default();
+        mails
+            .into_iter()
+            .map(|(_, from, sealed, _)| {
+                self.release(&from, sealed.len());
+                sealed
+            })
+            .collect()
+    }
+
+    fn clear_expired(&mut self) {
+        let max_age = self.max_age;
+        let mut dropped = vec![];
+        for mails in self.mails.values_mut() {
+            mails.retain(|(_, from, sealed, t)| {
+                let keep = t.elapsed() < max_age;
+                if !keep {
+                    dropped.push((*from, sealed.len()));
+                }
+                keep
+            });
+        }
+        self.mails.retain(|_, mails| !mails.is_empty());
+        for (from, len) in dropped {
+            self.release(&from, len);
+        }
+    }
+
+    pub fn add_peer(&mut self, peer_id: PeerId) {
+        self.peers.insert(peer_id);
+    }
+
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.peers.remove(peer_id);
+    }
+
+    /// the connected mailboxes closest (XOR) to the recipient.
+    pub fn closest(&self, to: &PeerId) -> Vec<PeerId> {
+        let mut peers: Vec<(Vec<u8>, PeerId)> = self
+            .peers
+            .iter()
+            .filter(|p| *p != to)
+            .map(|p| {
+                let distance = p
+                    .as_bytes()
+                    .iter()
+                    .zip(to.as_bytes().iter())
+                    .map(|(a, b)| a ^ b)
+                    .collect();
+                (distance, *p)
+            })
+            .collect();
+        peers.sort_by(|a, b| a.0.cmp(&b.0));
+        peers
+            .into_iter()
+            .take(MAILBOX_REPLICAS)
+            .map(|(_, p)| p)
+            .collect()
+    }
+
+    /// check the received mail is new, and remember it.
+    pub fn is_new(&mut self, id: u64) -> bool {
+        if !self.seen.0.insert(id) {
+            return false;
+        }
+        self.seen.1.push_back(id);
+        if self.seen.1.len() > MAIL_SEEN_MAX {
+            if let Some(old) = self.seen.1.pop_front() {
+                self.seen.0.remove(&old);
+            }
+        }
+        true
+    }
+}
+
+/// The DH public keys of the handshaked peers, mails to them are encrypted by it.
+#[derive(Default)]
+pub(crate) struct PeerKeys {
+    keys: HashMap<PeerId, [u8; 32]>,
+    /// remembered order, the oldest is removed when full.
+    order: VecDeque<PeerId>,
+}
+
+impl PeerKeys {
+    pub fn add(&mut self, peer_id: PeerId, key: [u8; 32]) {
+        if self.keys.insert(peer_id, key).is_some() {
+            return;
+        }
+        self.order.push_back(peer_id);
+        if self.order.len() > PEER_KEYS_MAX {
+            if let Some(old) = self.order.pop_front() {
+                self.keys.remove(&old);
+            }
+        }
+    }
+
+    pub fn get(&self, peer_id: &PeerId) -> Option<[u8; 32]> {
+        self.keys.get(peer_id).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::KeyType;
+
+    fn peer_id(b: u8) -> PeerId {
+        PeerId([b; PEER_ID_LENGTH])
+    }
+
+    #[test]
+    fn seal_open_decrypt() {
+        let hash = PeerIdHash::default();
+        let sender = KeyType::Ed25519.generate_kepair();
+        let recipient = KeyType::Ed25519.generate_kepair();
+        let from = sender.peer_id(&hash);
+        let to = recipient.peer_id(&hash);
+        let to_key = recipient.dh_public().unwrap();
+
+        let sealed = seal(&sender, &from, 7, &to, &to_key, b"hello").unwrap();
+        let (id, f, t, encrypted) = open(&sealed, &hash).unwrap();
+        assert_eq!((id, f, t), (7, from, to));
+        assert_eq!(
+            decrypt(&recipient, id, &f, &t, &encrypted),
+            Some(b"hello".to_vec())
+        );
+
+        // only the recipient can decrypt, and the header is authenticated.
+        let other = KeyType::Ed25519.generate_kepair();
+        assert!(decrypt(&other, id, &f, &t, &encrypted).is_none());
+        assert!(decrypt(&recipient, 8, &f, &t, &encrypted).is_none());
+        assert!(decrypt(&recipient, id, &t, &f, &encrypted).is_none());
+
+        // the tampered mail's signature is invalid.
+        let mut tampered = sealed.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(open(&tampered, &hash).is_none());
+    }
+
+    #[test]
+    fn sender_cannot_evict_others() {
+        // 4 mails of a recipient, every sender at most 1.
+        let mut mailbox = Mailbox::new(4, Duration::from_secs(3600), true);
+        let to = peer_id(0);
+        for i in 1..=4u8 {
+            assert!(mailbox.store(peer_id(i), to, i as u64, vec![i]));
+        }
+        // full, a new sender has no own mail to drop.
+        assert!(!mailbox.store(peer_id(5), to, 5, vec![5]));
+        // a sender only replaces its own oldest mail.
+        assert!(mailbox.store(peer_id(1), to, 6, vec![6]));
+        let mut mails = mailbox.take(&to);
+        mails.sort();
+        assert_eq!(mails, vec![vec![2], vec![3], vec![4], vec![6]]);
+        assert!(mailbox.take(&to).is_empty());
+    }
+
+    #[test]
+    fn duplicated_and_disabled() {
+        let mut mailbox = Mailbox::new(8, Duration::from_secs(3600), false);
+        assert!(mailbox.store(peer_id(1), peer_id(0), 1, vec![1]));
+        assert!(mailbox.store(peer_id(1), peer_id(0), 1, vec![1]));
+        assert_eq!(mailbox.take(&peer_id(0)).len(), 1);
+        assert!(!mailbox.is_accept(false));
+        assert!(mailbox.is_accept(true));
+
+        let mut disabled = Mailbox::new(0, Duration::from_secs(3600), true);
+        assert!(!disabled.store(peer_id(1), peer_id(0), 1, vec![1]));
+        assert!(!disabled.is_accept(true));
+    }
+}
@@ -0,0 +1,372 @@
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{io::Result, net::UdpSocket};
+
+use chamomile_types::Peer;
+
+use crate::dialer::DialKind;
+use crate::global::Global;
+
+/// the well-known routers of BitTorrent mainline DHT.
+const MAINLINE_ROUTERS: [&str; 3] = [
+    "router.bittorrent.com:6881",
+    "dht.transmissionbt.com:6881",
+    "router.utorrent.com:6881",
+];
+
+/// interval of announce & lookup, the mainline nodes drop announce after 30 minutes.
+const MAINLINE_INTERVAL: Duration = Duration::from_secs(900);
+
+/// waiting time of every lookup round's responses.
+const MAINLINE_ROUND_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// max lookup rounds.
+const MAINLINE_ROUNDS: usize = 8;
+
+/// queried nodes in every round, and announce to the closest these nodes.
+const MAINLINE_ALPHA: usize = 8;
+
+/// max bytes of KRPC packet.
+const MAINLINE_PACKET_MAX: usize = 2048;
+
+/// max accepted peers (values) of one response.
+const MAINLINE_VALUES_MAX: usize = 16;
+
+/// max nested depth of bencode value.
+const BENCODE_DEPTH_MAX: usize = 8;
+
+/// Minimal bencode value for KRPC messages.
+#[derive(Debug, Clone)]
+enum Bencode {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Bencode>),
+    Dict(BTreeMap<Vec<u8>, Bencode>),
+}
+
+impl Bencode {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Bencode::Int(i) => buf.extend(format!("i{}e", i).as_bytes()),
+            Bencode::Bytes(b) => {
+                buf.extend(format!("{}:", b.len()).as_bytes());
+                buf.extend(b);
+            }
+            Bencode::List(l) => {
+                buf.push(b'l');
+                for v in l {
+                    v.encode(buf);
+                }
+                buf.push(b'e');
+            }
+            Bencode::Dict(d) => {
+                buf.push(b'd');
+                for (k, v) in d {
+                    Bencode::Bytes(k.clone()).encode(buf);
+                    v.encode(buf);
+                }
+                buf.push(b'e');
+            }
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Bencode> {
+        let (v, len) = Self::parse(bytes, 0)?;
+        if len == bytes.len() {
+            Some(v)
+        } else {
+            None
+        }
+    }
+
+    /// parse the value at the start of bytes, return it and the used length.
+    fn parse(bytes: &[u8], depth: usize) -> Option<(Bencode, usize)> {
+        if depth > BENCODE_DEPTH_MAX {
+            return None;
+        }
+        match *bytes.first()? {
+            b'i' => {
+                let end = bytes.iter().position(|b| *b == b'e')?;
+                let i = std::str::from_utf8(&bytes[1..end]).ok()?.parse().ok()?;
+                Some((Bencode::Int(i), end + 1))
+            }
+            b'l' | b'd' => {
+                let is_list = bytes[0] == b'l';
+                let mut list = vec![];
+                let mut dict = BTreeMap::new();
+                let mut i = 1;
+                while *bytes.get(i)? != b'e' {
+                    let (v, len) = Self::parse(&bytes[i..], depth + 1)?;
+                    i += len;
+                    if is_list {
+                        list.push(v);
+                    } else {
+                        let key = match v {
+                            Bencode::Bytes(key) => key,
+                            _ => return None,
+                        };
+                        let (v, len) = Self::parse(&bytes[i..], depth + 1)?;
+                        i += len;
+                        dict.insert(key, v);
+                    }
+                }
+                let v = if is_list {
+                    Bencode::List(list)
+                } else {
+                    Bencode::Dict(dict)
+                };
+                Some((v, i + 1))
+            }
+            b'0'..=b'9' => {
+                let colon = bytes.iter().position(|b| *b == b':')?;
+                let len: usize = std::str::from_utf8(&bytes[..colon]).ok()?.parse().ok()?;
+                let end = (colon + 1).checked_add(len)?;
+                Some((Bencode::Bytes(bytes.get(colon + 1..end)?.to_vec()), end))
+            }
+            _ => None,
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Bencode> {
+        match self {
+            Bencode::Dict(d) => d.get(key.as_bytes()),
+            _ => None,
+        }
+    }
+
+    fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            Bencode::Bytes(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+fn dict(items: Vec<(&str, Bencode)>) -> Bencode {
+    Bencode::Dict(
+        items
+            .into_iter()
+            .map(|(k, v)| (k.as_bytes().to_vec(), v))
+            .collect(),
+    )
+}
+
+/// the rendezvous info hash of the network key.
+fn info_hash(key: &str) -> [u8; 20] {
+    let mut hash = [0u8; 20];
+    hash.copy_from_slice(&blake3::hash(key.as_bytes()).as_bytes()[..20]);
+    hash
+}
+
+fn distance(a: &[u8; 20], b: &[u8; 20]) -> [u8; 20] {
+    let mut d = [0u8; 20];
+    for i in 0..20 {
+        d[i] = a[i] ^ b[i];
+    }
+    d
+}
+
+fn compact_addr(bytes: &[u8]) -> SocketAddr {
+    let ip = Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]);
+    SocketAddr::new(IpAddr::V4(ip), u16::from_be_bytes([bytes[4], bytes[5]]))
+}
+
+/// the lookup state of one round-trip to mainline DHT.
+struct Lookup {
+    node_id: [u8; 20],
+    info_hash: [u8; 20],
+    socket: UdpSocket,
+    /// distance => (node addr, announce token).
+    nodes: BTreeMap<[u8; 20], (SocketAddr, Option<Vec<u8>>)>,
+    queried: HashSet<SocketAddr>,
+    /// the waiting queries' random transaction id => queried node addr.
+    pending: HashMap<[u8; 4], SocketAddr>,
+    /// the chamomile peers found in the info hash.
+    values: HashSet<SocketAddr>,
+}
+
+impl Lookup {
+    /// send the query with a random transaction id, the response must echo it
+    /// from the same addr.
+    async fn send(&mut self, addr: SocketAddr, q: &str, args: Vec<(&str, Bencode)>) {
+        let tid: [u8; 4] = rand::thread_rng().gen();
+        self.pending.insert(tid, addr);
+        let mut a = vec![("id", Bencode::Bytes(self.node_id.to_vec()))];
+        a.extend(args);
+        let msg = dict(vec![
+            ("a", dict(a)),
+            ("q", Bencode::Bytes(q.as_bytes().to_vec())),
+            ("t", Bencode::Bytes(tid.to_vec())),
+            ("y", Bencode::Bytes(b"q".to_vec())),
+        ]);
+        let mut buf = vec![];
+        msg.encode(&mut buf);
+        let _ = self.socket.send_to(&buf, addr).await;
+    }
+
+    async fn get_peers(&mut self, addr: SocketAddr) {
+        self.queried.insert(addr);
+        let info_hash = Bencode::Bytes(self.info_hash.to_vec());
+        self.send(addr, "get_peers", vec![("info_hash", info_hash)])
+            .await;
+    }
+
+    /// receive the responses of this round, until timeout.
+    async fn recv_round(&mut self) {
+        let mut buf = vec![0u8; MAINLINE_PACKET_MAX];
+        let deadline = tokio::time::Instant::now() + MAINLINE_ROUND_TIMEOUT;
+        while let Ok(Ok((len, from))) =
+            tokio::time::timeout_at(deadline, self.socket.recv_from(&mut buf)).await
+        {
+            if let Some(msg) = Bencode::decode(&buf[..len]) {
+                self.handle(from, msg);
+            }
+        }
+    }
+
+    fn handle(&mut self, from: SocketAddr, msg: Bencode) {
+        let tid = match msg.get("t").and_then(|v| v.bytes()) {
+            Some(t) if t.len() == 4 => [t[0], t[1], t[2], t[3]],
+            _ => return,
+        };
+        if self.pending.get(&tid) != Some(&from) {
+            debug!("Mainline DHT unexpected response from {}.", from);
+            return;
+        }
+        self.pending.remove(&tid);
+        let r = match msg.get("r") {
+            Some(r) => r,
+            None => return,
+        };
+        if let Some(id) = r.get("id").and_then(|v| v.bytes()) {
+            if id.len() == 20 {
+                let mut node = [0u8; 20];
+                node.copy_from_slice(id);
+                let token = r.get("token").and_then(|v| v.bytes()).map(|t| t.to_vec());
+                self.nodes
+                    .insert(distance(&node, &self.info_hash), (from, token));
+            }
+        }
+        if let Some(nodes) = r.get("nodes").and_then(|v| v.bytes()) {
+            for chunk in nodes.chunks_exact(26) {
+                let mut node = [0u8; 20];
+                node.copy_from_slice(&chunk[..20]);
+                self.nodes
+                    .entry(distance(&node, &self.info_hash))
+                    .or_insert((compact_addr(&chunk[20..]), None));
+            }
+        }
+        if let Some(Bencode::List(values)) = r.get("values") {
+            for v in values.iter().take(MAINLINE_VALUES_MAX) {
+                if let Some(peer) = v.bytes().filter(|b| b.len() == 6) {
+                    self.values.insert(compact_addr(peer));
+                }
+            }
+        }
+    }
+
+    /// the closest unqueried nodes.
+    fn next_round(&self) -> Vec<SocketAddr> {
+        self.nodes
+            .values()
+            .map(|(addr, _)| *addr)
+            .filter(|addr| !self.queried.contains(addr))
+            .take(MAINLINE_ALPHA)
+            .collect()
+    }
+}
+
+/// Publish & lookup self's rendezvous info in BitTorrent mainline DHT with the
+/// network key, when enable `mainline` feature. it is out-of-band bootstrap,
+/// the found peers will be dialed when connected peers less than `min_peers`.
+pub(crate) async fn start(key: String, global: Arc<Global>, min_peers: usize) -> Result<()> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    info!("mainline DHT bridge: {}", socket.local_addr()?);
+    let mut lookup = Lookup {
+        node_id: rand::thread_rng().gen(),
+        info_hash: info_hash(&key),
+        socket,
+        nodes: BTreeMap::new(),
+        queried: HashSet::new(),
+        pending: HashMap::new(),
+        values: HashSet::new(),
+    };
+
+    tokio::spawn(async move {
+        loop {
+            if global.out_sender.is_closed() {
+                break;
+            }
+
+            let found = round_trip(&mut lookup, &global).await;
+            debug!("Mainline DHT found {} peers.", found.len());
+            if global.peer_list.read().await.len() < min_peers.max(1) {
+                for socket in found {
+                    let mut peer = Peer::socket(socket);
+                    peer.transport = global.peer.transport;
+                    global.dialer.dial(peer, DialKind::Connect, None);
+                }
+            }
+
+            tokio::time::sleep(MAINLINE_INTERVAL).await;
+        }
+    });
+    Ok(())
+}
+
+/// iterative lookup the info hash, then announce self to the closest nodes.
+async fn round_trip(lookup: &mut Lookup, global: &Global) -> Vec<SocketAddr> {
+    lookup.nodes.clear();
+    lookup.queried.clear();
+    lookup.pending.clear();
+    lookup.values.clear();
+
+    for router in MAINLINE_ROUTERS {
+        match tokio::net::lookup_host(router).await {
+            Ok(addrs) => {
+                for addr in addrs.filter(|a| a.is_ipv4()) {
+                    lookup.get_peers(addr).await;
+                }
+            }
+            Err(e) => debug!("Mainline DHT resolve {} failure: {:?}", router, e),
+        }
+    }
+    lookup.recv_round().await;
+
+    for _ in 0..MAINLINE_ROUNDS {
+        let next = lookup.next_round();
+        if next.is_empty() {
+            break;
+        }
+        for addr in next {
+            lookup.get_peers(addr).await;
+        }
+        lookup.recv_round().await;
+    }
+
+    // announce to the closest nodes which had given the token.
+    let mut tokens: HashMap<SocketAddr, Vec<u8>> = HashMap::new();
+    for (addr, token) in lookup.nodes.values() {
+        if let Some(token) = token {
+            tokens.insert(*addr, token.clone());
+            if tokens.len() >= MAINLINE_ALPHA {
+                break;
+            }
+        }
+    }
+    let port = global.peer.socket.port() as i64;
+    for (addr, token) in tokens {
+        let args = vec![
+            ("implied_port", Bencode::Int(0)),
+            ("info_hash", Bencode::Bytes(lookup.info_hash.to_vec())),
+            ("port", Bencode::Int(port)),
+            ("token", Bencode::Bytes(token)),
+        ];
+        lookup.send(addr, "announce_peer", args).await;
+    }
+
+    lookup.values.drain().collect()
+}
@@ -0,0 +1,76 @@
+use rand::Rng;
+use std::collections::HashMap;
+
+use chamomile_types::{message::ReceiveMessage, PeerId};
+
+/// the aggregated result of multi-recipient data.
+struct MultiResult {
+    delivery: Vec<u8>,
+    waiting: usize,
+    delivered: Vec<PeerId>,
+    failed: Vec<PeerId>,
+}
+
+/// In-flight multi-recipient data, every recipient is sent with an inner tid,
+/// their deliveries are aggregated to one `DeliveryMulti`.
+#[derive(Default)]
+pub(crate) struct Multis {
+    /// inner tid => outside's tid and the recipient.
+    inners: HashMap<u64, (u64, PeerId)>,
+    /// outside's tid => aggregated result.
+    results: HashMap<u64, MultiResult>,
+}
+
+impl Multis {
+    pub fn is_empty(&self) -> bool {
+        self.inners.is_empty()
+    }
+
+    /// start a multi-recipient data, return the inner tid of every recipient.
+    pub fn start(&mut self, tid: u64, peers: &[PeerId], delivery: Vec<u8>) -> Vec<(u64, PeerId)> {
+        let mut rng = rand::thread_rng();
+        let mut tids = vec![];
+        for peer_id in peers {
+            let mut inner: u64 = rng.gen();
+            while inner == 0 || self.inners.contains_key(&inner) {
+                inner = rng.gen();
+            }
+            self.inners.insert(inner, (tid, *peer_id));
+            tids.push((inner, *peer_id));
+        }
+        self.results.insert(
+            tid,
+            MultiResult {
+                delivery,
+                waiting: tids.len(),
+                delivered: vec![],
+                failed: vec![],
+            },
+        );
+        tids
+    }
+
+    /// the recipient's delivery. None if it is not inner tid, Some(None) if still
+    /// waiting other recipients, or the aggregated `DeliveryMulti`.
+    pub fn done(&mut self, inner: u64, is_ok: bool) -> Option<Option<ReceiveMessage>> {
+        let (tid, peer_id) = self.inners.remove(&inner)?;
+        let result = self.results.get_mut(&tid)?;
+        if is_ok {
+            result.delivered.push(peer_id);
+        } else {
+            result.failed.push(peer_id);
+        }
+        result.waiting -= 1;
+        if result.waiting > 0 {
+            return Some(None);
+        }
+
+        let r = self.results.remove(&tid)?;
+        Some(Some(ReceiveMessage::DeliveryMulti(
+            tid,
+            r.delivered,
+            r.failed,
+            r.delivery,
+        )))
+    }
+}
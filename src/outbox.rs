@@ -0,0 +1,192 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chamomile_types::{types::PEER_ID_LENGTH, PeerId};
+
+/// queue of (delivery_id, data, saved unix time secs).
+type Queue = VecDeque<(u64, Vec<u8>, u64)>;
+
+/// Store-and-forward queue for offline stable peers.
+/// when send data to a stable peer, but it is not connected now,
+/// save to the queue, and send when it connected again.
+/// the changes are persisted in batch (`flush`), not every push & remove.
+pub(crate) struct Outbox {
+    /// None is ephemeral, only in memory.
+    save_path: Option<PathBuf>,
+    /// max queued messages of every peer. 0 is disable.
+    max_size: usize,
+    /// max queued time of every message.
+    max_age: Duration,
+    /// PeerId => queued messages.
+    queues: HashMap<PeerId, Queue>,
+    /// changed after the last flush.
+    dirty: bool,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl Outbox {
+    pub fn load(save_path: Option<PathBuf>, max_size: usize, max_age: Duration) -> Self {
+        let mut queues = HashMap::new();
+        if let (true, Some(path)) = (max_size > 0, &save_path) {
+            if let Ok(bytes) = std::fs::read(path) {
+                Self::from_bytes(&bytes, &mut queues);
+            }
+        }
+        // the saved queues may be larger than the current max size, drop the oldest.
+        let mut dirty = false;
+        for queue in queues.values_mut() {
+            let over = queue.len().saturating_sub(max_size);
+            if over > 0 {
+                warn!(
+                    "CHAMOMILE: OUTBOX DROP {} SAVED MESSAGES OVER MAX SIZE.",
+                    over
+                );
+                queue.drain(..over);
+                dirty = true;
+            }
+        }
+
+        Outbox {
+            save_path,
+            max_size,
+            max_age,
+            queues,
+            dirty,
+        }
+    }
+
+    pub fn is_enable(&self) -> bool {
+        self.max_size > 0
+    }
+
+    /// every message is: peer_id(32) + delivery_id(8) + time(8) + data_len(4) + data.
+    fn from_bytes(mut bytes: &[u8], queues: &mut HashMap<PeerId, Queue>) {
+        let head = PEER_ID_LENGTH + 20;
+        while bytes.len() >= head {
+            let peer_id = match PeerId::from_bytes(&bytes[0..PEER_ID_LENGTH]) {
+                Ok(peer_id) => peer_id,
+                Err(_) => break,
+            };
+            let mut tid_bytes = [0u8; 8];
+            tid_bytes.copy_from_slice(&bytes[PEER_ID_LENGTH..PEER_ID_LENGTH + 8]);
+            let mut time_bytes = [0u8; 8];
+            time_bytes.copy_from_slice(&bytes[PEER_ID_LENGTH + 8..PEER_ID_LENGTH + 16]);
+            let mut len_bytes = [0u8; 4];
+            len_bytes.copy_from_slice(&bytes[PEER_ID_LENGTH + 16..head]);
+            let len = u32::from_be_bytes(len_bytes) as usize;
+            if bytes.len() < head + len {
+                warn!("CHAMOMILE: OUTBOX FILE IS BROKEN.");
+                break;
+            }
+            queues.entry(peer_id).or_default().push_back((
+                u64::from_be_bytes(tid_bytes),
+                bytes[head..head + len].to_vec(),
+                u64::from_be_bytes(time_bytes),
+            ));
+            bytes = &bytes[head + len..];
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        for (peer_id, queue) in &self.queues {
+            for (tid, data, time) in queue {
+                bytes.extend(peer_id.as_bytes());
+                bytes.extend(&tid.to_be_bytes()[..]);
+                bytes.extend(&time.to_be_bytes()[..]);
+                bytes.extend(&(data.len() as u32).to_be_bytes()[..]);
+                bytes.extend(data);
+            }
+        }
+        bytes
+    }
+
+    /// the bytes need persist if changed after the last flush, (path, bytes),
+    /// write them without holding the outbox's lock.
+    pub fn flush(&mut self) -> Option<(PathBuf, Vec<u8>)> {
+        if !self.dirty {
+            return None;
+        }
+        self.dirty = false;
+        let path = self.save_path.clone()?;
+        Some((path, self.to_bytes()))
+    }
+
+    /// save the message to peer's queue, if the queue is full, will drop the
+    /// oldest message, and return it.
+    pub fn push(&mut self, peer_id: PeerId, tid: u64, data: Vec<u8>) -> Option<(u64, Vec<u8>)> {
+        let queue = self.queues.entry(peer_id).or_default();
+        queue.push_back((tid, data, now_secs()));
+        let dropped = if queue.len() > self.max_size {
+            queue.pop_front().map(|(tid, data, _)| (tid, data))
+        } else {
+            None
+        };
+        self.dirty = true;
+        dropped
+    }
+
+    /// remove the queued message of tid, and return it's data.
+    pub fn remove(&mut self, peer_id: &PeerId, tid: u64) -> Option<Vec<u8>> {
+        let queue = self.queues.get_mut(peer_id)?;
+        let i = queue.iter().position(|(t, _, _)| *t == tid)?;
+        let (_, data, _) = queue.remove(i)?;
+        if queue.is_empty() {
+            self.queues.remove(peer_id);
+        }
+        self.dirty = true;
+        Some(data)
+    }
+
+    /// take all queued messages (with saved time) of the peer to send, the not
+    /// sent messages need `restore` to the queue.
+    pub fn take(&mut self, peer_id: &PeerId) -> Vec<(u64, Vec<u8>, u64)> {
+        if let Some(queue) = self.queues.remove(peer_id) {
+            self.dirty = true;
+            queue.into_iter().collect()
+        } else {
+            vec![]
+        }
+    }
+
+    /// put the taken but not sent messages back to the front of peer's queue,
+    /// keep their saved time, so they still expire by `max_age`.
+    pub fn restore(&mut self, peer_id: PeerId, messages: Vec<(u64, Vec<u8>, u64)>) {
+        if messages.is_empty() {
+            return;
+        }
+        let queue = self.queues.entry(peer_id).or_default();
+        for message in messages.into_iter().rev() {
+            queue.push_front(message);
+        }
+        self.dirty = true;
+    }
+
+    /// remove the expired messages, and return them.
+    pub fn timer_clear(&mut self) -> Vec<(u64, Vec<u8>)> {
+        let deadline = now_secs().saturating_sub(self.max_age.as_secs());
+        let mut expired = vec![];
+        for queue in self.queues.values_mut() {
+            while let Some((_, _, time)) = queue.front() {
+                if *time >= deadline {
+                    break;
+                }
+                if let Some((tid, data, _)) = queue.pop_front() {
+                    expired.push((tid, data));
+                }
+            }
+        }
+        self.queues.retain(|_, queue| !queue.is_empty());
+        if !expired.is_empty() {
+            self.dirty = true;
+        }
+        expired
+    }
+}
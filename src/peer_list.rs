@@ -1,85 +1,361 @@
-use std::collections::HashMap;
-use std::io::BufRead;
+use std::collections::{HashMap, HashSet};
 use std::iter::Iterator;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::path::PathBuf;
-use tokio::{fs, io::Result, sync::mpsc::Sender};
-
-use chamomile_types::{types::new_io_error, Peer, PeerId};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio::{
+    fs,
+    io::Result,
+    sync::{
+        mpsc::{Sender, WeakSender},
+        watch,
+    },
+};
+
+use chamomile_types::{
+    types::{new_io_error, PEER_NAME_MAX},
+    Peer, PeerId,
+};
 
 use crate::kad::{DoubleKadTree, KadValue};
+use crate::primitives::{MAX_PEER_TAG_LENGTH, STORAGE_PEER_LIST_HEADER, STORAGE_PEER_LIST_VERSION};
 use crate::session::SessionMessage;
 use crate::transports::EndpointMessage;
 
+/// routes shards number.
+const ROUTES_SHARDS: usize = 16;
+
+/// directly connected session's route, session sender, stream sender and is stable.
+type Route = (
+    WeakSender<SessionMessage>,
+    WeakSender<EndpointMessage>,
+    bool,
+);
+
+/// Sharded index of the connected sessions, update with the PeerList,
+/// lookup the connected peer not need the PeerList's lock.
+/// it only keep the weak senders, not keep the session alive.
+pub(crate) struct Routes {
+    shards: Vec<RwLock<HashMap<PeerId, Route>>>,
+}
+
+impl Routes {
+    fn new() -> Self {
+        Routes {
+            shards: (0..ROUTES_SHARDS)
+                .map(|_| RwLock::new(HashMap::new()))
+                .collect(),
+        }
+    }
+
+    fn shard(&self, peer_id: &PeerId) -> &RwLock<HashMap<PeerId, Route>> {
+        &self.shards[peer_id.0[0] as usize % ROUTES_SHARDS]
+    }
+
+    fn insert(&self, peer_id: PeerId, v: &KadValue, is_stable: bool) {
+        if let Ok(mut shard) = self.shard(&peer_id).write() {
+            shard.insert(peer_id, (v.0.downgrade(), v.1.downgrade(), is_stable));
+        }
+    }
+
+    fn remove(&self, peer_id: &PeerId) {
+        if let Ok(mut shard) = self.shard(peer_id).write() {
+            shard.remove(peer_id);
+        }
+    }
+
+    fn retain(&self, f: impl Fn(&PeerId, &Route) -> bool) {
+        for shard in &self.shards {
+            if let Ok(mut shard) = shard.write() {
+                shard.retain(|k, v| f(k, v));
+            }
+        }
+    }
+
+    /// the connected session's sender.
+    pub fn get(&self, peer_id: &PeerId) -> Option<Sender<SessionMessage>> {
+        let shard = self.shard(peer_id).read().ok()?;
+        shard.get(peer_id).and_then(|(s, _, _)| s.upgrade())
+    }
+
+    /// the stable session's stream sender.
+    pub fn get_stable_stream(&self, peer_id: &PeerId) -> Option<Sender<EndpointMessage>> {
+        let shard = self.shard(peer_id).read().ok()?;
+        match shard.get(peer_id) {
+            Some((_, stream, true)) => stream.upgrade(),
+            _ => None,
+        }
+    }
+}
+
 /// PeerList.
 /// contains: dhts(KadTree) & stables(HashMap)
 pub(crate) struct PeerList {
-    save_path: PathBuf,
+    /// None is ephemeral, not saved.
+    save_path: Option<PathBuf>,
+    /// the latest snapshot to the background writer, the file is written without the lock.
+    saver: Option<watch::Sender<String>>,
     allows: Vec<Peer>,
     blocks: (Vec<PeerId>, Vec<IpAddr>),
+    /// PeerId => application's tags.
+    tags: HashMap<PeerId, Vec<String>>,
+    /// petname => PeerId.
+    names: HashMap<String, PeerId>,
 
     /// PeerId => KadValue(Sender<Sessionmessage>, Sender<EndpointMessage>, Peer)
     dhts: DoubleKadTree,
     /// PeerId => KadValue(Sender<SessionMessage>, Sender<EndpointMessage>, Peer)
     stables: HashMap<PeerId, (KadValue, bool)>,
+    /// PeerId => Peer, had stable connected peers, reconnect them when reboot.
+    stabled: HashMap<PeerId, Peer>,
+    /// the index of connected sessions, shared with Global.
+    routes: Arc<Routes>,
+    /// configured allowlist & seeds, never evicted, and reconnected when lost.
+    pinned: Vec<Peer>,
+    /// the connected pinned peers' PeerId.
+    pinned_ids: HashSet<PeerId>,
+}
+
+/// the content of saved peer list file.
+#[derive(Default)]
+struct SavedPeerList {
+    version: u32,
+    allows: Vec<Peer>,
+    tags: HashMap<PeerId, Vec<String>>,
+    stables: Vec<Peer>,
+    names: HashMap<String, PeerId>,
+}
+
+impl SavedPeerList {
+    fn parse_line(&mut self, line: &str) -> Result<()> {
+        if line.starts_with('/') {
+            self.allows.push(Peer::from_string(line)?);
+        } else if let Some(tag_line) = line.strip_prefix("tag ") {
+            let (id, tag) = tag_line
+                .split_once(' ')
+                .ok_or(new_io_error("peer tag line invalid."))?;
+            let peer_id = PeerId::from_hex(id)?;
+            self.tags.entry(peer_id).or_default().push(tag.to_owned());
+        } else if let Some(peer_line) = line.strip_prefix("stable ") {
+            self.stables.push(Peer::from_string(peer_line)?);
+        } else if let Some(name_line) = line.strip_prefix("name ") {
+            let (name, id) = name_line
+                .split_once(' ')
+                .ok_or(new_io_error("peer name line invalid."))?;
+            self.names.insert(name.to_owned(), PeerId::from_hex(id)?);
+        } else {
+            return Err(new_io_error("peer list line invalid."));
+        }
+        Ok(())
+    }
 }
 
 impl PeerList {
-    pub async fn save(&self) {
-        let mut file_string = String::new();
-        for addr in &self.allows {
-            file_string = format!("{}\n{}", file_string, addr.to_multiaddr_string());
+    /// snapshot the peers, persisted by the background writer, not under the lock.
+    pub fn save(&self) {
+        if let Some(saver) = &self.saver {
+            let _ = saver.send(self.to_file_string());
+        }
+    }
+
+    /// the known peers in the saved file's format, also used by export.
+    pub fn to_file_string(&self) -> String {
+        let mut file_string = format!(
+            "{} {}\n",
+            STORAGE_PEER_LIST_HEADER, STORAGE_PEER_LIST_VERSION
+        );
+        for peer in &self.allows {
+            file_string.push_str(&peer.to_string());
+            file_string.push('\n');
+        }
+        for (peer_id, tags) in &self.tags {
+            for tag in tags {
+                file_string.push_str(&format!("tag {} {}\n", peer_id.to_hex(), tag));
+            }
+        }
+        for peer in self.stabled.values() {
+            file_string.push_str(&format!("stable {}\n", peer.to_string()));
+        }
+        for (name, peer_id) in &self.names {
+            file_string.push_str(&format!("name {} {}\n", name, peer_id.to_hex()));
+        }
+        file_string
+    }
+
+    /// import the exported peers, tags and names, not the stables (they are the
+    /// exporter's), return the number of new peers.
+    pub async fn import(&mut self, path: &Path) -> Result<usize> {
+        let content = fs::read_to_string(path).await?;
+        let saved = Self::from_file_string(&content)?;
+        let mut count = 0;
+        for p in saved.allows {
+            let is_new = if p.effective_socket() {
+                !self.allows.iter().any(|ap| ap.socket == p.socket)
+            } else {
+                !self.allows.iter().any(|ap| ap.id == p.id)
+            };
+            if is_new {
+                self.allows.push(p);
+                count += 1;
+            }
+        }
+        for (peer_id, tags) in saved.tags {
+            let saved_tags = self.tags.entry(peer_id).or_default();
+            for tag in tags {
+                if !saved_tags.contains(&tag) {
+                    saved_tags.push(tag);
+                }
+            }
+        }
+        for (name, peer_id) in saved.names {
+            self.names.entry(name).or_insert(peer_id);
         }
-        let _ = fs::write(&self.save_path, file_string).await;
+        self.save();
+        Ok(count)
+    }
+
+    /// parse the saved file, older versions will migrate to current format.
+    fn from_file_string(content: &str) -> Result<SavedPeerList> {
+        let mut lines = content
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty())
+            .peekable();
+
+        let version = match lines.peek() {
+            Some(line) if line.starts_with(STORAGE_PEER_LIST_HEADER) => {
+                let version = line[STORAGE_PEER_LIST_HEADER.len()..]
+                    .trim()
+                    .parse()
+                    .map_err(|_e| new_io_error("peer list version failure."))?;
+                lines.next();
+                version
+            }
+            _ => 0, // legacy, only multiaddr lines without header.
+        };
+
+        let mut saved = SavedPeerList {
+            version,
+            ..Default::default()
+        };
+
+        match version {
+            0 => {
+                saved.allows = lines
+                    .filter_map(|l| Peer::from_multiaddr_string(l).ok())
+                    .collect();
+            }
+            // version 1 only has peer lines, version 2 add tag lines,
+            // version 3 add stable lines, version 4 add name lines.
+            1..=4 => {
+                for line in lines {
+                    if saved.parse_line(line).is_err() {
+                        warn!("CHAMOMILE: PEER LIST INVALID LINE: {}", line);
+                    }
+                }
+            }
+            _ => return Err(new_io_error("peer list version not supported.")),
+        }
+
+        Ok(saved)
     }
 
     pub fn load(
         peer_id: PeerId,
-        save_path: PathBuf,
+        save_path: Option<PathBuf>,
         mut allows: Vec<Peer>,
         blocks: (Vec<PeerId>, Vec<IpAddr>),
     ) -> Self {
         let default_socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0);
-        match std::fs::File::open(&save_path) {
-            Ok(file) => {
-                let addrs = std::io::BufReader::new(file).lines();
-                for addr in addrs {
-                    if let Ok(addr) = addr {
-                        if let Ok(p) = Peer::from_multiaddr_string(&addr) {
-                            let mut is_new = true;
-                            for ap in allows.iter() {
-                                if ap.socket == p.socket {
-                                    is_new = false;
-                                }
-                            }
-                            if is_new {
-                                allows.push(p);
-                            }
+        let pinned = allows.clone();
+        let mut need_migrate = false;
+        let mut tags = HashMap::new();
+        let mut stabled = HashMap::new();
+        let mut names = HashMap::new();
+
+        let saved_content = save_path
+            .as_ref()
+            .and_then(|path| Some((path, std::fs::read_to_string(path).ok()?)));
+        if let Some((save_path, content)) = saved_content {
+            match Self::from_file_string(&content) {
+                Ok(saved) => {
+                    need_migrate = saved.version != STORAGE_PEER_LIST_VERSION;
+                    for p in saved.allows {
+                        let is_new = if p.effective_socket() {
+                            !allows.iter().any(|ap| ap.socket == p.socket)
+                        } else {
+                            !allows.iter().any(|ap| ap.id == p.id)
+                        };
+                        if is_new {
+                            allows.push(p);
                         }
                     }
+                    tags = saved.tags;
+                    stabled = saved.stables.into_iter().map(|p| (p.id, p)).collect();
+                    names = saved.names;
                 }
-                PeerList {
-                    save_path,
-                    allows: allows,
-                    blocks: blocks,
-                    dhts: DoubleKadTree::new(peer_id, default_socket),
-                    stables: HashMap::new(),
+                Err(e) => {
+                    // the unknown file will be overwritten when save, copy it to .bak.
+                    let mut backup_path = save_path.clone();
+                    backup_path.set_extension("bak");
+                    warn!("CHAMOMILE: PEER LIST LOAD FAILURE: {:?}, backup it.", e);
+                    let _ = std::fs::copy(save_path, backup_path);
                 }
             }
-            Err(_) => PeerList {
-                save_path,
-                allows: allows,
-                blocks: blocks,
-                dhts: DoubleKadTree::new(peer_id, default_socket),
-                stables: HashMap::new(),
-            },
         }
+
+        let saver = save_path.clone().map(|path| {
+            let (saver, mut receiver) = watch::channel(String::new());
+            tokio::spawn(async move {
+                while receiver.changed().await.is_ok() {
+                    let content = receiver.borrow_and_update().clone();
+                    let _ = fs::write(&path, content).await;
+                }
+            });
+            saver
+        });
+
+        let peer_list = PeerList {
+            save_path,
+            saver,
+            allows,
+            blocks,
+            tags,
+            names,
+            dhts: DoubleKadTree::new(peer_id, default_socket),
+            stables: HashMap::new(),
+            stabled,
+            routes: Arc::new(Routes::new()),
+            pinned,
+            pinned_ids: HashSet::new(),
+        };
+
+        if need_migrate {
+            info!(
+                "Peer list migrate to version {}.",
+                STORAGE_PEER_LIST_VERSION
+            );
+            if let Some(path) = &peer_list.save_path {
+                let _ = std::fs::write(path, peer_list.to_file_string());
+            }
+        }
+
+        peer_list
+    }
+
+    pub fn routes(&self) -> Arc<Routes> {
+        self.routes.clone()
     }
 
     pub fn is_empty(&self) -> bool {
         self.stables.is_empty() && self.dhts.is_empty()
     }
 
+    /// connected peers number, include stables and DHTs.
+    pub fn len(&self) -> usize {
+        self.stables.len() + self.dhts.len()
+    }
+
     /// get all peers in the peer list.
     pub fn all(&self) -> HashMap<PeerId, &Sender<SessionMessage>> {
         let mut peers: HashMap<PeerId, &Sender<SessionMessage>> = HashMap::new();
@@ -96,6 +372,22 @@ impl PeerList {
         peers
     }
 
+    /// all connected peers and their session's sender.
+    pub fn all_peers(&self) -> Vec<(Peer, &Sender<SessionMessage>)> {
+        let mut peers: HashMap<PeerId, (Peer, &Sender<SessionMessage>)> = HashMap::new();
+        for key in self.dhts.keys().into_iter() {
+            if let Some((KadValue(sender, _, peer), true)) = self.dhts.search(&key) {
+                peers.insert(key, (*peer, sender));
+            }
+        }
+
+        for (p, v) in self.stables.iter() {
+            peers.insert(*p, ((v.0).2, &(v.0).0));
+        }
+
+        peers.into_values().collect()
+    }
+
     pub fn dht_keys(&self) -> Vec<PeerId> {
         self.dhts.keys()
     }
@@ -116,13 +408,73 @@ impl PeerList {
         self.stable_get(peer_id).or(self.dht_get(peer_id))
     }
 
-    /// search in stable list. result is stream channel sender.
-    pub fn get_stable_stream(&self, peer_id: &PeerId) -> Option<&Sender<EndpointMessage>> {
-        self.stable_get(peer_id)
-            .map(|(_ss, stream, is_it)| if is_it { Some(stream) } else { None })
-            .flatten()
+    /// the directly connected (DHT or stable) peer and its session.
+    pub fn connected(&self, peer_id: &PeerId) -> Option<(Peer, Sender<SessionMessage>)> {
+        if let Some((KadValue(sender, _, peer), is_direct)) = self.stables.get(peer_id) {
+            return if *is_direct {
+                Some((*peer, sender.clone()))
+            } else {
+                None
+            };
+        }
+        match self.dhts.search(peer_id) {
+            Some((KadValue(sender, _, peer), true)) => Some((*peer, sender.clone())),
+            _ => None,
+        }
+    }
+
+    /// the helpers of punching to the target, the relay first, then the closest
+    /// DHT peers of target, which are likely connected with it. only the capable
+    /// peers (advertised `PUNCH_FLAG`) can help.
+    #[cfg(feature = "hole-punching")]
+    pub fn punch_helpers(
+        &self,
+        target: &PeerId,
+        relay: &PeerId,
+        n: usize,
+        is_capable: impl Fn(&PeerId) -> bool,
+    ) -> Vec<Sender<SessionMessage>> {
+        let mut helpers: Vec<_> = if is_capable(relay) {
+            self.connected(relay).map(|(_, s)| s).into_iter().collect()
+        } else {
+            vec![]
+        };
+        for v in self.dhts.id_closest(target, n * 2 + 1) {
+            if helpers.len() >= n {
+                break;
+            }
+            if &v.2.id != relay && is_capable(&v.2.id) {
+                helpers.push(v.0.clone());
+            }
+        }
+        helpers
+    }
+
+    /// the n closest DHT peers of the key.
+    pub fn closest(&self, key: &PeerId, n: usize) -> Vec<(Peer, Sender<SessionMessage>)> {
+        self.dhts
+            .id_closest(key, n)
+            .into_iter()
+            .map(|v| (v.2, v.0.clone()))
+            .collect()
+    }
+
+    /// the relay path of redundant data to target, the closest DHT peer of it.
+    pub fn redundant_relay(&self, target: &PeerId) -> Option<Sender<SessionMessage>> {
+        self.dhts.id_closest(target, 1).first().map(|v| v.0.clone())
     }
 
+    /// the relayed stable peer is punched, it is direct now.
+    #[cfg(feature = "hole-punching")]
+    pub fn stable_direct(&mut self, peer_id: &PeerId, stream: Sender<EndpointMessage>) {
+        if let Some((v, is_direct)) = self.stables.get_mut(peer_id) {
+            v.1 = stream;
+            *is_direct = true;
+            self.routes.insert(*peer_id, v, true);
+        }
+    }
+
+    #[cfg(feature = "relay")]
     pub fn next_closest(&self, target: &PeerId, prev: &PeerId) -> Option<&Sender<SessionMessage>> {
         self.stables
             .get(target)
@@ -130,6 +482,7 @@ impl PeerList {
             .or(self.dhts.id_next_closest(target, prev).map(|v| &v.0))
     }
 
+    #[cfg(feature = "relay")]
     pub fn _ip_next_closest(
         &self,
         ip: &SocketAddr,
@@ -158,7 +511,19 @@ impl PeerList {
             .map(|v| (&(v.0).0, &(v.0).1, true))
     }
 
+    /// connected peer, and is stable, is directly.
+    pub fn peer_info(&self, peer_id: &PeerId) -> Option<(Peer, bool, bool)> {
+        if let Some((v, is_direct)) = self.stables.get(peer_id) {
+            return Some((v.2, true, *is_direct));
+        }
+        match self.dhts.search(peer_id) {
+            Some((v, true)) => Some((v.2, false, true)),
+            _ => None,
+        }
+    }
+
     /// if peer has connected in peer list.
+    #[cfg(feature = "dht")]
     pub fn contains(&self, peer_id: &PeerId) -> bool {
         self.stables.contains_key(peer_id) || self.dhts.contains(peer_id)
     }
@@ -167,11 +532,11 @@ impl PeerList {
     pub fn is_relay(&self, peer_id: &PeerId) -> Option<&Sender<SessionMessage>> {
         self.stables
             .get(peer_id)
-            .map(|v| if !v.1 { Some(&(v.0).0) } else { None })
-            .flatten()
+            .and_then(|v| if !v.1 { Some(&(v.0).0) } else { None })
     }
 
     /// get in DHT help
+    #[cfg(feature = "dht")]
     pub fn help_dht(&self, peer_id: &PeerId) -> Vec<Peer> {
         // TODO better closest peers
 
@@ -193,7 +558,34 @@ impl PeerList {
             }
         }
 
-        peers.values().map(|v| *v.clone()).collect()
+        peers.values().map(|v| **v).collect()
+    }
+
+    /// remove the closed sessions in DHT, return the removed peers.
+    pub fn clear_closed(&mut self) -> Vec<PeerId> {
+        let closed: Vec<PeerId> = self
+            .dhts
+            .keys()
+            .into_iter()
+            .filter(|k| match self.dhts.search(k) {
+                Some((v, true)) => v.0.is_closed(),
+                _ => false,
+            })
+            .collect();
+        for k in closed.iter() {
+            self.dhts.remove(k);
+        }
+
+        // remove the closed and evicted routes.
+        self.routes.retain(|k, (s, _, is_stable)| {
+            let is_in = if *is_stable {
+                self.stables.contains_key(k)
+            } else {
+                self.dhts.contains(k)
+            };
+            is_in && s.strong_count() > 0
+        });
+        closed
     }
 
     /// Step:
@@ -202,6 +594,9 @@ impl PeerList {
         &mut self,
         peer_id: &PeerId,
     ) -> Option<(Sender<SessionMessage>, Sender<EndpointMessage>, Peer)> {
+        if !self.stables.contains_key(peer_id) {
+            self.routes.remove(peer_id);
+        }
         self.dhts.remove(peer_id).map(|v| (v.0, v.1, v.2))
     }
 
@@ -217,14 +612,52 @@ impl PeerList {
 
         if let Some(i) = d {
             self.allows.remove(i);
-            self.save().await;
+            self.save();
+        }
+    }
+
+    /// had stable connected peers, which are not connected now.
+    pub fn stable_dropped(&self) -> Vec<Peer> {
+        self.stabled
+            .iter()
+            .filter(|(id, _)| !self.stables.contains_key(id))
+            .map(|(_, p)| *p)
+            .collect()
+    }
+
+    /// the known address of the peer, saved stable peers first, then allowlist.
+    pub fn known_addr(&self, peer_id: &PeerId) -> Option<Peer> {
+        self.stabled
+            .get(peer_id)
+            .filter(|p| p.effective_socket())
+            .or_else(|| {
+                self.allows
+                    .iter()
+                    .find(|p| p.effective_id() && p.effective_socket() && &p.id == peer_id)
+            })
+            .copied()
+    }
+
+    /// forget the had stable connected peer, not reconnect it when reboot.
+    pub async fn stable_forget(&mut self, peer_id: &PeerId) {
+        if self.stabled.remove(peer_id).is_some() {
+            self.save();
+        }
+    }
+
+    /// remember the stable connected peer, saved for reconnect when restart.
+    async fn stable_remember(&mut self, peer: Peer) {
+        if self.stabled.insert(peer.id, peer) != Some(peer) {
+            self.save();
         }
     }
 
     /// Peer leave Step:
     /// 1. remove from stables.
     pub fn stable_leave(&mut self, peer_id: &PeerId) {
-        self.stables.remove(peer_id);
+        if self.stables.remove(peer_id).is_some() {
+            self.routes.remove(peer_id);
+        }
     }
 
     /// Step:
@@ -234,13 +667,19 @@ impl PeerList {
         // 1. add to boostraps.
         if v.2.is_pub && !self.allows.contains(&v.2) {
             self.add_bootstrap(v.2);
-            self.save().await;
+            self.save();
         }
 
         // 2. add to kad.
-        if self.dhts.add(v) {
+        let peer_id = v.2.id;
+        if self.is_pinned(&v.2) {
+            self.pinned_ids.insert(peer_id);
+        }
+        self.routes.insert(peer_id, &v, false);
+        if self.dhts.add(v, &self.pinned_ids) {
             true
         } else {
+            self.routes.remove(&peer_id);
             false
         }
     }
@@ -248,7 +687,9 @@ impl PeerList {
     /// Peer stable connect ok Step:
     /// 1. add to bootstrap;
     /// 2. add to stables;
-    pub fn add_stable(&mut self, peer_id: PeerId, v: KadValue, is_direct: bool) {
+    pub async fn add_stable(&mut self, peer_id: PeerId, v: KadValue, is_direct: bool) {
+        self.routes.insert(peer_id, &v, true);
+        self.stable_remember(v.2).await;
         match self.stables.get_mut(&peer_id) {
             Some((KadValue(s, ss, p), direct)) => {
                 let _ = s.try_send(SessionMessage::Close);
@@ -265,21 +706,26 @@ impl PeerList {
         }
     }
 
-    pub fn stable_to_dht(&mut self, peer_id: &PeerId) -> Result<()> {
+    pub async fn stable_to_dht(&mut self, peer_id: &PeerId) -> Result<()> {
         self.remove_allow_peer(peer_id);
+        self.stable_forget(peer_id).await;
         if let Some((v, is_direct)) = self.stables.remove(peer_id) {
             if is_direct {
-                if self.dhts.add(v) {
+                self.routes.insert(*peer_id, &v, false);
+                if self.dhts.add(v, &self.pinned_ids) {
                     return Ok(());
                 }
             }
         }
+        self.routes.remove(peer_id);
         Err(new_io_error("stable is closed"))
     }
 
-    pub fn dht_to_stable(&mut self, peer_id: &PeerId) -> Result<()> {
+    pub async fn dht_to_stable(&mut self, peer_id: &PeerId) -> Result<()> {
         if let Some(v) = self.dhts.remove(peer_id) {
             self.add_allow_peer(*peer_id);
+            self.routes.insert(*peer_id, &v, true);
+            self.stable_remember(v.2).await;
             self.stables.insert(*peer_id, (v, true));
             Ok(())
         } else {
@@ -290,10 +736,70 @@ impl PeerList {
 
 // Block and allow list.
 impl PeerList {
+    /// the peer is a configured allowlist peer or seed.
+    pub fn is_pinned(&self, peer: &Peer) -> bool {
+        self.pinned_ids.contains(&peer.id)
+            || self.pinned.iter().any(|p| {
+                (p.effective_id() && p.id == peer.id)
+                    || (p.effective_socket() && p.socket == peer.socket)
+            })
+    }
+
+    /// the ips of configured pinned peers (allowlist and seeds).
+    pub fn pinned_ips(&self) -> Vec<IpAddr> {
+        self.pinned
+            .iter()
+            .filter(|p| p.effective_socket())
+            .map(|p| p.socket.ip())
+            .collect()
+    }
+
+    /// pin the resolved seed.
+    pub fn pin(&mut self, peer: Peer) {
+        if !self.pinned.iter().any(|p| p.socket == peer.socket) {
+            self.pinned.push(peer);
+        }
+    }
+
+    /// the pinned peers which are not connected now.
+    pub fn pinned_offline(&self) -> Vec<Peer> {
+        let connected = self.all_peers();
+        self.pinned
+            .iter()
+            .filter(|p| p.effective_socket())
+            .filter(|p| {
+                !connected
+                    .iter()
+                    .any(|(c, _)| (p.effective_id() && p.id == c.id) || p.socket == c.socket)
+            })
+            .copied()
+            .collect()
+    }
+
+    /// the bootstrap peers and pinned seeds, and is connected.
+    pub fn seeds(&self) -> Vec<(Peer, bool)> {
+        let connected = self.all_peers();
+        let mut seeds: Vec<Peer> = self.bootstrap().into_iter().copied().collect();
+        for p in self.pinned.iter().filter(|p| p.effective_socket()) {
+            if !seeds.iter().any(|s| s.socket == p.socket) {
+                seeds.push(*p);
+            }
+        }
+        seeds
+            .into_iter()
+            .map(|p| {
+                let is_connected = connected
+                    .iter()
+                    .any(|(c, _)| (p.effective_id() && p.id == c.id) || p.socket == c.socket);
+                (p, is_connected)
+            })
+            .collect()
+    }
+
     pub fn bootstrap(&self) -> Vec<&Peer> {
         self.allows
             .iter()
-            .filter_map(|p| if p.effective_socket() { Some(p) } else { None })
+            .filter(|p| p.effective_socket())
             .collect()
     }
 
@@ -322,13 +828,14 @@ impl PeerList {
     }
 
     pub fn remove_allow_peer(&mut self, peer: &PeerId) -> Option<Peer> {
-        let pos = match self.allows.iter().position(|x| &x.id == peer) {
-            Some(x) => x,
-            None => return None,
-        };
+        let pos = self.allows.iter().position(|x| &x.id == peer)?;
         Some(self.allows.remove(pos))
     }
 
+    pub fn is_allow_peer(&self, peer: &PeerId) -> bool {
+        self.allows.iter().any(|ap| &ap.id == peer)
+    }
+
     pub fn is_block_peer(&self, peer: &PeerId) -> bool {
         self.blocks.0.contains(peer)
     }
@@ -350,18 +857,89 @@ impl PeerList {
     }
 
     pub fn _remove_block_peer(&mut self, peer: &PeerId) -> Option<PeerId> {
-        let pos = match self.blocks.0.iter().position(|x| *x == *peer) {
-            Some(x) => x,
-            None => return None,
-        };
+        let pos = self.blocks.0.iter().position(|x| *x == *peer)?;
         Some(self.blocks.0.remove(pos))
     }
 
     pub fn _remove_block_addr(&mut self, addr: &SocketAddr) -> Option<IpAddr> {
-        let pos = match self.blocks.1.iter().position(|x| *x == addr.ip()) {
-            Some(x) => x,
-            None => return None,
-        };
+        let pos = self.blocks.1.iter().position(|x| *x == addr.ip())?;
         Some(self.blocks.1.remove(pos))
     }
 }
+
+// Peer's tags.
+impl PeerList {
+    /// add tag to peer, return false if the tag is invalid.
+    pub async fn add_tag(&mut self, peer_id: PeerId, tag: String) -> bool {
+        if tag.is_empty() || tag.len() > MAX_PEER_TAG_LENGTH || tag.contains('\n') {
+            return false;
+        }
+
+        let tags = self.tags.entry(peer_id).or_default();
+        if !tags.contains(&tag) {
+            tags.push(tag);
+            self.save();
+        }
+        true
+    }
+
+    pub async fn remove_tag(&mut self, peer_id: &PeerId, tag: &str) {
+        if let Some(tags) = self.tags.get_mut(peer_id) {
+            if let Some(pos) = tags.iter().position(|t| t == tag) {
+                tags.remove(pos);
+                if tags.is_empty() {
+                    self.tags.remove(peer_id);
+                }
+                self.save();
+            }
+        }
+    }
+
+    pub fn tags(&self) -> Vec<(PeerId, Vec<String>)> {
+        self.tags.iter().map(|(p, t)| (*p, t.clone())).collect()
+    }
+}
+
+// Peer's petnames.
+impl PeerList {
+    /// add petname to peer, return false if the name is invalid.
+    pub async fn add_name(&mut self, name: String, peer_id: PeerId) -> bool {
+        if name.is_empty()
+            || name.len() > PEER_NAME_MAX
+            || name.contains(char::is_whitespace)
+            || peer_id.petname().is_some()
+        {
+            return false;
+        }
+
+        if self.names.insert(name, peer_id) != Some(peer_id) {
+            self.save();
+        }
+        true
+    }
+
+    pub async fn remove_name(&mut self, name: &str) {
+        if self.names.remove(name).is_some() {
+            self.save();
+        }
+    }
+
+    pub fn names(&self) -> Vec<(String, PeerId)> {
+        self.names.iter().map(|(n, p)| (n.clone(), *p)).collect()
+    }
+
+    /// the named peer's id if it is petname's placeholder, or itself.
+    /// None if the petname is not registered.
+    pub fn resolve(&self, peer_id: PeerId) -> Option<PeerId> {
+        match peer_id.petname() {
+            Some(name) => match self.names.get(&name) {
+                Some(id) => Some(*id),
+                None => {
+                    warn!("CHAMOMILE: PEER NAME {} IS NOT FOUND.", name);
+                    None
+                }
+            },
+            None => Some(peer_id),
+        }
+    }
+}
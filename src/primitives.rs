@@ -1,7 +1,52 @@
 pub const PEER_ID_LENGTH: usize = 42;
 
-pub const STORAGE_NAME: &'static str = "p2p";
+pub const STORAGE_NAME: &str = "p2p";
 
-pub const STORAGE_KEY_KEY: &'static str = "key";
+pub const STORAGE_KEY_KEY: &str = "key";
 
-pub const STORAGE_PEER_LIST_KEY: &'static str = "peer_list";
+pub const STORAGE_PEER_LIST_KEY: &str = "peer_list";
+
+pub const STORAGE_OUTBOX_KEY: &str = "outbox";
+
+pub const STORAGE_BUFFER_KEY: &str = "buffer";
+
+#[cfg(feature = "capture")]
+pub const STORAGE_CAPTURE_KEY: &str = "capture.jsonl";
+
+#[cfg(feature = "capture")]
+pub const STORAGE_RECORD_KEY: &str = "record.jsonl";
+
+pub const STORAGE_AUDIT_KEY: &str = "audit.log";
+
+/// first line of the saved peer list, followed by the format version.
+pub const STORAGE_PEER_LIST_HEADER: &str = "chamomile-peer-list";
+
+/// current saved peer list format version. 0 is the legacy headerless format.
+pub const STORAGE_PEER_LIST_VERSION: u32 = 4;
+
+/// in-flight dial is expired after it, will dial again.
+pub const DIAL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// the expired deadline is kept after it, for the session to drop the waiting data.
+pub const DEADLINE_KEEP: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// min keepalive interval of session, the configured less than it will use it.
+pub const MIN_KEEPALIVE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// session's keepalive interval in low power profile.
+pub const LOW_POWER_KEEPALIVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// the maintenance intervals are multiplied by it in low power profile.
+pub const LOW_POWER_SCALE: u32 = 6;
+
+/// max bytes of a peer's tag.
+pub const MAX_PEER_TAG_LENGTH: usize = 64;
+
+/// max bytes of the application heartbeat payload, piggybacked on keepalive.
+pub const MAX_HEARTBEAT_LENGTH: usize = 256;
+
+/// max relays of a relay data, more hops are dropped.
+pub const MAX_RELAY_HOPS: usize = 8;
+
+/// max queued data messages to a known but not connected peer, when dial on demand.
+pub const MAX_DIAL_DATA: usize = 64;
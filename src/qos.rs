@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::{sync::RwLock, time::Instant};
+
+use chamomile_types::{types::QosClass, PeerId};
+
+/// max waiting time of every check, the spare tokens may be given back by others.
+const QOS_TICK: Duration = Duration::from_millis(5);
+
+/// burst time of the buckets, tokens more than it are overflowed to spare.
+const QOS_BURST: f64 = 0.1;
+
+/// the uplink share weights of interactive, bulk and background (total 10).
+const QOS_WEIGHTS: [f64; 3] = [6.0, 3.0, 1.0];
+
+fn index(class: QosClass) -> usize {
+    match class {
+        QosClass::Interactive => 0,
+        QosClass::Bulk => 1,
+        QosClass::Background => 2,
+    }
+}
+
+struct Buckets {
+    /// every class's tokens (bytes), negative is the debt of large frame.
+    tokens: [f64; 3],
+    /// overflowed tokens of idle classes, any class can borrow it.
+    spare: f64,
+    last: Instant,
+}
+
+/// Token-bucket scheduler sharing the uplink by stable sessions' classes,
+/// every class has its guaranteed share, and the unused share is borrowed
+/// by others, so background sessions cannot starve interactive sessions.
+pub(crate) struct Qos {
+    /// uplink bytes per second.
+    rate: f64,
+    buckets: Mutex<Buckets>,
+    /// assigned peers' class, default is `Bulk`.
+    classes: RwLock<HashMap<PeerId, QosClass>>,
+}
+
+impl Qos {
+    pub fn new(uplink: u64) -> Self {
+        let rate = uplink as f64;
+        let mut tokens = [0f64; 3];
+        for (i, t) in tokens.iter_mut().enumerate() {
+            *t = rate * QOS_WEIGHTS[i] / 10.0 * QOS_BURST;
+        }
+        Qos {
+            rate,
+            buckets: Mutex::new(Buckets {
+                tokens,
+                spare: 0.0,
+                last: Instant::now(),
+            }),
+            classes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub async fn set_class(&self, peer_id: PeerId, class: QosClass) {
+        if class == QosClass::Bulk {
+            self.classes.write().await.remove(&peer_id);
+        } else {
+            self.classes.write().await.insert(peer_id, class);
+        }
+    }
+
+    pub async fn class(&self, peer_id: &PeerId) -> QosClass {
+        self.classes
+            .read()
+            .await
+            .get(peer_id)
+            .copied()
+            .unwrap_or(QosClass::Bulk)
+    }
+
+    /// waiting until the class has tokens to send the bytes.
+    pub async fn acquire(&self, class: QosClass, bytes: usize) {
+        let i = index(class);
+        loop {
+            let wait = {
+                let mut b = self.buckets.lock().unwrap();
+                let now = Instant::now();
+                let elapsed = now.duration_since(b.last).as_secs_f64();
+                b.last = now;
+                for (n, weight) in QOS_WEIGHTS.iter().enumerate() {
+                    let class_rate = self.rate * weight / 10.0;
+                    let burst = class_rate * QOS_BURST;
+                    b.tokens[n] += class_rate * elapsed;
+                    if b.tokens[n] > burst {
+                        b.spare += b.tokens[n] - burst;
+                        b.tokens[n] = burst;
+                    }
+                }
+                b.spare = b.spare.min(self.rate * QOS_BURST);
+
+                let bytes = bytes as f64;
+                if b.tokens[i] > 0.0 {
+                    b.tokens[i] -= bytes;
+                    return;
+                }
+                if b.spare >= bytes {
+                    b.spare -= bytes;
+                    return;
+                }
+                let class_rate = self.rate * QOS_WEIGHTS[i] / 10.0;
+                Duration::from_secs_f64((-b.tokens[i] + 1.0) / class_rate).min(QOS_TICK)
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::{
+    fs::OpenOptions,
+    io::AsyncWriteExt,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use chamomile_types::{types::new_io_error, Peer, PeerId};
+
+use crate::global::Global;
+use crate::keys::Cipher;
+use crate::session::{new_session_channel, ConnectType, Session};
+use crate::transports::{new_endpoint_channel, EndpointMessage};
+
+/// max buffered record events, when full, drop the new events.
+const RECORD_CHANNEL_SIZE: usize = 4096;
+
+/// the session's timer.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Timer {
+    HeartBeat,
+    Robust,
+    Flush,
+    Bulk,
+    Cover,
+}
+
+impl Timer {
+    fn to_byte(self) -> u8 {
+        match self {
+            Timer::HeartBeat => 0,
+            Timer::Robust => 1,
+            Timer::Flush => 2,
+            Timer::Bulk => 3,
+            Timer::Cover => 4,
+        }
+    }
+
+    fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0 => Some(Timer::HeartBeat),
+            1 => Some(Timer::Robust),
+            2 => Some(Timer::Flush),
+            3 => Some(Timer::Bulk),
+            4 => Some(Timer::Cover),
+            _ => None,
+        }
+    }
+}
+
+/// recorded event of a session, in the order the session handled them.
+pub(crate) enum Event {
+    /// the session's state when recording start, is_stable, is_recv_data,
+    /// version and the remote peer.
+    Start(bool, bool, u8, Peer),
+    /// inbound decrypted core frame.
+    In(Vec<u8>),
+    /// inbound endpoint message (not data).
+    Endpoint(Vec<u8>),
+    /// outbound core frame (padded, before encrypted).
+    Out(Vec<u8>),
+    /// timer event.
+    Timer(Timer),
+    /// outside's message (not replayable, only the position).
+    Outside,
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::Start(..) => "start",
+            Event::In(_) => "in",
+            Event::Endpoint(_) => "endpoint",
+            Event::Out(_) => "out",
+            Event::Timer(_) => "timer",
+            Event::Outside => "outside",
+        }
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        match self {
+            Event::Start(is_stable, is_recv_data, version, peer) => {
+                let mut bytes = vec![*is_stable as u8, *is_recv_data as u8, *version];
+                bytes.extend(peer.to_bytes());
+                bytes
+            }
+            Event::In(b) | Event::Endpoint(b) | Event::Out(b) => b.clone(),
+            Event::Timer(t) => vec![t.to_byte()],
+            Event::Outside => vec![],
+        }
+    }
+
+    fn from_parts(name: &str, bytes: Vec<u8>) -> Option<Self> {
+        match name {
+            "start" if bytes.len() > 3 => Some(Event::Start(
+                bytes[0] == 1,
+                bytes[1] == 1,
+                bytes[2],
+                Peer::from_bytes(&bytes[3..]).ok()?,
+            )),
+            "in" => Some(Event::In(bytes)),
+            "endpoint" => Some(Event::Endpoint(bytes)),
+            "out" => Some(Event::Out(bytes)),
+            "timer" => Timer::from_byte(*bytes.first()?).map(Event::Timer),
+            "outside" => Some(Event::Outside),
+            _ => None,
+        }
+    }
+}
+
+/// Session recording for debugging (`capture` feature), record the full ordered
+/// event stream (inbound & outbound frames, timers) of the selected sessions to
+/// `db_dir/record.jsonl`, every line is a JSON object:
+/// `{"ts":<unix ms>,"peer":"<hex>","event":"<name>","bytes":"<hex>"}`.
+/// it can be replayed by `SendMessage::Replay`, the replay is deterministic only
+/// when recording is started before the session connected.
+pub(crate) struct Recorder {
+    /// recording peers, and is the start event written.
+    peers: RwLock<HashMap<PeerId, bool>>,
+    /// None is ephemeral, nothing will be written.
+    sender: Option<Sender<String>>,
+}
+
+impl Recorder {
+    pub fn start(path: Option<PathBuf>) -> Self {
+        let sender = path.map(|path| {
+            let (sender, receiver) = channel(RECORD_CHANNEL_SIZE);
+            tokio::spawn(write_loop(path, receiver));
+            sender
+        });
+        Recorder {
+            peers: RwLock::new(HashMap::new()),
+            sender,
+        }
+    }
+
+    /// start or stop record the peer's session.
+    pub fn set(&self, peer_id: PeerId, enable: bool) {
+        if self.sender.is_none() {
+            warn!("CHAMOMILE: RECORD IS DISABLED IN EPHEMERAL MODE.");
+            return;
+        }
+        let mut peers = self.peers.write().unwrap();
+        if enable {
+            peers.insert(peer_id, false);
+        } else {
+            peers.remove(&peer_id);
+        }
+    }
+
+    pub fn is_record(&self, peer_id: &PeerId) -> bool {
+        let peers = self.peers.read().unwrap();
+        !peers.is_empty() && peers.contains_key(peer_id)
+    }
+
+    /// need write the start event before others.
+    pub fn is_started(&self, peer_id: &PeerId) -> bool {
+        self.peers.read().unwrap().get(peer_id) == Some(&true)
+    }
+
+    /// add an event, not waiting it written.
+    pub fn record(&self, peer_id: &PeerId, event: Event) {
+        if let Event::Start(..) = event {
+            if let Some(started) = self.peers.write().unwrap().get_mut(peer_id) {
+                *started = true;
+            }
+        }
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let line = format!(
+            "{{\"ts\":{},\"peer\":\"{}\",\"event\":\"{}\",\"bytes\":\"{}\"}}\n",
+            ts,
+            peer_id.to_hex(),
+            event.name(),
+            to_hex(&event.bytes())
+        );
+        if let Some(Err(_)) = self.sender.as_ref().map(|s| s.try_send(line)) {
+            warn!("CHAMOMILE: RECORD IS FULL, DROP EVENT.");
+        }
+    }
+}
+
+async fn write_loop(path: PathBuf, mut receiver: Receiver<String>) {
+    while let Some(line) = receiver.recv().await {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await;
+        match file {
+            Ok(mut file) => {
+                let _ = file.write_all(line.as_bytes()).await;
+            }
+            Err(e) => warn!("CHAMOMILE: RECORD WRITE FAILURE: {:?}", e),
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    hex.extend(bytes.iter().map(|b| format!("{:02x}", b)));
+    hex
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// the string field's value of the recorded line.
+fn field<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let key = format!("\"{}\":\"", name);
+    let start = line.find(&key)? + key.len();
+    let end = start + line[start..].find('"')?;
+    Some(&line[start..end])
+}
+
+/// load the peer's recorded events, from the last start event.
+fn load(content: &str, peer_id: &PeerId) -> Vec<Event> {
+    let hex = peer_id.to_hex();
+    let mut events = vec![];
+    for line in content.lines() {
+        if field(line, "peer") != Some(hex.as_str()) {
+            continue;
+        }
+        let event = field(line, "event")
+            .zip(field(line, "bytes").and_then(from_hex))
+            .and_then(|(name, bytes)| Event::from_parts(name, bytes));
+        match event {
+            Some(event @ Event::Start(..)) => events = vec![event],
+            Some(event) if !events.is_empty() => events.push(event),
+            _ => {}
+        }
+    }
+    events
+}
+
+/// Replay the recorded session of the peer, feed the inbound frames and timers
+/// through a new session state machine in order, and compare the outbound frames
+/// with the recorded. the new session is not in the routes, but its messages are
+/// sent to outside, so replay it on a node which not connected to the peer.
+pub(crate) async fn replay(
+    global: Arc<Global>,
+    path: &Path,
+    peer_id: PeerId,
+) -> std::io::Result<()> {
+    let content = tokio::fs::read_to_string(path).await?;
+    let mut events = load(&content, &peer_id).into_iter();
+    let (is_stable, is_recv_data, version, remote_peer) = match events.next() {
+        Some(Event::Start(s, r, v, p)) => (s, r, v, p),
+        _ => return Err(new_io_error("no recorded session of the peer.")),
+    };
+
+    // a completed session key with self, the frames are encrypted by it.
+    let (_, remote_pk) = global.generate_remote();
+    let (session_key, _) = global
+        .complete_remote(
+            &remote_pk.0,
+            remote_pk.2,
+            &remote_pk.5,
+            Cipher::negotiate(version),
+        )
+        .ok_or_else(|| new_io_error("replay session key failure."))?;
+    let (endpoint_sender, mut endpoint_receiver) = new_endpoint_channel();
+    let (_stream_sender, stream_receiver) = new_endpoint_channel();
+    let (session_sender, _session_receiver) = new_session_channel();
+    let mut session = Session::new(
+        remote_peer,
+        session_sender,
+        stream_receiver,
+        ConnectType::Direct(endpoint_sender),
+        session_key,
+        global,
+        is_recv_data,
+    );
+    session.is_stable = is_stable;
+    session.version = version;
+
+    let (mut total, mut diverged) = (0usize, 0usize);
+    for (seq, event) in events.enumerate() {
+        total += 1;
+        match event {
+            Event::Out(expected) => {
+                // the frames sent by the replayed session until now.
+                let mut actual = None;
+                while let Ok(msg) = endpoint_receiver.try_recv() {
+                    if let EndpointMessage::Data(e_data) = msg {
+                        actual = session.session_key.decrypt(&e_data).ok();
+                        break;
+                    }
+                }
+                if actual.as_ref() != Some(&expected) {
+                    if diverged == 0 {
+                        warn!("CHAMOMILE: REPLAY DIVERGED AT EVENT {}.", seq);
+                    }
+                    diverged += 1;
+                }
+            }
+            event => {
+                if let Err(e) = session.replay_event(event).await {
+                    info!("Replay session closed at event {}: {:?}.", seq, e);
+                    break;
+                }
+            }
+        }
+    }
+    info!(
+        "Replay {}: {} events, {} outbound frames diverged.",
+        peer_id.short_show(),
+        total,
+        diverged
+    );
+    Ok(())
+}
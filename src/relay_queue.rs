@@ -0,0 +1,142 @@
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{error::TrySendError, Sender};
+
+use chamomile_types::PeerId;
+
+use crate::config::BufferOverflow;
+use crate::session::SessionMessage;
+
+/// the circuit, relay data's from and to.
+type CircuitId = (PeerId, PeerId);
+
+/// the backlog of a circuit, waiting the busy downstream session.
+struct Circuit {
+    /// the downstream session, updated when the next hop changed.
+    sender: Sender<SessionMessage>,
+    /// queued relay data, the passed relays and data.
+    queue: VecDeque<(Vec<PeerId>, Bytes)>,
+    /// queued bytes.
+    bytes: usize,
+}
+
+/// Relay forwarding queues. when the downstream session is busy (channel is
+/// full), the relay data is queued per circuit with bytes limit, and sent in
+/// order by a task, so a slow downstream will not block the upstream session,
+/// and will not buffer without limit.
+pub(crate) struct RelayQueues {
+    /// max queued bytes of every circuit, 0 is not queue (drop when busy).
+    max: usize,
+    /// when circuit's queue is full, drop the new data or the oldest.
+    overflow: BufferOverflow,
+    circuits: Arc<Mutex<HashMap<CircuitId, Circuit>>>,
+}
+
+impl RelayQueues {
+    pub fn new(max: usize, overflow: BufferOverflow) -> Self {
+        RelayQueues {
+            max,
+            overflow,
+            circuits: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// forward the relay data to the downstream session, queue it if the session
+    /// is busy. return the dropped bytes (the new or the oldest data).
+    pub fn forward(
+        &self,
+        sender: &Sender<SessionMessage>,
+        from: PeerId,
+        to: PeerId,
+        relays: Vec<PeerId>,
+        data: Bytes,
+    ) -> usize {
+        let id = (from, to);
+        let mut circuits = self.circuits.lock().unwrap();
+        let is_new = !circuits.contains_key(&id);
+
+        // no backlog, send it directly if the downstream has capacity.
+        if is_new {
+            match sender.try_reserve() {
+                Ok(permit) => {
+                    permit.send(SessionMessage::RelayData(from, to, relays, data));
+                    return 0;
+                }
+                Err(TrySendError::Closed(_)) => return data.len(),
+                Err(TrySendError::Full(_)) => {}
+            }
+        }
+
+        let len = data.len();
+        if len > self.max {
+            return len;
+        }
+
+        let circuit = circuits.entry(id).or_insert_with(|| Circuit {
+            sender: sender.clone(),
+            queue: VecDeque::new(),
+            bytes: 0,
+        });
+        circuit.sender = sender.clone();
+
+        let mut dropped = 0;
+        if circuit.bytes + len > self.max {
+            match self.overflow {
+                BufferOverflow::RejectNew => dropped = len,
+                BufferOverflow::DropOldest => {
+                    while circuit.bytes + len > self.max {
+                        match circuit.queue.pop_front() {
+                            Some((_, old)) => {
+                                circuit.bytes -= old.len();
+                                dropped += old.len();
+                            }
+                            None => break,
+                        }
+                    }
+                }
+            }
+        }
+        if circuit.bytes + len <= self.max {
+            circuit.bytes += len;
+            circuit.queue.push_back((relays, data));
+        }
+
+        if is_new {
+            debug!("Relay downstream is busy, queue the circuit.");
+            tokio::spawn(drain(self.circuits.clone(), id));
+        }
+
+        dropped
+    }
+}
+
+/// send the circuit's queued data in order, until the queue is empty.
+async fn drain(circuits: Arc<Mutex<HashMap<CircuitId, Circuit>>>, id: CircuitId) {
+    loop {
+        let (sender, relays, data) = {
+            let mut circuits = circuits.lock().unwrap();
+            let circuit = match circuits.get_mut(&id) {
+                Some(circuit) => circuit,
+                None => return,
+            };
+            match circuit.queue.pop_front() {
+                Some((relays, data)) => {
+                    circuit.bytes -= data.len();
+                    (circuit.sender.clone(), relays, data)
+                }
+                None => {
+                    circuits.remove(&id);
+                    return;
+                }
+            }
+        };
+
+        let msg = SessionMessage::RelayData(id.0, id.1, relays, data);
+        if sender.send(msg).await.is_err() {
+            debug!("Relay downstream is closed, drop the circuit's queue.");
+            circuits.lock().unwrap().remove(&id);
+            return;
+        }
+    }
+}
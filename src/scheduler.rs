@@ -0,0 +1,90 @@
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+use tokio::time::{sleep_until, Instant};
+
+/// wall clock is ahead of monotonic clock more than it, the device had suspended.
+const SUSPEND_GAP: Duration = Duration::from_secs(30);
+
+/// server's periodic maintenance jobs.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Job {
+    /// check network is lost.
+    Check,
+    /// clear the expired buffers.
+    Clear,
+    /// refresh DHT buckets, remove the closed sessions.
+    Refresh,
+}
+
+/// run jobs by their intervals, every job has own timer,
+/// so busy transport messages will not delay them.
+pub(crate) struct Scheduler {
+    /// job, interval and last running time.
+    jobs: Vec<(Job, Duration, Instant)>,
+    /// the jobs run in the same wakeup, waiting to return.
+    batch: VecDeque<Job>,
+    /// the monotonic & wall clock of last wakeup, to check suspend.
+    clock: (Instant, SystemTime),
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler {
+            jobs: vec![],
+            batch: VecDeque::new(),
+            clock: (Instant::now(), SystemTime::now()),
+        }
+    }
+
+    /// add a periodic job, zero interval will not run it.
+    pub fn add(&mut self, job: Job, interval: Duration) {
+        if interval > Duration::ZERO {
+            self.jobs.push((job, interval, Instant::now()));
+        }
+    }
+
+    /// waiting the next job, the intervals are multiplied by scale, and if `is_batch`,
+    /// the jobs due in half of their interval will run in the same wakeup.
+    /// it is safe to cancel when waiting.
+    pub async fn next(&mut self, scale: u32, is_batch: bool) -> Job {
+        loop {
+            if let Some(job) = self.batch.pop_front() {
+                return job;
+            }
+
+            let next = self
+                .jobs
+                .iter()
+                .map(|(_, interval, last)| *last + *interval * scale)
+                .min();
+            match next {
+                Some(at) => sleep_until(at).await,
+                None => std::future::pending().await,
+            }
+
+            let now = Instant::now();
+            let wall = SystemTime::now()
+                .duration_since(self.clock.1)
+                .unwrap_or(Duration::ZERO);
+            // the timers are stale after suspend, run all jobs once and restart them.
+            let is_resumed = wall > now.duration_since(self.clock.0) + SUSPEND_GAP;
+            if is_resumed {
+                debug!("Scheduler resumed from suspend, run all jobs.");
+            }
+            self.clock = (now, SystemTime::now());
+
+            for (job, interval, last) in self.jobs.iter_mut() {
+                let interval = *interval * scale;
+                let slack = if is_batch {
+                    interval / 2
+                } else {
+                    Duration::ZERO
+                };
+                if is_resumed || *last + interval <= now + slack {
+                    *last = now;
+                    self.batch.push_back(*job);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use chamomile_types::{message::SeedInfo, Peer};
+
+/// continuous dial failures of a dead seed, it will be tried last.
+const SEED_DEAD_FAILURES: u32 = 3;
+
+/// the seed's dial results.
+#[derive(Default)]
+struct Health {
+    /// continuous failures, reset when connected.
+    failures: u32,
+    /// the last connected time.
+    last_seen: Option<Instant>,
+}
+
+impl Health {
+    fn is_dead(&self) -> bool {
+        self.failures >= SEED_DEAD_FAILURES
+    }
+}
+
+/// Health of the configured seeds (allowlist & resolved multiaddr seeds), the
+/// bootstrap tries the healthy seeds first (rotated every round), and the dead last.
+pub(crate) struct SeedHealth {
+    /// seed's socket => health, only the seeds are tracked.
+    healths: Mutex<HashMap<SocketAddr, Health>>,
+    /// bootstrap rounds, the rotation offset.
+    round: AtomicUsize,
+}
+
+impl SeedHealth {
+    pub fn new(seeds: impl Iterator<Item = SocketAddr>) -> Self {
+        SeedHealth {
+            healths: Mutex::new(seeds.map(|s| (s, Health::default())).collect()),
+            round: AtomicUsize::new(0),
+        }
+    }
+
+    /// track the resolved seed.
+    pub fn add(&self, socket: SocketAddr) {
+        self.healths.lock().unwrap().entry(socket).or_default();
+    }
+
+    /// the seed is connected, not a seed is ignored.
+    pub fn success(&self, socket: &SocketAddr) {
+        if let Some(h) = self.healths.lock().unwrap().get_mut(socket) {
+            h.failures = 0;
+            h.last_seen = Some(Instant::now());
+        }
+    }
+
+    /// the dial to seed failure, not a seed is ignored.
+    pub fn failure(&self, socket: &SocketAddr) {
+        if let Some(h) = self.healths.lock().unwrap().get_mut(socket) {
+            h.failures = h.failures.saturating_add(1);
+            if h.failures == SEED_DEAD_FAILURES {
+                warn!("CHAMOMILE: SEED {} IS DEAD, DEMOTE IT.", socket);
+            }
+        }
+    }
+
+    /// start a new bootstrap round, rotate the peers by the round, and move the
+    /// dead seeds to last (fewer failures first).
+    pub fn order(&self, mut peers: Vec<Peer>) -> Vec<Peer> {
+        let round = self.round.fetch_add(1, Ordering::Relaxed);
+        if !peers.is_empty() {
+            let n = round % peers.len();
+            peers.rotate_left(n);
+        }
+        let healths = self.healths.lock().unwrap();
+        peers.sort_by_key(|p| match healths.get(&p.socket) {
+            Some(h) if h.is_dead() => h.failures,
+            _ => 0,
+        });
+        peers
+    }
+
+    /// rotate the not resolved seeds by current round.
+    pub fn rotate<T>(&self, items: &mut [T]) {
+        if !items.is_empty() {
+            let n = self.round.load(Ordering::Relaxed) % items.len();
+            items.rotate_left(n);
+        }
+    }
+
+    /// the peers' health info.
+    pub fn infos(&self, peers: Vec<(Peer, bool)>) -> Vec<SeedInfo> {
+        let healths = self.healths.lock().unwrap();
+        peers
+            .into_iter()
+            .map(|(peer, is_connected)| {
+                let (failures, last_seen, is_dead) = match healths.get(&peer.socket) {
+                    Some(h) => (h.failures, h.last_seen.map(|t| t.elapsed()), h.is_dead()),
+                    None => (0, None, false),
+                };
+                SeedInfo {
+                    peer,
+                    is_connected,
+                    failures,
+                    last_seen,
+                    is_dead,
+                }
+            })
+            .collect()
+    }
+}
@@ -1,36 +1,104 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use bytes::Bytes;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::{
     fs,
     io::Result,
     select,
     sync::mpsc::{Receiver, Sender},
-    sync::RwLock,
+    sync::{oneshot, RwLock, Semaphore},
 };
 
 use chamomile_types::{
-    delivery_split,
-    message::{DeliveryType, ReceiveMessage, SendMessage, StateRequest, StateResponse},
-    types::{Broadcast, PeerId, TransportType},
+    message::{
+        DeliveryFailure, DeliveryType, DialReason, Misbehavior, PeerInfo, ReceiveMessage,
+        RelayInfo, SendMessage, StateRequest, StateResponse,
+    },
+    types::{
+        new_io_error, BlockTarget, Broadcast, DataPath, PeerId, PeerPolicy, PowerProfile,
+        TransportType,
+    },
     Peer,
 };
 
+use crate::audit::Audit;
+use crate::ban::{self, Bans, SharedBans};
 use crate::buffer::Buffer;
-use crate::config::Config;
+#[cfg(feature = "dht")]
+use crate::config::SelectPurpose;
+use crate::config::{Config, DeliveryPolicy};
+use crate::dialer::{dialer_loop, DialKind, Dialer};
+use crate::echo::Echoes;
 use crate::global::Global;
-use crate::hole_punching::{nat, DHT};
+use crate::group::{Groups, GROUP_NAME_MAX};
+use crate::hole_punching::nat;
+#[cfg(feature = "hole-punching")]
+use crate::hole_punching::Punches;
+#[cfg(feature = "dht")]
+use crate::hole_punching::DHT;
+use crate::journal::Journal;
 use crate::kad::KadValue;
-use crate::keys::{KeyType, Keypair};
+use crate::keys::{Cipher, KeyType, Keypair};
+use crate::mailbox::{self, Mailbox};
+use crate::multi::Multis;
+use crate::outbox::Outbox;
 use crate::peer_list::PeerList;
-use crate::primitives::{STORAGE_KEY_KEY, STORAGE_NAME, STORAGE_PEER_LIST_KEY};
+use crate::primitives::{
+    DEADLINE_KEEP, DIAL_TIMEOUT, MAX_HEARTBEAT_LENGTH, MIN_KEEPALIVE_INTERVAL, STORAGE_AUDIT_KEY,
+    STORAGE_BUFFER_KEY, STORAGE_KEY_KEY, STORAGE_NAME, STORAGE_OUTBOX_KEY, STORAGE_PEER_LIST_KEY,
+};
+use crate::qos::Qos;
+#[cfg(feature = "relay")]
+use crate::relay_queue::RelayQueues;
+use crate::scheduler::{Job, Scheduler};
+use crate::seeds::SeedHealth;
+use crate::service::{service_key, Services, SERVICE_NAME_MAX, SERVICE_QUERY_TIMEOUT};
 use crate::session::{
     direct_stable, new_session_channel, relay_stable, session_spawn, ConnectType, Session,
     SessionMessage,
 };
+use crate::stats;
+use crate::swarm::Swarms;
+use crate::token;
+use crate::topic::Topics;
 use crate::transports::{
-    start as transport_start, EndpointMessage, RemotePublic, TransportRecvMessage,
-    TransportSendMessage,
+    bridge as bridge_start, resolve, start as transport_start, EndpointMessage, FrameCounter,
+    RemotePublic, TransportRecvMessage, TransportSendMessage,
 };
+use crate::watchdog::{recover, Watchdog};
+
+/// the storage dirs of running instances in this process.
+static STORAGES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// keep the instance's storage dir, so other instances in this process cannot
+/// use the same keypair & peers, release when the instance stopped.
+struct StorageLock(PathBuf);
+
+impl StorageLock {
+    fn lock(path: PathBuf) -> Result<Self> {
+        let mut storages = STORAGES.lock().map_err(|_e| new_io_error("storage lock"))?;
+        if storages.contains(&path) {
+            return Err(new_io_error("db_dir is used by other running instance"));
+        }
+        storages.push(path.clone());
+        Ok(StorageLock(path))
+    }
+}
+
+impl Drop for StorageLock {
+    fn drop(&mut self) {
+        if let Ok(mut storages) = STORAGES.lock() {
+            storages.retain(|p| p != &self.0);
+        }
+    }
+}
 
 /// start server
 pub async fn start(
@@ -40,153 +108,566 @@ pub async fn start(
 ) -> Result<PeerId> {
     let Config {
         mut db_dir,
+        ephemeral,
         mut peer,
         mut allowlist,
+        import_peers,
+        seeds,
         blocklist,
         allow_peer_list,
         block_peer_list,
         permission,
         only_stable_data,
         delivery_length,
+        delivery_policy,
+        delivery_echo,
+        bootstrap_min_peers,
+        bootstrap_retry_min,
+        bootstrap_retry_max,
+        pinned_reconnect,
+        dht_events,
+        session_stats,
+        bandwidth_summary,
+        outbox_size,
+        outbox_age,
+        dial_on_demand,
+        ordered_delivery,
+        buffer_ttl,
+        buffer_max_entries,
+        buffer_max_bytes,
+        buffer_overflow,
+        buffer_persist,
+        check_interval,
+        watchdog_rounds,
+        watchdog_wait,
+        clear_interval,
+        refresh_interval,
+        keepalive_interval,
+        keepalive_misses,
+        idle_timeout,
+        stable_reconnect_times,
+        stable_reconnect_min,
+        stable_reconnect_max,
+        resume_window,
+        emulation,
+        handshake_payload,
+        handshake_check,
+        peer_id_hash,
+        gater,
+        relay_accounting,
+        relay_sticky,
+        #[cfg(feature = "hole-punching")]
+        punch_helpers,
+        #[cfg(feature = "relay")]
+        relay_queue_size,
+        #[cfg(feature = "relay")]
+        relay_queue_overflow,
+        stable_check,
+        peer_selector,
+        join_issuers,
+        stable_quotas,
+        data_path,
+        bootstrap_concurrency,
+        dial_concurrency,
+        max_message_size,
+        batch_delay,
+        batch_size,
+        padding_buckets,
+        cover_traffic,
+        outside,
+        psk,
+        mailbox_size,
+        mailbox_age,
+        mailbox_open,
+        topic_replicas,
+        swarm_threshold,
+        swarm_cache,
+        bridge,
+        power_profile,
+        uplink,
+        handshake_retry,
+        trusted_proxies,
+        ban_threshold,
+        ban_time,
+        ban_ip,
+        ban_rate,
+        block_share,
+        audit_log_size,
+        #[cfg(feature = "admin")]
+        admin_addr,
+        #[cfg(feature = "admin")]
+        admin_token,
+        #[cfg(feature = "mainline")]
+        mainline_key,
     } = config;
     allowlist.extend(allow_peer_list.iter().map(|pid| Peer::peer(*pid)));
+    let seed_health = SeedHealth::new(
+        allowlist
+            .iter()
+            .filter(|p| p.effective_socket())
+            .map(|p| p.socket),
+    );
+    let allowlisted = allowlist
+        .iter()
+        .map(|p| p.id)
+        .filter(|id| id != &PeerId::default())
+        .collect();
     db_dir.push(STORAGE_NAME);
-    if !db_dir.exists() {
-        fs::create_dir_all(&db_dir).await?;
-    }
-    let mut key_path = db_dir.clone();
-    key_path.push(STORAGE_KEY_KEY);
-    let key_bytes = fs::read(&key_path).await.unwrap_or(vec![]); // safe.
-
-    let key = match Keypair::from_db_bytes(&key_bytes) {
-        Ok(keypair) => keypair,
-        Err(_) => {
-            let key = KeyType::Ed25519.generate_kepair();
-            let key_bytes = key.to_db_bytes();
-            fs::write(key_path, key_bytes).await?;
-            key
+    // ephemeral mode never touch the db_dir, a new keypair every start.
+    let (key, storage_lock) = if ephemeral {
+        (KeyType::Ed25519.generate_kepair(), None)
+    } else {
+        if !db_dir.exists() {
+            fs::create_dir_all(&db_dir).await?;
         }
+        let storage_lock = StorageLock::lock(fs::canonicalize(&db_dir).await?)?;
+        let mut key_path = db_dir.clone();
+        key_path.push(STORAGE_KEY_KEY);
+        let key_bytes = fs::read(&key_path).await.unwrap_or(vec![]); // safe.
+
+        let key = match Keypair::from_db_bytes(&key_bytes) {
+            Ok(keypair) => keypair,
+            Err(_) => {
+                let key = KeyType::Ed25519.generate_kepair();
+                let key_bytes = key.to_db_bytes();
+                fs::write(key_path, key_bytes).await?;
+                key
+            }
+        };
+        (key, Some(storage_lock))
     };
+    let storage_path = |name: &str| (!ephemeral).then(|| db_dir.join(name));
+
+    let peer_id = key.peer_id(&peer_id_hash);
 
-    let peer_id = key.peer_id();
+    let outbox = Outbox::load(storage_path(STORAGE_OUTBOX_KEY), outbox_size, outbox_age);
 
-    let mut peer_list_path = db_dir;
-    peer_list_path.push(STORAGE_PEER_LIST_KEY);
-    let peer_list = Arc::new(RwLock::new(PeerList::load(
+    #[cfg(feature = "capture")]
+    let capture_path = storage_path(crate::primitives::STORAGE_CAPTURE_KEY);
+    #[cfg(feature = "capture")]
+    let record_path = storage_path(crate::primitives::STORAGE_RECORD_KEY);
+
+    let journal_path = db_dir.join(STORAGE_BUFFER_KEY);
+    let journal = Journal::load(journal_path, buffer_persist && !ephemeral);
+
+    let audit_path = db_dir.join(STORAGE_AUDIT_KEY);
+    let audit = Audit::start(audit_path, if ephemeral { 0 } else { audit_log_size });
+
+    let peer_list_path = storage_path(STORAGE_PEER_LIST_KEY);
+    let mut peer_list = PeerList::load(
         peer_id,
         peer_list_path,
         allowlist,
         (block_peer_list, blocklist),
-    )));
+    );
+    if let Some(path) = import_peers {
+        match peer_list.import(&path).await {
+            Ok(n) => info!("Imported {} peers from {:?}.", n, path),
+            Err(e) => warn!("CHAMOMILE: IMPORT PEERS FAILURE: {:?}", e),
+        }
+    }
+    let routes = peer_list.routes();
+    let peer_list = Arc::new(RwLock::new(peer_list));
 
     let mut transports: HashMap<TransportType, Sender<TransportSendMessage>> = HashMap::new();
+    let transport_bandwidths: HashMap<TransportType, Arc<FrameCounter>> = [
+        TransportType::QUIC,
+        TransportType::TCP,
+        TransportType::RTP,
+        TransportType::UDT,
+    ]
+    .iter()
+    .map(|t| (*t, Arc::new(FrameCounter::default())))
+    .collect();
 
-    let (local_addr, trans_send, trans_option, main_option) = transport_start(&peer, None)
-        .await
-        .expect("Transport binding failure!");
+    let (local_addr, trans_send, trans_option, main_option) = transport_start(
+        &peer,
+        None,
+        max_message_size,
+        handshake_retry,
+        trusted_proxies.clone(),
+        transport_bandwidths[&peer.transport].clone(),
+    )
+    .await
+    .expect("Transport binding failure!");
     let mut trans_recv = trans_option.unwrap(); // safe
     let main_trans = main_option.unwrap(); // safe
 
-    peer.id = key.peer_id();
+    peer.id = peer_id;
     peer.socket = local_addr;
     transports.insert(peer.transport, trans_send.clone());
 
+    if bridge {
+        let mut bridge_peer = peer;
+        bridge_peer.transport = match peer.transport {
+            TransportType::QUIC => TransportType::TCP,
+            _ => TransportType::QUIC,
+        };
+        let (bridge_addr, bridge_send) = bridge_start(
+            &bridge_peer,
+            main_trans.clone(),
+            max_message_size,
+            handshake_retry,
+            trusted_proxies,
+            transport_bandwidths[&bridge_peer.transport].clone(),
+        )
+        .await?;
+        info!(
+            "Bridge {} at: {}",
+            bridge_peer.transport.to_str(),
+            bridge_addr
+        );
+        transports.insert(bridge_peer.transport, bridge_send);
+    }
+
+    let (dialer, dial_receiver) = Dialer::new();
+    // permissionless peer is a relay, only when compiled it.
+    #[cfg(not(feature = "relay"))]
+    let _ = permission;
     let global = Arc::new(Global {
         peer,
         key,
         out_sender,
-        delivery_length,
+        subscribers: Arc::new(RwLock::new(vec![])),
+        delivery: delivery_policy.unwrap_or(DeliveryPolicy::Prefix(delivery_length)),
+        echoes: Echoes::new(outbox_age.max(buffer_ttl), delivery_echo),
+        dht_events,
+        session_stats,
+        ordered_delivery,
+        keepalive_interval: keepalive_interval.max(MIN_KEEPALIVE_INTERVAL),
+        emulation,
+        keepalive_misses,
+        idle_timeout,
+        services: Arc::new(RwLock::new(Services::default())),
+        groups: Arc::new(RwLock::new(Groups::default())),
+        multis: Arc::new(RwLock::new(Multis::default())),
+        mailbox: Arc::new(RwLock::new(Mailbox::new(
+            mailbox_size,
+            mailbox_age,
+            mailbox_open,
+        ))),
+        peer_keys: Default::default(),
+        topic_replicas,
+        topics: Arc::new(RwLock::new(Topics::default())),
+        swarm_threshold,
+        swarms: Arc::new(RwLock::new(Swarms::new(swarm_cache))),
+        audit,
+        deadlines: Arc::new(RwLock::new(HashMap::new())),
+        connectings: Arc::new(RwLock::new(HashMap::new())),
+        reconnects: Arc::new(RwLock::new(HashSet::new())),
+        reconnect: (
+            stable_reconnect_times,
+            stable_reconnect_min,
+            stable_reconnect_max,
+        ),
+        resumptions: Arc::new(RwLock::new(HashMap::new())),
+        resume_window,
+        seeds,
+        seed_health,
+        handshake_payload,
+        handshake_check,
+        peer_id_hash,
+        gater,
+        relay_accounting,
+        relay_sticky,
+        stable_check,
+        peer_selector,
+        join_issuers,
+        stable_quotas,
+        data_path,
+        stable_classes: Arc::new(RwLock::new(HashMap::new())),
+        allowlisted,
+        bridge,
+        bridges: Arc::new(RwLock::new(HashSet::new())),
+        heartbeat: Arc::new(RwLock::new(vec![])),
+        bootstrap_concurrency,
+        dialer,
+        max_message_size,
+        dials: Arc::new(RwLock::new(HashMap::new())),
+        probes: Arc::new(RwLock::new(HashMap::new())),
+        online: AtomicBool::new(false),
+        watchdog: Watchdog::new(watchdog_rounds, watchdog_wait),
+        low_power: AtomicBool::new(power_profile == PowerProfile::LowPower),
+        policies: Arc::new(RwLock::new(HashMap::new())),
+        bans: Bans::new(ban_threshold, Duration::from_secs(ban_time), ban_ip),
+        shared_bans: SharedBans::new(block_share),
+        ban_rate,
+        qos: if uplink > 0 {
+            Some(Qos::new(uplink))
+        } else {
+            None
+        },
+        #[cfg(feature = "capture")]
+        capture: crate::capture::Capture::start(capture_path),
+        #[cfg(feature = "capture")]
+        recorder: crate::record::Recorder::start(record_path),
+        batch: (batch_delay, batch_size),
+        padding_buckets,
+        cover_traffic,
+        outside,
+        psk,
         trans: main_trans,
         transports: Arc::new(RwLock::new(transports)),
-        buffer: Arc::new(RwLock::new(Buffer::init())),
+        buffer: Buffer::init(
+            buffer_ttl,
+            buffer_max_entries,
+            buffer_max_bytes,
+            buffer_overflow,
+        ),
+        outbox: Arc::new(RwLock::new(outbox)),
+        dial_on_demand,
+        dial_datas: Arc::new(RwLock::new(HashMap::new())),
+        journal: Arc::new(RwLock::new(journal)),
+        latencies: Arc::new(RwLock::new(HashMap::new())),
+        clock_offsets: Arc::new(RwLock::new(HashMap::new())),
+        bandwidths: Arc::new(RwLock::new(HashMap::new())),
+        relay_paths: Arc::new(RwLock::new(HashMap::new())),
+        transport_bandwidths,
         peer_list: peer_list.clone(),
-        is_relay_data: !permission,
+        routes,
+        #[cfg(feature = "relay")]
+        is_relay_data: !permission || bridge,
+        #[cfg(feature = "relay")]
+        relay_queues: RelayQueues::new(relay_queue_size, relay_queue_overflow),
+        #[cfg(feature = "hole-punching")]
+        punches: Punches::new(punch_helpers),
     });
 
-    // bootstrap allow list.
-    for a in peer_list.read().await.bootstrap() {
-        let (session_key, remote_pk) = global.generate_remote();
-        let _ = global
-            .trans_send(
-                &a.transport,
-                TransportSendMessage::Connect(a.socket, remote_pk, session_key),
-            )
-            .await;
-    }
+    let recv_data = !only_stable_data;
+    tokio::spawn(dialer_loop(global.clone(), dial_receiver, dial_concurrency));
 
+    // bootstrap allow list, and restore the saved stables.
+    bootstrap(global.clone()).await;
+    stable_reboot(global.clone(), recv_data, true).await;
     drop(peer_list);
 
-    let recv_data = !only_stable_data;
+    tokio::spawn(bandwidth_loop(global.clone(), bandwidth_summary));
+
+    if buffer_persist {
+        tokio::spawn(journal_replay(global.clone(), buffer_ttl, recv_data));
+    }
+
+    #[cfg(feature = "admin")]
+    if let Some(addr) = admin_addr {
+        crate::admin::listen(addr, admin_token, global.clone()).await?;
+    }
+
+    #[cfg(feature = "mainline")]
+    if let Some(key) = mainline_key {
+        crate::mainline::start(key, global.clone(), bootstrap_min_peers).await?;
+    }
+
+    if !pinned_reconnect.is_zero() {
+        tokio::spawn(pinned_loop(global.clone(), pinned_reconnect));
+    }
+
+    if bootstrap_min_peers > 0 {
+        tokio::spawn(bootstrap_retry(
+            global.clone(),
+            bootstrap_min_peers,
+            bootstrap_retry_min,
+            bootstrap_retry_max,
+        ));
+    }
+
     let inner_global = global.clone();
+    let mut scheduler = Scheduler::new();
+    scheduler.add(Job::Check, check_interval);
+    scheduler.add(Job::Clear, clear_interval);
+    scheduler.add(Job::Refresh, refresh_interval);
     tokio::spawn(async move {
+        #[allow(clippy::large_enum_variant)]
         enum FutureResult {
             Trans(TransportRecvMessage),
-            Clear,
-            Check,
+            Job(Job),
         }
         loop {
             let futres = select! {
                 v = async {
-                    trans_recv.recv().await.map(|msg| FutureResult::Trans(msg))
-                } => v,
-                v = async {
-                    // Check Timer: every 10s to check network. (read only).
-                    tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-                    Some(FutureResult::Check)
+                    trans_recv.recv().await.map(FutureResult::Trans)
                 } => v,
                 v = async {
-                    // Clear Timer: every 60s to check buffer.
-                    tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-                    Some(FutureResult::Clear)
+                    let (scale, is_batch) = inner_global.power_scale();
+                    Some(FutureResult::Job(scheduler.next(scale, is_batch).await))
                 } => v,
             };
 
             match futres {
-                Some(FutureResult::Trans(TransportRecvMessage(
+                Some(FutureResult::Trans(TransportRecvMessage::Misbehavior(addr, misbehavior))) => {
+                    debug!("Transport misbehavior from {}: {:?}.", addr, misbehavior);
+                    inner_global
+                        .misbehave(&Peer::socket(addr), false, misbehavior)
+                        .await;
+                }
+                Some(FutureResult::Trans(TransportRecvMessage::ConnectFailure(
+                    addr,
+                    transport,
+                    reason,
+                    dial,
+                ))) => {
+                    debug!("Connect to {} failure: {:?}.", addr, reason);
+                    inner_global.dial_done(&addr).await;
+                    inner_global.dialer.failure(addr);
+                    inner_global.seed_health.failure(&addr);
+                    inner_global.dial_datas_failed(&addr).await;
+                    let probe = match dial {
+                        Some(dial) => inner_global.probe_done(&addr, &dial).await,
+                        None => None,
+                    };
+                    if let Some((tid, peer, ..)) = probe {
+                        let _ = inner_global
+                            .out_send(ReceiveMessage::Probe(tid, peer, None))
+                            .await;
+                        continue;
+                    }
+                    let mut peer = Peer::socket(addr);
+                    peer.transport = transport;
+                    let _ = inner_global
+                        .out_send(ReceiveMessage::DialFailure(peer, reason))
+                        .await;
+                }
+                Some(FutureResult::Trans(TransportRecvMessage::Connected(
                     addr,
-                    RemotePublic(remote_key, remote_peer, dh_key),
+                    RemotePublic(
+                        remote_key,
+                        remote_peer,
+                        dh_key,
+                        remote_version,
+                        remote_payload,
+                        psk_confirm,
+                    ),
                     is_self,
                     stream_sender,
                     stream_receiver,
                     endpoint_sender,
                 ))) => {
                     debug!("Incoming remote peer...");
+                    stats::counter(stats::METRIC_INCOMING, 1);
+                    let is_dial = is_self.is_some();
+                    // the dial's session key bytes, to match the probe of this dial.
+                    let dial = is_self.as_ref().map(|key| key.out_bytes());
+                    if is_dial {
+                        inner_global.dial_done(&addr).await;
+                        inner_global.dialer.success(&addr);
+                        inner_global.seed_health.success(&addr);
+                    }
                     // 1. check is block ip.
-                    if inner_global.peer_list.read().await.is_block_addr(&addr) {
+                    if inner_global.peer_list.read().await.is_block_addr(&addr)
+                        || inner_global.is_banned(None, &addr)
+                        || !inner_global.gate_addr(&addr)
+                    {
                         debug!("Incoming remote ip is blocked, close it.");
+                        stats::counter(stats::METRIC_BLOCKED, 1);
+                        inner_global.audit.log(format!("blocked_addr {}", addr));
                         let _ = endpoint_sender.send(EndpointMessage::Close).await;
+                        if is_self.is_some() {
+                            let _ = inner_global
+                                .out_send(ReceiveMessage::DialFailure(
+                                    nat(addr, remote_peer),
+                                    DialReason::Blocked,
+                                ))
+                                .await;
+                        }
                         continue;
                     }
 
-                    let remote_id = remote_key.peer_id();
+                    let remote_id = remote_key.peer_id(&inner_global.peer_id_hash);
                     let remote_peer = nat(addr, remote_peer);
                     debug!("Incoming remote NAT addr: {}", remote_peer.socket);
 
                     // 2. check is self or is block peer.
-                    if &remote_id == inner_global.peer_id()
-                        || inner_global
-                            .peer_list
-                            .read()
-                            .await
-                            .is_block_peer(&remote_id)
-                    {
+                    let is_block = inner_global
+                        .peer_list
+                        .read()
+                        .await
+                        .is_block_peer(&remote_id)
+                        || inner_global.is_banned(Some(&remote_id), &addr)
+                        || !inner_global.gate_peer(&remote_peer);
+                    if &remote_id == inner_global.peer_id() || is_block {
                         debug!("Incoming remote peer is blocked, close it.");
+                        stats::counter(stats::METRIC_BLOCKED, 1);
+                        if is_block {
+                            inner_global.audit.log(format!(
+                                "blocked_peer {} {}",
+                                remote_id.to_hex(),
+                                addr
+                            ));
+                        }
                         let _ = endpoint_sender.send(EndpointMessage::Close).await;
+                        if is_block && is_self.is_some() {
+                            let _ = inner_global
+                                .out_send(ReceiveMessage::DialFailure(
+                                    remote_peer,
+                                    DialReason::Blocked,
+                                ))
+                                .await;
+                        }
                         continue;
                     }
 
-                    // 3. check session key and send self info to remote.
+                    // 3. check handshake payload and negotiate protocol version.
+                    let version = match inner_global.check_handshake(
+                        &remote_id,
+                        remote_version,
+                        &remote_payload,
+                    ) {
+                        Some(version) => version,
+                        None => {
+                            debug!("Incoming remote handshake is not accepted, close it.");
+                            stats::counter(stats::METRIC_HANDSHAKE_FAILURE, 1);
+                            inner_global.audit.log(format!(
+                                "handshake_rejected {} {} payload",
+                                remote_id.to_hex(),
+                                addr
+                            ));
+                            let _ = endpoint_sender.send(EndpointMessage::Close).await;
+                            if is_self.is_some() {
+                                let _ = inner_global
+                                    .out_send(ReceiveMessage::DialFailure(
+                                        remote_peer,
+                                        DialReason::Handshake,
+                                    ))
+                                    .await;
+                            }
+                            continue;
+                        }
+                    };
+
+                    // 4. check psk & session key and send self info to remote.
+                    let cipher = Cipher::negotiate(remote_version);
+                    debug!("Incoming remote session cipher: {:?}", cipher);
                     let session_key = if let Some(mut session_key) = is_self {
-                        if session_key.complete(&remote_key.pk, dh_key) {
+                        if inner_global.complete_local(
+                            &mut session_key,
+                            &remote_key,
+                            dh_key,
+                            &psk_confirm,
+                            cipher,
+                        ) {
                             session_key
                         } else {
                             debug!("Incoming remote session key is invalid, close it.");
+                            stats::counter(stats::METRIC_HANDSHAKE_FAILURE, 1);
+                            inner_global.audit.log(format!(
+                                "handshake_rejected {} {} session_key",
+                                remote_id.to_hex(),
+                                addr
+                            ));
                             let _ = endpoint_sender.send(EndpointMessage::Close).await;
+                            inner_global
+                                .misbehave(&Peer::socket(addr), false, Misbehavior::BadSignature)
+                                .await;
+                            let _ = inner_global
+                                .out_send(ReceiveMessage::DialFailure(
+                                    remote_peer,
+                                    DialReason::Handshake,
+                                ))
+                                .await;
                             continue;
                         }
                     } else {
                         if let Some((session_key, remote_pk)) =
-                            inner_global.complete_remote(&remote_key, dh_key)
+                            inner_global.complete_remote(&remote_key, dh_key, &psk_confirm, cipher)
                         {
                             let _ = endpoint_sender
                                 .send(EndpointMessage::Handshake(remote_pk))
@@ -194,13 +675,70 @@ pub async fn start(
                             session_key
                         } else {
                             debug!("Incoming remote session key is invalid, close it.");
+                            stats::counter(stats::METRIC_HANDSHAKE_FAILURE, 1);
+                            inner_global.audit.log(format!(
+                                "handshake_rejected {} {} session_key",
+                                remote_id.to_hex(),
+                                addr
+                            ));
                             let _ = endpoint_sender.send(EndpointMessage::Close).await;
+                            inner_global
+                                .misbehave(&Peer::socket(addr), false, Misbehavior::BadSignature)
+                                .await;
                             continue;
                         }
                     };
 
-                    // 4. check is stable relay connections.
-                    if let Some(ss) = inner_global.peer_list.read().await.is_relay(&remote_id) {
+                    inner_global.audit.log(format!(
+                        "handshake_accepted {} {}",
+                        remote_id.to_hex(),
+                        addr
+                    ));
+
+                    // probe is finished when handshaked, not save to DHTs.
+                    let probe = match &dial {
+                        Some(dial) => inner_global.probe_done(&addr, dial).await,
+                        None => None,
+                    };
+                    if let Some((tid, _, start, _)) = probe {
+                        debug!("Incoming remote is probe, close it.");
+                        let _ = endpoint_sender.send(EndpointMessage::Close).await;
+                        let rtt = start.elapsed().as_millis() as u32;
+                        let _ = inner_global
+                            .out_send(ReceiveMessage::Probe(tid, remote_peer, Some(rtt)))
+                            .await;
+                        continue;
+                    }
+
+                    // 5. check is stable relay connections, when punching, only upgrade
+                    // by the first punched path.
+                    let relay = inner_global
+                        .peer_list
+                        .read()
+                        .await
+                        .is_relay(&remote_id)
+                        .cloned();
+                    #[cfg(feature = "hole-punching")]
+                    match inner_global
+                        .punches
+                        .accept(inner_global.peer_id(), &remote_id, is_dial)
+                    {
+                        Some(true) if relay.is_some() => {
+                            debug!("Incoming remote is punched: {}.", remote_id.short_show());
+                            inner_global
+                                .peer_list
+                                .write()
+                                .await
+                                .stable_direct(&remote_id, stream_sender.clone());
+                        }
+                        Some(false) => {
+                            debug!("Incoming remote is not the punched path, close it.");
+                            let _ = endpoint_sender.send(EndpointMessage::Close).await;
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    if let Some(ss) = relay {
                         debug!("Incoming remote upgrade to direct.");
                         let _ = ss
                             .send(SessionMessage::DirectIncoming(
@@ -213,240 +751,215 @@ pub async fn start(
                         continue;
                     }
 
-                    // 5. save to DHTs.
+                    // 6. save to DHTs.
                     let (session_sender, session_receiver) = new_session_channel();
                     let kv = KadValue(session_sender.clone(), stream_sender, remote_peer);
                     let is_new = inner_global.peer_list.write().await.add_dht(kv).await;
 
-                    // 6. check if had connected.
+                    // 7. check if had connected.
                     if !is_new {
                         debug!("Incoming remote add dht failure, close it.");
                         let _ = endpoint_sender.send(EndpointMessage::Close).await;
                         continue;
                     }
 
-                    // 7. DHT help.
-                    let peers = inner_global.peer_list.read().await.help_dht(&remote_id);
-                    let _ = endpoint_sender.send(EndpointMessage::DHT(DHT(peers))).await;
-
-                    session_spawn(
-                        Session::new(
-                            remote_peer,
-                            session_sender,
-                            stream_receiver,
-                            ConnectType::Direct(endpoint_sender),
-                            session_key,
-                            inner_global.clone(),
-                            recv_data,
-                        ),
-                        session_receiver,
+                    inner_global.bridge_join(remote_id, remote_version).await;
+                    #[cfg(feature = "hole-punching")]
+                    inner_global.punches.join(remote_id, remote_version);
+                    inner_global
+                        .dht_event(ReceiveMessage::PeerJoin(remote_peer))
+                        .await;
+
+                    // 8. DHT help.
+                    #[cfg(feature = "dht")]
+                    {
+                        let mut peers = inner_global.peer_list.read().await.help_dht(&remote_id);
+                        if let Some(selector) = &inner_global.peer_selector {
+                            let purpose = SelectPurpose::DhtHelp;
+                            peers = selector.select(purpose, Some(&remote_peer), peers, |p| p);
+                        }
+                        let _ = endpoint_sender.send(EndpointMessage::DHT(DHT(peers))).await;
+                    }
+
+                    let datas = inner_global.dial_datas_take(&remote_id).await;
+                    let mut session = Session::new(
+                        remote_peer,
+                        session_sender.clone(),
+                        stream_receiver,
+                        ConnectType::Direct(endpoint_sender),
+                        session_key,
+                        inner_global.clone(),
+                        recv_data,
                     );
+                    session.version = version;
+                    session_spawn(session, session_receiver);
+                    for (tid, data) in datas {
+                        let _ = session_sender
+                            .send(SessionMessage::Data(tid, Bytes::from(data)))
+                            .await;
+                    }
                     debug!("Incoming remote sessioned: {}.", remote_id.short_show());
+                    inner_global.network_ready().await;
                 }
-                Some(FutureResult::Check) => {
-                    if inner_global.peer_list.read().await.is_empty() {
-                        let _ = inner_global.out_send(ReceiveMessage::NetworkLost).await;
+                Some(FutureResult::Job(Job::Check)) => {
+                    let peer_list_lock = inner_global.peer_list.read().await;
+                    stats::gauge(
+                        stats::METRIC_DHT_PEERS,
+                        peer_list_lock.dht_keys().len() as f64,
+                    );
+                    stats::gauge(
+                        stats::METRIC_STABLE_PEERS,
+                        peer_list_lock.stable_all().len() as f64,
+                    );
+                    let is_empty = peer_list_lock.is_empty();
+                    drop(peer_list_lock);
+                    if is_empty {
+                        if !inner_global.watchdog.is_enable() {
+                            inner_global.network_lost();
+                            let _ = inner_global.out_send(ReceiveMessage::NetworkLost).await;
+                        } else if inner_global.watchdog.start() {
+                            inner_global.network_lost();
+                            inner_global.audit.log("network_lost".to_owned());
+                            let _ = inner_global.out_send(ReceiveMessage::NetworkLost).await;
+                            tokio::spawn(recover(inner_global.clone(), recv_data));
+                        }
                     }
                 }
-                Some(FutureResult::Clear) => {
-                    inner_global.buffer.write().await.timer_clear().await;
-                }
-                None => break,
-            }
-        }
-    });
+                Some(FutureResult::Job(Job::Clear)) => {
+                    let (expired, len) = inner_global.buffer.timer_clear().await;
+                    stats::gauge(stats::METRIC_BUFFER_DEPTH, len as f64);
+                    inner_global
+                        .delivery_failures(expired, DeliveryFailure::Timeout)
+                        .await;
 
-    tokio::spawn(async move {
-        loop {
-            match self_receiver.recv().await {
-                Some(SendMessage::StableConnect(tid, to, data)) => {
-                    debug!("Outside: StableConnect to {}.", to.id.short_show());
-                    if &to.id == global.peer_id() {
-                        warn!("CHAMOMILE: STABLE CONNECT NERVER TO SELF.");
-                        if tid != 0 {
-                            let _ = global
-                                .out_send(ReceiveMessage::Delivery(
-                                    DeliveryType::StableConnect,
-                                    tid,
-                                    false,
-                                    delivery_split!(data, delivery_length),
-                                ))
-                                .await;
-                        }
-                        continue;
+                    let window = inner_global.resume_window;
+                    inner_global
+                        .resumptions
+                        .write()
+                        .await
+                        .retain(|_, r| !r.is_expired(window));
+                    inner_global.bans.clear();
+                    inner_global.shared_bans.clear();
+                    inner_global.dialer.clear();
+                    inner_global.echoes.clear();
+
+                    // request the timeout chunks again.
+                    inner_global.swarms.write().await.clear();
+                    let fetching = inner_global.swarms.read().await.fetching();
+                    for id in fetching {
+                        inner_global.swarm_fetch(&id).await;
                     }
 
-                    // 1. get it or closest peer.
-                    let peer_list_lock = global.peer_list.read().await;
-                    let results = peer_list_lock.get(&to.id);
-                    if results.is_none() {
-                        drop(peer_list_lock);
-                        warn!("CHAMOMILE: CANNOT REACH NETWORK.");
+                    let expired = inner_global.outbox.write().await.timer_clear();
+                    for (tid, data) in expired {
                         if tid != 0 {
-                            let _ = global
+                            let _ = inner_global
                                 .out_send(ReceiveMessage::Delivery(
-                                    DeliveryType::StableConnect,
+                                    DeliveryType::Data,
                                     tid,
-                                    false,
-                                    delivery_split!(data, delivery_length),
+                                    Err(DeliveryFailure::Timeout),
+                                    inner_global.delivery(&data),
                                 ))
                                 .await;
                         }
-                        continue;
+                    }
+                    inner_global.outbox_flush().await;
+                }
+                Some(FutureResult::Job(Job::Refresh)) => {
+                    let closed = inner_global.peer_list.write().await.clear_closed();
+                    for peer_id in closed {
+                        debug!("DHT refresh: remove closed {}.", peer_id.short_show());
+                        inner_global
+                            .dht_event(ReceiveMessage::PeerLeave(peer_id))
+                            .await;
                     }
 
-                    // 2. if connected, send to remote.
-                    let (s, _, is_it) = results.unwrap(); // safe checked.
-                    if is_it {
-                        debug!("Outside: StableConnect multiple stable connected.");
-                        let _ = s.send(SessionMessage::StableConnect(tid, data)).await;
-                        drop(peer_list_lock);
-                    } else {
-                        let ss = s.clone();
-                        drop(peer_list_lock);
-
-                        // 3. check if had in buffer tmp.
-                        if let Some(sender) = global.buffer.read().await.get_tmp_session(&to.id) {
-                            debug!("Outside: StableConnect is in tmp, send to it.");
-                            let _ = sender.send(SessionMessage::StableConnect(tid, data)).await;
-                            continue;
-                        }
+                    // publish the joined groups' topics again, and find new members.
+                    inner_global.topics.write().await.clear();
+                    let joined = inner_global.groups.read().await.joined();
+                    for group in joined {
+                        inner_global.topic_sync(&group, true).await;
+                    }
+                    // and the registered services' provider records.
+                    let registered = inner_global.services.read().await.registered();
+                    for name in registered {
+                        inner_global.service_sync(&name, true).await;
+                    }
+                }
+                None => break,
+            }
+        }
+    });
 
-                        // 4. add to stable buffer.
-                        let mut buffer_lock = global.buffer.write().await;
-                        let delivery = delivery_split!(data, global.delivery_length);
-                        if buffer_lock.add_connect(to.id, tid, data) {
-                            debug!("Outside: StableConnect is processing, save to buffer.");
-                            drop(buffer_lock);
+    tokio::spawn(async move {
+        let _storage_lock = storage_lock;
+        loop {
+            let msg = match self_receiver.recv().await {
+                Some(msg) => {
+                    let resolved = resolve_names(&*global.peer_list.read().await, msg);
+                    match resolved {
+                        Ok(msg) => Some(msg),
+                        Err(msg) => {
+                            unresolved_failure(&global, msg).await;
                             continue;
                         }
-                        drop(buffer_lock);
-
-                        let g = global.clone();
-                        if to.effective_socket() {
-                            debug!("Outside: StableConnect start new connection with IP.");
-                            tokio::spawn(async move {
-                                let _ = direct_stable(tid, delivery, to, g, recv_data).await;
-                            });
-                        } else {
-                            debug!("Outside: StableConnect start new connection with ID.");
-                            tokio::spawn(async move {
-                                let _ = relay_stable(tid, delivery, to, ss, g, recv_data).await;
-                            });
-                        }
                     }
                 }
-                Some(SendMessage::StableResult(tid, to, is_ok, is_force, data)) => {
-                    debug!("Outside: StableResult to {}.", to.id.short_show());
-                    if &to.id == global.peer_id() {
-                        warn!("CHAMOMILE: STABLE CONNECT NERVER TO SELF.");
-                        if tid != 0 {
-                            let _ = global
-                                .out_send(ReceiveMessage::Delivery(
-                                    DeliveryType::StableResult,
-                                    tid,
-                                    false,
-                                    delivery_split!(data, delivery_length),
-                                ))
-                                .await;
-                        }
+                None => None,
+            };
+            if let Some(msg) = &msg {
+                global.echoes.keep(msg, &global.delivery);
+            }
+            let msg = match msg {
+                Some(SendMessage::Deadline(deadline, msg)) => {
+                    if !deadline_start(&global, deadline, &msg).await {
                         continue;
                     }
-
-                    // 1. check if in tmp.
-                    if let Some(sender) = global.buffer.read().await.get_tmp_session(&to.id) {
-                        debug!("Outside: StableResult get the tmp session.");
-                        let _ = sender
-                            .send(SessionMessage::StableResult(tid, is_ok, is_force, data))
+                    Some(*msg)
+                }
+                msg => msg,
+            };
+            match msg {
+                Some(SendMessage::StableConnect(tid, to, data)) => {
+                    if data.len() > global.max_message_size {
+                        global
+                            .oversize(DeliveryType::StableConnect, tid, data)
                             .await;
                         continue;
                     }
-
-                    // 2. check if in DHT or stable.
-                    let peer_list_lock = global.peer_list.read().await;
-                    let results = peer_list_lock.get(&to.id);
-                    if results.is_none() {
-                        drop(peer_list_lock);
-                        warn!("CHAMOMILE: CANNOT REACH NETWORK.");
-                        if tid != 0 {
-                            let _ = global
-                                .out_send(ReceiveMessage::Delivery(
-                                    DeliveryType::StableResult,
-                                    tid,
-                                    false,
-                                    delivery_split!(data, delivery_length),
-                                ))
-                                .await;
-                        }
-                        continue;
-                    }
-
-                    let (s, _, is_it) = results.unwrap(); // safe checked.
-                    if is_it {
-                        debug!("Outside: StableResult get the is_it session.");
-                        let _ = s
-                            .send(SessionMessage::StableResult(tid, is_ok, is_force, data))
-                            .await;
-                        drop(peer_list_lock);
-                    } else {
-                        // 3. check if is_ok, if ok, start stable connected.
-                        if !is_ok {
-                            drop(peer_list_lock);
-                            continue;
-                        }
-
-                        let ss = s.clone();
-                        drop(peer_list_lock);
-
-                        // 4. check if had in buffer tmp.
-                        if let Some(sender) = global.buffer.read().await.get_tmp_session(&to.id) {
-                            debug!("Outside: StableResult had tmp session.");
-                            let _ = sender
-                                .send(SessionMessage::StableResult(tid, is_ok, is_force, data))
-                                .await;
-                            continue;
-                        }
-
-                        // 5. add to stable buffer.
-                        let mut buffer_lock = global.buffer.write().await;
-                        let delivery = delivery_split!(data, global.delivery_length);
-                        if buffer_lock.add_result(to.id, tid, data) {
-                            debug!("Outside: StableResult is processing, save to buffer.");
-                            drop(buffer_lock);
-                            continue;
-                        }
-                        drop(buffer_lock);
-
+                    // had a in-flight dial to it, waiting and reuse the dialed session.
+                    if let Some(rx) = global.dial_wait(to.socket, to.id).await {
+                        debug!("Outside: StableConnect waiting the in-flight dial.");
                         let g = global.clone();
-                        debug!("Outside: StableResult start new connection with ID.");
-                        if to.effective_socket() {
-                            tokio::spawn(async move {
-                                let _ = direct_stable(tid, delivery, to, g, recv_data).await;
-                            });
-                        } else {
-                            tokio::spawn(async move {
-                                let _ = relay_stable(tid, delivery, to, ss, g, recv_data).await;
-                            });
-                        }
+                        tokio::spawn(async move {
+                            let _ = tokio::time::timeout(DIAL_TIMEOUT, rx).await;
+                            stable_connect(&g, tid, to, data, recv_data).await;
+                        });
+                        continue;
                     }
+                    stable_connect(&global, tid, to, data, recv_data).await;
+                }
+                Some(SendMessage::StableResult(tid, to, is_ok, is_force, data)) => {
+                    stable_result(&global, tid, to, is_ok, is_force, data, recv_data).await;
+                }
+                Some(SendMessage::StableCancel(tid, pid)) => {
+                    stable_cancel(&global, tid, pid).await;
                 }
                 Some(SendMessage::StableDisconnect(pid)) => {
                     debug!("Outside: StableDisconnect to {}.", pid.short_show());
-                    if let Some((sender, _, is_it)) = global.peer_list.read().await.get(&pid) {
-                        if is_it {
-                            let _ = sender.send(SessionMessage::Close).await;
-                        }
+                    global.reconnects.write().await.remove(&pid);
+                    global.peer_list.write().await.stable_forget(&pid).await;
+                    if let Some(sender) = global.routes.get(&pid) {
+                        let _ = sender.send(SessionMessage::Close).await;
                     }
                 }
                 Some(SendMessage::Connect(peer)) => {
                     debug!("Outside: DHT Connect to {}.", peer.socket);
-                    let (session_key, remote_pk) = global.generate_remote();
-                    let _ = global
-                        .trans_send(
-                            &peer.transport,
-                            TransportSendMessage::Connect(peer.socket, remote_pk, session_key),
-                        )
-                        .await;
+                    if !global.dialer.dial(peer, DialKind::Connect, None) {
+                        let _ = global
+                            .out_send(ReceiveMessage::DialFailure(peer, DialReason::Unreachable))
+                            .await;
+                    }
                 }
                 Some(SendMessage::DisConnect(peer)) => {
                     debug!("Outside: DHT Disconnect to {}.", peer.socket);
@@ -458,7 +971,58 @@ pub async fn start(
                         .await;
                 }
                 Some(SendMessage::Data(tid, to, data)) => {
-                    // check if send to self. better circle for application.
+                    if data.len() > global.max_message_size {
+                        global.oversize(DeliveryType::Data, tid, data).await;
+                        continue;
+                    }
+                    send_data(&global, tid, to, Bytes::from(data)).await;
+                }
+                Some(SendMessage::RedundantData(tid, to, data)) => {
+                    if data.len() > global.max_message_size {
+                        global.oversize(DeliveryType::Data, tid, data).await;
+                        continue;
+                    }
+                    if let Some(sender) = global.routes.get(&to) {
+                        let _ = sender
+                            .send(SessionMessage::RedundantData(tid, data.into()))
+                            .await;
+                    } else {
+                        send_data(&global, tid, to, Bytes::from(data)).await;
+                    }
+                }
+                Some(SendMessage::DataMulti(tid, mut tos, data)) => {
+                    if data.len() > global.max_message_size {
+                        global.oversize(DeliveryType::Data, tid, data).await;
+                        continue;
+                    }
+                    tos.sort();
+                    tos.dedup();
+                    debug!("Outside: DataMulti to {} peers.", tos.len());
+                    // shared bytes, not copy for every peer.
+                    let data = Bytes::from(data);
+                    if tid == 0 {
+                        for to in tos {
+                            send_data(&global, 0, to, data.clone()).await;
+                        }
+                        continue;
+                    }
+                    if tos.is_empty() {
+                        let delivery = global.delivery(&data);
+                        let msg = ReceiveMessage::DeliveryMulti(tid, vec![], vec![], delivery);
+                        let _ = global.out_send(msg).await;
+                        continue;
+                    }
+                    let delivery = global.delivery(&data);
+                    let inners = global.multis.write().await.start(tid, &tos, delivery);
+                    for (inner, to) in inners {
+                        send_data(&global, inner, to, data.clone()).await;
+                    }
+                }
+                Some(SendMessage::ProtocolData(tid, to, protocol, data)) => {
+                    if data.len() > global.max_message_size {
+                        global.oversize(DeliveryType::Data, tid, data).await;
+                        continue;
+                    }
                     if &to == global.peer_id() {
                         info!("CHAMOMILE: DATA TO SELF.");
                         if tid != 0 {
@@ -466,24 +1030,22 @@ pub async fn start(
                                 .out_send(ReceiveMessage::Delivery(
                                     DeliveryType::Data,
                                     tid,
-                                    true,
-                                    delivery_split!(data, delivery_length),
+                                    Ok(()),
+                                    global.delivery(&data),
                                 ))
                                 .await;
                         }
-                        let _ = global.out_send(ReceiveMessage::Data(to, data)).await;
+                        let _ = global
+                            .out_send(ReceiveMessage::ProtocolData(to, protocol, data))
+                            .await;
                         continue;
                     }
 
-                    if let Some((sender, _, is_it)) = global.peer_list.read().await.get(&to) {
-                        if is_it {
-                            let _ = sender.send(SessionMessage::Data(tid, data)).await;
-                        } else {
-                            // only happen on permissionless.
-                            let _ = sender
-                                .send(SessionMessage::RelayData(*global.peer_id(), to, data))
-                                .await;
-                        }
+                    // only send to directly connected sessions.
+                    if let Some(sender) = global.routes.get(&to) {
+                        let _ = sender
+                            .send(SessionMessage::ProtocolData(tid, protocol, data.into()))
+                            .await;
                     } else {
                         warn!("CHAMOMILE: CANNOT REACH NETWORK.");
                         if tid != 0 {
@@ -491,73 +1053,1462 @@ pub async fn start(
                                 .out_send(ReceiveMessage::Delivery(
                                     DeliveryType::Data,
                                     tid,
-                                    false,
-                                    delivery_split!(data, delivery_length),
+                                    Err(DeliveryFailure::PeerOffline),
+                                    global.delivery(&data),
                                 ))
                                 .await;
                         }
                     }
                 }
-                Some(SendMessage::Broadcast(broadcast, data)) => match broadcast {
-                    Broadcast::StableAll => {
-                        for (_to, (sender, _)) in global.peer_list.read().await.stable_all() {
-                            let _ = sender.send(SessionMessage::Data(0, data.clone())).await;
+                Some(SendMessage::Broadcast(broadcast, data)) => {
+                    if data.len() > global.max_message_size {
+                        global.oversize(DeliveryType::Data, 0, data.to_vec()).await;
+                        continue;
+                    }
+                    match broadcast {
+                        Broadcast::StableAll => {
+                            for (_to, (sender, _)) in global.peer_list.read().await.stable_all() {
+                                let _ = sender.send(SessionMessage::Data(0, data.clone())).await;
+                            }
                         }
+                        Broadcast::Gossip => {
+                            let threshold = global.swarm_threshold;
+                            if threshold > 0 && data.len() > threshold {
+                                let published = global.swarms.write().await.publish(
+                                    &global.key,
+                                    *global.peer_id(),
+                                    &data,
+                                );
+                                if let Some((_, sealed)) = published {
+                                    debug!("Outside: Gossip by swarm, {} bytes.", data.len());
+                                    global.swarm_announce(sealed.into(), Some(data), &[]).await;
+                                    continue;
+                                }
+                            }
+                            // TODO more Gossip base on Kad.
+                            for sender in global.gossip_peers(&[]).await {
+                                let _ = sender.send(SessionMessage::Data(0, data.clone())).await;
+                            }
+                        }
+                    }
+                }
+                Some(SendMessage::GroupJoin(group)) => {
+                    group_join(&global, group, false).await;
+                }
+                Some(SendMessage::GroupJoinEncrypted(group)) => {
+                    group_join(&global, group, true).await;
+                }
+                Some(SendMessage::GroupAdmit(group, peer_id)) => {
+                    let mut groups = global.groups.write().await;
+                    if !groups.is_joined(&group) || !groups.admit(&group, peer_id) {
+                        continue;
                     }
-                    Broadcast::Gossip => {
-                        // TODO more Gossip base on Kad.
-                        for (_to, sender) in global.peer_list.read().await.all() {
-                            let _ = sender.send(SessionMessage::Data(0, data.clone())).await;
+                    let own_key = groups.own_key(&group);
+                    let is_member = groups.members(&group).contains(&peer_id);
+                    drop(groups);
+                    debug!("Outside: GroupAdmit {} {}.", group, peer_id.short_show());
+                    if let (Some((epoch, key)), true) = (own_key, is_member) {
+                        if let Some(sender) = global.routes.get(&peer_id) {
+                            let msg = SessionMessage::GroupKey(group, epoch, key);
+                            let _ = sender.send(msg).await;
                         }
                     }
-                },
-                Some(SendMessage::Stream(_symbol, _stream_type, _data)) => {
-                    // TODO WIP
                 }
-                Some(SendMessage::NetworkState(req, res_sender)) => match req {
-                    StateRequest::Stable => {
-                        let peers = global
-                            .peer_list
-                            .read()
-                            .await
-                            .stable_all()
-                            .iter()
-                            .map(|(id, (_, is_direct))| (*id, *is_direct))
-                            .collect();
-                        let _ = res_sender.send(StateResponse::Stable(peers)).await;
+                Some(SendMessage::GroupLeave(group)) => {
+                    if !global.groups.write().await.leave(&group) {
+                        continue;
                     }
-                    StateRequest::DHT => {
-                        let peers = global.peer_list.read().await.dht_keys();
-                        let _ = res_sender.send(StateResponse::DHT(peers)).await;
+                    debug!("Outside: GroupLeave {}.", group);
+                    for (_, sender) in global.peer_list.read().await.all() {
+                        let _ = sender.send(SessionMessage::GroupLeave(group.clone())).await;
                     }
-                    StateRequest::Seed => {
-                        let seeds = global
-                            .peer_list
+                    global.topic_sync(&group, false).await;
+                }
+                Some(SendMessage::GroupData(group, data)) => {
+                    if data.len() > global.max_message_size {
+                        global.oversize(DeliveryType::Data, 0, data).await;
+                        continue;
+                    }
+                    // encrypted group's data is sealed once by sender key.
+                    let mut groups = global.groups.write().await;
+                    let sealed = groups.seal(&group, &data).map(Bytes::from);
+                    let members = groups.members(&group);
+                    drop(groups);
+                    let data = Bytes::from(data);
+                    for peer_id in members {
+                        if let Some(sender) = global.routes.get(&peer_id) {
+                            let msg = match &sealed {
+                                Some(sealed) => {
+                                    SessionMessage::GroupSealed(group.clone(), sealed.clone())
+                                }
+                                None => SessionMessage::GroupData(group.clone(), data.clone()),
+                            };
+                            let _ = sender.send(msg).await;
+                        }
+                    }
+                }
+                Some(SendMessage::RegisterService(name)) => {
+                    if name.len() > SERVICE_NAME_MAX {
+                        warn!("CHAMOMILE: SERVICE NAME IS TOO LONG.");
+                        continue;
+                    }
+                    if global.services.write().await.register(&name) {
+                        debug!("Outside: RegisterService {}.", name);
+                        service_announce(&global, true, name).await;
+                    }
+                }
+                Some(SendMessage::UnregisterService(name)) => {
+                    if global.services.write().await.unregister(&name) {
+                        debug!("Outside: UnregisterService {}.", name);
+                        service_announce(&global, false, name).await;
+                    }
+                }
+                Some(SendMessage::FindService(name)) => {
+                    find_service(&global, name).await;
+                }
+                Some(SendMessage::MailboxSend(tid, to, data)) => {
+                    mailbox_send(&global, tid, to, data).await;
+                }
+                Some(SendMessage::IssueJoinToken(subject, audience, ttl, res_sender)) => {
+                    debug!("Outside: IssueJoinToken for {}.", subject.short_show());
+                    let token = token::issue(&global.key, &subject, audience, ttl);
+                    let _ = res_sender.send(token.unwrap_or_default()).await;
+                }
+                Some(SendMessage::Deadline(..)) => {
+                    warn!("CHAMOMILE: NESTED DEADLINE MESSAGE.");
+                }
+                Some(SendMessage::Stream(_symbol, _stream_type, _data)) => {
+                    // TODO WIP
+                }
+                Some(SendMessage::NetworkState(req, res_sender)) => match req {
+                    StateRequest::Stable => {
+                        let peers = global
+                            .peer_list
+                            .read()
+                            .await
+                            .stable_all()
+                            .iter()
+                            .map(|(id, (_, is_direct))| (*id, *is_direct))
+                            .collect();
+                        let _ = res_sender.send(StateResponse::Stable(peers)).await;
+                    }
+                    StateRequest::DHT => {
+                        let peers = global.peer_list.read().await.dht_keys();
+                        let _ = res_sender.send(StateResponse::DHT(peers)).await;
+                    }
+                    StateRequest::Seed => {
+                        let seeds = global.peer_list.read().await.seeds();
+                        let infos = global.seed_health.infos(seeds);
+                        let _ = res_sender.send(StateResponse::Seed(infos)).await;
+                    }
+                    StateRequest::Tags => {
+                        let tags = global.peer_list.read().await.tags();
+                        let _ = res_sender.send(StateResponse::Tags(tags)).await;
+                    }
+                    StateRequest::Names => {
+                        let names = global.peer_list.read().await.names();
+                        let _ = res_sender.send(StateResponse::Names(names)).await;
+                    }
+                    StateRequest::Pending => {
+                        let (tmps, connects, results) = global.buffer.pending().await;
+                        let _ = res_sender
+                            .send(StateResponse::Pending(tmps, connects, results))
+                            .await;
+                    }
+                    StateRequest::Groups => {
+                        let groups = global.groups.read().await.all();
+                        let _ = res_sender.send(StateResponse::Groups(groups)).await;
+                    }
+                    StateRequest::PeerInfo(peer_id) => {
+                        let info = peer_info(&global, &peer_id).await;
+                        let _ = res_sender.send(StateResponse::PeerInfo(info)).await;
+                    }
+                    StateRequest::Relays => {
+                        let relays = relay_infos(&global).await;
+                        let _ = res_sender.send(StateResponse::Relays(relays)).await;
+                    }
+                    StateRequest::Latency => {
+                        let latencies = global
+                            .latencies
                             .read()
                             .await
-                            .bootstrap()
                             .iter()
-                            .map(|p| **p)
+                            .map(|(id, rtt)| (*id, *rtt))
                             .collect();
-                        let _ = res_sender.send(StateResponse::Seed(seeds)).await;
+                        let _ = res_sender.send(StateResponse::Latency(latencies)).await;
+                    }
+                    StateRequest::Identity => {
+                        let res = StateResponse::Identity(*global.peer_id(), global.key.to_bytes());
+                        let _ = res_sender.send(res).await;
+                    }
+                    StateRequest::Time => {
+                        let median = global.clock_median().await;
+                        let offsets = global
+                            .clock_offsets
+                            .read()
+                            .await
+                            .iter()
+                            .map(|(id, offset)| (*id, *offset))
+                            .collect();
+                        let _ = res_sender.send(StateResponse::Time(median, offsets)).await;
+                    }
+                    StateRequest::Bandwidth => {
+                        let peers = global
+                            .bandwidths
+                            .read()
+                            .await
+                            .iter()
+                            .map(|(id, b)| {
+                                let (sent, received) = b.get();
+                                (*id, sent, received)
+                            })
+                            .collect();
+                        let transports = global
+                            .transport_bandwidths
+                            .iter()
+                            .map(|(t, b)| {
+                                let (sent, received) = b.get();
+                                (*t, sent, received)
+                            })
+                            .collect();
+                        let _ = res_sender
+                            .send(StateResponse::Bandwidth(peers, transports))
+                            .await;
                     }
                 },
                 Some(SendMessage::NetworkReboot) => {
-                    // rebootstrap allow list.
-                    for a in global.peer_list.read().await.bootstrap() {
-                        let (session_key, remote_pk) = global.generate_remote();
+                    // rebootstrap allow list, and reconnect the dropped stables.
+                    global.watchdog.reset();
+                    bootstrap(global.clone()).await;
+                    stable_reboot(global.clone(), recv_data, false).await;
+                }
+                Some(SendMessage::PowerProfile(profile)) => {
+                    debug!("Outside: PowerProfile {:?}.", profile);
+                    global.set_power(profile);
+                }
+                Some(SendMessage::Capture(peer_id, enable)) => {
+                    debug!("Outside: Capture {} {}.", peer_id.short_show(), enable);
+                    #[cfg(feature = "capture")]
+                    global.capture.set(peer_id, enable);
+                    #[cfg(not(feature = "capture"))]
+                    warn!("CHAMOMILE: CAPTURE NEED `capture` FEATURE.");
+                }
+                Some(SendMessage::Record(peer_id, enable)) => {
+                    debug!("Outside: Record {} {}.", peer_id.short_show(), enable);
+                    #[cfg(feature = "capture")]
+                    global.recorder.set(peer_id, enable);
+                    #[cfg(not(feature = "capture"))]
+                    warn!("CHAMOMILE: RECORD NEED `capture` FEATURE.");
+                }
+                Some(SendMessage::Replay(path, peer_id)) => {
+                    debug!("Outside: Replay {} {:?}.", peer_id.short_show(), path);
+                    #[cfg(feature = "capture")]
+                    {
+                        let global = global.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = crate::record::replay(global, &path, peer_id).await {
+                                warn!("CHAMOMILE: REPLAY FAILURE: {:?}", e);
+                            }
+                        });
+                    }
+                    #[cfg(not(feature = "capture"))]
+                    warn!("CHAMOMILE: REPLAY NEED `capture` FEATURE.");
+                }
+                Some(SendMessage::PeerPolicy(peer_id, policy)) => {
+                    debug!("Outside: PeerPolicy {} {:?}.", peer_id.short_show(), policy);
+                    let mut policies = global.policies.write().await;
+                    if policy == PeerPolicy::default() {
+                        policies.remove(&peer_id);
+                    } else {
+                        policies.insert(peer_id, policy);
+                    }
+                }
+                Some(SendMessage::PeerQos(peer_id, class)) => {
+                    debug!("Outside: PeerQos {} {:?}.", peer_id.short_show(), class);
+                    if let Some(qos) = &global.qos {
+                        qos.set_class(peer_id, class).await;
+                    }
+                }
+                Some(SendMessage::Ping(tid, to)) => {
+                    debug!("Outside: Ping to {}.", to.short_show());
+                    if let Some(sender) = global.routes.get(&to) {
+                        let _ = sender.send(SessionMessage::Ping(tid)).await;
+                    } else {
+                        let _ = global.out_send(ReceiveMessage::Pong(tid, to, None)).await;
+                    }
+                }
+                Some(SendMessage::Heartbeat(payload)) => {
+                    debug!("Outside: Heartbeat {} bytes.", payload.len());
+                    if payload.len() > MAX_HEARTBEAT_LENGTH {
+                        warn!("CHAMOMILE: HEARTBEAT PAYLOAD IS TOO LONG.");
+                    } else {
+                        *global.heartbeat.write().await = payload;
+                    }
+                }
+                Some(SendMessage::ShareBlock(target, time)) => {
+                    debug!("Outside: ShareBlock {:?} {}s.", target, time.as_secs());
+                    match ban::seal_block(&global.key, &target, time) {
+                        Some(sealed) => global.share_block(sealed, target, time, &[]).await,
+                        None => warn!("CHAMOMILE: SHARED BLOCK SIGN FAILURE."),
+                    }
+                }
+                Some(SendMessage::BlockOverride(target, is_override)) => {
+                    debug!("Outside: BlockOverride {:?} {}.", target, is_override);
+                    global.shared_bans.set_override(target, is_override);
+                }
+                Some(SendMessage::ProbeAddr(tid, peer)) => {
+                    debug!("Outside: ProbeAddr to {}.", peer.socket);
+                    let (session_key, remote_pk) = global.generate_remote();
+                    if !global.probe_start(tid, peer, session_key.out_bytes()).await {
                         let _ = global
-                            .trans_send(
-                                &a.transport,
-                                TransportSendMessage::Connect(a.socket, remote_pk, session_key),
-                            )
+                            .out_send(ReceiveMessage::Probe(tid, peer, None))
                             .await;
+                        continue;
                     }
+                    let kind = DialKind::Probe(session_key, remote_pk);
+                    if !global.dialer.dial(peer, kind, None) {
+                        global.probe_cancel(&peer.socket, tid).await;
+                        let _ = global
+                            .out_send(ReceiveMessage::Probe(tid, peer, None))
+                            .await;
+                        continue;
+                    }
+                    let g = global.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(DIAL_TIMEOUT).await;
+                        if g.probe_cancel(&peer.socket, tid).await {
+                            let _ = g.out_send(ReceiveMessage::Probe(tid, peer, None)).await;
+                        }
+                    });
+                }
+                Some(SendMessage::ExportPeers(path)) => {
+                    debug!("Outside: ExportPeers to {:?}.", path);
+                    let content = global.peer_list.read().await.to_file_string();
+                    let error = match tokio::fs::write(&path, content).await {
+                        Ok(()) => None,
+                        Err(e) => {
+                            warn!("CHAMOMILE: EXPORT PEERS FAILURE: {:?}", e);
+                            Some(e.to_string())
+                        }
+                    };
+                    let _ = global
+                        .out_send(ReceiveMessage::PeersExported(path, error))
+                        .await;
+                }
+                Some(SendMessage::NamePeer(name, peer_id)) => {
+                    debug!("Outside: NamePeer {} {}.", name, peer_id.short_show());
+                    if !global.peer_list.write().await.add_name(name, peer_id).await {
+                        warn!("CHAMOMILE: PEER NAME IS INVALID.");
+                    }
+                }
+                Some(SendMessage::UnnamePeer(name)) => {
+                    debug!("Outside: UnnamePeer {}.", name);
+                    global.peer_list.write().await.remove_name(&name).await;
+                }
+                Some(SendMessage::TagPeer(peer_id, tag)) => {
+                    debug!("Outside: TagPeer {} {}.", peer_id.short_show(), tag);
+                    if !global.peer_list.write().await.add_tag(peer_id, tag).await {
+                        warn!("CHAMOMILE: PEER TAG IS INVALID.");
+                    }
+                }
+                Some(SendMessage::UntagPeer(peer_id, tag)) => {
+                    debug!("Outside: UntagPeer {} {}.", peer_id.short_show(), tag);
+                    global
+                        .peer_list
+                        .write()
+                        .await
+                        .remove_tag(&peer_id, &tag)
+                        .await;
+                }
+                Some(SendMessage::Subscribe(filter, sender)) => {
+                    debug!("Outside: Subscribe {:?}.", filter);
+                    let mut subscribers = global.subscribers.write().await;
+                    subscribers.retain(|(_, s)| !s.is_closed());
+                    subscribers.push((filter, sender));
+                }
+                None => {
+                    global.outbox_flush().await;
+                    break;
                 }
-                None => break,
             }
         }
     });
 
     Ok(peer_id)
 }
+
+/// start a stable connection to the peer, if connected, send the connect info to it.
+pub(crate) async fn stable_connect(
+    global: &Arc<Global>,
+    tid: u64,
+    to: Peer,
+    data: Vec<u8>,
+    recv_data: bool,
+) {
+    debug!("Outside: StableConnect to {}.", to.id.short_show());
+    if &to.id == global.peer_id() {
+        warn!("CHAMOMILE: STABLE CONNECT NERVER TO SELF.");
+        if tid != 0 {
+            let _ = global
+                .out_send(ReceiveMessage::Delivery(
+                    DeliveryType::StableConnect,
+                    tid,
+                    Err(DeliveryFailure::Invalid),
+                    global.delivery(&data),
+                ))
+                .await;
+        }
+        return;
+    }
+
+    if global.peer_list.read().await.is_block_peer(&to.id) {
+        debug!("Outside: StableConnect to blocked peer.");
+        if tid != 0 {
+            let _ = global
+                .out_send(ReceiveMessage::Delivery(
+                    DeliveryType::StableConnect,
+                    tid,
+                    Err(DeliveryFailure::Blocked),
+                    global.delivery(&data),
+                ))
+                .await;
+        }
+        return;
+    }
+
+    // 1. get it or closest peer.
+    let peer_list_lock = global.peer_list.read().await;
+    let results = peer_list_lock.get(&to.id);
+    if results.is_none() {
+        drop(peer_list_lock);
+        warn!("CHAMOMILE: CANNOT REACH NETWORK.");
+        if tid != 0 {
+            let _ = global
+                .out_send(ReceiveMessage::Delivery(
+                    DeliveryType::StableConnect,
+                    tid,
+                    Err(DeliveryFailure::PeerOffline),
+                    global.delivery(&data),
+                ))
+                .await;
+        }
+        return;
+    }
+
+    // 2. if connected, send to remote.
+    let (s, _, is_it) = results.unwrap(); // safe checked.
+    if is_it {
+        debug!("Outside: StableConnect multiple stable connected.");
+        let _ = s.send(SessionMessage::StableConnect(tid, data)).await;
+        drop(peer_list_lock);
+    } else {
+        let ss = s.clone();
+        drop(peer_list_lock);
+
+        // 3. check if had in buffer tmp.
+        if let Some(sender) = global.buffer.get_tmp_session(to.id).await {
+            debug!("Outside: StableConnect is in tmp, send to it.");
+            let _ = sender.send(SessionMessage::StableConnect(tid, data)).await;
+            return;
+        }
+
+        // 4. add to stable buffer.
+        let delivery = global.delivery(&data);
+        if global.journal.read().await.is_enable() {
+            global
+                .journal
+                .write()
+                .await
+                .add(true, tid, to, data.clone())
+                .await;
+        }
+        let (is_processing, dropped) = match global.buffer.add_connect(to.id, tid, data).await {
+            Ok(v) => v,
+            Err((data, mut dropped)) => {
+                warn!("CHAMOMILE: BUFFER IS FULL.");
+                dropped.push((DeliveryType::StableConnect, tid, data));
+                global
+                    .delivery_failures(dropped, DeliveryFailure::BufferFull)
+                    .await;
+                return;
+            }
+        };
+        global
+            .delivery_failures(dropped, DeliveryFailure::BufferFull)
+            .await;
+        if is_processing {
+            debug!("Outside: StableConnect is processing, save to buffer.");
+            return;
+        }
+
+        debug!("Outside: StableConnect start new connection.");
+        spawn_stable(global, tid, delivery, to, ss, recv_data).await;
+    }
+}
+
+/// resend the saved buffered stable connects & results of last running,
+/// waiting the network online first.
+async fn journal_replay(global: Arc<Global>, ttl: Duration, recv_data: bool) {
+    let replays = global.journal.read().await.replays();
+    if replays.is_empty() {
+        return;
+    }
+
+    let deadline = Instant::now() + ttl;
+    while !global.online.load(Ordering::SeqCst) && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    info!("Resend {} saved stable connects & results.", replays.len());
+    for (is_connect, tid, peer, data) in replays {
+        if is_connect {
+            stable_connect(&global, tid, peer, data, recv_data).await;
+        } else {
+            stable_result(&global, tid, peer, true, false, data, recv_data).await;
+        }
+    }
+}
+
+pub(crate) async fn stable_result(
+    global: &Arc<Global>,
+    tid: u64,
+    to: Peer,
+    is_ok: bool,
+    is_force: bool,
+    data: Vec<u8>,
+    recv_data: bool,
+) {
+    if data.len() > global.max_message_size {
+        global.oversize(DeliveryType::StableResult, tid, data).await;
+        return;
+    }
+    debug!("Outside: StableResult to {}.", to.id.short_show());
+    if &to.id == global.peer_id() {
+        warn!("CHAMOMILE: STABLE CONNECT NERVER TO SELF.");
+        if tid != 0 {
+            let _ = global
+                .out_send(ReceiveMessage::Delivery(
+                    DeliveryType::StableResult,
+                    tid,
+                    Err(DeliveryFailure::Invalid),
+                    global.delivery(&data),
+                ))
+                .await;
+        }
+        return;
+    }
+
+    // 1. check if in tmp.
+    if let Some(sender) = global.buffer.get_tmp_session(to.id).await {
+        debug!("Outside: StableResult get the tmp session.");
+        let _ = sender
+            .send(SessionMessage::StableResult(tid, is_ok, is_force, data))
+            .await;
+        return;
+    }
+
+    // 2. check if in DHT or stable.
+    let peer_list_lock = global.peer_list.read().await;
+    let results = peer_list_lock.get(&to.id);
+    if results.is_none() {
+        drop(peer_list_lock);
+        warn!("CHAMOMILE: CANNOT REACH NETWORK.");
+        if tid != 0 {
+            let _ = global
+                .out_send(ReceiveMessage::Delivery(
+                    DeliveryType::StableResult,
+                    tid,
+                    Err(DeliveryFailure::PeerOffline),
+                    global.delivery(&data),
+                ))
+                .await;
+        }
+        return;
+    }
+
+    let (s, _, is_it) = results.unwrap(); // safe checked.
+    if is_it {
+        debug!("Outside: StableResult get the is_it session.");
+        let _ = s
+            .send(SessionMessage::StableResult(tid, is_ok, is_force, data))
+            .await;
+        drop(peer_list_lock);
+    } else {
+        // 3. check if is_ok, if ok, start stable connected.
+        if !is_ok {
+            drop(peer_list_lock);
+            return;
+        }
+
+        let ss = s.clone();
+        drop(peer_list_lock);
+
+        // 4. check if had in buffer tmp.
+        if let Some(sender) = global.buffer.get_tmp_session(to.id).await {
+            debug!("Outside: StableResult had tmp session.");
+            let _ = sender
+                .send(SessionMessage::StableResult(tid, is_ok, is_force, data))
+                .await;
+            return;
+        }
+
+        // 5. add to stable buffer.
+        let delivery = global.delivery(&data);
+        if global.journal.read().await.is_enable() {
+            global
+                .journal
+                .write()
+                .await
+                .add(false, tid, to, data.clone())
+                .await;
+        }
+        let (is_processing, dropped) = match global.buffer.add_result(to.id, tid, data).await {
+            Ok(v) => v,
+            Err((data, mut dropped)) => {
+                warn!("CHAMOMILE: BUFFER IS FULL.");
+                dropped.push((DeliveryType::StableResult, tid, data));
+                global
+                    .delivery_failures(dropped, DeliveryFailure::BufferFull)
+                    .await;
+                return;
+            }
+        };
+        global
+            .delivery_failures(dropped, DeliveryFailure::BufferFull)
+            .await;
+        if is_processing {
+            debug!("Outside: StableResult is processing, save to buffer.");
+            return;
+        }
+
+        debug!("Outside: StableResult start new connection with ID.");
+        spawn_stable(global, tid, delivery, to, ss, recv_data).await;
+    }
+}
+
+/// join the group, tell connected peers. if `is_encrypted`, the group's data is
+/// sealed by self's sender key, and the key is sent to the allowlisted and admitted
+/// members.
+async fn group_join(global: &Arc<Global>, group: String, is_encrypted: bool) {
+    if group.len() > GROUP_NAME_MAX {
+        warn!("CHAMOMILE: GROUP NAME IS TOO LONG.");
+        return;
+    }
+    let mut groups = global.groups.write().await;
+    let is_new = groups.join(&group);
+    let own_key = if is_encrypted && groups.own_key(&group).is_none() {
+        Some(groups.own_key_or_init(&group))
+    } else {
+        None
+    };
+    let members = groups.members(&group);
+    for peer_id in &members {
+        if global.allowlisted.contains(peer_id) {
+            groups.admit(&group, *peer_id);
+        }
+    }
+    let admitted = groups.admitted(&group);
+    drop(groups);
+
+    if let Some((epoch, key)) = own_key {
+        debug!("Outside: group {} is encrypted.", group);
+        for peer_id in &admitted {
+            if let Some(sender) = global.routes.get(peer_id) {
+                let msg = SessionMessage::GroupKey(group.clone(), epoch, key);
+                let _ = sender.send(msg).await;
+            }
+        }
+    }
+    if !is_new {
+        return;
+    }
+
+    debug!("Outside: GroupJoin {}.", group);
+    for (_, sender) in global.peer_list.read().await.all() {
+        let _ = sender.send(SessionMessage::GroupJoin(group.clone())).await;
+    }
+    global.topic_sync(&group, true).await;
+    for peer_id in members {
+        let _ = global
+            .out_send(ReceiveMessage::GroupJoin(group.clone(), peer_id))
+            .await;
+    }
+}
+
+/// tell all connected peers self registered or unregistered the service,
+/// and publish the provider record to the closest peers of the service key.
+async fn service_announce(global: &Arc<Global>, is_register: bool, name: String) {
+    for (_, sender) in global.peer_list.read().await.all() {
+        let _ = sender
+            .send(SessionMessage::ServiceAnnounce(is_register, name.clone()))
+            .await;
+    }
+    global.service_sync(&name, is_register).await;
+}
+
+/// find service's providers, the connected providers, and ask all connected peers
+/// for their connected providers, when all replied or timeout, return to outside.
+/// the connected peer's session info.
+async fn peer_info(global: &Arc<Global>, peer_id: &PeerId) -> Option<PeerInfo> {
+    let (peer, is_stable, is_direct) = global.peer_list.read().await.peer_info(peer_id)?;
+    let rtt = global.latencies.read().await.get(peer_id).copied();
+    let (sent, received, age) = match global.bandwidths.read().await.get(peer_id) {
+        Some(b) => {
+            let (sent, received) = b.get();
+            (sent, received, b.age())
+        }
+        None => (0, 0, Duration::ZERO),
+    };
+    Some(PeerInfo {
+        peer,
+        is_stable,
+        is_direct,
+        age,
+        rtt,
+        sent,
+        received,
+    })
+}
+
+/// the relay-connected stable peers' circuits.
+async fn relay_infos(global: &Arc<Global>) -> Vec<RelayInfo> {
+    let stables: Vec<PeerId> = global
+        .peer_list
+        .read()
+        .await
+        .stable_all()
+        .into_iter()
+        .filter(|(_, (_, is_direct))| !is_direct)
+        .map(|(id, _)| id)
+        .collect();
+    let relay_paths = global.relay_paths.read().await;
+    let latencies = global.latencies.read().await;
+    let bandwidths = global.bandwidths.read().await;
+    stables
+        .into_iter()
+        .filter_map(|peer| {
+            let relay = *relay_paths.get(&peer)?;
+            let (sent, received, age) = match bandwidths.get(&peer) {
+                Some(b) => {
+                    let (sent, received) = b.get();
+                    (sent, received, b.age())
+                }
+                None => (0, 0, Duration::ZERO),
+            };
+            Some(RelayInfo {
+                peer,
+                relay,
+                age,
+                rtt: latencies.get(&peer).copied(),
+                sent,
+                received,
+            })
+        })
+        .collect()
+}
+
+/// seal the mail, and store it at the connected mailboxes closest to the recipient.
+async fn mailbox_send(global: &Arc<Global>, tid: u64, to: PeerId, data: Vec<u8>) {
+    debug!("Outside: MailboxSend to {}.", to.short_show());
+    if data.len() > global.max_message_size {
+        global.oversize(DeliveryType::Data, tid, data).await;
+        return;
+    }
+
+    let to_key = global.peer_keys.lock().ok().and_then(|keys| keys.get(&to));
+    let to_key = match to_key {
+        Some(key) => key,
+        None => {
+            debug!("Outside: MailboxSend unknown key of {}.", to.short_show());
+            if tid != 0 {
+                let _ = global
+                    .out_send(ReceiveMessage::Delivery(
+                        DeliveryType::Data,
+                        tid,
+                        Err(DeliveryFailure::UnknownKey),
+                        global.delivery(&data),
+                    ))
+                    .await;
+            }
+            return;
+        }
+    };
+
+    let id: u64 = rand::thread_rng().gen();
+    let mut stored = false;
+    let from = global.peer.id;
+    if let Some(sealed) = mailbox::seal(&global.key, &from, id, &to, &to_key, &data) {
+        let closest = global.mailbox.read().await.closest(&to);
+        for peer_id in closest {
+            if let Some(sender) = global.routes.get(&peer_id) {
+                if sender
+                    .send(SessionMessage::MailboxStore(sealed.clone()))
+                    .await
+                    .is_ok()
+                {
+                    stored = true;
+                }
+            }
+        }
+    }
+    if !stored {
+        debug!("Outside: MailboxSend no connected mailbox.");
+    }
+
+    if tid != 0 {
+        let result = if stored {
+            Ok(())
+        } else {
+            Err(DeliveryFailure::PeerOffline)
+        };
+        let _ = global
+            .out_send(ReceiveMessage::Delivery(
+                DeliveryType::Data,
+                tid,
+                result,
+                global.delivery(&data),
+            ))
+            .await;
+    }
+}
+
+async fn find_service(global: &Arc<Global>, name: String) {
+    debug!("Outside: FindService {}.", name);
+    let qid: u64 = rand::thread_rng().gen();
+    let mut found = global
+        .services
+        .read()
+        .await
+        .providers(&name, global.peer_id());
+    // the stored provider records, when self is the closest peer of the service.
+    for peer in global
+        .topics
+        .read()
+        .await
+        .members(&service_key(&name), global.peer_id())
+    {
+        if !found.iter().any(|p| p.id == peer.id) {
+            found.push(peer);
+        }
+    }
+    let senders: Vec<Sender<SessionMessage>> = global
+        .peer_list
+        .read()
+        .await
+        .all()
+        .into_values()
+        .cloned()
+        .collect();
+
+    if senders.is_empty() {
+        let _ = global.out_send(ReceiveMessage::Services(name, found)).await;
+        return;
+    }
+
+    let started =
+        global
+            .services
+            .write()
+            .await
+            .query_start(qid, name.clone(), senders.len(), found.clone());
+    if !started {
+        warn!("CHAMOMILE: TOO MANY SERVICE QUERIES.");
+        let _ = global.out_send(ReceiveMessage::Services(name, found)).await;
+        return;
+    }
+    for sender in senders {
+        if sender
+            .send(SessionMessage::ServiceQuery(qid, name.clone()))
+            .await
+            .is_err()
+        {
+            // session closed, not wait it.
+            let result = global.services.write().await.query_reply(qid, vec![]);
+            if let Some((name, providers)) = result {
+                let _ = global
+                    .out_send(ReceiveMessage::Services(name, providers))
+                    .await;
+            }
+        }
+    }
+
+    let g = global.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(SERVICE_QUERY_TIMEOUT).await;
+        let result = g.services.write().await.query_end(qid);
+        if let Some((name, providers)) = result {
+            let _ = g.out_send(ReceiveMessage::Services(name, providers)).await;
+        }
+    });
+}
+
+/// send data to the peer, directly or relay, dial it if the address is known,
+/// or save to outbox if it is offline.
+async fn send_data(global: &Arc<Global>, tid: u64, to: PeerId, data: Bytes) {
+    if global.peer_list.read().await.is_block_peer(&to) {
+        debug!("Outside: Data to blocked peer.");
+        if tid != 0 {
+            let _ = global
+                .out_send(ReceiveMessage::Delivery(
+                    DeliveryType::Data,
+                    tid,
+                    Err(DeliveryFailure::Blocked),
+                    global.delivery(&data),
+                ))
+                .await;
+        }
+        return;
+    }
+
+    // check if send to self. better circle for application.
+    if &to == global.peer_id() {
+        info!("CHAMOMILE: DATA TO SELF.");
+        if tid != 0 {
+            let _ = global
+                .out_send(ReceiveMessage::Delivery(
+                    DeliveryType::Data,
+                    tid,
+                    Ok(()),
+                    global.delivery(&data),
+                ))
+                .await;
+        }
+        let msg = global.data_message(to, DataPath::Local, data.to_vec());
+        let _ = global.out_send(msg).await;
+        return;
+    }
+
+    if let Some(sender) = global.routes.get(&to) {
+        let _ = sender.send(SessionMessage::Data(tid, data)).await;
+    } else if let Some(peer) = global.dial_target(&to).await {
+        global.dial_data(peer, tid, data.to_vec()).await;
+    } else if let Some((sender, _, is_it)) = global.peer_list.read().await.get(&to) {
+        if is_it {
+            let _ = sender.send(SessionMessage::Data(tid, data)).await;
+        } else {
+            // only happen on permissionless.
+            let _ = sender
+                .send(SessionMessage::RelayData(
+                    *global.peer_id(),
+                    to,
+                    vec![],
+                    data,
+                ))
+                .await;
+        }
+    } else if global.outbox.read().await.is_enable()
+        && global.peer_list.read().await.is_allow_peer(&to)
+    {
+        debug!("Outside: Data to offline stable peer, save to outbox.");
+        let dropped = global.outbox.write().await.push(to, tid, data.to_vec());
+        if let Some((tid, data)) = dropped {
+            if tid != 0 {
+                let _ = global
+                    .out_send(ReceiveMessage::Delivery(
+                        DeliveryType::Data,
+                        tid,
+                        Err(DeliveryFailure::BufferFull),
+                        global.delivery(&data),
+                    ))
+                    .await;
+            }
+        }
+    } else {
+        warn!("CHAMOMILE: CANNOT REACH NETWORK.");
+        if tid != 0 {
+            let _ = global
+                .out_send(ReceiveMessage::Delivery(
+                    DeliveryType::Data,
+                    tid,
+                    Err(DeliveryFailure::PeerOffline),
+                    global.delivery(&data),
+                ))
+                .await;
+        }
+    }
+}
+
+/// replace the petname placeholder ids of the message's target,
+/// if any petname is not registered, return back the message as error.
+fn resolve_names(
+    peer_list: &PeerList,
+    msg: SendMessage,
+) -> std::result::Result<SendMessage, SendMessage> {
+    let unknown = std::cell::Cell::new(false);
+    let id = |peer_id| {
+        peer_list.resolve(peer_id).unwrap_or_else(|| {
+            unknown.set(true);
+            peer_id
+        })
+    };
+    let peer = |mut p: Peer| {
+        p.id = id(p.id);
+        p
+    };
+    let msg = match msg {
+        SendMessage::StableConnect(tid, to, data) => {
+            SendMessage::StableConnect(tid, peer(to), data)
+        }
+        SendMessage::StableResult(tid, to, is_ok, is_force, data) => {
+            SendMessage::StableResult(tid, peer(to), is_ok, is_force, data)
+        }
+        SendMessage::StableDisconnect(to) => SendMessage::StableDisconnect(id(to)),
+        SendMessage::StableCancel(tid, to) => SendMessage::StableCancel(tid, id(to)),
+        SendMessage::GroupAdmit(group, to) => SendMessage::GroupAdmit(group, id(to)),
+        SendMessage::Connect(to) => SendMessage::Connect(peer(to)),
+        SendMessage::Data(tid, to, data) => SendMessage::Data(tid, id(to), data),
+        SendMessage::RedundantData(tid, to, data) => SendMessage::RedundantData(tid, id(to), data),
+        SendMessage::DataMulti(tid, tos, data) => {
+            SendMessage::DataMulti(tid, tos.into_iter().map(id).collect(), data)
+        }
+        SendMessage::Ping(tid, to) => SendMessage::Ping(tid, id(to)),
+        SendMessage::PeerQos(to, class) => SendMessage::PeerQos(id(to), class),
+        SendMessage::PeerPolicy(to, policy) => SendMessage::PeerPolicy(id(to), policy),
+        SendMessage::Capture(to, enable) => SendMessage::Capture(id(to), enable),
+        SendMessage::Record(to, enable) => SendMessage::Record(id(to), enable),
+        SendMessage::Replay(path, to) => SendMessage::Replay(path, id(to)),
+        SendMessage::TagPeer(to, tag) => SendMessage::TagPeer(id(to), tag),
+        SendMessage::UntagPeer(to, tag) => SendMessage::UntagPeer(id(to), tag),
+        SendMessage::ProtocolData(tid, to, protocol, data) => {
+            SendMessage::ProtocolData(tid, id(to), protocol, data)
+        }
+        SendMessage::MailboxSend(tid, to, data) => SendMessage::MailboxSend(tid, id(to), data),
+        SendMessage::IssueJoinToken(subject, audience, ttl, sender) => {
+            SendMessage::IssueJoinToken(id(subject), audience.map(id), ttl, sender)
+        }
+        SendMessage::ShareBlock(BlockTarget::Peer(to), time) => {
+            SendMessage::ShareBlock(BlockTarget::Peer(id(to)), time)
+        }
+        SendMessage::BlockOverride(BlockTarget::Peer(to), is_override) => {
+            SendMessage::BlockOverride(BlockTarget::Peer(id(to)), is_override)
+        }
+        SendMessage::NetworkState(StateRequest::PeerInfo(to), sender) => {
+            SendMessage::NetworkState(StateRequest::PeerInfo(id(to)), sender)
+        }
+        SendMessage::Deadline(deadline, msg) => match resolve_names(peer_list, *msg) {
+            Ok(msg) => SendMessage::Deadline(deadline, Box::new(msg)),
+            Err(msg) => return Err(SendMessage::Deadline(deadline, Box::new(msg))),
+        },
+        msg => msg,
+    };
+    if unknown.get() {
+        Err(msg)
+    } else {
+        Ok(msg)
+    }
+}
+
+/// the message's target petname is not registered, failure delivery to outside.
+async fn unresolved_failure(global: &Global, msg: SendMessage) {
+    let (t, tid, data) = match msg {
+        SendMessage::Data(tid, _, data)
+        | SendMessage::RedundantData(tid, _, data)
+        | SendMessage::DataMulti(tid, _, data)
+        | SendMessage::ProtocolData(tid, _, _, data)
+        | SendMessage::MailboxSend(tid, _, data) => (DeliveryType::Data, tid, data),
+        SendMessage::StableConnect(tid, _, data) => (DeliveryType::StableConnect, tid, data),
+        SendMessage::StableResult(tid, _, _, _, data) => (DeliveryType::StableResult, tid, data),
+        SendMessage::Deadline(_, msg) => return Box::pin(unresolved_failure(global, *msg)).await,
+        _ => return,
+    };
+    if tid != 0 {
+        let _ = global
+            .out_send(ReceiveMessage::Delivery(
+                t,
+                tid,
+                Err(DeliveryFailure::Invalid),
+                global.delivery(&data),
+            ))
+            .await;
+    }
+}
+
+/// check the message's deadline, if expired, return failure delivery and false.
+/// if not, start a timer, when timeout and the message still waiting in buffer or
+/// outbox, drop it and return failure delivery.
+async fn deadline_start(global: &Arc<Global>, deadline: Instant, msg: &SendMessage) -> bool {
+    let (t, tid, peer_id, data) = match msg {
+        SendMessage::Data(tid, to, data) | SendMessage::RedundantData(tid, to, data) => {
+            (DeliveryType::Data, *tid, *to, data)
+        }
+        SendMessage::StableConnect(tid, to, data) => {
+            (DeliveryType::StableConnect, *tid, to.id, data)
+        }
+        _ => return true,
+    };
+    if tid == 0 {
+        return true;
+    }
+
+    if deadline <= Instant::now() {
+        debug!("Outside: message deadline is expired.");
+        let _ = global
+            .out_send(ReceiveMessage::Delivery(
+                t,
+                tid,
+                Err(DeliveryFailure::Timeout),
+                global.delivery(data),
+            ))
+            .await;
+        return false;
+    }
+
+    global
+        .deadlines
+        .write()
+        .await
+        .insert(tid, (t.clone(), peer_id, deadline));
+    let g = global.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep_until(deadline.into()).await;
+        // had delivery, removed.
+        if !g.deadlines.read().await.contains_key(&tid) {
+            return;
+        }
+        debug!("Message {} timeout at deadline.", tid);
+        match t {
+            DeliveryType::StableConnect => {
+                g.deadlines.write().await.remove(&tid);
+                stable_cancel(&g, tid, peer_id).await
+            }
+            _ => {
+                let data = g.outbox.write().await.remove(&peer_id, tid);
+                if data.is_none() {
+                    // waiting in the session's batch or bulk queue, the session drops
+                    // it when flush. or it had sent, removed when the keep is over.
+                    tokio::time::sleep(DEADLINE_KEEP).await;
+                }
+                if g.deadlines.write().await.remove(&tid).is_none() {
+                    return;
+                }
+                if let Some(data) = data {
+                    let _ = g
+                        .out_send(ReceiveMessage::Delivery(
+                            t,
+                            tid,
+                            Err(DeliveryFailure::Timeout),
+                            g.delivery(&data),
+                        ))
+                        .await;
+                }
+            }
+        }
+    });
+    true
+}
+
+/// spawn the stable connecting task, directly if had IP, or relay by the closest peer.
+/// keep the task, so outside can cancel it.
+async fn spawn_stable(
+    global: &Arc<Global>,
+    tid: u64,
+    delivery: Vec<u8>,
+    to: Peer,
+    ss: Sender<SessionMessage>,
+    recv_data: bool,
+) {
+    let id = to.id;
+    let mut connectings = global.connectings.write().await;
+    connectings.retain(|_, (_, _, t)| !t.is_finished());
+    if connectings.contains_key(&id) {
+        // the connecting task will send the buffered ones when connected.
+        debug!(
+            "Stable connecting to {} is in-flight, not start again.",
+            id.short_show()
+        );
+        return;
+    }
+
+    let g = global.clone();
+    let cancel_delivery = delivery.clone();
+    let task = if to.effective_socket() {
+        tokio::spawn(async move {
+            let _ = direct_stable(tid, delivery, to, g, recv_data).await;
+        })
+    } else {
+        tokio::spawn(async move {
+            let _ = relay_stable(tid, delivery, to, ss, g, recv_data).await;
+        })
+    };
+    connectings.insert(id, (tid, cancel_delivery, task.abort_handle()));
+}
+
+/// cancel a pending stable connect, remove it from buffer, if no more connects
+/// to the peer (or it is the connecting task's), stop the connecting task.
+pub(crate) async fn stable_cancel(global: &Arc<Global>, tid: u64, peer_id: PeerId) {
+    debug!("Outside: StableCancel {} to {}.", tid, peer_id.short_show());
+    let (delivery, need_abort) = match global.buffer.cancel_connect(peer_id, tid).await {
+        Some((data, is_empty)) => (Some(global.delivery(&data)), is_empty),
+        None => (None, true),
+    };
+
+    let is_stable = global
+        .peer_list
+        .read()
+        .await
+        .stable_all()
+        .contains_key(&peer_id);
+    let mut task_delivery = None;
+    if need_abort && !is_stable {
+        let mut connectings = global.connectings.write().await;
+        let is_it = match connectings.get(&peer_id) {
+            Some((t, _, task)) => !task.is_finished() && (delivery.is_some() || *t == tid),
+            None => false,
+        };
+        if is_it {
+            let (t, d, task) = connectings.remove(&peer_id).unwrap(); // safe checked.
+            drop(connectings);
+            task.abort();
+            global.buffer.remove_tmp(peer_id).await;
+            debug!("Outside: StableCancel stopped the connecting.");
+            if t == tid {
+                task_delivery = Some(d);
+            }
+        }
+    }
+
+    if let Some(delivery) = delivery.or(task_delivery) {
+        if tid != 0 {
+            let _ = global
+                .out_send(ReceiveMessage::Delivery(
+                    DeliveryType::StableConnect,
+                    tid,
+                    Err(DeliveryFailure::Cancelled),
+                    delivery,
+                ))
+                .await;
+        }
+    }
+}
+
+/// reconnect to all dropped stable peers, when network reboot or restart.
+/// if `is_restore` (restart), tell outside `StableLeave` when reconnect failure.
+pub(crate) async fn stable_reboot(global: Arc<Global>, recv_data: bool, is_restore: bool) {
+    let peers = global.peer_list.read().await.stable_dropped();
+    for peer in peers {
+        if global.reconnects.read().await.contains(&peer.id) {
+            continue;
+        }
+        let g = global.clone();
+        tokio::spawn(async move {
+            if peer.effective_socket() {
+                bootstrap_dial(&g, peer.transport, peer.socket, peer.id).await;
+            }
+            stable_reconnect(g.clone(), peer, recv_data).await;
+            let is_ok = g.peer_list.read().await.stable_all().contains_key(&peer.id);
+            if is_restore && !is_ok {
+                let _ = g.out_send(ReceiveMessage::StableLeave(peer.id)).await;
+            }
+        });
+    }
+}
+
+/// reconnect to the dropped stable peer with exponential backoff and jitter,
+/// until remote stable result, or outside disconnect it, or reach max times
+/// (at least once, when network reboot).
+/// (boxed, because session spawn it, and it spawn new sessions.)
+pub(crate) fn stable_reconnect(
+    global: Arc<Global>,
+    peer: Peer,
+    recv_data: bool,
+) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    Box::pin(async move {
+        let (times, min, max) = global.reconnect;
+        global.reconnects.write().await.insert(peer.id);
+
+        let mut delay = min;
+        for _ in 0..times.max(1) {
+            let jitter = rand::thread_rng().gen_range(0, delay.as_millis() as u64 / 2 + 1);
+            tokio::time::sleep(delay + Duration::from_millis(jitter)).await;
+
+            if !global.reconnects.read().await.contains(&peer.id) || global.out_sender.is_closed() {
+                return;
+            }
+
+            // waiting remote's stable result.
+            if global
+                .peer_list
+                .read()
+                .await
+                .stable_all()
+                .contains_key(&peer.id)
+            {
+                continue;
+            }
+
+            debug!("Stable reconnect to {}.", peer.id.short_show());
+            stable_connect(&global, 0, peer, vec![], recv_data).await;
+            delay = std::cmp::min(delay * 2, max);
+        }
+
+        // waiting the last stable result.
+        tokio::time::sleep(delay).await;
+
+        global.reconnects.write().await.remove(&peer.id);
+    })
+}
+
+/// dial all bootstrap peers and seeds concurrently, at most
+/// `bootstrap_concurrency` dials in-flight, the healthy seeds first.
+pub(crate) async fn bootstrap(global: Arc<Global>) {
+    let peers: Vec<Peer> = global
+        .peer_list
+        .read()
+        .await
+        .bootstrap()
+        .into_iter()
+        .copied()
+        .collect();
+    let peers = global.seed_health.order(peers);
+    let mut seeds = global.seeds.clone();
+    global.seed_health.rotate(&mut seeds);
+
+    tokio::spawn(async move {
+        let semaphore = Arc::new(Semaphore::new(global.bootstrap_concurrency.max(1)));
+        for peer in peers {
+            let permit = match semaphore.clone().acquire_owned().await {
+                Ok(permit) => permit,
+                Err(_) => break,
+            };
+            let g = global.clone();
+            tokio::spawn(async move {
+                bootstrap_dial(&g, peer.transport, peer.socket, peer.id).await;
+                drop(permit);
+            });
+        }
+
+        for addr in seeds {
+            let permit = match semaphore.clone().acquire_owned().await {
+                Ok(permit) => permit,
+                Err(_) => break,
+            };
+            let g = global.clone();
+            tokio::spawn(async move {
+                match resolve(&addr).await {
+                    // only dial the first resolved address.
+                    Ok(sockets) => {
+                        if let Some(socket) = sockets.into_iter().next() {
+                            let mut seed = Peer::socket(socket);
+                            seed.transport = addr.transport;
+                            g.peer_list.write().await.pin(seed);
+                            g.seed_health.add(socket);
+                            bootstrap_dial(&g, addr.transport, socket, PeerId::default()).await;
+                        }
+                    }
+                    Err(e) => warn!("CHAMOMILE: RESOLVE {} FAILURE: {:?}", addr, e),
+                }
+                drop(permit);
+            });
+        }
+    });
+}
+
+/// dial to the socket, and wait it finished or timeout.
+async fn bootstrap_dial(
+    global: &Global,
+    transport: TransportType,
+    socket: SocketAddr,
+    peer_id: PeerId,
+) {
+    let mut peer = Peer::socket(socket);
+    peer.id = peer_id;
+    peer.transport = transport;
+    let (tx, rx) = oneshot::channel();
+    if global.dialer.dial(peer, DialKind::Connect, Some(tx)) {
+        let _ = tokio::time::timeout(DIAL_TIMEOUT, rx).await;
+    }
+}
+
+/// retry connect to bootstrap seeds with exponential backoff and jitter,
+/// until connected peers reach `min_peers`.
+async fn bootstrap_retry(global: Arc<Global>, min_peers: usize, min: Duration, max: Duration) {
+    let mut delay = min;
+    loop {
+        let jitter = rand::thread_rng().gen_range(0, delay.as_millis() as u64 / 2 + 1);
+        tokio::time::sleep(delay + Duration::from_millis(jitter)).await;
+
+        if global.out_sender.is_closed() {
+            break;
+        }
+
+        if global.peer_list.read().await.len() >= min_peers {
+            delay = min;
+            continue;
+        }
+
+        debug!("Bootstrap retry, connected peers less than {}.", min_peers);
+        bootstrap(global.clone()).await;
+        delay = std::cmp::min(delay * 2, max);
+    }
+}
+
+/// reconnect the lost pinned peers (allowlist & seeds), the dialer backs off
+/// the failed ones. it is the seeds' health check too, the connected are healthy,
+/// and the offline are dialed (healthy first).
+async fn pinned_loop(global: Arc<Global>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if global.out_sender.is_closed() {
+            break;
+        }
+
+        for (seed, is_connected) in global.peer_list.read().await.seeds() {
+            if is_connected {
+                global.seed_health.success(&seed.socket);
+            }
+        }
+        let offline = global.peer_list.read().await.pinned_offline();
+        let offline = global.seed_health.order(offline);
+        for peer in offline {
+            debug!("Pinned peer {} is lost, reconnect it.", peer.socket);
+            let g = global.clone();
+            tokio::spawn(async move {
+                bootstrap_dial(&g, peer.transport, peer.socket, peer.id).await;
+            });
+        }
+    }
+}
+
+/// every `interval` send the bandwidth summary to outside and log it,
+/// if interval is zero, only log it every 60s.
+async fn bandwidth_loop(global: Arc<Global>, interval: Duration) {
+    let is_event = !interval.is_zero();
+    let interval = if is_event {
+        interval
+    } else {
+        Duration::from_secs(60)
+    };
+    loop {
+        tokio::time::sleep(interval).await;
+        if global.out_sender.is_closed() {
+            break;
+        }
+
+        let (mut sent, mut received) = (0, 0);
+        let mut transports = vec![];
+        for (t, b) in global.transport_bandwidths.iter() {
+            let (s, r) = b.get();
+            if s + r > 0 {
+                debug!(
+                    "Bandwidth {}: sent {} bytes, received {} bytes.",
+                    t.to_str(),
+                    s,
+                    r
+                );
+            }
+            sent += s;
+            received += r;
+            transports.push((*t, s, r));
+        }
+        let sessions = global.bandwidths.read().await.len();
+        info!(
+            "Bandwidth: {} sessions, sent {} bytes, received {} bytes.",
+            sessions, sent, received
+        );
+        if is_event {
+            let _ = global
+                .out_send(ReceiveMessage::Bandwidth(sessions, transports))
+                .await;
+        }
+    }
+}
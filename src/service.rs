@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use chamomile_types::{Peer, PeerId};
+
+/// max bytes of service's name.
+pub(crate) const SERVICE_NAME_MAX: usize = 255;
+
+/// max waiting time of the neighbors' replies when find service.
+pub(crate) const SERVICE_QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// max services of a connected peer provided.
+const SERVICE_PEER_MAX: usize = 32;
+
+/// max provider records of all connected peers.
+const SERVICE_PROVIDERS_MAX: usize = 4096;
+
+/// max finding queries waiting neighbors' replies.
+const SERVICE_QUERIES_MAX: usize = 64;
+
+/// max providers of a neighbor's reply.
+pub(crate) const SERVICE_REPLY_MAX: usize = 32;
+
+/// max found providers of a query.
+const SERVICE_FOUND_MAX: usize = 256;
+
+/// the DHT key of the service's provider records.
+pub(crate) fn service_key(name: &str) -> PeerId {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(b"chamomile-service:");
+    hasher.update(name.as_bytes());
+    PeerId(*hasher.finalize().as_bytes())
+}
+
+/// Named services registration.
+/// self registered services, the provider records of connected peers,
+/// and the finding queries waiting neighbors' replies.
+/// the providers also publish the records to the closest peers of the service key
+/// (stored as the DHT records of `Topics`), so the not connected ones can be found.
+#[derive(Default)]
+pub(crate) struct Services {
+    registered: HashSet<String>,
+    /// service => connected providers.
+    providers: HashMap<String, HashMap<PeerId, Peer>>,
+    /// query id => (service, waiting replies number, found providers).
+    queries: HashMap<u64, (String, usize, Vec<Peer>)>,
+}
+
+impl Services {
+    /// register a service, return false if had registered.
+    pub fn register(&mut self, name: &str) -> bool {
+        self.registered.insert(name.to_owned())
+    }
+
+    /// unregister a service, return false if not registered.
+    pub fn unregister(&mut self, name: &str) -> bool {
+        self.registered.remove(name)
+    }
+
+    pub fn registered(&self) -> Vec<String> {
+        self.registered.iter().cloned().collect()
+    }
+
+    /// update connected peer's provider record, false if the peer's or all records
+    /// are full.
+    pub fn update_provider(&mut self, name: &str, peer: Peer, is_register: bool) -> bool {
+        if !is_register {
+            if let Some(providers) = self.providers.get_mut(name) {
+                providers.remove(&peer.id);
+                if providers.is_empty() {
+                    self.providers.remove(name);
+                }
+            }
+            return true;
+        }
+
+        let is_new = self
+            .providers
+            .get(name)
+            .map(|providers| !providers.contains_key(&peer.id))
+            .unwrap_or(true);
+        if is_new {
+            let (mut total, mut of_peer) = (0, 0);
+            for providers in self.providers.values() {
+                total += providers.len();
+                if providers.contains_key(&peer.id) {
+                    of_peer += 1;
+                }
+            }
+            if total >= SERVICE_PROVIDERS_MAX || of_peer >= SERVICE_PEER_MAX {
+                return false;
+            }
+        }
+        self.providers
+            .entry(name.to_owned())
+            .or_default()
+            .insert(peer.id, peer);
+        true
+    }
+
+    /// remove the disconnected peer's records.
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        for providers in self.providers.values_mut() {
+            providers.remove(peer_id);
+        }
+        self.providers.retain(|_, providers| !providers.is_empty());
+    }
+
+    /// the connected providers of the service, not include the peer.
+    pub fn providers(&self, name: &str, except: &PeerId) -> Vec<Peer> {
+        self.providers
+            .get(name)
+            .map(|providers| {
+                providers
+                    .values()
+                    .filter(|p| &p.id != except)
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// start a query, the found providers is the connected providers,
+    /// waiting the `waiting` neighbors' replies. false if too many queries.
+    pub fn query_start(
+        &mut self,
+        qid: u64,
+        name: String,
+        waiting: usize,
+        found: Vec<Peer>,
+    ) -> bool {
+        if self.queries.len() >= SERVICE_QUERIES_MAX {
+            return false;
+        }
+        self.queries.insert(qid, (name, waiting, found));
+        true
+    }
+
+    /// a neighbor replied, if all replied, return the finished query.
+    pub fn query_reply(&mut self, qid: u64, peers: Vec<Peer>) -> Option<(String, Vec<Peer>)> {
+        let (_, waiting, found) = self.queries.get_mut(&qid)?;
+        for peer in peers.into_iter().take(SERVICE_REPLY_MAX) {
+            if found.len() >= SERVICE_FOUND_MAX {
+                break;
+            }
+            if !found.iter().any(|p| p.id == peer.id) {
+                found.push(peer);
+            }
+        }
+        *waiting = waiting.saturating_sub(1);
+        if *waiting == 0 {
+            self.query_end(qid)
+        } else {
+            None
+        }
+    }
+
+    /// take out the query, if it is not finished.
+    pub fn query_end(&mut self, qid: u64) -> Option<(String, Vec<Peer>)> {
+        self.queries
+            .remove(&qid)
+            .map(|(name, _, found)| (name, found))
+    }
+}
@@ -1,5 +1,8 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use bytes::Bytes;
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::{
     io::Result,
     select,
@@ -7,19 +10,111 @@ use tokio::{
 };
 
 use chamomile_types::{
-    delivery_split,
-    message::{DeliveryType, ReceiveMessage},
-    types::new_io_error,
+    message::{
+        DeliveryFailure, DeliveryType, DialReason, Misbehavior, ReceiveMessage, SessionStats,
+    },
+    peer::PEER_LENGTH,
+    types::{new_io_error, DataPath, PEER_ID_LENGTH},
     Peer, PeerId,
 };
 
-use crate::global::Global;
-use crate::hole_punching::{nat, DHT};
+use crate::ban;
+use crate::config::StableDecision;
+use crate::dialer::{DialGuard, DialKind};
+use crate::global::{Global, PeerClass};
+#[cfg(feature = "hole-punching")]
+use crate::hole_punching;
+use crate::hole_punching::nat;
+#[cfg(feature = "dht")]
+use crate::hole_punching::DHT;
 use crate::kad::KadValue;
-use crate::keys::SessionKey;
-use crate::transports::{
-    new_endpoint_channel, EndpointMessage, RemotePublic, TransportSendMessage,
-};
+use crate::keys::{Cipher, SessionKey};
+use crate::mailbox;
+use crate::primitives::DIAL_TIMEOUT;
+#[cfg(feature = "relay")]
+use crate::primitives::MAX_RELAY_HOPS;
+#[cfg(feature = "capture")]
+use crate::record::{Event, Timer};
+use crate::service::{service_key, SERVICE_REPLY_MAX};
+use crate::stats::{self, Bandwidth};
+use crate::swarm::{self, SwarmId};
+use crate::token;
+use crate::topic::{topic_key, TOPIC_PEERS_MAX};
+#[cfg(feature = "capture")]
+use crate::transports::codec;
+#[cfg(feature = "hole-punching")]
+use crate::transports::PUNCH_FLAG;
+use crate::transports::{emulate, new_endpoint_channel, Emulation, EndpointMessage, RemotePublic};
+
+/// outside's ping will timeout after it.
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// feature flag in keepalive, support receive `CoreData::OrderedData`.
+const FEATURE_ORDERED: u8 = 1;
+
+/// feature flag in keepalive, support receive `CoreData::ProtocolData`.
+const FEATURE_PROTOCOL: u8 = 2;
+
+/// feature flag in keepalive, support receive `CoreData::Batch`.
+const FEATURE_BATCH: u8 = 4;
+
+/// feature flag in keepalive, support group membership & data.
+const FEATURE_GROUP: u8 = 8;
+
+/// feature flag in keepalive, support service records & queries.
+const FEATURE_SERVICE: u8 = 16;
+
+/// feature flag in keepalive, support receive padded frames and cover frames.
+const FEATURE_PADDING: u8 = 32;
+
+/// feature flag in keepalive, self is a mailbox, store mails for offline peers.
+/// (every version support send & fetch mails, only the role is optional).
+const FEATURE_MAILBOX: u8 = 64;
+
+/// feature flag in keepalive, support receive `CoreData::BlockShare`.
+const FEATURE_BLOCK_SHARE: u8 = 128;
+
+/// all supported features, send to remote in keepalive.
+const CORE_FEATURES: u8 = FEATURE_ORDERED
+    | FEATURE_PROTOCOL
+    | FEATURE_BATCH
+    | FEATURE_GROUP
+    | FEATURE_SERVICE
+    | FEATURE_PADDING
+    | FEATURE_BLOCK_SHARE;
+
+/// max waiting bulk data in session, when full, send the oldest one.
+const BULK_QUEUE_MAX: usize = 1024;
+
+/// max control messages handled before sending a bulk data.
+const BULK_PREEMPT_MAX: usize = 64;
+
+/// max queued messages of relayed stable session, when waiting the relay re-pinned.
+const RELAY_PENDING_MAX: usize = 1024;
+
+/// the negotiated protocol version support receive `CoreData::Redundant`.
+const REDUNDANT_VERSION: u8 = 2;
+
+/// max remembered redundant data ids, for drop the duplicate.
+const REDUNDANT_SEEN_MAX: usize = 1024;
+
+/// the negotiated protocol version support the swarm broadcast.
+const SWARM_VERSION: u8 = 3;
+
+/// the negotiated protocol version support the topic records.
+const TOPIC_VERSION: u8 = 4;
+
+/// the negotiated protocol version support the relay data with passed relays.
+const RELAY_HOPS_VERSION: u8 = 5;
+
+/// only data message smaller than it will be batched.
+const BATCH_ITEM_MAX: usize = 1024;
+
+/// max out-of-order data waiting for the missing one.
+const MAX_REORDER_BUFFER: usize = 1024;
+
+/// waiting time for the missing ordered data, after it, skip the missing.
+const REORDER_TIMEOUT: Duration = Duration::from_secs(4);
 
 /// direct start stable connection, if had IP.
 pub(crate) async fn direct_stable(
@@ -34,27 +129,51 @@ pub(crate) async fn direct_stable(
     let (stream_sender, mut stream_receiver) = new_endpoint_channel(); // session's use.
     let (mut session_key, remote_pk) = global.generate_remote();
 
-    // 1. send stable connect.
-    global
-        .trans_send(
-            &to.transport,
-            TransportSendMessage::StableConnect(
-                stream_sender.clone(),
-                endpoint_receiver,
-                to.socket,
-                remote_pk,
-            ),
-        )
-        .await?;
+    // 0. a dial to the peer is in-flight, waiting it, if connected, send the
+    // buffered stable connects by the dialed session, not dial again.
+    if let Some(rx) = global.dial_wait(to.socket, to.id).await {
+        let _ = tokio::time::timeout(DIAL_TIMEOUT, rx).await;
+        let dialed = match global.peer_list.read().await.get(&to.id) {
+            Some((s, _, true)) => Some(s.clone()),
+            _ => None,
+        };
+        if let Some(sender) = dialed {
+            debug!("Session stable connect by the in-flight dialed session.");
+            for (tid, data) in global.buffer.remove_connect(to.id).await {
+                let _ = sender.send(SessionMessage::StableConnect(tid, data)).await;
+            }
+            return Ok(());
+        }
+    }
 
-    // 2. waiting remote send remote info.
-    if let Some(EndpointMessage::Handshake(RemotePublic(remote_key, remote_peer, dh_key))) =
-        stream_receiver.recv().await
+    // 1. send stable connect by dialer, if refused, try relay.
+    let kind = DialKind::Stable(stream_sender.clone(), endpoint_receiver, remote_pk);
+    let handshake = if global.dialer.dial(to, kind, None) {
+        // 2. waiting remote send remote info, release the dial even if aborted.
+        let guard = DialGuard::new(global.clone(), to.socket);
+        let handshake = stream_receiver.recv().await;
+        guard.done().await;
+        handshake
+    } else {
+        None
+    };
+    if let Some(EndpointMessage::Handshake(RemotePublic(
+        remote_key,
+        remote_peer,
+        dh_key,
+        remote_version,
+        remote_payload,
+        psk_confirm,
+    ))) = handshake
     {
         // 3.1.1 if ok connected. keep it and update to stable.
-        let remote_id = remote_key.peer_id();
+        global.dialer.success(&to.socket);
+        let remote_id = remote_key.peer_id(&global.peer_id_hash);
         if !to.effective_id() && remote_id != to.id {
             warn!("CHAMOMILE: STABLE CONNECT FAILURE UNKNOWN PEER.");
+            let _ = global
+                .out_send(ReceiveMessage::DialFailure(to, DialReason::Handshake))
+                .await;
             return Err(new_io_error("session stable unknown peer."));
         }
 
@@ -66,7 +185,7 @@ pub(crate) async fn direct_stable(
                     .out_send(ReceiveMessage::Delivery(
                         DeliveryType::StableConnect,
                         tid,
-                        false,
+                        Err(DeliveryFailure::Invalid),
                         delivery,
                     ))
                     .await?;
@@ -74,13 +193,34 @@ pub(crate) async fn direct_stable(
             return Err(new_io_error("session stable self failure."));
         }
 
-        // 3.1.2 check & update session key.
-        if !session_key.complete(&remote_key.pk, dh_key) {
-            global.buffer.write().await.remove_connect(&to.id);
+        // 3.1.2 check handshake payload, psk & update session key.
+        let version = global.check_handshake(&remote_id, remote_version, &remote_payload);
+        if version.is_none()
+            || !global.complete_local(
+                &mut session_key,
+                &remote_key,
+                dh_key,
+                &psk_confirm,
+                Cipher::negotiate(remote_version),
+            )
+        {
+            global.buffer.remove_connect(to.id).await;
+            let _ = global
+                .out_send(ReceiveMessage::DialFailure(to, DialReason::Handshake))
+                .await;
             return Err(new_io_error("session stable key failure."));
         }
 
         let remote_peer = nat(to.socket, remote_peer);
+        if !global.gate_peer(&remote_peer) {
+            let _ = endpoint_sender.send(EndpointMessage::Close).await;
+            global.buffer.remove_connect(to.id).await;
+            gated_failure(&global, tid, delivery, remote_peer).await?;
+            return Err(new_io_error("session stable gater denied."));
+        }
+        global.bridge_join(remote_id, remote_version).await;
+        #[cfg(feature = "hole-punching")]
+        global.punches.join(remote_id, remote_version);
         let (session_sender, session_receiver) = new_session_channel(); // server's use.
 
         // 3.1.3 save to tmp buffer.
@@ -101,12 +241,11 @@ pub(crate) async fn direct_stable(
             global,
             is_recv_data,
         );
+        session.version = version.unwrap_or(0); // safe checked.
 
         // 3.1.4 send all connect info to remote.
         for buffer in buffers {
-            session
-                .send_core_data(CoreData::StableConnect(buffer.0, buffer.1))
-                .await?;
+            session.send_stable_connect(buffer.0, buffer.1).await?;
         }
 
         // 3.1.5 upgrade to stable.
@@ -136,12 +275,12 @@ pub(crate) async fn direct_stable(
                     .out_send(ReceiveMessage::Delivery(
                         DeliveryType::StableConnect,
                         tid,
-                        false,
+                        Err(DeliveryFailure::PeerOffline),
                         delivery,
                     ))
                     .await?;
             }
-            global.buffer.write().await.remove_connect(&to.id);
+            global.buffer.remove_connect(to.id).await;
             Err(new_io_error("no closest peer."))
         }
     }
@@ -156,6 +295,12 @@ pub(crate) async fn relay_stable(
     is_recv_data: bool,
 ) -> Result<()> {
     debug!("Session want to connect relay.");
+    // prefer the transport bridge when the peer's transport is not self's,
+    // or the selected relay by the peer selector.
+    let relay_sender = match global.bridge_relay(&to).await {
+        Some(sender) => sender,
+        None => global.select_relay(&to).await.unwrap_or(relay_sender),
+    };
 
     // 1. try relay connect. (timeout).
     // 2. send stable connect.
@@ -188,25 +333,32 @@ pub(crate) async fn relay_stable(
         } => v
     };
 
-    if let Some(SessionMessage::RelayResult(remote, recv_ss)) = msg {
-        let RemotePublic(remote_key, remote_peer, dh_key) = remote;
+    if let Some(SessionMessage::RelayResult(remote, recv_ss, relay_id)) = msg {
+        let RemotePublic(
+            remote_key,
+            remote_peer,
+            dh_key,
+            remote_version,
+            remote_payload,
+            psk_confirm,
+        ) = remote;
 
-        let remote_id = remote_key.peer_id();
+        let remote_id = remote_key.peer_id(&global.peer_id_hash);
         if remote_id != to.id {
             warn!("CHAMOMILE: STABLE CONNECT FAILURE UNKNOWN PEER.");
-            global.buffer.write().await.remove_tmp(&to.id);
+            global.buffer.remove_tmp(to.id).await;
             return Err(new_io_error("session stable unknown peer."));
         }
 
         if &remote_id == global.peer_id() {
             warn!("CHAMOMILE: STABLE CONNECT NERVER TO SELF.");
-            global.buffer.write().await.remove_tmp(&to.id);
+            global.buffer.remove_tmp(to.id).await;
             if tid != 0 {
                 global
                     .out_send(ReceiveMessage::Delivery(
                         DeliveryType::StableConnect,
                         tid,
-                        false,
+                        Err(DeliveryFailure::Invalid),
                         delivery,
                     ))
                     .await?;
@@ -214,26 +366,44 @@ pub(crate) async fn relay_stable(
             return Err(new_io_error("session stable self failure."));
         }
 
-        if !session_key.complete(&remote_key.pk, dh_key) {
-            global.buffer.write().await.remove_tmp(&to.id);
+        let version = global.check_handshake(&remote_id, remote_version, &remote_payload);
+        if version.is_none()
+            || !global.complete_local(
+                &mut session_key,
+                &remote_key,
+                dh_key,
+                &psk_confirm,
+                Cipher::negotiate(remote_version),
+            )
+        {
+            global.buffer.remove_tmp(to.id).await;
             return Err(new_io_error("session stable key failure."));
         }
 
-        global.buffer.write().await.update_peer(&to.id, remote_peer);
+        if !global.gate_peer(&remote_peer) {
+            global.buffer.remove_tmp(to.id).await;
+            gated_failure(&global, tid, delivery, remote_peer).await?;
+            return Err(new_io_error("session stable gater denied."));
+        }
+
+        global.buffer.update_peer(to.id, remote_peer).await;
         let mut session = Session::new(
             remote_peer,
             session_sender,
             stream_receiver,
-            ConnectType::Relay(recv_ss),
+            ConnectType::Relay(recv_ss, relay_id),
             session_key,
             global,
             is_recv_data,
         );
+        session.version = version.unwrap_or(0); // safe checked.
+        #[cfg(feature = "hole-punching")]
+        {
+            session.remote_punch = remote_version & PUNCH_FLAG != 0;
+        }
 
         for buffer in connects {
-            session
-                .send_core_data(CoreData::StableConnect(buffer.0, buffer.1))
-                .await?;
+            session.send_stable_connect(buffer.0, buffer.1).await?;
         }
 
         for buffer in results {
@@ -254,24 +424,79 @@ pub(crate) async fn relay_stable(
                 .out_send(ReceiveMessage::Delivery(
                     DeliveryType::StableConnect,
                     tid,
-                    false,
+                    Err(DeliveryFailure::RelayRefused),
                     delivery,
                 ))
                 .await?;
         }
-        global.buffer.write().await.remove_tmp(&to.id);
+        global.buffer.remove_tmp(to.id).await;
         debug!("Session clear stable buffer.");
         Err(new_io_error("session relay reach faiure."))
     }
 }
 
+/// the stable connected peer is denied by the gater, tell outside it failure.
+async fn gated_failure(global: &Global, tid: u64, delivery: Vec<u8>, peer: Peer) -> Result<()> {
+    debug!("Session stable {} denied by gater.", peer.id.short_show());
+    global
+        .out_send(ReceiveMessage::StableResult(peer, false, vec![]))
+        .await?;
+    if tid != 0 {
+        global
+            .out_send(ReceiveMessage::Delivery(
+                DeliveryType::StableConnect,
+                tid,
+                Err(DeliveryFailure::Blocked),
+                delivery,
+            ))
+            .await?;
+    }
+    Ok(())
+}
+
 pub(crate) fn session_spawn(mut session: Session, session_receiver: Receiver<SessionMessage>) {
     tokio::spawn(async move { session.listen(session_receiver).await });
 }
 
 pub(crate) enum ConnectType {
     Direct(Sender<EndpointMessage>),
-    Relay(Sender<SessionMessage>),
+    /// relay session's sender, and the relay peer.
+    Relay(Sender<SessionMessage>, PeerId),
+}
+
+impl ConnectType {
+    /// wrap the direct endpoint by the network emulation, so all messages the
+    /// session sends go through it. relay's is emulated by the relay's session.
+    fn emulated(self, emulation: Option<Emulation>) -> ConnectType {
+        match (self, emulation) {
+            (ConnectType::Direct(sender), Some(emulation)) => {
+                ConnectType::Direct(emulate(sender, emulation))
+            }
+            (endpoint, _) => endpoint,
+        }
+    }
+}
+
+/// stable session's resumption tickets with the remote peer.
+#[derive(Default)]
+pub(crate) struct Resumption {
+    /// the ticket given to remote.
+    pub issued: Option<u64>,
+    /// the ticket received from remote.
+    pub received: Option<u64>,
+    /// ordered data's sequences (send, recv) when closed.
+    pub seqs: (u64, u64),
+    /// the session closed time, resume only after closed.
+    pub closed_at: Option<Instant>,
+}
+
+impl Resumption {
+    pub fn is_expired(&self, window: Duration) -> bool {
+        match self.closed_at {
+            Some(t) => t.elapsed() > window,
+            None => false,
+        }
+    }
 }
 
 pub(crate) struct Session {
@@ -285,6 +510,68 @@ pub(crate) struct Session {
     pub is_stable: bool,
     pub heartbeat: u32,
     pub relay_sessions: HashMap<PeerId, Sender<SessionMessage>>,
+    /// outside's ping id => ping's send time.
+    pub pings: HashMap<u64, Instant>,
+    pub bandwidth: Arc<Bandwidth>,
+    /// remote support receive ordered data.
+    pub remote_ordered: bool,
+    /// remote support receive protocol data, None is unknown (no keepalive yet).
+    pub remote_protocol: Option<bool>,
+    /// next ordered data's sequence to send.
+    pub send_seq: u64,
+    /// next ordered data's sequence want to receive.
+    pub recv_seq: u64,
+    /// sequence => received out-of-order data (delivery_id, data).
+    pub reorders: BTreeMap<u64, (u64, Bytes)>,
+    /// the time of waiting the missing ordered data.
+    pub reorder_since: Option<Instant>,
+    /// the last time of data traffic (not keepalive).
+    pub last_active: Instant,
+    /// closed by outside or remote, not reconnect it.
+    pub no_reconnect: bool,
+    /// negotiated protocol version with remote.
+    pub version: u8,
+    /// remote support receive batched data.
+    pub remote_batch: bool,
+    /// waiting small data messages, will send in one batch.
+    pub batch: Vec<CoreData>,
+    /// waiting data's bytes in batch.
+    pub batch_bytes: usize,
+    /// the time of first message in batch.
+    pub batch_since: Option<Instant>,
+    /// waiting bulk data, control messages will send before them.
+    pub bulk: VecDeque<CoreData>,
+    /// remote support groups, and had sent self joined groups to it.
+    pub remote_group: (bool, bool),
+    /// remote support services, and had sent self registered services to it.
+    pub remote_service: (bool, bool),
+    /// remote support padded frames.
+    pub remote_padding: bool,
+    /// the time of next cover frame.
+    pub cover_at: Option<Instant>,
+    /// remote is a mailbox, and had fetched self mails from it.
+    pub remote_mailbox: (bool, bool),
+    /// remote presented a valid join token.
+    pub verified: bool,
+    /// inbound frames in current second, for the rate violation.
+    pub recv_frames: (u32, Instant),
+    /// remote support receive shared block entries.
+    pub remote_block_share: bool,
+    /// keepalives sent again without answered.
+    pub retransmits: u64,
+    /// the last time of sending stats to outside.
+    pub stats_at: Instant,
+    /// the relay peer of relayed session, re-dial it when the relay lost.
+    pub relay_peer: Option<Peer>,
+    /// the time of relay lost, waiting re-pinned to the same relay.
+    pub relay_lost: Option<Instant>,
+    /// the messages to relay when relay lost, sent when re-pinned.
+    pub relay_pending: Mutex<VecDeque<SessionMessage>>,
+    /// the received redundant data ids, the duplicate is dropped.
+    pub redundant_seen: (HashSet<u64>, VecDeque<u64>),
+    /// remote of relayed session support the parallel hole punching.
+    #[cfg(feature = "hole-punching")]
+    pub remote_punch: bool,
 }
 
 enum FutureResult {
@@ -292,6 +579,10 @@ enum FutureResult {
     Endpoint(EndpointMessage),
     HeartBeat,
     Robust,
+    Flush,
+    Resume,
+    Bulk,
+    Cover,
 }
 
 impl Session {
@@ -304,6 +595,7 @@ impl Session {
         global: Arc<Global>,
         is_recv_data: bool,
     ) -> Session {
+        let endpoint = endpoint.emulated(global.emulation);
         Session {
             remote_peer,
             session_sender,
@@ -315,6 +607,38 @@ impl Session {
             is_stable: false,
             heartbeat: 0,
             relay_sessions: HashMap::new(),
+            pings: HashMap::new(),
+            bandwidth: Arc::new(Bandwidth::default()),
+            remote_ordered: false,
+            remote_protocol: None,
+            send_seq: 0,
+            recv_seq: 0,
+            reorders: BTreeMap::new(),
+            reorder_since: None,
+            last_active: Instant::now(),
+            no_reconnect: false,
+            version: 0,
+            remote_batch: false,
+            batch: vec![],
+            batch_bytes: 0,
+            batch_since: None,
+            bulk: VecDeque::new(),
+            remote_group: (false, false),
+            remote_service: (false, false),
+            remote_padding: false,
+            cover_at: None,
+            remote_mailbox: (false, false),
+            verified: false,
+            recv_frames: (0, Instant::now()),
+            remote_block_share: false,
+            retransmits: 0,
+            stats_at: Instant::now(),
+            relay_peer: None,
+            relay_lost: None,
+            relay_pending: Mutex::new(VecDeque::new()),
+            redundant_seen: (HashSet::new(), VecDeque::new()),
+            #[cfg(feature = "hole-punching")]
+            remote_punch: false,
         }
     }
 
@@ -326,10 +650,84 @@ impl Session {
         &self.remote_peer.id
     }
 
+    async fn is_replaced(&self) -> bool {
+        match self
+            .global
+            .peer_list
+            .read()
+            .await
+            .stable_get(self.remote_id())
+        {
+            Some((s, ..)) => !s.same_channel(&self.session_sender),
+            None => false,
+        }
+    }
+
     async fn close(&mut self, is_leave: bool) -> Result<()> {
+        self.flush_bulk().await;
+        let remote_id = *self.remote_id();
+        for (tid, _) in self.pings.drain() {
+            let _ = self
+                .global
+                .out_send(ReceiveMessage::Pong(tid, remote_id, None))
+                .await;
+        }
+
+        // replaced by the new stable session of same peer (e.g. remote restarted),
+        // only close self, keep the peer's state.
+        if self.is_stable && self.is_replaced().await {
+            let _ = self.direct_send(EndpointMessage::Close).await;
+            return Ok(());
+        }
+
+        self.global.services.write().await.remove_peer(&remote_id);
+        self.global.mailbox.write().await.remove_peer(&remote_id);
+        let left = self.global.groups.write().await.remove_peer(&remote_id);
+        for group in left {
+            if self.global.groups.read().await.is_joined(&group) {
+                self.global.group_rekey(&group).await;
+                let _ = self
+                    .out_send(ReceiveMessage::GroupLeave(group, remote_id))
+                    .await;
+            }
+        }
+
         let peer_id = self.remote_id();
+        self.global.latencies.write().await.remove(peer_id);
+        self.global.clock_offsets.write().await.remove(peer_id);
+        if is_leave && self.is_direct() {
+            self.global.bridges.write().await.remove(peer_id);
+            #[cfg(feature = "hole-punching")]
+            self.global.punches.leave(peer_id);
+        }
+        if let ConnectType::Relay(_, relay_id) = &self.endpoint {
+            let mut relay_paths = self.global.relay_paths.write().await;
+            if relay_paths.get(peer_id) == Some(relay_id) {
+                relay_paths.remove(peer_id);
+            }
+        }
+        let mut bandwidths = self.global.bandwidths.write().await;
+        if let Some(b) = bandwidths.get(peer_id) {
+            if Arc::ptr_eq(b, &self.bandwidth) {
+                bandwidths.remove(peer_id);
+            }
+        }
+        drop(bandwidths);
 
         if self.is_stable {
+            self.global
+                .audit
+                .log(format!("stable_closed {}", peer_id.to_hex()));
+            self.global.stable_classes.write().await.remove(peer_id);
+            // closed by self, remote or ban (not reconnect), the ticket is revoked.
+            let mut resumptions = self.global.resumptions.write().await;
+            if self.no_reconnect {
+                resumptions.remove(peer_id);
+            } else if let Some(r) = resumptions.get_mut(peer_id) {
+                r.seqs = (self.send_seq, self.recv_seq);
+                r.closed_at = Some(Instant::now());
+            }
+            drop(resumptions);
             let _ = self.out_send(ReceiveMessage::StableLeave(*peer_id)).await;
             if !self.is_direct() {
                 let _ = self
@@ -340,83 +738,140 @@ impl Session {
             if is_leave {
                 self.global.peer_list.write().await.stable_leave(peer_id);
                 let _ = self.direct_send(EndpointMessage::Close).await;
+                if !self.no_reconnect && self.global.reconnect.0 > 0 {
+                    self.no_reconnect = true;
+                    tokio::spawn(crate::server::stable_reconnect(
+                        self.global.clone(),
+                        self.remote_peer,
+                        self.is_recv_data,
+                    ));
+                }
             } else if self.is_direct() {
                 self.global.stable_to_dht(peer_id).await?;
+                self.global
+                    .dht_event(ReceiveMessage::PeerJoin(self.remote_peer))
+                    .await;
             }
         } else if self.is_direct() {
             if is_leave {
-                self.global.buffer.write().await.remove_tmp(peer_id);
-                self.global.peer_list.write().await.remove_peer(peer_id);
+                self.global.buffer.remove_tmp(*peer_id).await;
+                let removed = self.global.peer_list.write().await.remove_peer(peer_id);
+                if removed.is_some() {
+                    self.global
+                        .dht_event(ReceiveMessage::PeerLeave(*peer_id))
+                        .await;
+                }
             } else {
                 self.global.tmp_to_dht(peer_id).await?;
             }
         } else {
-            self.global.buffer.write().await.remove_tmp(peer_id);
+            self.global.buffer.remove_tmp(*peer_id).await;
         }
 
         Err(new_io_error("close session"))
     }
 
     fn is_direct(&self) -> bool {
+        matches!(self.endpoint, ConnectType::Direct(..))
+    }
+
+    fn data_path(&self) -> DataPath {
         match self.endpoint {
-            ConnectType::Direct(..) => true,
-            _ => false,
-        }
-    }
-
-    async fn failure_send(&self, e_data: Vec<u8>) -> Result<()> {
-        if let Ok(bytes) = self.session_key.decrypt(e_data) {
-            if let Ok(msg) = CoreData::from_bytes(bytes) {
-                match msg {
-                    CoreData::Ping => {}
-                    CoreData::Pong => {}
-                    CoreData::Unstable => {}
-                    CoreData::Delivery(..) => {}
-                    CoreData::Data(tid, data) => {
-                        if tid != 0 {
-                            self.out_send(ReceiveMessage::Delivery(
-                                DeliveryType::Data,
-                                tid,
-                                false,
-                                delivery_split!(data, self.global.delivery_length),
-                            ))
-                            .await?;
-                        }
-                    }
-                    CoreData::StableConnect(tid, data) => {
-                        if tid != 0 {
-                            self.out_send(ReceiveMessage::Delivery(
-                                DeliveryType::StableConnect,
-                                tid,
-                                false,
-                                delivery_split!(data, self.global.delivery_length),
-                            ))
-                            .await?;
-                        }
-                    }
-                    CoreData::StableResult(tid, _, data) => {
-                        if tid != 0 {
-                            self.out_send(ReceiveMessage::Delivery(
-                                DeliveryType::StableResult,
-                                tid,
-                                false,
-                                delivery_split!(data, self.global.delivery_length),
-                            ))
-                            .await?;
-                        }
-                    }
-                    CoreData::ResultConnect(tid, data) => {
-                        if tid != 0 {
-                            self.out_send(ReceiveMessage::Delivery(
-                                DeliveryType::StableResult,
-                                tid,
-                                false,
-                                delivery_split!(data, self.global.delivery_length),
-                            ))
-                            .await?;
-                        }
+            ConnectType::Direct(..) => DataPath::Direct(self.remote_peer.transport),
+            ConnectType::Relay(_, relay_id) => DataPath::Relay(relay_id),
+        }
+    }
+
+    async fn failure_send(&self, e_data: Bytes) -> Result<()> {
+        if let Ok(bytes) = self.session_key.decrypt(&e_data) {
+            match CoreData::from_bytes(bytes) {
+                Ok(CoreData::Batch(msgs)) => {
+                    for msg in msgs {
+                        self.failure_core_msg(msg).await?;
                     }
                 }
+                Ok(msg) => self.failure_core_msg(msg).await?,
+                Err(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn failure_core_msg(&self, msg: CoreData) -> Result<()> {
+        match msg {
+            CoreData::Ping(..) => {}
+            CoreData::Pong(..) => {}
+            CoreData::AppPing(..) => {}
+            CoreData::AppPong(..) => {}
+            CoreData::Ticket(..) => {}
+            CoreData::Resume(..) => {}
+            CoreData::Unstable => {}
+            CoreData::Batch(..) => {}
+            CoreData::GroupJoin(..) => {}
+            CoreData::GroupLeave(..) => {}
+            CoreData::GroupData(..) => {}
+            CoreData::GroupKey(..) => {}
+            CoreData::GroupSealed(..) => {}
+            CoreData::ServiceAnnounce(..) => {}
+            CoreData::ServiceQuery(..) => {}
+            CoreData::ServiceReply(..) => {}
+            CoreData::Cover => {}
+            CoreData::MailboxStore(..) => {}
+            CoreData::MailboxFetch => {}
+            CoreData::MailboxMail(..) => {}
+            CoreData::BlockShare(..) => {}
+            CoreData::SwarmManifest(..) => {}
+            CoreData::SwarmWant(..) => {}
+            CoreData::SwarmChunk(..) => {}
+            CoreData::TopicJoin(..) => {}
+            CoreData::TopicPeers(..) => {}
+            CoreData::Delivery(..) => {}
+            CoreData::Data(tid, data)
+            | CoreData::OrderedData(_, tid, data)
+            | CoreData::ProtocolData(tid, _, data)
+            | CoreData::Redundant(_, tid, data) => {
+                if tid != 0 {
+                    self.out_send(ReceiveMessage::Delivery(
+                        DeliveryType::Data,
+                        tid,
+                        Err(DeliveryFailure::PeerOffline),
+                        self.global.delivery(&data),
+                    ))
+                    .await?;
+                }
+            }
+            CoreData::StableConnect(tid, data) => {
+                if tid != 0 {
+                    self.out_send(ReceiveMessage::Delivery(
+                        DeliveryType::StableConnect,
+                        tid,
+                        Err(DeliveryFailure::PeerOffline),
+                        self.global.delivery(&data),
+                    ))
+                    .await?;
+                }
+            }
+            CoreData::StableResult(tid, _, data) => {
+                if tid != 0 {
+                    self.out_send(ReceiveMessage::Delivery(
+                        DeliveryType::StableResult,
+                        tid,
+                        Err(DeliveryFailure::PeerOffline),
+                        self.global.delivery(&data),
+                    ))
+                    .await?;
+                }
+            }
+            CoreData::ResultConnect(tid, data) => {
+                if tid != 0 {
+                    self.out_send(ReceiveMessage::Delivery(
+                        DeliveryType::StableResult,
+                        tid,
+                        Err(DeliveryFailure::PeerOffline),
+                        self.global.delivery(&data),
+                    ))
+                    .await?;
+                }
             }
         }
         Ok(())
@@ -428,162 +883,1354 @@ impl Session {
 
     async fn direct_send(&self, msg: EndpointMessage) -> Result<()> {
         match &self.endpoint {
-            ConnectType::Direct(sender) => sender
-                .send(msg)
-                .await
-                .map_err(|_e| new_io_error("Endpoint missing")),
+            ConnectType::Direct(sender) => {
+                #[cfg(feature = "capture")]
+                self.global
+                    .capture
+                    .record(self.remote_id(), false, false, msg.kind(), &[]);
+                self.qos_acquire(msg.payload_len()).await;
+                self.count_sent(msg.payload_len());
+                sender
+                    .send(msg)
+                    .await
+                    .map_err(|_e| new_io_error("Endpoint missing"))
+            }
             _ => Ok(()),
         }
     }
 
     async fn relay_send(&self, msg: SessionMessage) -> Result<()> {
         match &self.endpoint {
-            ConnectType::Relay(sender) => sender
-                .send(msg)
-                .await
-                .map_err(|_e| new_io_error("Endpoint missing")),
+            ConnectType::Relay(sender, relay_id) => {
+                if let SessionMessage::RelayData(_, to, _, data) = &msg {
+                    self.qos_acquire(data.len()).await;
+                    self.count_sent(data.len());
+                    if let Some(accounting) = &self.global.relay_accounting {
+                        accounting.relay_used(relay_id, to, data.len());
+                    }
+                }
+                let msg = if sender.is_closed() {
+                    msg
+                } else {
+                    match sender.send(msg).await {
+                        Ok(()) => return Ok(()),
+                        Err(e) => e.0,
+                    }
+                };
+                // relay lost, waiting re-pinned to the same relay.
+                if !self.is_sticky() {
+                    return Err(new_io_error("Endpoint missing"));
+                }
+                let mut pending = self.relay_pending.lock().unwrap();
+                if pending.len() >= RELAY_PENDING_MAX {
+                    return Err(new_io_error("Relay pending is full"));
+                }
+                pending.push_back(msg);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// relayed stable session will wait the lost relay come back.
+    fn is_sticky(&self) -> bool {
+        self.is_stable && !self.global.relay_sticky.is_zero()
+    }
+
+    /// the relay's session is closed, re-pin to the same relay when it connected
+    /// again (self re-dial it), the messages are pending until then. close when
+    /// the relay is not back in `relay_sticky`.
+    async fn relay_repin(&mut self) -> Result<()> {
+        let relay_id = match &self.endpoint {
+            ConnectType::Relay(sender, relay_id) if sender.is_closed() => *relay_id,
+            _ => return Ok(()),
+        };
+        if !self.is_sticky() {
+            return Err(new_io_error("relay closed"));
+        }
+
+        let relay = self.global.peer_list.read().await.connected(&relay_id);
+        if let Some((_, sender)) = relay {
+            debug!("Relay re-pinned: {}.", relay_id.short_show());
+            self.global.audit.log(format!(
+                "relay_repinned {} via {}",
+                self.remote_id().to_hex(),
+                relay_id.to_hex()
+            ));
+            self.endpoint = ConnectType::Relay(sender.clone(), relay_id);
+            self.relay_lost = None;
+            self.heartbeat = 0;
+            let pending: Vec<_> = self.relay_pending.lock().unwrap().drain(..).collect();
+            for msg in pending {
+                sender
+                    .send(msg)
+                    .await
+                    .map_err(|_e| new_io_error("Endpoint missing"))?;
+            }
+            return Ok(());
+        }
+
+        match self.relay_lost {
+            None => {
+                debug!("Relay lost: {}, waiting it.", relay_id.short_show());
+                self.relay_lost = Some(Instant::now());
+                if let Some(peer) = self.relay_peer {
+                    self.global.dialer.dial(peer, DialKind::Connect, None);
+                }
+                Ok(())
+            }
+            Some(t) if t.elapsed() > self.global.relay_sticky => {
+                warn!("CHAMOMILE: RELAY LOST, NOT BACK IN TIME.");
+                Err(new_io_error("relay lost"))
+            }
             _ => Ok(()),
         }
     }
 
+    /// relayed stable session, ask the relay and the closest peers to help the
+    /// hole punching at same time, the first punched path upgrade to direct.
+    #[cfg(feature = "hole-punching")]
+    async fn punch(&self) {
+        let relay_id = match &self.endpoint {
+            ConnectType::Relay(_, relay_id) => *relay_id,
+            _ => return,
+        };
+        let n = self.global.punches.helpers();
+        if !self.is_stable || !self.remote_punch || n == 0 {
+            return;
+        }
+        if !self.global.punches.start(*self.remote_id()) {
+            return;
+        }
+
+        let punches = &self.global.punches;
+        let helpers = self.global.peer_list.read().await.punch_helpers(
+            self.remote_id(),
+            &relay_id,
+            n,
+            |id| punches.is_capable(id),
+        );
+        debug!(
+            "Punch to {} by {} helpers.",
+            self.remote_id().short_show(),
+            helpers.len()
+        );
+        for helper in helpers {
+            let msg = EndpointMessage::HolePunch(*self.remote_id());
+            let _ = helper.send(SessionMessage::Hole(msg)).await;
+        }
+    }
+
+    /// remote want to punch to target, tell both sides the other's address.
+    #[cfg(feature = "hole-punching")]
+    async fn punch_help(&self, target: PeerId) -> Result<()> {
+        if !self.is_direct() || &target == self.my_id() || &target == self.remote_id() {
+            return Ok(());
+        }
+        if !self.global.policy(self.remote_id()).await.relay {
+            return Ok(());
+        }
+        // the old version peers not know the `HoleConnect` with address.
+        if !self.global.punches.is_capable(self.remote_id())
+            || !self.global.punches.is_capable(&target)
+        {
+            return Ok(());
+        }
+        let connected = self.global.peer_list.read().await.connected(&target);
+        if let Some((peer, sender)) = connected {
+            let msg = EndpointMessage::HoleConnect(self.remote_peer);
+            if sender.send(SessionMessage::Hole(msg)).await.is_ok() {
+                self.direct_send(EndpointMessage::HoleConnect(peer)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// waiting the stable session's class share of the uplink.
+    async fn qos_acquire(&self, n: usize) {
+        if self.is_stable {
+            if let Some(qos) = &self.global.qos {
+                let class = qos.class(self.remote_id()).await;
+                qos.acquire(class, n).await;
+            }
+        }
+    }
+
+    /// count session's payload bytes, the transport's frame bytes are counted by
+    /// the transport.
+    fn count_sent(&self, n: usize) {
+        self.bandwidth.add_sent(n);
+    }
+
+    /// score the remote's misbehavior, only direct session, the relayed frames
+    /// may be broken by the relay. close the session if it is banned.
+    async fn misbehave(&mut self, misbehavior: Misbehavior) -> Result<()> {
+        if self.is_direct()
+            && self
+                .global
+                .misbehave(&self.remote_peer, true, misbehavior)
+                .await
+        {
+            self.no_reconnect = true;
+            let _ = self.direct_send(EndpointMessage::Close).await;
+            return Err(new_io_error("banned"));
+        }
+        Ok(())
+    }
+
+    /// count the inbound frame, more than `ban_rate` in one second is a rate violation.
+    async fn check_rate(&mut self) -> Result<()> {
+        if self.global.ban_rate == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        if now.duration_since(self.recv_frames.1) >= Duration::from_secs(1) {
+            self.recv_frames = (0, now);
+        }
+        self.recv_frames.0 += 1;
+        // only once in every second.
+        if self.recv_frames.0 == self.global.ban_rate + 1 {
+            return self.misbehave(Misbehavior::RateViolation).await;
+        }
+        Ok(())
+    }
+
+    fn count_received(&self, n: usize) {
+        self.bandwidth.add_received(n);
+    }
+
+    /// when known remote support groups, send self joined groups once.
+    async fn send_groups(&mut self) -> Result<()> {
+        if self.remote_group != (true, false) {
+            return Ok(());
+        }
+        self.remote_group.1 = true;
+        let joined = self.global.groups.read().await.joined();
+        for group in joined {
+            self.send_core_data(CoreData::GroupJoin(group)).await?;
+        }
+        Ok(())
+    }
+
+    /// when known remote support services, send self registered services once.
+    async fn send_services(&mut self) -> Result<()> {
+        if self.remote_service != (true, false) {
+            return Ok(());
+        }
+        self.remote_service.1 = true;
+        let registered = self.global.services.read().await.registered();
+        for name in registered {
+            self.send_core_data(CoreData::ServiceAnnounce(true, name))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// supported features, and the mailbox role.
+    async fn features(&self) -> u8 {
+        if self.global.mailbox.read().await.is_enable() {
+            CORE_FEATURES | FEATURE_MAILBOX
+        } else {
+            CORE_FEATURES
+        }
+    }
+
+    /// when known remote is a mailbox, fetch self mails from it once.
+    async fn fetch_mails(&mut self) -> Result<()> {
+        if self.remote_mailbox != (true, false) {
+            return Ok(());
+        }
+        self.remote_mailbox.1 = true;
+        self.global
+            .mailbox
+            .write()
+            .await
+            .add_peer(*self.remote_id());
+        self.send_core_data(CoreData::MailboxFetch).await
+    }
+
+    /// one neighbor's reply of finding service, when all replied, return to outside.
+    async fn service_reply(&self, qid: u64, providers: Vec<Peer>) -> Result<()> {
+        let result = self
+            .global
+            .services
+            .write()
+            .await
+            .query_reply(qid, providers);
+        if let Some((name, providers)) = result {
+            self.out_send(ReceiveMessage::Services(name, providers))
+                .await?;
+        }
+        Ok(())
+    }
+
     async fn send_core_data(&self, data: CoreData) -> Result<()> {
-        let e_data = self.session_key.encrypt(data.to_bytes());
+        let e_data = self.seal(data);
         if self.is_direct() {
-            self.direct_send(EndpointMessage::Data(e_data)).await
+            self.direct_send(EndpointMessage::Data(e_data.into())).await
         } else {
             self.relay_send(SessionMessage::RelayData(
                 *self.my_id(),
                 *self.remote_id(),
-                e_data,
+                vec![],
+                e_data.into(),
             ))
             .await
         }
     }
 
-    async fn handle_core_data(&mut self, e_data: Vec<u8>) -> Result<()> {
-        if let Ok(bytes) = self.session_key.decrypt(e_data) {
-            if let Ok(msg) = CoreData::from_bytes(bytes) {
-                match msg {
-                    CoreData::Ping => {
-                        self.send_core_data(CoreData::Pong).await?;
-                    }
-                    CoreData::Pong => {
-                        self.heartbeat = 0;
-                    }
-                    CoreData::Data(tid, p_data) => {
-                        if self.is_recv_data {
-                            let delivery_data =
-                                delivery_split!(p_data, self.global.delivery_length);
-                            self.out_send(ReceiveMessage::Data(*self.remote_id(), p_data))
-                                .await?;
-                            if tid != 0 {
-                                self.send_core_data(CoreData::Delivery(
-                                    DeliveryType::Data,
-                                    tid,
-                                    delivery_data,
-                                ))
-                                .await?;
-                            }
-                        }
-                    }
-                    CoreData::Delivery(t, tid, data) => {
-                        if tid != 0 {
-                            match t {
-                                DeliveryType::Data => {
-                                    if self.is_recv_data {
-                                        self.out_send(ReceiveMessage::Delivery(t, tid, true, data))
-                                            .await?;
-                                    }
-                                }
-                                _ => {
-                                    self.out_send(ReceiveMessage::Delivery(t, tid, true, data))
-                                        .await?;
-                                }
-                            }
-                        }
-                    }
-                    CoreData::StableConnect(tid, data) => {
-                        let delivery_data = delivery_split!(data, self.global.delivery_length);
-                        self.out_send(ReceiveMessage::StableConnect(self.remote_peer, data))
-                            .await?;
-                        if tid != 0 {
-                            self.send_core_data(CoreData::Delivery(
-                                DeliveryType::StableConnect,
-                                tid,
-                                delivery_data,
-                            ))
-                            .await?;
-                        }
-                    }
-                    CoreData::StableResult(tid, is_ok, data) => {
-                        let delivery_data = delivery_split!(data, self.global.delivery_length);
-                        self.out_send(ReceiveMessage::StableResult(self.remote_peer, is_ok, data))
-                            .await?;
-                        if tid != 0 {
-                            self.send_core_data(CoreData::Delivery(
-                                DeliveryType::StableResult,
-                                tid,
-                                delivery_data,
-                            ))
-                            .await?;
-                        }
-                    }
-                    CoreData::ResultConnect(tid, data) => {
-                        let delivery_data = delivery_split!(data, self.global.delivery_length);
-                        self.out_send(ReceiveMessage::ResultConnect(self.remote_peer, data))
-                            .await?;
-                        if tid != 0 {
-                            self.send_core_data(CoreData::Delivery(
-                                DeliveryType::StableResult,
-                                tid,
-                                delivery_data,
-                            ))
-                            .await?;
-                        }
+    /// encode the message to frame (padded if need), and encrypt it.
+    fn seal(&self, data: CoreData) -> Vec<u8> {
+        self.bandwidth.add_messages(true, data.messages());
+        let mut bytes = data.to_bytes();
+        #[cfg(feature = "capture")]
+        self.global
+            .capture
+            .record(self.remote_id(), false, true, bytes[0], &bytes);
+        if self.is_stable && self.remote_padding && !self.global.padding_buckets.is_empty() {
+            bytes = pad_bytes(bytes, &self.global.padding_buckets);
+        }
+        #[cfg(feature = "capture")]
+        self.record(|| Event::Out(bytes.clone()));
+        self.session_key.encrypt(bytes)
+    }
+
+    async fn handle_core_data(&mut self, e_data: Bytes) -> Result<()> {
+        if let Ok(bytes) = self.session_key.decrypt(&e_data) {
+            #[cfg(feature = "capture")]
+            if let Some(t) = bytes.first() {
+                self.global
+                    .capture
+                    .record(self.remote_id(), true, true, *t, &bytes);
+            }
+            #[cfg(feature = "capture")]
+            self.record(|| Event::In(bytes.clone()));
+            match CoreData::from_bytes(bytes) {
+                Ok(CoreData::Batch(msgs)) => {
+                    self.remote_batch = true;
+                    for msg in msgs {
+                        self.handle_core_msg(msg).await?;
                     }
-                    CoreData::Unstable => self.close(false).await?,
                 }
+                Ok(msg) => self.handle_core_msg(msg).await?,
+                Err(_) => self.misbehave(Misbehavior::InvalidFrame).await?,
             }
         } else {
             warn!("Session Key decrypt failure!");
+            self.misbehave(Misbehavior::BadSignature).await?;
         }
 
         Ok(())
     }
 
-    async fn upgrade(&mut self) -> Result<()> {
-        debug!("UPGRADE TO STABLE CONNECTION");
-        self.is_stable = true;
-        self.is_recv_data = true;
-        self.global.upgrade(self.remote_id()).await
-    }
-
-    async fn forever(&mut self, mut session_receiver: Receiver<SessionMessage>) -> Result<()> {
-        loop {
+    async fn handle_core_msg(&mut self, msg: CoreData) -> Result<()> {
+        if !matches!(
+            msg,
+            CoreData::Ping(..) | CoreData::Pong(..) | CoreData::Cover
+        ) {
+            self.last_active = Instant::now();
+            self.bandwidth.add_messages(false, 1);
+        }
+        match msg {
+            CoreData::Ping(t, features, payload) => {
+                if !payload.is_empty() && self.is_stable {
+                    self.out_send(ReceiveMessage::Heartbeat(*self.remote_id(), payload))
+                        .await?;
+                }
+                self.remote_ordered = features & FEATURE_ORDERED != 0;
+                self.remote_protocol = Some(features & FEATURE_PROTOCOL != 0);
+                self.remote_batch = features & FEATURE_BATCH != 0;
+                let features = self.features().await;
+                self.send_core_data(CoreData::Pong(t, features, now_millis()))
+                    .await?;
+                self.remote_group.0 = features & FEATURE_GROUP != 0;
+                self.send_groups().await?;
+                self.remote_service.0 = features & FEATURE_SERVICE != 0;
+                self.send_services().await?;
+                self.remote_padding = features & FEATURE_PADDING != 0;
+                self.remote_mailbox.0 = features & FEATURE_MAILBOX != 0;
+                self.remote_block_share = features & FEATURE_BLOCK_SHARE != 0;
+                self.fetch_mails().await?;
+            }
+            CoreData::Pong(t, features, remote_now) => {
+                self.heartbeat = 0;
+                self.remote_ordered = features & FEATURE_ORDERED != 0;
+                self.remote_protocol = Some(features & FEATURE_PROTOCOL != 0);
+                self.remote_batch = features & FEATURE_BATCH != 0;
+                self.remote_group.0 = features & FEATURE_GROUP != 0;
+                self.send_groups().await?;
+                self.remote_service.0 = features & FEATURE_SERVICE != 0;
+                self.send_services().await?;
+                self.remote_padding = features & FEATURE_PADDING != 0;
+                self.remote_mailbox.0 = features & FEATURE_MAILBOX != 0;
+                self.remote_block_share = features & FEATURE_BLOCK_SHARE != 0;
+                self.fetch_mails().await?;
+                // old version's pong has no ping's timestamp.
+                if t != 0 {
+                    let now = now_millis();
+                    let rtt = now.saturating_sub(t) as u32;
+                    stats::histogram(stats::METRIC_RTT, rtt as f64);
+                    self.global
+                        .latencies
+                        .write()
+                        .await
+                        .insert(*self.remote_id(), rtt);
+                    // old version's pong has no sender's timestamp.
+                    if remote_now != 0 {
+                        let offset = remote_now as i64 - ((t + now) / 2) as i64;
+                        self.global.clock_offset(*self.remote_id(), offset).await;
+                    }
+                }
+            }
+            CoreData::AppPing(tid) => {
+                self.send_core_data(CoreData::AppPong(tid)).await?;
+            }
+            CoreData::AppPong(tid) => {
+                if let Some(t) = self.pings.remove(&tid) {
+                    let rtt = t.elapsed().as_millis() as u32;
+                    self.out_send(ReceiveMessage::Pong(tid, *self.remote_id(), Some(rtt)))
+                        .await?;
+                }
+            }
+            CoreData::Data(tid, p_data) => {
+                self.recv_data(tid, p_data).await?;
+            }
+            CoreData::Redundant(id, tid, p_data) => {
+                let seen = &mut self.redundant_seen;
+                if seen.0.insert(id) {
+                    seen.1.push_back(id);
+                    if seen.1.len() > REDUNDANT_SEEN_MAX {
+                        if let Some(old) = seen.1.pop_front() {
+                            seen.0.remove(&old);
+                        }
+                    }
+                    self.recv_data(tid, p_data).await?;
+                } else {
+                    debug!("Redundant data is duplicate, drop it.");
+                }
+            }
+            CoreData::ProtocolData(tid, protocol, p_data) => {
+                self.remote_protocol = Some(true);
+                if self.accept_data().await {
+                    let delivery_data = self.global.delivery(&p_data);
+                    self.out_send(ReceiveMessage::ProtocolData(
+                        *self.remote_id(),
+                        protocol,
+                        p_data.into(),
+                    ))
+                    .await?;
+                    if tid != 0 {
+                        self.send_core_data(CoreData::Delivery(
+                            DeliveryType::Data,
+                            tid,
+                            delivery_data,
+                        ))
+                        .await?;
+                    }
+                }
+            }
+            CoreData::GroupJoin(group) => {
+                let id = *self.remote_id();
+                let mut groups = self.global.groups.write().await;
+                if groups.add_member(&group, id) && groups.is_joined(&group) {
+                    // encrypted group, send self's sender key to the new member if it
+                    // is allowlisted or admitted by outside (`GroupAdmit`).
+                    if self.global.allowlisted.contains(&id) {
+                        groups.admit(&group, id);
+                    }
+                    let own_key = if groups.is_admitted(&group, &id) {
+                        groups.own_key(&group)
+                    } else {
+                        None
+                    };
+                    drop(groups);
+                    if let Some((epoch, key)) = own_key {
+                        self.send_core_data(CoreData::GroupKey(group.clone(), epoch, key))
+                            .await?;
+                    }
+                    self.out_send(ReceiveMessage::GroupJoin(group, id)).await?;
+                }
+            }
+            CoreData::GroupLeave(group) => {
+                let id = *self.remote_id();
+                let mut groups = self.global.groups.write().await;
+                if groups.remove_member(&group, &id) && groups.is_joined(&group) {
+                    drop(groups);
+                    self.global.group_rekey(&group).await;
+                    self.out_send(ReceiveMessage::GroupLeave(group, id)).await?;
+                }
+            }
+            CoreData::GroupKey(group, epoch, key) => {
+                let id = *self.remote_id();
+                self.global
+                    .groups
+                    .write()
+                    .await
+                    .add_sender_key(&group, id, epoch, key);
+            }
+            CoreData::GroupSealed(group, sealed) => {
+                let id = *self.remote_id();
+                let is_accept = self.accept_data().await;
+                let groups = self.global.groups.read().await;
+                if is_accept && groups.is_joined(&group) && groups.is_member(&group, &id) {
+                    let data = groups.open(&group, id, &sealed);
+                    drop(groups);
+                    match data {
+                        Some(data) => {
+                            self.out_send(ReceiveMessage::GroupData(group, id, data))
+                                .await?
+                        }
+                        None => debug!("Group {} sealed data cannot open.", group),
+                    }
+                }
+            }
+            CoreData::GroupData(group, p_data) => {
+                let is_member = {
+                    let groups = self.global.groups.read().await;
+                    groups.is_joined(&group) && groups.is_member(&group, self.remote_id())
+                };
+                if is_member && self.accept_data().await {
+                    self.out_send(ReceiveMessage::GroupData(
+                        group,
+                        *self.remote_id(),
+                        p_data.into(),
+                    ))
+                    .await?;
+                }
+            }
+            CoreData::ServiceAnnounce(is_register, name) => {
+                let ok = self.global.services.write().await.update_provider(
+                    &name,
+                    self.remote_peer,
+                    is_register,
+                );
+                if !ok {
+                    debug!("Session service records is full, ignore {}.", name);
+                }
+            }
+            CoreData::ServiceQuery(qid, name) => {
+                let mut providers = self
+                    .global
+                    .services
+                    .read()
+                    .await
+                    .providers(&name, self.remote_id());
+                // and the stored provider records of the service key.
+                let records = self
+                    .global
+                    .topics
+                    .read()
+                    .await
+                    .members(&service_key(&name), self.remote_id());
+                for peer in records {
+                    if !providers.iter().any(|p| p.id == peer.id) {
+                        providers.push(peer);
+                    }
+                }
+                providers.truncate(SERVICE_REPLY_MAX);
+                self.send_core_data(CoreData::ServiceReply(qid, providers))
+                    .await?;
+            }
+            CoreData::ServiceReply(qid, providers) => {
+                self.service_reply(qid, providers).await?;
+            }
+            CoreData::Cover => {}
+            CoreData::MailboxStore(sealed) => {
+                // only store the mail sealed by remote self.
+                let is_trusted =
+                    self.is_stable || self.global.allowlisted.contains(self.remote_id());
+                match mailbox::open(&sealed, &self.global.peer_id_hash) {
+                    Some((id, from, to, _)) if &from == self.remote_id() => {
+                        let mut mailbox = self.global.mailbox.write().await;
+                        let stored =
+                            mailbox.is_accept(is_trusted) && mailbox.store(from, to, id, sealed);
+                        drop(mailbox);
+                        if !stored {
+                            debug!(
+                                "Session: mailbox is full, disabled or not accept, drop the mail."
+                            );
+                        }
+                    }
+                    _ => warn!(
+                        "Session: invalid mail from {}.",
+                        self.remote_id().short_show()
+                    ),
+                }
+            }
+            CoreData::MailboxFetch => {
+                let mails = self.global.mailbox.write().await.take(self.remote_id());
+                for sealed in mails {
+                    self.send_core_data(CoreData::MailboxMail(sealed)).await?;
+                }
+            }
+            CoreData::MailboxMail(sealed) => {
+                match mailbox::open(&sealed, &self.global.peer_id_hash) {
+                    Some((id, from, to, encrypted)) if &to == self.my_id() => {
+                        match mailbox::decrypt(&self.global.key, id, &from, &to, &encrypted) {
+                            Some(data) => {
+                                if self.global.mailbox.write().await.is_new(id) {
+                                    self.out_send(ReceiveMessage::Mail(from, data)).await?;
+                                }
+                            }
+                            None => {
+                                warn!("Session: cannot decrypt mail from {}.", from.short_show())
+                            }
+                        }
+                    }
+                    _ => warn!(
+                        "Session: invalid mail from {}.",
+                        self.remote_id().short_show()
+                    ),
+                }
+            }
+            CoreData::SwarmManifest(sealed) => {
+                match swarm::open(&sealed, &self.global.peer_id_hash) {
+                    Some((id, origin, len, hashes)) => {
+                        if &origin != self.my_id() && self.accept_data().await {
+                            let from = *self.remote_id();
+                            let is_new = self
+                                .global
+                                .swarms
+                                .write()
+                                .await
+                                .announce(id, origin, len, hashes, from);
+                            if is_new {
+                                debug!("Swarm from {}, {} bytes.", origin.short_show(), len);
+                                self.global
+                                    .swarm_announce(sealed.into(), None, &[from, origin])
+                                    .await;
+                            }
+                            self.global.swarm_fetch(&id).await;
+                        }
+                    }
+                    None => self.misbehave(Misbehavior::InvalidFrame).await?,
+                }
+            }
+            CoreData::TopicJoin(topic, is_join) => {
+                let mut topics = self.global.topics.write().await;
+                topics.publish(topic, self.remote_peer, is_join);
+                if is_join {
+                    let mut peers = topics.members(&topic, self.remote_id());
+                    drop(topics);
+                    // and the closer peers, remote will query them too.
+                    let closer = self
+                        .global
+                        .peer_list
+                        .read()
+                        .await
+                        .closest(&topic, self.global.topic_replicas);
+                    for (peer, _) in closer {
+                        if &peer.id != self.remote_id() && !peers.iter().any(|p| p.id == peer.id) {
+                            peers.push(peer);
+                        }
+                    }
+                    peers.truncate(TOPIC_PEERS_MAX);
+                    if !peers.is_empty() {
+                        self.send_core_data(CoreData::TopicPeers(topic, peers))
+                            .await?;
+                    }
+                }
+            }
+            CoreData::TopicPeers(topic, mut peers) => {
+                let is_joined = self
+                    .global
+                    .groups
+                    .read()
+                    .await
+                    .joined()
+                    .iter()
+                    .any(|group| topic_key(group) == topic);
+                if is_joined {
+                    peers.truncate(TOPIC_PEERS_MAX);
+                    self.global.topic_connect(peers).await;
+                }
+            }
+            CoreData::SwarmWant(id, index) => {
+                let chunk = self.global.swarms.read().await.chunk(&id, index);
+                // empty is not have it.
+                let chunk = chunk.unwrap_or_default();
+                self.send_bulk_data(CoreData::SwarmChunk(id, index, chunk))
+                    .await?;
+            }
+            CoreData::SwarmChunk(id, index, data) => {
+                if data.is_empty() {
+                    let from = *self.remote_id();
+                    self.global.swarms.write().await.miss(&id, index, &from);
+                } else {
+                    let received = self.global.swarms.write().await.receive(&id, index, data);
+                    match received {
+                        Ok(Some((origin, data))) => {
+                            debug!("Swarm finished, {} bytes.", data.len());
+                            let path = if &origin == self.remote_id() {
+                                self.data_path()
+                            } else {
+                                DataPath::Relay(*self.remote_id())
+                            };
+                            let msg = self.global.data_message(origin, path, data.to_vec());
+                            self.out_send(msg).await?;
+                        }
+                        Ok(None) => {}
+                        Err(()) => self.misbehave(Misbehavior::InvalidFrame).await?,
+                    }
+                }
+                self.global.swarm_fetch(&id).await;
+            }
+            CoreData::BlockShare(sealed) => {
+                // only accept from and issued by trusted peers.
+                let from = *self.remote_id();
+                if self.global.shared_bans.is_enable() && self.global.allowlisted.contains(&from) {
+                    match ban::open_block(&sealed, &self.global.peer_id_hash) {
+                        Some((issuer, target, time))
+                            if self.global.allowlisted.contains(&issuer) =>
+                        {
+                            info!(
+                                "Session: shared block {:?} by {}.",
+                                target,
+                                issuer.short_show()
+                            );
+                            self.global
+                                .share_block(sealed, target, time, &[from, issuer])
+                                .await;
+                        }
+                        _ => warn!("Session: invalid shared block from {}.", from.short_show()),
+                    }
+                }
+            }
+            CoreData::OrderedData(seq, tid, p_data) => {
+                self.remote_ordered = true;
+                if seq == self.recv_seq {
+                    self.recv_seq += 1;
+                    self.recv_data(tid, p_data).await?;
+                    self.flush_reorders(false).await?;
+                } else if seq > self.recv_seq {
+                    self.reorders.insert(seq, (tid, p_data));
+                    if self.reorder_since.is_none() {
+                        self.reorder_since = Some(Instant::now());
+                    }
+                    if self.reorders.len() > MAX_REORDER_BUFFER {
+                        self.flush_reorders(true).await?;
+                    }
+                } else {
+                    // late data, the missing had skipped.
+                    self.recv_data(tid, p_data).await?;
+                }
+            }
+            CoreData::Delivery(t, tid, data) => {
+                if tid != 0 {
+                    match t {
+                        DeliveryType::Data => {
+                            if self.is_recv_data {
+                                self.out_send(ReceiveMessage::Delivery(t, tid, Ok(()), data))
+                                    .await?;
+                            }
+                        }
+                        _ => {
+                            self.out_send(ReceiveMessage::Delivery(t, tid, Ok(()), data))
+                                .await?;
+                        }
+                    }
+                }
+            }
+            CoreData::StableConnect(tid, data) => {
+                if tid != 0 {
+                    self.send_core_data(CoreData::Delivery(
+                        DeliveryType::StableConnect,
+                        tid,
+                        self.global.delivery(&data),
+                    ))
+                    .await?;
+                }
+                self.stable_connect(data).await?;
+            }
+            CoreData::StableResult(tid, is_ok, data) => {
+                let delivery_data = self.global.delivery(&data);
+                let is_reconnect = self
+                    .global
+                    .reconnects
+                    .write()
+                    .await
+                    .remove(self.remote_id());
+                if is_reconnect && is_ok {
+                    self.resume_seqs().await;
+                    self.out_send(ReceiveMessage::StableRestored(self.remote_peer))
+                        .await?;
+                } else {
+                    self.out_send(ReceiveMessage::StableResult(self.remote_peer, is_ok, data))
+                        .await?;
+                }
+                if tid != 0 {
+                    self.send_core_data(CoreData::Delivery(
+                        DeliveryType::StableResult,
+                        tid,
+                        delivery_data,
+                    ))
+                    .await?;
+                }
+            }
+            CoreData::ResultConnect(tid, data) => {
+                let delivery_data = self.global.delivery(&data);
+                self.out_send(ReceiveMessage::ResultConnect(self.remote_peer, data))
+                    .await?;
+                if tid != 0 {
+                    self.send_core_data(CoreData::Delivery(
+                        DeliveryType::StableResult,
+                        tid,
+                        delivery_data,
+                    ))
+                    .await?;
+                }
+            }
+            CoreData::Unstable => {
+                self.no_reconnect = true;
+                self.global
+                    .peer_list
+                    .write()
+                    .await
+                    .stable_forget(self.remote_id())
+                    .await;
+                self.close(false).await?
+            }
+            CoreData::Batch(..) => {} // no nested batch.
+            CoreData::Ticket(ticket) => {
+                self.global
+                    .resumptions
+                    .write()
+                    .await
+                    .entry(*self.remote_id())
+                    .or_default()
+                    .received = Some(ticket);
+            }
+            CoreData::Resume(ticket) => {
+                let window = self.global.resume_window;
+                let is_ok = match self.global.resumptions.read().await.get(self.remote_id()) {
+                    Some(r) => {
+                        r.issued == Some(ticket) && r.closed_at.is_some() && !r.is_expired(window)
+                    }
+                    None => false,
+                };
+                if is_ok && !self.is_stable {
+                    // only the stable re-negotiation with outside is skipped, the
+                    // admission and the quota are checked again.
+                    match self.admit(vec![]) {
+                        Some((StableDecision::Outside | StableDecision::Accept, _)) => {}
+                        Some((StableDecision::Reject, _)) => {
+                            return self.stable_result(0, false, true, vec![]).await;
+                        }
+                        _ => return self.stable_result(0, false, false, vec![]).await,
+                    }
+                    let class = self.peer_class();
+                    if !self
+                        .global
+                        .stable_quota_take(*self.remote_id(), class)
+                        .await
+                    {
+                        warn!("CHAMOMILE: STABLE QUOTA OF {:?} PEERS IS FULL.", class);
+                        return self.stable_result(0, false, false, vec![]).await;
+                    }
+                    debug!("Session resume stable: {}.", self.remote_id().short_show());
+                    self.upgrade().await?;
+                    self.resume_seqs().await;
+                    self.send_core_data(CoreData::StableResult(0, true, vec![]))
+                        .await?;
+                    self.out_send(ReceiveMessage::StableRestored(self.remote_peer))
+                        .await?;
+                } else {
+                    // ticket invalid, as a stable connect without info.
+                    self.stable_connect(vec![]).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// remote's stable connect, decide by the stable check, or send to outside.
+    async fn stable_connect(&mut self, data: Vec<u8>) -> Result<()> {
+        match self.admit(data) {
+            Some((StableDecision::Outside, data)) => {
+                self.out_send(ReceiveMessage::StableConnect(self.remote_peer, data))
+                    .await
+            }
+            Some((StableDecision::Accept, _)) => self.stable_result(0, true, false, vec![]).await,
+            Some((StableDecision::RelayOnly, _)) | None => {
+                self.stable_result(0, false, false, vec![]).await
+            }
+            Some((StableDecision::Reject, _)) => self.stable_result(0, false, true, vec![]).await,
+        }
+    }
+
+    /// the admission of remote's stable connect (and resume): the join token, the
+    /// connection gater and the stable check. return the decision and the connect
+    /// info without token, None if the join token is invalid.
+    fn admit(&mut self, mut data: Vec<u8>) -> Option<(StableDecision, Vec<u8>)> {
+        if !self.global.join_issuers.is_empty() {
+            let info = match token::split(&data) {
+                Some((t, info)) => {
+                    let issuers = &self.global.join_issuers;
+                    if token::verify(
+                        t,
+                        issuers,
+                        self.my_id(),
+                        self.remote_id(),
+                        &self.global.peer_id_hash,
+                    ) {
+                        Some(info.to_vec())
+                    } else {
+                        None
+                    }
+                }
+                None => None,
+            };
+            match info {
+                Some(info) => {
+                    self.verified = true;
+                    data = info;
+                }
+                None => {
+                    debug!(
+                        "Session invalid join token: {}.",
+                        self.remote_id().short_show()
+                    );
+                    return None;
+                }
+            }
+        }
+
+        let decision = if !self.global.gate_peer(&self.remote_peer) {
+            StableDecision::Reject
+        } else {
+            match &self.global.stable_check {
+                Some(check) => (check.0)(&self.remote_peer, &data),
+                None => StableDecision::Outside,
+            }
+        };
+        if decision != StableDecision::Outside {
+            debug!(
+                "Session stable check {}: {:?}.",
+                self.remote_id().short_show(),
+                decision
+            );
+        }
+        Some((decision, data))
+    }
+
+    async fn stable_result(
+        &mut self,
+        tid: u64,
+        is_ok: bool,
+        is_force: bool,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let is_ok = if is_ok && !self.is_stable {
+            let class = self.peer_class();
+            let ok = self
+                .global
+                .stable_quota_take(*self.remote_id(), class)
+                .await;
+            if !ok {
+                warn!("CHAMOMILE: STABLE QUOTA OF {:?} PEERS IS FULL.", class);
+            }
+            ok
+        } else {
+            is_ok
+        };
+
+        let sent = self
+            .send_core_data(CoreData::StableResult(tid, is_ok, data))
+            .await;
+        if sent.is_err() && is_ok && !self.is_stable {
+            // release the taken quota place, not upgraded.
+            self.global
+                .stable_classes
+                .write()
+                .await
+                .remove(self.remote_id());
+        }
+        sent?;
+
+        if !self.is_stable && is_ok {
+            self.upgrade().await?;
+        }
+
+        if !self.is_stable && !is_ok {
+            self.close(false).await?;
+        }
+
+        if is_force {
+            return Err(new_io_error("force close"));
+        }
+        Ok(())
+    }
+
+    fn peer_class(&self) -> PeerClass {
+        if self.global.allowlisted.contains(self.remote_id()) {
+            PeerClass::Allowlisted
+        } else if self.verified {
+            PeerClass::Verified
+        } else {
+            PeerClass::Anonymous
+        }
+    }
+
+    async fn upgrade(&mut self) -> Result<()> {
+        debug!("UPGRADE TO STABLE CONNECTION");
+        self.is_stable = true;
+        self.is_recv_data = true;
+        self.global.upgrade(self.remote_id()).await?;
+        let class = self.peer_class();
+        self.global
+            .stable_classes
+            .write()
+            .await
+            .insert(*self.remote_id(), class);
+
+        // send the queued outbox data when offline, the not sent data are kept in
+        // the outbox.
+        let queued = self.global.outbox.write().await.take(self.remote_id());
+        let mut queued = queued.into_iter();
+        while let Some((tid, data, time)) = queued.next() {
+            let data = Bytes::from(data);
+            if let Err(e) = self.send_data(tid, data.clone()).await {
+                let mut rest = vec![(tid, data.to_vec(), time)];
+                rest.extend(queued);
+                self.global
+                    .outbox
+                    .write()
+                    .await
+                    .restore(*self.remote_id(), rest);
+                return Err(e);
+            }
+        }
+
+        // give remote a new resumption ticket.
+        if self.global.resume_window > Duration::ZERO {
+            let ticket = rand::thread_rng().gen::<u64>();
+            let mut resumptions = self.global.resumptions.write().await;
+            let r = resumptions.entry(*self.remote_id()).or_default();
+            r.issued = Some(ticket);
+            r.closed_at = None;
+            drop(resumptions);
+            self.send_core_data(CoreData::Ticket(ticket)).await?;
+        }
+
+        #[cfg(feature = "hole-punching")]
+        self.punch().await;
+        Ok(())
+    }
+
+    /// send stable connect, if it is reconnect without info, and had remote's
+    /// resumption ticket, use the ticket to resume.
+    async fn send_stable_connect(&self, tid: u64, data: Vec<u8>) -> Result<()> {
+        if tid == 0
+            && data.is_empty()
+            && self
+                .global
+                .reconnects
+                .read()
+                .await
+                .contains(self.remote_id())
+        {
+            let window = self.global.resume_window;
+            let ticket = match self.global.resumptions.read().await.get(self.remote_id()) {
+                Some(r) if !r.is_expired(window) => r.received,
+                _ => None,
+            };
+            if let Some(ticket) = ticket {
+                return self.send_core_data(CoreData::Resume(ticket)).await;
+            }
+        }
+        self.send_core_data(CoreData::StableConnect(tid, data))
+            .await
+    }
+
+    /// restore the ordered data's sequences when resumed.
+    async fn resume_seqs(&mut self) {
+        if let Some(r) = self.global.resumptions.read().await.get(self.remote_id()) {
+            self.send_seq = r.seqs.0;
+            self.recv_seq = r.seqs.1;
+        }
+    }
+
+    /// send data to remote, if stable and remote support, use ordered data.
+    async fn send_data(&mut self, tid: u64, data: Bytes) -> Result<()> {
+        if self.is_stable && self.remote_ordered && self.global.ordered_delivery {
+            let seq = self.send_seq;
+            self.send_seq += 1;
+            self.send_batch_data(CoreData::OrderedData(seq, tid, data))
+                .await
+        } else {
+            self.send_batch_data(CoreData::Data(tid, data)).await
+        }
+    }
+
+    /// send the critical data by the direct path and a relay path at same time,
+    /// remote deliver the first arrived one. if not direct stable session, remote
+    /// not support it, or no relay path, send it as normal data.
+    async fn send_redundant(&mut self, tid: u64, data: Bytes) -> Result<()> {
+        if self.is_stable && self.is_direct() && self.version >= REDUNDANT_VERSION {
+            let relay = self
+                .global
+                .peer_list
+                .read()
+                .await
+                .redundant_relay(self.remote_id());
+            if let Some(relay) = relay {
+                let id = rand::thread_rng().gen::<u64>();
+                let e_data = Bytes::from(self.seal(CoreData::Redundant(id, tid, data)));
+                let msg = SessionMessage::RelayData(
+                    *self.my_id(),
+                    *self.remote_id(),
+                    vec![],
+                    e_data.clone(),
+                );
+                let _ = relay.send(msg).await;
+                return self.direct_send(EndpointMessage::Data(e_data)).await;
+            }
+        }
+        self.send_data(tid, data).await
+    }
+
+    /// send data message, if batch enabled and remote support, the small one
+    /// will wait in batch, and send together when full or delay.
+    async fn send_batch_data(&mut self, data: CoreData) -> Result<()> {
+        let (delay, size) = self.global.batch;
+        let len = match &data {
+            CoreData::Data(_, d) | CoreData::OrderedData(_, _, d) => d.len(),
+            CoreData::ProtocolData(_, p, d)
+            | CoreData::GroupData(p, d)
+            | CoreData::GroupSealed(p, d) => p.len() + d.len(),
+            _ => usize::MAX,
+        };
+        if delay.is_zero() || !self.remote_batch || len > BATCH_ITEM_MAX {
+            // keep the order with the waiting ones.
+            self.flush_batch().await?;
+            return self.send_bulk_data(data).await;
+        }
+
+        self.batch.push(data);
+        self.batch_bytes += len;
+        if self.batch_since.is_none() {
+            self.batch_since = Some(Instant::now());
+        }
+        if self.batch_bytes >= size {
+            self.flush_batch().await?;
+        }
+        Ok(())
+    }
+
+    /// add data to the bulk queue, it will send when no control messages waiting.
+    /// if the queue is full, send the oldest one first.
+    async fn send_bulk_data(&mut self, data: CoreData) -> Result<()> {
+        if self.bulk.len() >= BULK_QUEUE_MAX {
+            self.send_bulk_one().await?;
+        }
+        self.bulk.push_back(data);
+        Ok(())
+    }
+
+    async fn send_bulk_one(&mut self) -> Result<()> {
+        if let Some(data) = self.bulk.pop_front() {
+            if let Some(data) = self.expire_data(data).await {
+                self.send_core_data(data).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// drop the waiting data (or the ones in batch) which deadline is expired,
+    /// and timeout failure to outside.
+    async fn expire_data(&self, data: CoreData) -> Option<CoreData> {
+        match data {
+            CoreData::Batch(msgs) => {
+                let mut keeps = Vec::with_capacity(msgs.len());
+                for msg in msgs {
+                    if !self.is_expired(&msg).await {
+                        keeps.push(msg);
+                    }
+                }
+                match keeps.len() {
+                    0 => None,
+                    1 => keeps.pop(),
+                    _ => Some(CoreData::Batch(keeps)),
+                }
+            }
+            data if self.is_expired(&data).await => None,
+            data => Some(data),
+        }
+    }
+
+    /// the data's deadline is expired, send the timeout failure to outside.
+    async fn is_expired(&self, data: &CoreData) -> bool {
+        let (tid, bytes) = match data {
+            CoreData::Data(tid, data)
+            | CoreData::OrderedData(_, tid, data)
+            | CoreData::ProtocolData(tid, _, data)
+            | CoreData::Redundant(_, tid, data) => (*tid, data),
+            _ => return false,
+        };
+        if !self.global.deadline_expired(tid).await {
+            return false;
+        }
+        debug!("Session data {} deadline is expired, drop it.", tid);
+        let _ = self
+            .out_send(ReceiveMessage::Delivery(
+                DeliveryType::Data,
+                tid,
+                Err(DeliveryFailure::Timeout),
+                self.global.delivery(bytes),
+            ))
+            .await;
+        true
+    }
+
+    /// send all waiting batch & bulk data, if endpoint is closed,
+    /// delivery failure to outside.
+    async fn flush_bulk(&mut self) {
+        let _ = self.flush_batch().await;
+        while let Some(data) = self.bulk.pop_front() {
+            let data = match self.expire_data(data).await {
+                Some(data) => data,
+                None => continue,
+            };
+            let is_closed = match &self.endpoint {
+                ConnectType::Direct(sender) => sender.is_closed(),
+                ConnectType::Relay(sender, _) => sender.is_closed(),
+            };
+            if is_closed {
+                let _ = self.failure_core_msg(data).await;
+            } else {
+                let _ = self.send_core_data(data).await;
+            }
+        }
+    }
+
+    /// send the waiting batch data.
+    async fn flush_batch(&mut self) -> Result<()> {
+        self.batch_since = None;
+        self.batch_bytes = 0;
+        let msgs = std::mem::take(&mut self.batch);
+        match self.expire_data(CoreData::Batch(msgs)).await {
+            Some(data) => self.send_bulk_data(data).await,
+            None => Ok(()),
+        }
+    }
+
+    /// accept the remote's data, by the remote's policy, or `is_recv_data`.
+    async fn accept_data(&self) -> bool {
+        let policy = self.global.policy(self.remote_id()).await;
+        policy.data.unwrap_or(self.is_recv_data) && (self.is_direct() || policy.relayed_data)
+    }
+
+    /// received data from remote, send to outside and feedback the delivery.
+    async fn recv_data(&self, tid: u64, p_data: Bytes) -> Result<()> {
+        if self.accept_data().await {
+            let delivery_data = self.global.delivery(&p_data);
+            let msg = self
+                .global
+                .data_message(*self.remote_id(), self.data_path(), p_data.into());
+            self.out_send(msg).await?;
+            if tid != 0 {
+                self.send_core_data(CoreData::Delivery(DeliveryType::Data, tid, delivery_data))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// deliver the waiting ordered data, if `skip` is true, will skip the missing one.
+    async fn flush_reorders(&mut self, skip: bool) -> Result<()> {
+        while let Some(first) = self.reorders.keys().next().copied() {
+            if first != self.recv_seq {
+                if !skip {
+                    break;
+                }
+                debug!("Ordered data skip missing {}..{}.", self.recv_seq, first);
+                self.recv_seq = first;
+            }
+            if let Some((tid, data)) = self.reorders.remove(&first) {
+                self.recv_seq += 1;
+                self.recv_data(tid, data).await?;
+            }
+        }
+
+        self.reorder_since = if self.reorders.is_empty() {
+            None
+        } else {
+            Some(Instant::now())
+        };
+        Ok(())
+    }
+
+    async fn forever(&mut self, mut session_receiver: Receiver<SessionMessage>) -> Result<()> {
+        loop {
+            self.relay_repin().await?;
+            let flush_at = self.batch_since.map(|t| t + self.global.batch.0);
+            // outside is slow, pause reading from remote until outside has room,
+            // the endpoint channel will full, and transport stops reading too.
+            let is_paused = self.global.is_out_full();
+            let keepalive_at = self.global.keepalive_at();
+            let (robust_scale, _) = self.global.power_scale();
+            let cover = self.global.cover_traffic;
+            let cover_at = if !cover.is_zero()
+                && self.is_stable
+                && self.remote_padding
+                && !self.global.padding_buckets.is_empty()
+            {
+                // random in [cover / 2, cover * 3 / 2].
+                Some(*self.cover_at.get_or_insert_with(|| {
+                    let jitter = rand::thread_rng().gen_range(0, cover.as_millis() as u64 + 1);
+                    Instant::now() + cover / 2 + Duration::from_millis(jitter)
+                }))
+            } else {
+                None
+            };
             let res = select! {
                 v = async {
                     session_receiver
                         .recv()
                         .await
-                        .map(|msg| FutureResult::Out(msg))
+                        .map(FutureResult::Out)
                 } => v,
                 v = async {
                     self.stream_receiver
                         .recv()
                         .await
-                        .map(|msg| FutureResult::Endpoint(msg))
-                } => v,
+                        .map(FutureResult::Endpoint)
+                }, if !is_paused => v,
+                v = async {
+                    self.global
+                        .out_sender
+                        .reserve()
+                        .await
+                        .ok()
+                        .map(|_| FutureResult::Resume)
+                }, if is_paused => v,
 
                 v = async {
-                    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+                    tokio::time::sleep_until(keepalive_at.into()).await;
                     Some(FutureResult::HeartBeat)
                 } => v,
                 v = async {
                     // 60s to check all connection channels is ok.
-                    tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+                    tokio::time::sleep(Duration::from_secs(60) * robust_scale).await;
                     Some(FutureResult::Robust)
                 } => v,
+                v = async {
+                    if let Some(t) = flush_at {
+                        tokio::time::sleep_until(t.into()).await;
+                    }
+                    Some(FutureResult::Flush)
+                }, if flush_at.is_some() => v,
+                v = async { Some(FutureResult::Bulk) }, if !self.bulk.is_empty() => v,
+                v = async {
+                    if let Some(t) = cover_at {
+                        tokio::time::sleep_until(t.into()).await;
+                    }
+                    Some(FutureResult::Cover)
+                }, if cover_at.is_some() => v,
             };
+            #[cfg(feature = "capture")]
+            let res = self.record_future(res)?;
             match res {
                 Some(FutureResult::Out(msg)) => {
                     self.handle_outside(msg).await?;
@@ -594,26 +2241,234 @@ impl Session {
                 Some(FutureResult::HeartBeat) => {
                     self.handle_heartbeat().await?;
                 }
-                Some(FutureResult::Robust) => {
-                    self.handle_robust().await?;
+                Some(FutureResult::Robust) => {
+                    self.handle_robust().await?;
+                }
+                Some(FutureResult::Flush) => {
+                    self.flush_batch().await?;
+                }
+                Some(FutureResult::Bulk) => {
+                    // control messages first, then send one bulk data.
+                    for _ in 0..BULK_PREEMPT_MAX {
+                        match session_receiver.try_recv() {
+                            Ok(msg) => self.handle_outside(msg).await?,
+                            Err(_) => break,
+                        }
+                    }
+                    self.send_bulk_one().await?;
+                }
+                Some(FutureResult::Cover) => {
+                    self.cover_at = None;
+                    self.send_core_data(CoreData::Cover).await?;
+                }
+                Some(FutureResult::Resume) => {
+                    debug!("Session resume reading: {}.", self.remote_id().short_show());
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// record the session's event, the start state is recorded before the first one.
+    #[cfg(feature = "capture")]
+    fn record(&self, event: impl FnOnce() -> Event) {
+        let recorder = &self.global.recorder;
+        if !recorder.is_record(self.remote_id()) {
+            return;
+        }
+        if !recorder.is_started(self.remote_id()) {
+            let start = Event::Start(
+                self.is_stable,
+                self.is_recv_data,
+                self.version,
+                self.remote_peer,
+            );
+            recorder.record(self.remote_id(), start);
+        }
+        recorder.record(self.remote_id(), event());
+    }
+
+    /// record the timers & endpoint messages, the data messages are recorded
+    /// when decrypted.
+    #[cfg(feature = "capture")]
+    fn record_future(&self, res: Option<FutureResult>) -> Result<Option<FutureResult>> {
+        let timer = match &res {
+            Some(FutureResult::Out(_)) => {
+                self.record(|| Event::Outside);
+                return Ok(res);
+            }
+            Some(FutureResult::Endpoint(EndpointMessage::Data(_)))
+            | Some(FutureResult::Endpoint(EndpointMessage::RelayData(..)))
+            | Some(FutureResult::Resume)
+            | None => return Ok(res),
+            Some(FutureResult::Endpoint(_)) => {
+                if !self.global.recorder.is_record(self.remote_id()) {
+                    return Ok(res);
+                }
+                if let Some(FutureResult::Endpoint(msg)) = res {
+                    let bytes = codec::encode(msg);
+                    self.record(|| Event::Endpoint(bytes.clone()));
+                    return Ok(Some(FutureResult::Endpoint(codec::decode(bytes)?)));
+                }
+                return Ok(res);
+            }
+            Some(FutureResult::HeartBeat) => Timer::HeartBeat,
+            Some(FutureResult::Robust) => Timer::Robust,
+            Some(FutureResult::Flush) => Timer::Flush,
+            Some(FutureResult::Bulk) => Timer::Bulk,
+            Some(FutureResult::Cover) => Timer::Cover,
+        };
+        self.record(|| Event::Timer(timer));
+        Ok(res)
+    }
+
+    /// handle a recorded event, the inbound frames are encrypted again by the session key.
+    #[cfg(feature = "capture")]
+    pub async fn replay_event(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::In(bytes) => {
+                let e_data = self.session_key.encrypt(bytes);
+                self.handle_endpoint(EndpointMessage::Data(e_data.into()))
+                    .await
+            }
+            Event::Endpoint(bytes) => self.handle_endpoint(codec::decode(bytes)?).await,
+            Event::Timer(Timer::HeartBeat) => self.handle_heartbeat().await,
+            Event::Timer(Timer::Robust) => self.handle_robust().await,
+            Event::Timer(Timer::Flush) => self.flush_batch().await,
+            Event::Timer(Timer::Bulk) => self.send_bulk_one().await,
+            Event::Timer(Timer::Cover) => {
+                self.cover_at = None;
+                self.send_core_data(CoreData::Cover).await
+            }
+            Event::Start(..) | Event::Out(_) | Event::Outside => Ok(()),
+        }
+    }
+
+    pub async fn listen(&mut self, session_receiver: Receiver<SessionMessage>) -> Result<()> {
+        debug!("Session running: {}.", self.remote_id().short_show());
+        self.global
+            .bandwidths
+            .write()
+            .await
+            .insert(*self.remote_id(), self.bandwidth.clone());
+        if let ConnectType::Relay(_, relay_id) = &self.endpoint {
+            let relay_id = *relay_id;
+            self.global
+                .relay_paths
+                .write()
+                .await
+                .insert(*self.remote_id(), relay_id);
+            let relay = self.global.peer_list.read().await.connected(&relay_id);
+            self.relay_peer = relay.map(|(peer, _)| peer);
+        }
+        stats::gauge_add(stats::METRIC_SESSIONS, 1.0);
+        let _ = self.forever(session_receiver).await;
+        debug!("Session broke: {}.", self.remote_id().short_show());
+        stats::gauge_add(stats::METRIC_SESSIONS, -1.0);
+        self.close(true).await
+    }
+
+    async fn handle_outside(&mut self, msg: SessionMessage) -> Result<()> {
+        self.last_active = Instant::now();
+        match msg {
+            SessionMessage::Data(tid, data) => {
+                self.send_data(tid, data).await?;
+            }
+            SessionMessage::RedundantData(tid, data) => {
+                self.send_redundant(tid, data).await?;
+            }
+            SessionMessage::SwarmManifest(sealed, data) => {
+                if self.version >= SWARM_VERSION {
+                    self.send_core_data(CoreData::SwarmManifest(sealed.to_vec()))
+                        .await?;
+                } else if let Some(data) = data {
+                    // remote not support swarm, origin give it the full data.
+                    self.send_data(0, data).await?;
+                }
+            }
+            SessionMessage::SwarmWant(id, index) => {
+                self.send_core_data(CoreData::SwarmWant(id, index)).await?;
+            }
+            SessionMessage::TopicJoin(topic, is_join) => {
+                if self.version >= TOPIC_VERSION {
+                    self.send_core_data(CoreData::TopicJoin(topic, is_join))
+                        .await?;
+                }
+            }
+            SessionMessage::ProtocolData(tid, protocol, data) => {
+                // remote not support it, or the protocol is too long.
+                if self.remote_protocol == Some(false) || protocol.len() > 255 {
+                    if tid != 0 {
+                        self.out_send(ReceiveMessage::Delivery(
+                            DeliveryType::Data,
+                            tid,
+                            Err(DeliveryFailure::Unsupported),
+                            self.global.delivery(&data),
+                        ))
+                        .await?;
+                    }
+                } else {
+                    self.send_batch_data(CoreData::ProtocolData(tid, protocol, data))
+                        .await?;
+                }
+            }
+            SessionMessage::GroupJoin(group) => {
+                if self.remote_group.0 {
+                    self.send_core_data(CoreData::GroupJoin(group)).await?;
+                }
+            }
+            SessionMessage::GroupLeave(group) => {
+                if self.remote_group.0 {
+                    self.send_core_data(CoreData::GroupLeave(group)).await?;
+                }
+            }
+            SessionMessage::GroupData(group, data) => {
+                if self.remote_group.0 {
+                    self.send_batch_data(CoreData::GroupData(group, data))
+                        .await?;
+                }
+            }
+            SessionMessage::GroupKey(group, epoch, key) => {
+                if self.remote_group.0 {
+                    self.send_core_data(CoreData::GroupKey(group, epoch, key))
+                        .await?;
+                }
+            }
+            SessionMessage::GroupSealed(group, data) => {
+                if self.remote_group.0 {
+                    self.send_batch_data(CoreData::GroupSealed(group, data))
+                        .await?;
+                }
+            }
+            SessionMessage::ServiceAnnounce(is_register, name) => {
+                if self.remote_service.0 {
+                    self.send_core_data(CoreData::ServiceAnnounce(is_register, name))
+                        .await?;
+                }
+            }
+            SessionMessage::ServiceQuery(qid, name) => {
+                if self.remote_service.0 {
+                    self.send_core_data(CoreData::ServiceQuery(qid, name))
+                        .await?;
+                } else {
+                    // remote not support it, not wait it.
+                    self.service_reply(qid, vec![]).await?;
+                }
+            }
+            SessionMessage::MailboxStore(sealed) => {
+                if self.remote_mailbox.0 {
+                    self.send_core_data(CoreData::MailboxStore(sealed)).await?;
+                }
+            }
+            SessionMessage::BlockShare(sealed) => {
+                if self.remote_block_share {
+                    self.send_core_data(CoreData::BlockShare(sealed)).await?;
                 }
-                None => break,
             }
-        }
-        Ok(())
-    }
-
-    pub async fn listen(&mut self, session_receiver: Receiver<SessionMessage>) -> Result<()> {
-        debug!("Session running: {}.", self.remote_id().short_show());
-        let _ = self.forever(session_receiver).await;
-        debug!("Session broke: {}.", self.remote_id().short_show());
-        self.close(true).await
-    }
-
-    async fn handle_outside(&mut self, msg: SessionMessage) -> Result<()> {
-        match msg {
-            SessionMessage::Data(tid, data) => {
-                self.send_core_data(CoreData::Data(tid, data)).await?;
+            SessionMessage::Ping(tid) => {
+                self.pings.insert(tid, Instant::now());
+                self.send_core_data(CoreData::AppPing(tid)).await?;
             }
             SessionMessage::StableConnect(tid, data) => {
                 debug!(
@@ -621,8 +2476,7 @@ impl Session {
                     self.remote_id().short_show()
                 );
 
-                self.send_core_data(CoreData::StableConnect(tid, data))
-                    .await?;
+                self.send_stable_connect(tid, data).await?;
 
                 if !self.is_stable {
                     self.upgrade().await?;
@@ -633,23 +2487,9 @@ impl Session {
                     "SessionMessage StableResult to: {:?}",
                     self.remote_id().short_show()
                 );
-
-                self.send_core_data(CoreData::StableResult(tid, is_ok, data))
-                    .await?;
-
-                if !self.is_stable && is_ok {
-                    self.upgrade().await?;
-                }
-
-                if !self.is_stable && !is_ok {
-                    self.close(false).await?;
-                }
-
-                if is_force {
-                    return Err(new_io_error("force close"));
-                }
+                self.stable_result(tid, is_ok, is_force, data).await?;
             }
-            SessionMessage::RelayData(from, to, data) => {
+            SessionMessage::RelayData(from, to, relays, data) => {
                 debug!("SessionMessage RelayData to: {:?}", to.short_show());
                 if &to == self.remote_id() && &from == self.my_id() {
                     warn!("CHAMOMILE: RELAY TO SELF, MUST DIRECTLY.");
@@ -658,12 +2498,25 @@ impl Session {
                 }
 
                 if self.is_direct() {
-                    self.direct_send(EndpointMessage::RelayData(from, to, data))
+                    if &from != self.my_id() {
+                        if let Some(accounting) = &self.global.relay_accounting {
+                            accounting.forwarded(&from, &to, self.remote_id(), data.len());
+                        }
+                    }
+                    // the old version peers not know the passed relays.
+                    let relays = if self.version >= RELAY_HOPS_VERSION {
+                        relays
+                    } else {
+                        vec![]
+                    };
+                    self.direct_send(EndpointMessage::RelayData(from, to, relays, data))
                         .await?;
                 } else {
                     debug!("SessionMessage RelayData need relay again");
                     if let Some((ss, _, _)) = self.global.peer_list.read().await.dht_get(&to) {
-                        let _ = ss.send(SessionMessage::RelayData(from, to, data)).await;
+                        let _ = ss
+                            .send(SessionMessage::RelayData(from, to, relays, data))
+                            .await;
                     } else {
                         warn!("CHAMOMILE: CANNOT REACH NETWORK.");
                     }
@@ -694,7 +2547,12 @@ impl Session {
             SessionMessage::RelayClose(peer_id) => {
                 self.relay_sessions.remove(&peer_id);
             }
+            #[cfg(feature = "hole-punching")]
+            SessionMessage::Hole(msg) => {
+                self.direct_send(msg).await?;
+            }
             SessionMessage::Close => {
+                self.no_reconnect = true;
                 self.close(false).await?;
             }
             SessionMessage::DirectIncoming(
@@ -709,7 +2567,20 @@ impl Session {
                     .await;
                 // 2. update stream and info.
                 self.stream_receiver = stream_receiver;
-                self.endpoint = ConnectType::Direct(endpoint_sender);
+                self.endpoint =
+                    ConnectType::Direct(endpoint_sender).emulated(self.global.emulation);
+                self.relay_lost = None;
+                let pending: Vec<_> = self.relay_pending.lock().unwrap().drain(..).collect();
+                for msg in pending {
+                    if let SessionMessage::RelayData(_, _, _, data) = msg {
+                        self.direct_send(EndpointMessage::Data(data)).await?;
+                    }
+                }
+                self.global
+                    .relay_paths
+                    .write()
+                    .await
+                    .remove(self.remote_id());
                 self.remote_peer = remote_peer;
                 // 3. need use new session_key? no !.
             }
@@ -719,6 +2590,12 @@ impl Session {
     }
 
     async fn handle_endpoint(&mut self, msg: EndpointMessage) -> Result<()> {
+        #[cfg(feature = "capture")]
+        self.global
+            .capture
+            .record(self.remote_id(), true, false, msg.kind(), &[]);
+        self.count_received(msg.payload_len());
+        self.check_rate().await?;
         match msg {
             EndpointMessage::Close => {
                 return Err(new_io_error("close"));
@@ -726,70 +2603,87 @@ impl Session {
             EndpointMessage::Handshake(_) => {
                 error!("endpoint handshake only happen once.");
             }
+            #[cfg(feature = "dht")]
             EndpointMessage::DHT(DHT(peers)) => {
-                if peers.len() > 0 {
+                if !peers.is_empty() {
                     for p in peers {
                         if &p.id != self.my_id()
                             && !self.global.peer_list.read().await.contains(&p.id)
                         {
-                            let (session_key, remote_pk) = self.global.generate_remote();
-                            let _ = self
-                                .global
-                                .trans_send(
-                                    &p.transport,
-                                    TransportSendMessage::Connect(p.socket, remote_pk, session_key),
-                                )
-                                .await;
+                            self.global.dialer.dial(p, DialKind::Connect, None);
                         }
                     }
                 }
             }
-            EndpointMessage::Hole(_hole) => {
-                // TODO
+            #[cfg(not(feature = "dht"))]
+            EndpointMessage::DHT(_) => {
+                debug!("DHT is not enabled, ignore DHT help.");
+            }
+            #[cfg(feature = "hole-punching")]
+            EndpointMessage::Hole(hole) => {
+                let peer_list = self.global.peer_list.read().await;
+                let _ = hole_punching::handle(self.remote_id(), hole, &peer_list).await;
+            }
+            #[cfg(feature = "hole-punching")]
+            EndpointMessage::HolePunch(target) => {
+                self.punch_help(target).await?;
+            }
+            #[cfg(feature = "hole-punching")]
+            EndpointMessage::HoleConnect(peer) => {
+                // helped by remote, dial the relayed stable peer.
+                if &peer.id != self.my_id()
+                    && self
+                        .global
+                        .peer_list
+                        .read()
+                        .await
+                        .is_relay(&peer.id)
+                        .is_some()
+                {
+                    debug!("Punch to {} at {}.", peer.id.short_show(), peer.socket);
+                    self.global.punches.start(peer.id);
+                    self.global.dialer.dial(peer, DialKind::Connect, None);
+                }
             }
-            EndpointMessage::HoleConnect => {
-                // TODO
+            #[cfg(not(feature = "hole-punching"))]
+            EndpointMessage::Hole(_)
+            | EndpointMessage::HoleConnect(_)
+            | EndpointMessage::HolePunch(_) => {
+                debug!("Hole punching is not enabled, ignore it.");
             }
             EndpointMessage::Data(e_data) => {
                 self.handle_core_data(e_data).await?;
             }
-            EndpointMessage::RelayData(from, to, data) => {
+            EndpointMessage::RelayData(from, to, _relays, data) => {
+                self.last_active = Instant::now();
                 if &to == self.my_id() {
                     if &from == self.remote_id() {
                         self.handle_core_data(data).await?;
                     } else {
-                        if let Some(stream_sender) =
-                            self.global.peer_list.read().await.get_stable_stream(&from)
-                        {
+                        if let Some(stream_sender) = self.global.routes.get_stable_stream(&from) {
                             debug!("RelayData is in STABLE.");
                             let _ = stream_sender.send(EndpointMessage::Data(data)).await;
                         } else if let Some(stream_sender) =
-                            self.global.buffer.read().await.get_tmp_stream(&from)
+                            self.global.buffer.get_tmp_stream(from).await
                         {
                             debug!("RelayData is in TMP.");
                             let _ = stream_sender.send(EndpointMessage::Data(data)).await;
                         } else {
                             debug!("RelayData is MISSING.");
-                            if self.is_recv_data {
+                            let policy = self.global.policy(&from).await;
+                            if policy.data.unwrap_or(self.is_recv_data) && policy.relayed_data {
                                 // only happen permissionless
-                                self.out_send(ReceiveMessage::Data(from, data)).await?;
+                                let path = DataPath::Relay(*self.remote_id());
+                                let msg = self.global.data_message(from, path, data.into());
+                                self.out_send(msg).await?;
                             }
                         }
                     }
                 } else {
-                    if self.global.is_relay_data {
-                        if let Some(sender) = self
-                            .global
-                            .peer_list
-                            .read()
-                            .await
-                            .next_closest(&to, self.remote_id())
-                        {
-                            let _ = sender.send(SessionMessage::RelayData(from, to, data)).await;
-                        } else {
-                            debug!("RelayData not found next closest!");
-                        }
-                    }
+                    #[cfg(feature = "relay")]
+                    self.relay_forward_data(from, to, _relays, data).await;
+                    #[cfg(not(feature = "relay"))]
+                    debug!("Relay is not enabled, drop RelayData.");
                 }
             }
             EndpointMessage::RelayHandshake(from_peer, to) => {
@@ -799,35 +2693,60 @@ impl Session {
                     &to == self.my_id()
                 );
                 if &to == self.my_id() {
-                    let remote_peer_id = from_peer.id().clone();
+                    let remote_peer_id = *from_peer.id();
                     if &remote_peer_id == self.my_id() {
                         warn!("CHAMOMILE: RELAY NERVER TO SELF.");
                         return Ok(());
                     }
 
-                    if let Some(sender) = self
-                        .global
-                        .buffer
-                        .read()
-                        .await
-                        .get_tmp_session(&remote_peer_id)
-                    {
+                    if let Some(sender) = self.global.buffer.get_tmp_session(remote_peer_id).await {
                         debug!("Relay Result have got. send to session.");
                         // this is relay connect sender.
                         let _ = sender
                             .send(SessionMessage::RelayResult(
                                 from_peer,
                                 self.session_sender.clone(),
+                                *self.remote_id(),
                             ))
                             .await;
                         return Ok(());
                     }
 
                     // this is relay connect receiver.
-                    let RemotePublic(remote_key, remote_peer, dh_key) = from_peer;
+                    let RemotePublic(
+                        remote_key,
+                        remote_peer,
+                        dh_key,
+                        remote_version,
+                        remote_payload,
+                        psk_confirm,
+                    ) = from_peer;
 
-                    let result = self.global.complete_remote(&remote_key, dh_key);
-                    if result.is_none() {
+                    if self
+                        .global
+                        .peer_list
+                        .read()
+                        .await
+                        .is_block_peer(&remote_peer_id)
+                        || self.global.bans.is_banned_peer(&remote_peer_id)
+                        || !self.global.gate_peer(&remote_peer)
+                    {
+                        debug!("Relay remote peer is blocked, close it.");
+                        return Ok(());
+                    }
+
+                    let version = self.global.check_handshake(
+                        &remote_peer_id,
+                        remote_version,
+                        &remote_payload,
+                    );
+                    let result = self.global.complete_remote(
+                        &remote_key,
+                        dh_key,
+                        &psk_confirm,
+                        Cipher::negotiate(remote_version),
+                    );
+                    if version.is_none() || result.is_none() {
                         return Ok(());
                     }
                     let (new_session_key, new_remote_pk) = result.unwrap(); // safe checked.
@@ -835,24 +2754,37 @@ impl Session {
                     let (new_stream_sender, new_stream_receiver) = new_endpoint_channel(); // session's use.
                     let (new_session_sender, new_session_receiver) = new_session_channel(); // server's use.
 
-                    self.global.buffer.write().await.add_tmp(
-                        remote_peer_id,
-                        KadValue(new_session_sender.clone(), new_stream_sender, remote_peer),
-                        false,
-                    );
-
-                    let new_session = Session::new(
+                    self.global
+                        .buffer
+                        .add_tmp(
+                            remote_peer_id,
+                            KadValue(new_session_sender.clone(), new_stream_sender, remote_peer),
+                            false,
+                        )
+                        .await;
+
+                    let mut new_session = Session::new(
                         remote_peer,
                         new_session_sender,
                         new_stream_receiver,
-                        ConnectType::Relay(self.session_sender.clone()),
+                        ConnectType::Relay(self.session_sender.clone(), *self.remote_id()),
                         new_session_key,
                         self.global.clone(),
                         false, // default is not recv data.
                     );
+                    new_session.version = version.unwrap_or(0); // safe checked.
+                    #[cfg(feature = "hole-punching")]
+                    {
+                        new_session.remote_punch = remote_version & PUNCH_FLAG != 0;
+                    }
 
                     // if use session_run directly, it will cycle error in rust check.
                     session_spawn(new_session, new_session_receiver);
+                    self.global.audit.log(format!(
+                        "relay_accepted {} via {}",
+                        remote_peer_id.to_hex(),
+                        self.remote_id().to_hex()
+                    ));
 
                     self.direct_send(EndpointMessage::RelayHandshake(
                         new_remote_pk,
@@ -860,21 +2792,10 @@ impl Session {
                     ))
                     .await?;
                 } else {
-                    if self.global.is_relay_data {
-                        if let Some(sender) = self
-                            .global
-                            .peer_list
-                            .read()
-                            .await
-                            .next_closest(&to, self.remote_id())
-                        {
-                            let _ = sender
-                                .send(SessionMessage::RelayConnect(from_peer, to))
-                                .await;
-                        } else {
-                            debug!("RelayHandshake not found next closest!");
-                        }
-                    }
+                    #[cfg(feature = "relay")]
+                    self.relay_forward_connect(from_peer, to).await;
+                    #[cfg(not(feature = "relay"))]
+                    debug!("Relay is not enabled, drop RelayHandshake.");
                 }
             }
         }
@@ -882,18 +2803,183 @@ impl Session {
         Ok(())
     }
 
+    /// self is a relay, forward the data to the next closest peer. the data had
+    /// passed self (loop), or passed max relays, will be dropped.
+    #[cfg(feature = "relay")]
+    async fn relay_forward_data(
+        &self,
+        from: PeerId,
+        to: PeerId,
+        mut relays: Vec<PeerId>,
+        data: Bytes,
+    ) {
+        if &from == self.my_id() || relays.contains(self.my_id()) {
+            warn!("CHAMOMILE: RELAY LOOP, DROP IT.");
+            return;
+        }
+        if relays.len() >= MAX_RELAY_HOPS {
+            warn!("CHAMOMILE: RELAY HOPS OVER LIMIT, DROP IT.");
+            return;
+        }
+        if !self.global.is_relay_data
+            || !self.global.relay_allow_forward(&from, &to, data.len())
+            || !self.global.policy(&from).await.relay
+        {
+            return;
+        }
+        let len = data.len();
+        let dropped = match self
+            .global
+            .peer_list
+            .read()
+            .await
+            .next_closest(&to, self.remote_id())
+        {
+            Some(sender) => {
+                relays.push(*self.my_id());
+                self.global
+                    .relay_queues
+                    .forward(sender, from, to, relays, data)
+            }
+            None => {
+                debug!("RelayData not found next closest!");
+                return;
+            }
+        };
+        stats::counter(stats::METRIC_RELAY_BYTES, len as u64);
+        if dropped > 0 {
+            debug!("Relay downstream is busy, dropped {} bytes.", dropped);
+            stats::counter(stats::METRIC_RELAY_DROPPED, dropped as u64);
+            if let Some(accounting) = &self.global.relay_accounting {
+                accounting.dropped(&from, &to, dropped);
+            }
+        }
+    }
+
+    /// self is a relay, forward the relay connect to the next closest peer.
+    #[cfg(feature = "relay")]
+    async fn relay_forward_connect(&self, from_peer: RemotePublic, to: PeerId) {
+        if !self.global.is_relay_data
+            || !self.global.relay_allow_circuit(from_peer.id(), &to)
+            || !self.global.policy(from_peer.id()).await.relay
+        {
+            return;
+        }
+        if let Some(sender) = self
+            .global
+            .peer_list
+            .read()
+            .await
+            .next_closest(&to, self.remote_id())
+        {
+            self.global.audit.log(format!(
+                "relay_forward {} {}",
+                from_peer.id().to_hex(),
+                to.to_hex()
+            ));
+            let _ = sender
+                .send(SessionMessage::RelayConnect(from_peer, to))
+                .await;
+        } else {
+            debug!("RelayHandshake not found next closest!");
+        }
+    }
+
     async fn handle_heartbeat(&mut self) -> Result<()> {
-        if self.heartbeat > 3 {
+        // waiting the lost relay re-pinned, keepalive after it.
+        if self.relay_lost.is_some() {
+            return Ok(());
+        }
+        if self.heartbeat > self.global.keepalive_misses {
             return Err(new_io_error("timeout"));
         }
 
+        // DHT session idle timeout, stable & pinned session keep alive.
+        let idle_timeout = self.global.idle_timeout;
+        if !self.is_stable
+            && idle_timeout > Duration::ZERO
+            && self.last_active.elapsed() > idle_timeout
+            && !self.global.is_circuit_relay(self.remote_id()).await
+            && !self
+                .global
+                .peer_list
+                .read()
+                .await
+                .is_pinned(&self.remote_peer)
+        {
+            debug!("Session idle timeout: {}.", self.remote_id().short_show());
+            return Err(new_io_error("idle timeout"));
+        }
+
+        // outside's ping timeout.
+        let timeouts: Vec<u64> = self
+            .pings
+            .iter()
+            .filter(|(_, t)| t.elapsed() > PING_TIMEOUT)
+            .map(|(tid, _)| *tid)
+            .collect();
+        for tid in timeouts {
+            self.pings.remove(&tid);
+            self.out_send(ReceiveMessage::Pong(tid, *self.remote_id(), None))
+                .await?;
+        }
+
+        // missing ordered data timeout.
+        if let Some(t) = self.reorder_since {
+            if t.elapsed() > REORDER_TIMEOUT {
+                self.flush_reorders(true).await?;
+            }
+        }
+
+        // stable session's stats.
+        let interval = self.global.session_stats;
+        if self.is_stable && interval > Duration::ZERO && self.stats_at.elapsed() >= interval {
+            self.stats_at = Instant::now();
+            self.send_stats().await?;
+        }
+
+        if self.heartbeat > 0 {
+            self.retransmits += 1;
+        }
         self.heartbeat += 1;
-        self.send_core_data(CoreData::Ping).await
+        let payload = if self.is_stable {
+            self.global.heartbeat.read().await.clone()
+        } else {
+            vec![]
+        };
+        self.send_core_data(CoreData::Ping(now_millis(), self.features().await, payload))
+            .await
+    }
+
+    async fn send_stats(&self) -> Result<()> {
+        let (sent, received) = self.bandwidth.get();
+        let (sent_messages, received_messages) = self.bandwidth.messages();
+        let rtt = self
+            .global
+            .latencies
+            .read()
+            .await
+            .get(self.remote_id())
+            .copied();
+        let stats = SessionStats {
+            is_direct: self.is_direct(),
+            age: self.bandwidth.age(),
+            rtt,
+            sent,
+            received,
+            sent_messages,
+            received_messages,
+            retransmits: self.retransmits,
+        };
+        self.out_send(ReceiveMessage::SessionStats(*self.remote_id(), stats))
+            .await
     }
 
     async fn handle_robust(&mut self) -> Result<()> {
         // 60s timer out when lost connection, and cannot build a new one.
         debug!("60s timer to do robust check, check all connections is connected.");
+        #[cfg(feature = "hole-punching")]
+        self.punch().await;
 
         Ok(())
     }
@@ -902,19 +2988,52 @@ impl Session {
 /// server send to session message in channel.
 pub(crate) enum SessionMessage {
     /// send bytes to session what want to send to peer..
-    Data(u64, Vec<u8>),
+    Data(u64, Bytes),
+    /// send critical bytes by direct and relay path at same time.
+    RedundantData(u64, Bytes),
+    /// announce the swarm's sealed manifest, and the full data if self is origin.
+    SwarmManifest(Bytes, Option<Bytes>),
+    /// request the swarm's chunk, params is swarm id and chunk index.
+    SwarmWant(SwarmId, u32),
+    /// publish (or unpublish) self's membership of the topic to remote.
+    TopicJoin(PeerId, bool),
+    /// send bytes with sub-protocol id.
+    ProtocolData(u64, String, Bytes),
+    /// tell remote self registered (or unregistered) the service.
+    ServiceAnnounce(bool, String),
+    /// find service's providers, params is query id and service.
+    ServiceQuery(u64, String),
+    /// tell remote self joined the group.
+    GroupJoin(String),
+    /// tell remote self left the group.
+    GroupLeave(String),
+    /// send bytes to remote as a group's member.
+    GroupData(String, Bytes),
+    /// send self's sender key of encrypted group to remote member.
+    GroupKey(String, u32, [u8; 32]),
+    /// send sealed bytes to remote as a encrypted group's member.
+    GroupSealed(String, Bytes),
+    /// store the sealed mail at remote mailbox.
+    MailboxStore(Vec<u8>),
+    /// send the signed shared block entry to remote (trusted peer).
+    BlockShare(Vec<u8>),
+    /// outside's ping to remote, params is `ping_id`.
+    Ping(u64),
     /// when need build a stable connection.
     StableConnect(u64, Vec<u8>),
     /// when receive a stable result.
     StableResult(u64, bool, bool, Vec<u8>),
-    /// relay data help.
-    RelayData(PeerId, PeerId, Vec<u8>),
+    /// relay data help, from, to, the passed relays and data.
+    RelayData(PeerId, PeerId, Vec<PeerId>, Bytes),
     /// relay connect help.
     RelayConnect(RemotePublic, PeerId),
     /// relay connect result from other sessions.
-    RelayResult(RemotePublic, Sender<SessionMessage>),
+    RelayResult(RemotePublic, Sender<SessionMessage>, PeerId),
     /// relay closed.
     RelayClose(PeerId),
+    /// send the hole punching message to remote.
+    #[cfg(feature = "hole-punching")]
+    Hole(EndpointMessage),
     /// close the session.
     Close,
     /// Directly incoming.
@@ -933,30 +3052,104 @@ pub(crate) fn new_session_channel() -> (Sender<SessionMessage>, Receiver<Session
 
 /// core data transfer and encrypted.
 pub(crate) enum CoreData {
-    Ping,
-    Pong,
-    Data(u64, Vec<u8>),
+    /// keepalive, params is sender's timestamp (ms), sender's features and the
+    /// application heartbeat payload (only stable session, old version has none).
+    Ping(u64, u8, Vec<u8>),
+    /// keepalive response, params is the ping's timestamp, sender's features and
+    /// sender's timestamp (ms).
+    Pong(u64, u8, u64),
+    Data(u64, Bytes),
     Delivery(DeliveryType, u64, Vec<u8>),
     StableConnect(u64, Vec<u8>),
     StableResult(u64, bool, Vec<u8>),
     ResultConnect(u64, Vec<u8>),
     Unstable,
+    /// outside's ping, params is `ping_id`.
+    AppPing(u64),
+    /// outside's pong, params is `ping_id`.
+    AppPong(u64),
+    /// stable session's data with sequence, params is `seq`, `delivery_id` and data.
+    OrderedData(u64, u64, Bytes),
+    /// data with sub-protocol id, params is `delivery_id`, `protocol` and data.
+    ProtocolData(u64, String, Bytes),
+    /// resumption ticket for next stable reconnect.
+    Ticket(u64),
+    /// resume the stable session with remote's ticket.
+    Resume(u64),
+    /// small data messages coalesced in one frame.
+    Batch(Vec<CoreData>),
+    /// self joined the group, params is `group`.
+    GroupJoin(String),
+    /// self left the group, params is `group`.
+    GroupLeave(String),
+    /// group's data, params is `group` and data.
+    GroupData(String, Bytes),
+    /// sender's key of encrypted group, params is `group`, `epoch` and key.
+    GroupKey(String, u32, [u8; 32]),
+    /// encrypted group's data, params is `group` and sealed data.
+    GroupSealed(String, Bytes),
+    /// self registered or unregistered the service, params is `is_register` and service.
+    ServiceAnnounce(bool, String),
+    /// find service's providers, params is query id and service.
+    ServiceQuery(u64, String),
+    /// the service's providers connected to self, params is query id and providers.
+    ServiceReply(u64, Vec<Peer>),
+    /// cover frame, no content, only padded to bucket.
+    Cover,
+    /// store the sealed mail for its recipient (remote is mailbox).
+    MailboxStore(Vec<u8>),
+    /// fetch self stored mails (remote is mailbox).
+    MailboxFetch,
+    /// the sealed mail stored for self.
+    MailboxMail(Vec<u8>),
+    /// the signed shared block entry (remote is trusted peer).
+    BlockShare(Vec<u8>),
+    /// data sent by direct & relay path, params is `id` (for dedup), `delivery_id`
+    /// and data.
+    Redundant(u64, u64, Bytes),
+    /// the sealed manifest of swarm broadcast.
+    SwarmManifest(Vec<u8>),
+    /// request the swarm's chunk, params is swarm id and chunk index.
+    SwarmWant(SwarmId, u32),
+    /// the swarm's chunk, params is swarm id, chunk index and chunk (empty is not have).
+    SwarmChunk(SwarmId, u32, Bytes),
+    /// self joined or left the topic (store the record), params is topic and
+    /// is_join, remote reply the members when join.
+    TopicJoin(PeerId, bool),
+    /// the members and closer peers of the topic, params is topic and peers.
+    TopicPeers(PeerId, Vec<Peer>),
 }
 
 impl CoreData {
+    /// the count of messages (not keepalive or cover) in it.
+    fn messages(&self) -> u64 {
+        match self {
+            CoreData::Ping(..) | CoreData::Pong(..) | CoreData::Cover => 0,
+            CoreData::Batch(msgs) => msgs.len() as u64,
+            _ => 1,
+        }
+    }
+
+    #[allow(clippy::wrong_self_convention)]
     pub fn to_bytes(self) -> Vec<u8> {
         let mut bytes = vec![0u8];
         match self {
-            CoreData::Ping => {
+            CoreData::Ping(t, features, payload) => {
                 bytes[0] = 1u8;
+                bytes.extend(&t.to_le_bytes()[..]);
+                bytes.push(features);
+                bytes.extend(payload);
             }
-            CoreData::Pong => {
+            CoreData::Pong(t, features, now) => {
                 bytes[0] = 2u8;
+                bytes.extend(&t.to_le_bytes()[..]);
+                bytes.push(features);
+                bytes.extend(&now.to_le_bytes()[..]);
             }
-            CoreData::Data(tid, mut data) => {
+            CoreData::Data(tid, data) => {
                 bytes[0] = 3u8;
                 bytes.extend(&tid.to_le_bytes()[..]);
-                bytes.append(&mut data);
+                bytes.extend_from_slice(&data);
             }
             CoreData::Delivery(t, tid, data) => {
                 bytes[0] = 4u8;
@@ -988,29 +3181,239 @@ impl CoreData {
             CoreData::Unstable => {
                 bytes[0] = 8u8;
             }
+            CoreData::AppPing(tid) => {
+                bytes[0] = 9u8;
+                bytes.extend(&tid.to_le_bytes()[..]);
+            }
+            CoreData::AppPong(tid) => {
+                bytes[0] = 10u8;
+                bytes.extend(&tid.to_le_bytes()[..]);
+            }
+            CoreData::Ticket(ticket) => {
+                bytes[0] = 12u8;
+                bytes.extend(&ticket.to_le_bytes()[..]);
+            }
+            CoreData::Resume(ticket) => {
+                bytes[0] = 13u8;
+                bytes.extend(&ticket.to_le_bytes()[..]);
+            }
+            CoreData::ProtocolData(tid, protocol, data) => {
+                bytes[0] = 14u8;
+                bytes.extend(&tid.to_le_bytes()[..]);
+                bytes.push(protocol.len() as u8);
+                bytes.extend(protocol.as_bytes());
+                bytes.extend_from_slice(&data);
+            }
+            CoreData::OrderedData(seq, tid, data) => {
+                bytes[0] = 11u8;
+                bytes.extend(&seq.to_le_bytes()[..]);
+                bytes.extend(&tid.to_le_bytes()[..]);
+                bytes.extend_from_slice(&data);
+            }
+            CoreData::GroupJoin(group) => {
+                bytes[0] = 16u8;
+                bytes.extend(group.as_bytes());
+            }
+            CoreData::GroupLeave(group) => {
+                bytes[0] = 17u8;
+                bytes.extend(group.as_bytes());
+            }
+            CoreData::GroupData(group, data) => {
+                bytes[0] = 18u8;
+                bytes.push(group.len() as u8);
+                bytes.extend(group.as_bytes());
+                bytes.extend_from_slice(&data);
+            }
+            CoreData::GroupKey(group, epoch, key) => {
+                bytes[0] = 27u8;
+                bytes.extend(&epoch.to_le_bytes()[..]);
+                bytes.extend(&key[..]);
+                bytes.extend(group.as_bytes());
+            }
+            CoreData::GroupSealed(group, data) => {
+                bytes[0] = 28u8;
+                bytes.push(group.len() as u8);
+                bytes.extend(group.as_bytes());
+                bytes.extend_from_slice(&data);
+            }
+            CoreData::ServiceAnnounce(is_register, name) => {
+                bytes[0] = 19u8;
+                bytes.push(if is_register { 1u8 } else { 0u8 });
+                bytes.extend(name.as_bytes());
+            }
+            CoreData::ServiceQuery(qid, name) => {
+                bytes[0] = 20u8;
+                bytes.extend(&qid.to_le_bytes()[..]);
+                bytes.extend(name.as_bytes());
+            }
+            CoreData::ServiceReply(qid, providers) => {
+                bytes[0] = 21u8;
+                bytes.extend(&qid.to_le_bytes()[..]);
+                for peer in providers {
+                    bytes.append(&mut peer.to_bytes());
+                }
+            }
+            CoreData::Cover => {
+                bytes[0] = 23u8;
+            }
+            CoreData::MailboxStore(mut sealed) => {
+                bytes[0] = 24u8;
+                bytes.append(&mut sealed);
+            }
+            CoreData::MailboxFetch => {
+                bytes[0] = 25u8;
+            }
+            CoreData::MailboxMail(mut sealed) => {
+                bytes[0] = 26u8;
+                bytes.append(&mut sealed);
+            }
+            CoreData::BlockShare(mut sealed) => {
+                bytes[0] = 29u8;
+                bytes.append(&mut sealed);
+            }
+            CoreData::SwarmManifest(mut sealed) => {
+                bytes[0] = 31u8;
+                bytes.append(&mut sealed);
+            }
+            CoreData::SwarmWant(id, index) => {
+                bytes[0] = 32u8;
+                bytes.extend(&id);
+                bytes.extend(&index.to_le_bytes()[..]);
+            }
+            CoreData::SwarmChunk(id, index, data) => {
+                bytes[0] = 33u8;
+                bytes.extend(&id);
+                bytes.extend(&index.to_le_bytes()[..]);
+                bytes.extend_from_slice(&data);
+            }
+            CoreData::TopicJoin(topic, is_join) => {
+                bytes[0] = 34u8;
+                bytes.extend(topic.as_bytes());
+                bytes.push(if is_join { 1u8 } else { 0u8 });
+            }
+            CoreData::TopicPeers(topic, peers) => {
+                bytes[0] = 35u8;
+                bytes.extend(topic.as_bytes());
+                for peer in peers {
+                    bytes.append(&mut peer.to_bytes());
+                }
+            }
+            CoreData::Redundant(id, tid, data) => {
+                bytes[0] = 30u8;
+                bytes.extend(&id.to_le_bytes()[..]);
+                bytes.extend(&tid.to_le_bytes()[..]);
+                bytes.extend_from_slice(&data);
+            }
+            CoreData::Batch(msgs) => {
+                bytes[0] = 15u8;
+                for msg in msgs {
+                    let item = msg.to_bytes();
+                    bytes.extend(&(item.len() as u32).to_le_bytes()[..]);
+                    bytes.extend(item);
+                }
+            }
         }
 
         bytes
     }
 
     fn from_bytes(mut bytes: Vec<u8>) -> std::result::Result<Self, ()> {
-        if bytes.len() < 1 {
+        if bytes.is_empty() {
             return Err(());
         }
 
-        let t: Vec<u8> = bytes.drain(0..1).collect();
-        match t[0] {
-            1u8 => Ok(CoreData::Ping),
-            2u8 => Ok(CoreData::Pong),
-            3u8 => {
-                if bytes.len() < 8 {
+        // padded frame is [22][frame len u32][frame][padding zeros].
+        if bytes[0] == 22u8 {
+            if bytes.len() < 5 {
+                return Err(());
+            }
+            let mut len_bytes = [0u8; 4];
+            len_bytes.copy_from_slice(&bytes[1..5]);
+            let end = 5 + u32::from_le_bytes(len_bytes) as usize;
+            if bytes.len() < end || bytes.get(5) == Some(&22u8) {
+                return Err(());
+            }
+            bytes.truncate(end);
+            bytes.drain(0..5);
+            return CoreData::from_bytes(bytes);
+        }
+
+        // data messages are zero-copy slice of the decrypted bytes.
+        match bytes[0] {
+            3u8 if bytes.len() >= 9 => {
+                let tid = u64_from_bytes(&bytes[1..]);
+                return Ok(CoreData::Data(tid, Bytes::from(bytes).slice(9..)));
+            }
+            11u8 if bytes.len() >= 17 => {
+                let seq = u64_from_bytes(&bytes[1..]);
+                let tid = u64_from_bytes(&bytes[9..]);
+                return Ok(CoreData::OrderedData(
+                    seq,
+                    tid,
+                    Bytes::from(bytes).slice(17..),
+                ));
+            }
+            14u8 if bytes.len() >= 10 => {
+                let tid = u64_from_bytes(&bytes[1..]);
+                let end = 10 + bytes[9] as usize;
+                if bytes.len() < end {
                     return Err(());
                 }
-                let mut tid_bytes = [0u8; 8];
-                tid_bytes.copy_from_slice(bytes.drain(0..8).as_slice());
-                let tid = u64::from_le_bytes(tid_bytes);
-                Ok(CoreData::Data(tid, bytes))
+                let protocol = String::from_utf8(bytes[10..end].to_vec()).map_err(|_| ())?;
+                return Ok(CoreData::ProtocolData(
+                    tid,
+                    protocol,
+                    Bytes::from(bytes).slice(end..),
+                ));
+            }
+            18u8 if bytes.len() >= 2 => {
+                let end = 2 + bytes[1] as usize;
+                if bytes.len() < end {
+                    return Err(());
+                }
+                let group = String::from_utf8(bytes[2..end].to_vec()).map_err(|_| ())?;
+                return Ok(CoreData::GroupData(group, Bytes::from(bytes).slice(end..)));
+            }
+            28u8 if bytes.len() >= 2 => {
+                let end = 2 + bytes[1] as usize;
+                if bytes.len() < end {
+                    return Err(());
+                }
+                let group = String::from_utf8(bytes[2..end].to_vec()).map_err(|_| ())?;
+                return Ok(CoreData::GroupSealed(
+                    group,
+                    Bytes::from(bytes).slice(end..),
+                ));
+            }
+            30u8 if bytes.len() >= 17 => {
+                let id = u64_from_bytes(&bytes[1..]);
+                let tid = u64_from_bytes(&bytes[9..]);
+                return Ok(CoreData::Redundant(id, tid, Bytes::from(bytes).slice(17..)));
             }
+            33u8 if bytes.len() >= 37 => {
+                let (id, index) = swarm_index_from_bytes(&bytes[1..]);
+                return Ok(CoreData::SwarmChunk(
+                    id,
+                    index,
+                    Bytes::from(bytes).slice(37..),
+                ));
+            }
+            3u8 | 11u8 | 14u8 | 18u8 | 28u8 | 30u8 | 33u8 => return Err(()),
+            _ => {}
+        }
+
+        let t: Vec<u8> = bytes.drain(0..1).collect();
+        match t[0] {
+            1u8 => Ok(CoreData::Ping(
+                u64_from_bytes(&bytes),
+                features_from_bytes(&bytes),
+                bytes.get(9..).map(|b| b.to_vec()).unwrap_or_default(),
+            )),
+            2u8 => Ok(CoreData::Pong(
+                u64_from_bytes(&bytes),
+                features_from_bytes(&bytes),
+                bytes.get(9..).map(u64_from_bytes).unwrap_or(0),
+            )),
             4u8 => {
                 if bytes.len() < 9 {
                     return Err(());
@@ -1055,7 +3458,251 @@ impl CoreData {
                 Ok(CoreData::ResultConnect(tid, bytes))
             }
             8u8 => Ok(CoreData::Unstable),
+            9u8 => {
+                if bytes.len() < 8 {
+                    return Err(());
+                }
+                Ok(CoreData::AppPing(u64_from_bytes(&bytes)))
+            }
+            10u8 => {
+                if bytes.len() < 8 {
+                    return Err(());
+                }
+                Ok(CoreData::AppPong(u64_from_bytes(&bytes)))
+            }
+            12u8 => {
+                if bytes.len() < 8 {
+                    return Err(());
+                }
+                Ok(CoreData::Ticket(u64_from_bytes(&bytes)))
+            }
+            13u8 => {
+                if bytes.len() < 8 {
+                    return Err(());
+                }
+                Ok(CoreData::Resume(u64_from_bytes(&bytes)))
+            }
+            15u8 => {
+                let mut msgs = vec![];
+                let mut i = 0;
+                while i < bytes.len() {
+                    if bytes.len() < i + 4 {
+                        return Err(());
+                    }
+                    let mut len_bytes = [0u8; 4];
+                    len_bytes.copy_from_slice(&bytes[i..i + 4]);
+                    let end = i + 4 + u32::from_le_bytes(len_bytes) as usize;
+                    if bytes.len() < end || bytes.get(i + 4) == Some(&15u8) {
+                        return Err(());
+                    }
+                    msgs.push(CoreData::from_bytes(bytes[i + 4..end].to_vec())?);
+                    i = end;
+                }
+                Ok(CoreData::Batch(msgs))
+            }
+            23u8 => Ok(CoreData::Cover),
+            24u8 => Ok(CoreData::MailboxStore(bytes)),
+            25u8 => Ok(CoreData::MailboxFetch),
+            26u8 => Ok(CoreData::MailboxMail(bytes)),
+            29u8 => Ok(CoreData::BlockShare(bytes)),
+            31u8 => Ok(CoreData::SwarmManifest(bytes)),
+            34u8 => {
+                if bytes.len() != PEER_ID_LENGTH + 1 {
+                    return Err(());
+                }
+                let topic = PeerId::from_bytes(&bytes[..PEER_ID_LENGTH]).map_err(|_| ())?;
+                Ok(CoreData::TopicJoin(topic, bytes[PEER_ID_LENGTH] == 1u8))
+            }
+            35u8 => {
+                if bytes.len() < PEER_ID_LENGTH {
+                    return Err(());
+                }
+                let topic = PeerId::from_bytes(&bytes[..PEER_ID_LENGTH]).map_err(|_| ())?;
+                let chunks = bytes[PEER_ID_LENGTH..].chunks_exact(PEER_LENGTH);
+                if !chunks.remainder().is_empty() {
+                    return Err(());
+                }
+                let mut peers = vec![];
+                for chunk in chunks {
+                    peers.push(Peer::from_bytes(chunk).map_err(|_| ())?);
+                }
+                Ok(CoreData::TopicPeers(topic, peers))
+            }
+            32u8 => {
+                if bytes.len() != 36 {
+                    return Err(());
+                }
+                let (id, index) = swarm_index_from_bytes(&bytes);
+                Ok(CoreData::SwarmWant(id, index))
+            }
+            16u8 => Ok(CoreData::GroupJoin(
+                String::from_utf8(bytes).map_err(|_| ())?,
+            )),
+            17u8 => Ok(CoreData::GroupLeave(
+                String::from_utf8(bytes).map_err(|_| ())?,
+            )),
+            27u8 => {
+                if bytes.len() < 36 {
+                    return Err(());
+                }
+                let mut epoch_bytes = [0u8; 4];
+                epoch_bytes.copy_from_slice(&bytes[..4]);
+                let mut key = [0u8; 32];
+                key.copy_from_slice(&bytes[4..36]);
+                let group = String::from_utf8(bytes.split_off(36)).map_err(|_| ())?;
+                Ok(CoreData::GroupKey(
+                    group,
+                    u32::from_le_bytes(epoch_bytes),
+                    key,
+                ))
+            }
+            19u8 => {
+                if bytes.is_empty() {
+                    return Err(());
+                }
+                let is_register = bytes.drain(0..1).as_slice()[0] == 1u8;
+                let name = String::from_utf8(bytes).map_err(|_| ())?;
+                Ok(CoreData::ServiceAnnounce(is_register, name))
+            }
+            20u8 => {
+                if bytes.len() < 8 {
+                    return Err(());
+                }
+                let qid = u64_from_bytes(&bytes);
+                let name = String::from_utf8(bytes[8..].to_vec()).map_err(|_| ())?;
+                Ok(CoreData::ServiceQuery(qid, name))
+            }
+            21u8 => {
+                if bytes.len() < 8 {
+                    return Err(());
+                }
+                let qid = u64_from_bytes(&bytes);
+                let chunks = bytes[8..].chunks_exact(PEER_LENGTH);
+                if !chunks.remainder().is_empty() {
+                    return Err(());
+                }
+                let mut providers = vec![];
+                for chunk in chunks {
+                    providers.push(Peer::from_bytes(chunk).map_err(|_| ())?);
+                }
+                Ok(CoreData::ServiceReply(qid, providers))
+            }
             _ => Err(()),
         }
     }
 }
+
+/// read swarm id and chunk index (bytes.len() >= 36 is checked).
+fn swarm_index_from_bytes(bytes: &[u8]) -> (SwarmId, u32) {
+    let mut id = [0u8; 32];
+    id.copy_from_slice(&bytes[..32]);
+    let mut index_bytes = [0u8; 4];
+    index_bytes.copy_from_slice(&bytes[32..36]);
+    (id, u32::from_le_bytes(index_bytes))
+}
+
+/// current unix timestamp (ms).
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// delivery data of the payload, only the first `length` bytes.
+/// wrap the frame as padded frame, the size is the smallest bucket can hold it,
+/// or multiple of the largest bucket.
+fn pad_bytes(bytes: Vec<u8>, buckets: &[usize]) -> Vec<u8> {
+    let len = bytes.len() + 5;
+    let max = buckets.iter().copied().max().unwrap_or(1).max(1);
+    let size = buckets
+        .iter()
+        .copied()
+        .filter(|b| *b >= len)
+        .min()
+        .unwrap_or_else(|| len.div_ceil(max) * max);
+    let mut padded = Vec::with_capacity(size);
+    padded.push(22u8);
+    padded.extend(&(bytes.len() as u32).to_le_bytes()[..]);
+    padded.extend(bytes);
+    padded.resize(size, 0u8);
+    padded
+}
+
+/// read u64 from the first 8 bytes, if not enough, it will be 0.
+/// (old version's ping/pong has no timestamp).
+fn u64_from_bytes(bytes: &[u8]) -> u64 {
+    if bytes.len() < 8 {
+        return 0;
+    }
+    let mut t_bytes = [0u8; 8];
+    t_bytes.copy_from_slice(&bytes[0..8]);
+    u64::from_le_bytes(t_bytes)
+}
+
+/// read features byte after the ping/pong's timestamp, old version has no it.
+fn features_from_bytes(bytes: &[u8]) -> u8 {
+    bytes.get(8).copied().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sender(endpoint: &ConnectType) -> &Sender<EndpointMessage> {
+        match endpoint {
+            ConnectType::Direct(sender) => sender,
+            _ => panic!("not direct"),
+        }
+    }
+
+    #[tokio::test]
+    async fn emulated_loss() {
+        let (endpoint_sender, mut endpoint_receiver) = new_endpoint_channel();
+        let emulation = Emulation {
+            loss: 1.0,
+            ..Default::default()
+        };
+        let endpoint = ConnectType::Direct(endpoint_sender).emulated(Some(emulation));
+
+        // data is lost, control message is still sent.
+        let sender = sender(&endpoint);
+        let data = Bytes::from_static(b"data");
+        sender.send(EndpointMessage::Data(data)).await.unwrap();
+        sender.send(EndpointMessage::Close).await.unwrap();
+        assert!(matches!(
+            endpoint_receiver.recv().await,
+            Some(EndpointMessage::Close)
+        ));
+    }
+
+    #[tokio::test]
+    async fn emulated_latency() {
+        let latency = Duration::from_millis(100);
+        let (endpoint_sender, mut endpoint_receiver) = new_endpoint_channel();
+        let emulation = Emulation {
+            latency,
+            ..Default::default()
+        };
+        let endpoint = ConnectType::Direct(endpoint_sender).emulated(Some(emulation));
+
+        let start = Instant::now();
+        let data = Bytes::from_static(b"data");
+        sender(&endpoint)
+            .send(EndpointMessage::Data(data.clone()))
+            .await
+            .unwrap();
+        match endpoint_receiver.recv().await {
+            Some(EndpointMessage::Data(received)) => assert_eq!(received, data),
+            _ => panic!("data missing"),
+        }
+        assert!(start.elapsed() >= latency);
+    }
+
+    #[tokio::test]
+    async fn not_emulated() {
+        let (endpoint_sender, _endpoint_receiver) = new_endpoint_channel();
+        let endpoint = ConnectType::Direct(endpoint_sender.clone()).emulated(None);
+        assert!(sender(&endpoint).same_channel(&endpoint_sender));
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Bytes (and messages) counter of a session or a transport.
+/// shared between session and server, so use atomic, not lock.
+#[derive(Debug)]
+pub(crate) struct Bandwidth {
+    sent: AtomicU64,
+    received: AtomicU64,
+    sent_messages: AtomicU64,
+    received_messages: AtomicU64,
+    since: Instant,
+}
+
+impl Default for Bandwidth {
+    fn default() -> Self {
+        Bandwidth {
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            sent_messages: AtomicU64::new(0),
+            received_messages: AtomicU64::new(0),
+            since: Instant::now(),
+        }
+    }
+}
+
+impl Bandwidth {
+    /// the time since counter created.
+    pub fn age(&self) -> Duration {
+        self.since.elapsed()
+    }
+
+    pub fn add_sent(&self, n: usize) {
+        self.sent.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_received(&self, n: usize) {
+        self.received.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// return (sent, received) bytes.
+    pub fn get(&self) -> (u64, u64) {
+        (
+            self.sent.load(Ordering::Relaxed),
+            self.received.load(Ordering::Relaxed),
+        )
+    }
+
+    /// count sent (or received) messages, only session's.
+    pub fn add_messages(&self, is_sent: bool, n: u64) {
+        if is_sent {
+            self.sent_messages.fetch_add(n, Ordering::Relaxed);
+        } else {
+            self.received_messages.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    /// return (sent, received) messages.
+    pub fn messages(&self) -> (u64, u64) {
+        (
+            self.sent_messages.load(Ordering::Relaxed),
+            self.received_messages.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Metrics names, when enable `metrics` feature, will record to the `metrics` crate,
+/// use any exporter (e.g. Prometheus) to scrape them.
+pub(crate) const METRIC_INCOMING: &str = "chamomile_incoming_connections_total";
+pub(crate) const METRIC_HANDSHAKE_FAILURE: &str = "chamomile_handshake_failures_total";
+pub(crate) const METRIC_BLOCKED: &str = "chamomile_blocked_connections_total";
+pub(crate) const METRIC_BANNED: &str = "chamomile_banned_peers_total";
+pub(crate) const METRIC_SESSIONS: &str = "chamomile_sessions";
+pub(crate) const METRIC_BYTES_SENT: &str = "chamomile_bytes_sent_total";
+pub(crate) const METRIC_BYTES_RECEIVED: &str = "chamomile_bytes_received_total";
+#[cfg(feature = "relay")]
+pub(crate) const METRIC_RELAY_BYTES: &str = "chamomile_relay_bytes_total";
+#[cfg(feature = "relay")]
+pub(crate) const METRIC_RELAY_DROPPED: &str = "chamomile_relay_dropped_bytes_total";
+pub(crate) const METRIC_RTT: &str = "chamomile_rtt_ms";
+pub(crate) const METRIC_DHT_PEERS: &str = "chamomile_dht_peers";
+pub(crate) const METRIC_STABLE_PEERS: &str = "chamomile_stable_peers";
+pub(crate) const METRIC_BUFFER_DEPTH: &str = "chamomile_buffer_depth";
+pub(crate) const METRIC_OVERSIZE: &str = "chamomile_oversize_messages_total";
+
+#[cfg(feature = "metrics")]
+#[inline]
+pub(crate) fn counter(name: &'static str, n: u64) {
+    metrics::counter!(name).increment(n);
+}
+
+#[cfg(feature = "metrics")]
+#[inline]
+pub(crate) fn gauge(name: &'static str, v: f64) {
+    metrics::gauge!(name).set(v);
+}
+
+#[cfg(feature = "metrics")]
+#[inline]
+pub(crate) fn gauge_add(name: &'static str, v: f64) {
+    metrics::gauge!(name).increment(v);
+}
+
+#[cfg(feature = "metrics")]
+#[inline]
+pub(crate) fn histogram(name: &'static str, v: f64) {
+    metrics::histogram!(name).record(v);
+}
+
+#[cfg(not(feature = "metrics"))]
+#[inline]
+pub(crate) fn counter(_name: &'static str, _n: u64) {}
+
+#[cfg(not(feature = "metrics"))]
+#[inline]
+pub(crate) fn gauge(_name: &'static str, _v: f64) {}
+
+#[cfg(not(feature = "metrics"))]
+#[inline]
+pub(crate) fn gauge_add(_name: &'static str, _v: f64) {}
+
+#[cfg(not(feature = "metrics"))]
+#[inline]
+pub(crate) fn histogram(_name: &'static str, _v: f64) {}
@@ -0,0 +1,292 @@
+use bytes::{Bytes, BytesMut};
+use rand::seq::SliceRandom;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use chamomile_types::PeerId;
+
+use crate::config::PeerIdHash;
+use crate::keys::Keypair;
+
+/// the size of swarm's chunks, the last one may be smaller.
+const SWARM_CHUNK_SIZE: usize = 262144; // 256 KB
+
+/// max requesting chunks of a swarm at same time.
+const SWARM_INFLIGHT: usize = 4;
+
+/// chunk request without answer is expired after it, and the provider is not
+/// asked for the chunk in it when it not have the chunk.
+const SWARM_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// swarm is removed after it, finished or not.
+const SWARM_TTL: Duration = Duration::from_secs(600);
+
+/// the domain tag of manifest's signature.
+const SWARM_DOMAIN: &[u8] = b"chamomile-swarm-manifest-v1";
+
+/// swarm id, the hash of the sealed manifest.
+pub(crate) type SwarmId = [u8; 32];
+
+/// seal the manifest by origin's keypair, manifest is: len(8) + chunks' hashes(32 * n).
+fn seal(key: &Keypair, len: usize, hashes: &[[u8; 32]]) -> Option<Vec<u8>> {
+    let mut msg = (len as u64).to_le_bytes().to_vec();
+    for hash in hashes {
+        msg.extend(hash);
+    }
+    key.seal(SWARM_DOMAIN, &msg).ok()
+}
+
+/// verify the sealed manifest, return the (id, origin, len, chunks' hashes).
+pub(crate) fn open(
+    sealed: &[u8],
+    hash: &PeerIdHash,
+) -> Option<(SwarmId, PeerId, usize, Vec<[u8; 32]>)> {
+    let (origin, msg) = Keypair::open(sealed, SWARM_DOMAIN, hash)?;
+    if msg.len() < 8 || (msg.len() - 8) % 32 != 0 {
+        return None;
+    }
+    let mut len_bytes = [0u8; 8];
+    len_bytes.copy_from_slice(&msg[..8]);
+    let len = u64::from_le_bytes(len_bytes) as usize;
+    let hashes: Vec<[u8; 32]> = msg[8..]
+        .chunks(32)
+        .map(|c| {
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(c);
+            hash
+        })
+        .collect();
+    if len == 0 || hashes.len() != len.div_ceil(SWARM_CHUNK_SIZE) {
+        return None;
+    }
+    Some((*blake3::hash(sealed).as_bytes(), origin, len, hashes))
+}
+
+/// a large broadcast data, split to chunks.
+struct Swarm {
+    origin: PeerId,
+    len: usize,
+    hashes: Vec<[u8; 32]>,
+    chunks: Vec<Option<Bytes>>,
+    /// count of not received chunks.
+    missing: usize,
+    /// the peers announced the swarm, they have (or will have) the chunks.
+    providers: Vec<PeerId>,
+    /// requesting chunks, index => (provider, request time).
+    requests: HashMap<u32, (PeerId, Instant)>,
+    /// the providers which not have the chunk, (index, provider) => time.
+    misses: HashMap<(u32, PeerId), Instant>,
+    start: Instant,
+}
+
+/// Swarm distribution of large broadcasts.
+/// origin splits the data to chunks, and announces the signed manifest (chunks'
+/// hashes) to gossip peers, every peer forwards the manifest, and fetches the
+/// missing chunks from any peer which announced it, so the full data is not
+/// pushed to every peer by the origin.
+pub(crate) struct Swarms {
+    /// max bytes of fetching & finished swarms (not include self's), 0 is disable.
+    max_bytes: usize,
+    /// bytes of the swarms.
+    bytes: usize,
+    swarms: HashMap<SwarmId, Swarm>,
+}
+
+impl Swarms {
+    pub fn new(max_bytes: usize) -> Self {
+        Swarms {
+            max_bytes,
+            bytes: 0,
+            swarms: HashMap::new(),
+        }
+    }
+
+    /// split the data to chunks as origin, return the swarm id and sealed manifest.
+    pub fn publish(
+        &mut self,
+        key: &Keypair,
+        origin: PeerId,
+        data: &Bytes,
+    ) -> Option<(SwarmId, Vec<u8>)> {
+        let chunks: Vec<Bytes> = (0..data.len())
+            .step_by(SWARM_CHUNK_SIZE)
+            .map(|i| data.slice(i..(i + SWARM_CHUNK_SIZE).min(data.len())))
+            .collect();
+        let hashes: Vec<[u8; 32]> = chunks.iter().map(|c| *blake3::hash(c).as_bytes()).collect();
+        let sealed = seal(key, data.len(), &hashes)?;
+        let id = *blake3::hash(&sealed).as_bytes();
+
+        self.clear();
+        self.swarms.insert(
+            id,
+            Swarm {
+                origin,
+                len: 0, // self's data is not counted.
+                hashes,
+                chunks: chunks.into_iter().map(Some).collect(),
+                missing: 0,
+                providers: vec![],
+                requests: HashMap::new(),
+                misses: HashMap::new(),
+                start: Instant::now(),
+            },
+        );
+        Some((id, sealed))
+    }
+
+    /// remote announced the swarm, return true if it is new (forward & fetch it).
+    /// it is ignored if the cache is full.
+    pub fn announce(
+        &mut self,
+        id: SwarmId,
+        origin: PeerId,
+        len: usize,
+        hashes: Vec<[u8; 32]>,
+        from: PeerId,
+    ) -> bool {
+        if let Some(swarm) = self.swarms.get_mut(&id) {
+            if !swarm.providers.contains(&from) {
+                swarm.providers.push(from);
+            }
+            return false;
+        }
+
+        self.clear();
+        if self.bytes + len > self.max_bytes {
+            debug!("Swarm cache is full, ignore it.");
+            return false;
+        }
+        self.bytes += len;
+        let n = hashes.len();
+        self.swarms.insert(
+            id,
+            Swarm {
+                origin,
+                len,
+                hashes,
+                chunks: vec![None; n],
+                missing: n,
+                providers: vec![from],
+                requests: HashMap::new(),
+                misses: HashMap::new(),
+                start: Instant::now(),
+            },
+        );
+        true
+    }
+
+    /// the next chunk requests of the swarm, (provider, index). the timeout request
+    /// will send to other provider.
+    pub fn requests(&mut self, id: &SwarmId) -> Vec<(PeerId, u32)> {
+        let swarm = match self.swarms.get_mut(id) {
+            Some(swarm) if swarm.missing > 0 => swarm,
+            _ => return vec![],
+        };
+        let now = Instant::now();
+        let misses = &mut swarm.misses;
+        swarm.requests.retain(|index, (provider, t)| {
+            let is_alive = t.elapsed() < SWARM_REQUEST_TIMEOUT;
+            if !is_alive {
+                misses.insert((*index, *provider), now);
+            }
+            is_alive
+        });
+        misses.retain(|_, t| t.elapsed() < SWARM_REQUEST_TIMEOUT);
+
+        let mut rng = rand::thread_rng();
+        let mut indexes: Vec<u32> = (0..swarm.chunks.len() as u32)
+            .filter(|i| swarm.chunks[*i as usize].is_none() && !swarm.requests.contains_key(i))
+            .collect();
+        indexes.shuffle(&mut rng);
+
+        let mut requests = vec![];
+        for index in indexes {
+            if swarm.requests.len() >= SWARM_INFLIGHT {
+                break;
+            }
+            let providers: Vec<PeerId> = swarm
+                .providers
+                .iter()
+                .filter(|p| !swarm.misses.contains_key(&(index, **p)))
+                .copied()
+                .collect();
+            if let Some(provider) = providers.choose(&mut rng) {
+                swarm.requests.insert(index, (*provider, now));
+                requests.push((*provider, index));
+            }
+        }
+        requests
+    }
+
+    /// the chunk of remote's request, None if not have it.
+    pub fn chunk(&self, id: &SwarmId, index: u32) -> Option<Bytes> {
+        self.swarms
+            .get(id)
+            .and_then(|swarm| swarm.chunks.get(index as usize).cloned().flatten())
+    }
+
+    /// the provider not have the chunk now, request it from others.
+    pub fn miss(&mut self, id: &SwarmId, index: u32, from: &PeerId) {
+        if let Some(swarm) = self.swarms.get_mut(id) {
+            if swarm.requests.get(&index).map(|(p, _)| p == from) == Some(true) {
+                swarm.requests.remove(&index);
+            }
+            swarm.misses.insert((index, *from), Instant::now());
+        }
+    }
+
+    /// received chunk, Err if it is not the swarm's chunk. when all chunks received,
+    /// return the origin and full data.
+    pub fn receive(
+        &mut self,
+        id: &SwarmId,
+        index: u32,
+        data: Bytes,
+    ) -> std::result::Result<Option<(PeerId, Bytes)>, ()> {
+        let swarm = match self.swarms.get_mut(id) {
+            Some(swarm) => swarm,
+            None => return Ok(None), // expired.
+        };
+        let i = index as usize;
+        if i >= swarm.hashes.len() || blake3::hash(&data).as_bytes() != &swarm.hashes[i] {
+            return Err(());
+        }
+        swarm.requests.remove(&index);
+        if swarm.chunks[i].is_some() {
+            return Ok(None);
+        }
+        swarm.chunks[i] = Some(data);
+        swarm.missing -= 1;
+        if swarm.missing > 0 {
+            return Ok(None);
+        }
+
+        let mut full = BytesMut::with_capacity(swarm.len);
+        for chunk in swarm.chunks.iter().flatten() {
+            full.extend_from_slice(chunk);
+        }
+        Ok(Some((swarm.origin, full.freeze())))
+    }
+
+    /// the fetching swarms.
+    pub fn fetching(&self) -> Vec<SwarmId> {
+        self.swarms
+            .iter()
+            .filter(|(_, swarm)| swarm.missing > 0)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// remove the expired swarms.
+    pub fn clear(&mut self) {
+        let mut bytes = 0;
+        self.swarms.retain(|_, swarm| {
+            let is_alive = swarm.start.elapsed() < SWARM_TTL;
+            if !is_alive {
+                bytes += swarm.len;
+            }
+            is_alive
+        });
+        self.bytes -= bytes;
+    }
+}
@@ -0,0 +1,121 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chamomile_types::{types::PEER_ID_LENGTH, PeerId};
+
+use crate::config::PeerIdHash;
+use crate::keys::Keypair;
+
+/// the domain tag of join token's signature.
+const TOKEN_DOMAIN: &[u8] = b"chamomile-join-token-v1";
+
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// issue a join token sealed by issuer's keypair,
+/// token is: expire unix secs(8) + audience(32, zero is any) + subject(32).
+pub(crate) fn issue(
+    key: &Keypair,
+    subject: &PeerId,
+    audience: Option<PeerId>,
+    ttl: Duration,
+) -> Option<Vec<u8>> {
+    let mut msg = (now_secs() + ttl.as_secs()).to_le_bytes().to_vec();
+    msg.extend(audience.unwrap_or_default().as_bytes());
+    msg.extend(subject.as_bytes());
+    key.seal(TOKEN_DOMAIN, &msg).ok()
+}
+
+/// verify the token is issued by one of issuers, not expired,
+/// for self (audience) and the remote (subject).
+pub(crate) fn verify(
+    token: &[u8],
+    issuers: &[PeerId],
+    me: &PeerId,
+    remote: &PeerId,
+    hash: &PeerIdHash,
+) -> bool {
+    let (issuer, msg) = match Keypair::open(token, TOKEN_DOMAIN, hash) {
+        Some(v) => v,
+        None => return false,
+    };
+    if !issuers.contains(&issuer) || msg.len() != 8 + PEER_ID_LENGTH * 2 {
+        return false;
+    }
+
+    let mut expire_bytes = [0u8; 8];
+    expire_bytes.copy_from_slice(&msg[..8]);
+    let audience = &msg[8..8 + PEER_ID_LENGTH];
+    let subject = &msg[8 + PEER_ID_LENGTH..];
+    u64::from_le_bytes(expire_bytes) > now_secs()
+        && (audience == me.as_bytes() || audience == PeerId::default().as_bytes())
+        && subject == remote.as_bytes()
+}
+
+/// attach the join token to `StableConnect`'s data.
+/// data is: token len(2) + token + connect info.
+pub fn with_join_token(token: &[u8], data: Vec<u8>) -> Vec<u8> {
+    let mut bytes = (token.len() as u16).to_le_bytes().to_vec();
+    bytes.extend(token);
+    bytes.extend(data);
+    bytes
+}
+
+/// split the `StableConnect`'s data to join token and connect info.
+pub(crate) fn split(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    if data.len() < 2 {
+        return None;
+    }
+    let len = u16::from_le_bytes([data[0], data[1]]) as usize;
+    if data.len() < 2 + len {
+        return None;
+    }
+    Some((&data[2..2 + len], &data[2 + len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::KeyType;
+
+    #[test]
+    fn issue_verify() {
+        let hash = PeerIdHash::default();
+        let issuer = KeyType::Ed25519.generate_kepair();
+        let issuer_id = issuer.peer_id(&hash);
+        let me = PeerId([1u8; PEER_ID_LENGTH]);
+        let remote = PeerId([2u8; PEER_ID_LENGTH]);
+        let ttl = Duration::from_secs(60);
+
+        let token = issue(&issuer, &remote, Some(me), ttl).unwrap();
+        assert!(verify(&token, &[issuer_id], &me, &remote, &hash));
+        // not trusted issuer, other audience or subject.
+        assert!(!verify(&token, &[], &me, &remote, &hash));
+        assert!(!verify(&token, &[issuer_id], &remote, &remote, &hash));
+        assert!(!verify(&token, &[issuer_id], &me, &me, &hash));
+
+        // any audience.
+        let token = issue(&issuer, &remote, None, ttl).unwrap();
+        assert!(verify(&token, &[issuer_id], &me, &remote, &hash));
+
+        // expired.
+        let token = issue(&issuer, &remote, None, Duration::ZERO).unwrap();
+        assert!(!verify(&token, &[issuer_id], &me, &remote, &hash));
+
+        // tampered.
+        let mut token = issue(&issuer, &remote, None, ttl).unwrap();
+        token[0] ^= 1;
+        assert!(!verify(&token, &[issuer_id], &me, &remote, &hash));
+    }
+
+    #[test]
+    fn join_token_split() {
+        let data = with_join_token(b"token", b"info".to_vec());
+        assert_eq!(split(&data), Some((&b"token"[..], &b"info"[..])));
+        assert_eq!(split(&[]), None);
+        assert_eq!(split(&data[..4]), None);
+    }
+}
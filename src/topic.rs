@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use chamomile_types::{Peer, PeerId};
+
+/// topic record is expired after it, members publish it again in every DHT refresh.
+const TOPIC_RECORD_TTL: Duration = Duration::from_secs(600);
+
+/// max stored topics' records.
+const TOPICS_MAX: usize = 1024;
+
+/// max stored members of a topic, and max peers of a query's reply.
+pub(crate) const TOPIC_PEERS_MAX: usize = 32;
+
+/// the DHT key of the group's topic.
+pub(crate) fn topic_key(group: &str) -> PeerId {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(b"chamomile-topic:");
+    hasher.update(group.as_bytes());
+    PeerId(*hasher.finalize().as_bytes())
+}
+
+/// Topic records in DHT.
+/// group members publish their membership to the closest peers of the topic key
+/// (hash of the group name), and query them for the other members, so members
+/// which are not connected can find each other.
+/// the services' provider records are stored here too, by the service key.
+#[derive(Default)]
+pub(crate) struct Topics {
+    /// topic => members and the published time.
+    records: HashMap<PeerId, HashMap<PeerId, (Peer, Instant)>>,
+}
+
+impl Topics {
+    /// the member published (or unpublished) the topic, return false if full.
+    pub fn publish(&mut self, topic: PeerId, peer: Peer, is_join: bool) -> bool {
+        if !is_join {
+            if let Some(members) = self.records.get_mut(&topic) {
+                members.remove(&peer.id);
+                if members.is_empty() {
+                    self.records.remove(&topic);
+                }
+            }
+            return true;
+        }
+
+        if !self.records.contains_key(&topic) && self.records.len() >= TOPICS_MAX {
+            return false;
+        }
+        let members = self.records.entry(topic).or_default();
+        members.retain(|_, (_, t)| t.elapsed() < TOPIC_RECORD_TTL);
+        if !members.contains_key(&peer.id) && members.len() >= TOPIC_PEERS_MAX {
+            return false;
+        }
+        members.insert(peer.id, (peer, Instant::now()));
+        true
+    }
+
+    /// the members of the topic, not include the peer.
+    pub fn members(&self, topic: &PeerId, except: &PeerId) -> Vec<Peer> {
+        self.records
+            .get(topic)
+            .map(|members| {
+                members
+                    .values()
+                    .filter(|(p, t)| &p.id != except && t.elapsed() < TOPIC_RECORD_TTL)
+                    .map(|(p, _)| *p)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// remove the expired records.
+    pub fn clear(&mut self) {
+        for members in self.records.values_mut() {
+            members.retain(|_, (_, t)| t.elapsed() < TOPIC_RECORD_TTL);
+        }
+        self.records.retain(|_, members| !members.is_empty());
+    }
+}
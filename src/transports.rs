@@ -1,20 +1,76 @@
+use bytes::Bytes;
 use std::io::Result;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use tokio::sync::mpsc::{self, Receiver, Sender};
 
 use chamomile_types::{
-    peer::{Peer, PEER_LENGTH},
-    types::{new_io_error, PeerId, TransportType, PEER_ID_LENGTH},
+    message::{DialReason, Misbehavior},
+    peer::{Host, Multiaddr, Peer},
+    types::{new_io_error, PeerId, TransportType},
 };
 
+pub mod codec;
+mod emulate;
 mod rtp;
 mod tcp;
 //mod udp;
 mod quic;
 mod udt;
 
+pub(crate) use emulate::emulate;
+pub use emulate::Emulation;
+
 use crate::hole_punching::{Hole, DHT};
 use crate::keys::{Keypair, SessionKey};
+use crate::stats;
+
+/// the wire protocol version. 0 is the legacy peers without version,
+/// 2 support the redundant data, 3 support the swarm broadcast, 4 support the
+/// topic records, 5 support the relay data with passed relays.
+pub(crate) const PROTOCOL_VERSION: u8 = 5;
+/// the low bits of handshake version byte is version, high bits are capability flags.
+pub(crate) const VERSION_MASK: u8 = 0b0000_1111;
+/// capability flag: the node bridges transports (listen QUIC & TCP) and relays.
+pub(crate) const BRIDGE_FLAG: u8 = 0b0100_0000;
+/// capability flag: the node dials the punched peer (`HoleConnect`), and only
+/// upgrades by the connection dialed from the smaller PeerId.
+pub(crate) const PUNCH_FLAG: u8 = 0b1000_0000;
+
+/// negotiate the highest mutually supported protocol version with remote's version,
+/// all versions are supported (the legacy peers are 0).
+pub(crate) fn negotiate_version(remote: u8) -> u8 {
+    (remote & VERSION_MASK).min(PROTOCOL_VERSION)
+}
+
+/// Bytes counter of a transport's frames, include the handshakes, control messages,
+/// keepalives and the transport's framing, counted when write & read the socket.
+#[derive(Default, Debug)]
+pub struct FrameCounter {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl FrameCounter {
+    pub(crate) fn add_sent(&self, n: usize) {
+        self.sent.fetch_add(n as u64, Ordering::Relaxed);
+        stats::counter(stats::METRIC_BYTES_SENT, n as u64);
+    }
+
+    pub(crate) fn add_received(&self, n: usize) {
+        self.received.fetch_add(n as u64, Ordering::Relaxed);
+        stats::counter(stats::METRIC_BYTES_RECEIVED, n as u64);
+    }
+
+    /// return (sent, received) bytes.
+    pub fn get(&self) -> (u64, u64) {
+        (
+            self.sent.load(Ordering::Relaxed),
+            self.received.load(Ordering::Relaxed),
+        )
+    }
+}
 
 /// new a channel for send TransportSendMessage.
 pub fn new_transport_send_channel() -> (Sender<TransportSendMessage>, Receiver<TransportSendMessage>)
@@ -45,22 +101,62 @@ pub enum TransportSendMessage {
         SocketAddr,
         RemotePublic,
     ),
+    /// rebind the listening socket, e.g. after the network interface changed.
+    Rebind,
 }
 
-/// when endpoint get a incoming connection, will send to outside.
-/// params: `socket_addr`, `endpoint_stream_receiver`,
-/// `endpoint_stream_sender` and `is_stable`, `remote_pk bytes`.
-pub struct TransportRecvMessage(
-    pub SocketAddr,                // remote addr.
-    pub RemotePublic,              // remote public info.
-    pub Option<SessionKey>,        // is send by self and the send session_key.
-    pub Sender<EndpointMessage>,   // session's endpoint sender.
-    pub Receiver<EndpointMessage>, // session's endpoint receiver.
-    pub Sender<EndpointMessage>,   // transport's receiver.
-);
+/// Endpoint send to outside message channel.
+#[allow(clippy::large_enum_variant)]
+pub enum TransportRecvMessage {
+    /// when endpoint get a incoming connection, will send to outside.
+    /// params: `socket_addr`, `endpoint_stream_receiver`,
+    /// `endpoint_stream_sender` and `is_stable`, `remote_pk bytes`.
+    Connected(
+        SocketAddr,                // remote addr.
+        RemotePublic,              // remote public info.
+        Option<SessionKey>,        // is send by self and the send session_key.
+        Sender<EndpointMessage>,   // session's endpoint sender.
+        Receiver<EndpointMessage>, // session's endpoint receiver.
+        Sender<EndpointMessage>,   // transport's receiver.
+    ),
+    /// when connect to a socket address failure (send by self).
+    /// params: `socket_addr`, `transport_type`, failure reason and the dial's
+    /// session key bytes (None when stable connect).
+    ConnectFailure(SocketAddr, TransportType, DialReason, Option<Vec<u8>>),
+    /// the remote misbehaved in transport (e.g. oversize frame), score its address.
+    Misbehavior(SocketAddr, Misbehavior),
+}
+
+/// categorize the connect io error to dial failure reason.
+pub(crate) fn dial_reason(e: &std::io::Error) -> DialReason {
+    match e.kind() {
+        std::io::ErrorKind::TimedOut => DialReason::Timeout,
+        std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::ConnectionReset => {
+            DialReason::Refused
+        }
+        std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::InvalidData => {
+            DialReason::Handshake
+        }
+        _ => DialReason::Unreachable,
+    }
+}
+
+/// resolve the multiaddr to socket addresses, dns name will lookup by system.
+pub(crate) async fn resolve(addr: &Multiaddr) -> Result<Vec<SocketAddr>> {
+    match &addr.host {
+        Host::Ip(ip) => Ok(vec![SocketAddr::new(*ip, addr.port)]),
+        Host::Dns(name, _) => Ok(tokio::net::lookup_host((name.as_str(), addr.port))
+            .await?
+            .filter(|s| addr.is_match(&s.ip()))
+            .collect()),
+    }
+}
 
 /// Session Endpoint Message.
 /// bytes[0] is type, bytes[1..] is data.
+/// handshake messages has an envelope, the protocol version byte and application's
+/// payload is after the remote public info, legacy peers will ignore them,
+/// and missing it is version 0.
 pub enum EndpointMessage {
     /// type is 0u8.
     Close,
@@ -70,20 +166,34 @@ pub enum EndpointMessage {
     DHT(DHT),
     /// type is 3u8.
     Hole(Hole),
-    /// type is 4u8.
-    HoleConnect,
+    /// type is 4u8. dial the peer to punch, its address is observed by the helper.
+    HoleConnect(Peer),
     /// type is 5u8. encrypted's CoreData.
-    Data(Vec<u8>),
+    Data(Bytes),
     /// type is 6u8. Relay Handshake.
     RelayHandshake(RemotePublic, PeerId),
-    /// type is 7u8. encrypted's CoreData.
-    RelayData(PeerId, PeerId, Vec<u8>),
+    /// type is 7u8 (no relays, legacy) or 9u8. from, to, the passed relays (hops)
+    /// and encrypted's CoreData. the relays are only sent to version 5 peers.
+    RelayData(PeerId, PeerId, Vec<PeerId>, Bytes),
+    /// type is 8u8. help self punch to the peer, send `HoleConnect` to both.
+    HolePunch(PeerId),
 }
 
+/// max bytes of the frame's header, encryption and handshake info,
+/// the frame larger than `max_message_size` + it will close the connection.
+const FRAME_OVERHEAD: usize = 4096;
+
 /// main function. start the endpoint listening.
+/// `retry` is the max pending inbound QUIC handshakes before use stateless retry,
+/// 0 is disable. `proxies` are the trusted proxies, the inbound TCP connections from
+/// them has PROXY protocol v2 header. `counter` counts the transport's frame bytes.
 pub async fn start(
     peer: &Peer,
     out_send: Option<Sender<TransportRecvMessage>>,
+    max_message_size: usize,
+    retry: usize,
+    proxies: Vec<IpAddr>,
+    counter: Arc<FrameCounter>,
 ) -> Result<(
     SocketAddr,
     Sender<TransportSendMessage>,
@@ -99,155 +209,144 @@ pub async fn start(
         (recv_send.clone(), Some(recv_recv), Some(recv_send))
     };
 
+    let limit = max_message_size.saturating_add(FRAME_OVERHEAD);
     let local_addr = match peer.transport {
         //&TransportType::UDP => udp::UdpEndpoint::start(addr, recv_send, send_recv).await?,
-        TransportType::TCP => tcp::start(peer.socket, recv_send, send_recv, both).await?,
-        TransportType::QUIC => quic::start(peer.socket, recv_send, send_recv, both).await?,
+        TransportType::TCP => {
+            tcp::start(
+                peer.socket,
+                recv_send,
+                send_recv,
+                both,
+                limit,
+                proxies,
+                counter,
+            )
+            .await?
+        }
+        TransportType::QUIC => {
+            quic::start(
+                peer.socket,
+                recv_send,
+                send_recv,
+                both,
+                limit,
+                retry,
+                counter,
+            )
+            .await?
+        }
         _ => panic!("Not suppert, waiting"),
     };
 
     Ok((local_addr, send_send, recv_recv, main_out))
 }
 
-/// Rtemote Public Info, include local transport and public key bytes, session_key out_bytes.
-pub struct RemotePublic(pub Keypair, pub Peer, pub Vec<u8>);
+/// start a listening transport for bridge mode, its incoming will send to the main
+/// transport's channel.
+pub(crate) async fn bridge(
+    peer: &Peer,
+    out_send: Sender<TransportRecvMessage>,
+    max_message_size: usize,
+    retry: usize,
+    proxies: Vec<IpAddr>,
+    counter: Arc<FrameCounter>,
+) -> Result<(SocketAddr, Sender<TransportSendMessage>)> {
+    let (send_send, send_recv) = new_transport_send_channel();
+    let limit = max_message_size.saturating_add(FRAME_OVERHEAD);
+    let local_addr = match peer.transport {
+        TransportType::TCP => {
+            tcp::start(
+                peer.socket,
+                out_send,
+                send_recv,
+                true,
+                limit,
+                proxies,
+                counter,
+            )
+            .await?
+        }
+        TransportType::QUIC => {
+            quic::start(
+                peer.socket,
+                out_send,
+                send_recv,
+                true,
+                limit,
+                retry,
+                counter,
+            )
+            .await?
+        }
+        _ => return Err(new_io_error("bridge only support TCP & QUIC")),
+    };
+
+    Ok((local_addr, send_send))
+}
+
+/// Rtemote Public Info, include local transport and public key bytes, session_key out_bytes,
+/// the protocol version and application's payload (not in the bytes, they are in the
+/// handshake's envelope), and the pre-shared key's confirmation (empty if no psk).
+pub struct RemotePublic(
+    pub Keypair,
+    pub Peer,
+    pub Vec<u8>,
+    pub u8,
+    pub Vec<u8>,
+    pub Vec<u8>,
+);
 
 impl RemotePublic {
     pub fn id(&self) -> &PeerId {
         &self.1.id
     }
 
-    pub fn from_bytes(mut bytes: Vec<u8>) -> Result<Self> {
-        if bytes.len() < PEER_LENGTH + 2 {
-            return Err(new_io_error("Remote bytes failure."));
-        }
-        let peer = Peer::from_bytes(bytes.drain(0..PEER_LENGTH).as_slice())?;
-        let mut keypair_len_bytes = [0u8; 2];
-        keypair_len_bytes.copy_from_slice(bytes.drain(0..2).as_slice());
-        let keypair_len = u16::from_be_bytes(keypair_len_bytes) as usize;
-        if bytes.len() < keypair_len {
-            return Err(new_io_error("Remote bytes failure."));
-        }
-        let keypair = Keypair::from_bytes(bytes.drain(0..keypair_len).as_slice())?;
-        Ok(Self(keypair, peer, bytes))
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        codec::decode_remote(&bytes)
     }
 
-    pub fn to_bytes(mut self) -> Vec<u8> {
-        let mut bytes = vec![];
-        bytes.append(&mut self.1.to_bytes());
-        let mut keypair_bytes = self.0.to_bytes();
-        bytes.extend(&(keypair_bytes.len() as u16).to_be_bytes()[..]);
-        bytes.append(&mut keypair_bytes);
-        bytes.append(&mut self.2);
-        bytes
+    pub fn to_bytes(self) -> Vec<u8> {
+        codec::encode_remote(self)
     }
 }
 
 impl EndpointMessage {
-    pub fn to_bytes(self) -> Vec<u8> {
-        let mut bytes = vec![0u8];
+    /// the payload bytes length, only data messages has payload.
+    pub fn payload_len(&self) -> usize {
         match self {
-            EndpointMessage::Close => {
-                bytes[0] = 0u8;
-            }
-            EndpointMessage::Handshake(peer) => {
-                bytes[0] = 1u8;
-                let mut peer_bytes = peer.to_bytes();
-                bytes.extend(&(peer_bytes.len() as u32).to_be_bytes()[..]);
-                bytes.append(&mut peer_bytes);
-            }
-            EndpointMessage::DHT(dht) => {
-                bytes[0] = 2u8;
-                bytes.append(&mut dht.to_bytes());
-            }
-            EndpointMessage::Hole(hole) => {
-                bytes[0] = 3u8;
-                bytes.push(hole.to_byte());
-            }
-            EndpointMessage::HoleConnect => {
-                bytes[0] = 4u8;
-            }
-            EndpointMessage::Data(mut data) => {
-                bytes[0] = 5u8;
-                bytes.append(&mut data);
-            }
-            EndpointMessage::RelayHandshake(p1_peer, p2_id) => {
-                bytes[0] = 6u8;
-                let mut peer_bytes = p1_peer.to_bytes();
-                bytes.extend(&(peer_bytes.len() as u32).to_be_bytes()[..]);
-                bytes.append(&mut peer_bytes);
-                bytes.append(&mut p2_id.to_bytes());
-            }
-            EndpointMessage::RelayData(p1_id, p2_id, mut data) => {
-                bytes[0] = 7u8;
-                bytes.append(&mut p1_id.to_bytes());
-                bytes.append(&mut p2_id.to_bytes());
-                bytes.append(&mut data);
-            }
+            EndpointMessage::Data(data) => data.len(),
+            EndpointMessage::RelayData(_, _, _, data) => data.len(),
+            _ => 0,
         }
-
-        bytes
     }
 
-    fn from_bytes(mut bytes: Vec<u8>) -> Result<Self> {
-        if bytes.len() < 1 {
-            return Err(new_io_error("EndpointMessage bytes failure."));
-        }
-
-        let t: Vec<u8> = bytes.drain(0..1).collect();
-        match t[0] {
-            0u8 => Ok(EndpointMessage::Close),
-            1u8 => {
-                if bytes.len() < 4 {
-                    return Err(new_io_error("EndpointMessage bytes failure."));
-                }
-                let mut peer_len_bytes = [0u8; 4];
-                peer_len_bytes.copy_from_slice(bytes.drain(0..4).as_slice());
-                let peer_len = u32::from_be_bytes(peer_len_bytes) as usize;
-                if bytes.len() < peer_len {
-                    return Err(new_io_error("EndpointMessage bytes failure."));
-                }
-                let peer = RemotePublic::from_bytes(bytes.drain(0..peer_len).collect())
-                    .map_err(|_| new_io_error("EndpointMessage bytes failure."))?;
-                Ok(EndpointMessage::Handshake(peer))
-            }
-            2u8 => {
-                let dht = DHT::from_bytes(&bytes)?;
-                Ok(EndpointMessage::DHT(dht))
-            }
-            3u8 => {
-                if bytes.len() != 1 {
-                    return Err(new_io_error("EndpointMessage bytes failure."));
-                }
-                let hole = Hole::from_byte(bytes[0])?;
-                Ok(EndpointMessage::Hole(hole))
-            }
-            4u8 => Ok(EndpointMessage::HoleConnect),
-            5u8 => Ok(EndpointMessage::Data(bytes)),
-            6u8 => {
-                if bytes.len() < 4 {
-                    return Err(new_io_error("EndpointMessage bytes failure."));
-                }
-                let mut peer_len_bytes = [0u8; 4];
-                peer_len_bytes.copy_from_slice(bytes.drain(0..4).as_slice());
-                let peer_len = u32::from_be_bytes(peer_len_bytes) as usize;
-                if bytes.len() < peer_len + PEER_ID_LENGTH {
-                    return Err(new_io_error("EndpointMessage bytes failure."));
-                }
-                let peer = RemotePublic::from_bytes(bytes.drain(0..peer_len).collect())
-                    .map_err(|_| new_io_error("EndpointMessage bytes failure."))?;
-                let p2 = PeerId::from_bytes(&bytes.drain(0..PEER_ID_LENGTH).as_slice())?;
-                Ok(EndpointMessage::RelayHandshake(peer, p2))
-            }
-            7u8 => {
-                if bytes.len() < PEER_ID_LENGTH * 2 {
-                    return Err(new_io_error("EndpointMessage bytes failure."));
+    /// the message's type byte.
+    pub fn kind(&self) -> u8 {
+        match self {
+            EndpointMessage::Close => 0u8,
+            EndpointMessage::Handshake(_) => 1u8,
+            EndpointMessage::DHT(_) => 2u8,
+            EndpointMessage::Hole(_) => 3u8,
+            EndpointMessage::HoleConnect(_) => 4u8,
+            EndpointMessage::Data(_) => 5u8,
+            EndpointMessage::RelayHandshake(..) => 6u8,
+            EndpointMessage::RelayData(_, _, relays, _) => {
+                if relays.is_empty() {
+                    7u8
+                } else {
+                    9u8
                 }
-                let p1 = PeerId::from_bytes(&bytes.drain(0..PEER_ID_LENGTH).as_slice())?;
-                let p2 = PeerId::from_bytes(&bytes.drain(0..PEER_ID_LENGTH).as_slice())?;
-                Ok(EndpointMessage::RelayData(p1, p2, bytes))
             }
-            _ => Err(new_io_error("EndpointMessage bytes failure.")),
+            EndpointMessage::HolePunch(_) => 8u8,
         }
     }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        codec::encode(self)
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        codec::decode(bytes)
+    }
 }
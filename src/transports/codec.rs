@@ -0,0 +1,444 @@
+//! Wire codec of `EndpointMessage` and `RemotePublic`, standalone from the
+//! transports, so it can be fuzzed and property-tested. decode never panics on
+//! any input, the malformed bytes return error.
+//!
+//! With the `arbitrary` feature, `EndpointMessage` and `RemotePublic` implement
+//! `arbitrary::Arbitrary`, e.g. a fuzz target:
+//! `decode(bytes)` must not panic, and `encode(decode(encode(msg)))` is `encode(msg)`,
+//! the `roundtrip` test checks it by `cargo test --features arbitrary`.
+
+use bytes::Bytes;
+use std::io::Result;
+
+use chamomile_types::{
+    peer::{Peer, PEER_LENGTH},
+    types::{new_io_error, PeerId, PEER_ID_LENGTH},
+};
+
+use super::{EndpointMessage, RemotePublic};
+use crate::hole_punching::{Hole, DHT};
+use crate::keys::{Keypair, PSK_CONFIRM_LENGTH};
+use crate::primitives::MAX_RELAY_HOPS;
+
+fn failure() -> std::io::Error {
+    new_io_error("EndpointMessage bytes failure.")
+}
+
+/// bounds checked reader of the bytes.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(failure());
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut b = [0u8; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(b))
+    }
+
+    /// the handshake's version & payload after the remote, old version has no them.
+    fn version_payload(self) -> (u8, Vec<u8>) {
+        match self.0.split_first() {
+            Some((version, payload)) => (*version, payload.to_vec()),
+            None => (0, vec![]),
+        }
+    }
+}
+
+pub fn encode(msg: EndpointMessage) -> Vec<u8> {
+    let mut bytes = vec![msg.kind()];
+    match msg {
+        EndpointMessage::Close => {}
+        EndpointMessage::Handshake(mut peer) => {
+            let version = peer.3;
+            let mut payload = std::mem::take(&mut peer.4);
+            let mut peer_bytes = encode_remote(peer);
+            bytes.extend(&(peer_bytes.len() as u32).to_be_bytes()[..]);
+            bytes.append(&mut peer_bytes);
+            bytes.push(version);
+            bytes.append(&mut payload);
+        }
+        EndpointMessage::DHT(dht) => {
+            bytes.append(&mut dht.to_bytes());
+        }
+        EndpointMessage::Hole(hole) => {
+            bytes.push(hole.to_byte());
+        }
+        EndpointMessage::HoleConnect(peer) => {
+            bytes.append(&mut peer.to_bytes());
+        }
+        EndpointMessage::HolePunch(peer_id) => {
+            bytes.append(&mut peer_id.to_bytes());
+        }
+        EndpointMessage::Data(data) => {
+            bytes.extend_from_slice(&data);
+        }
+        EndpointMessage::RelayHandshake(mut p1_peer, p2_id) => {
+            let version = p1_peer.3;
+            let mut payload = std::mem::take(&mut p1_peer.4);
+            let mut peer_bytes = encode_remote(p1_peer);
+            bytes.extend(&(peer_bytes.len() as u32).to_be_bytes()[..]);
+            bytes.append(&mut peer_bytes);
+            bytes.append(&mut p2_id.to_bytes());
+            bytes.push(version);
+            bytes.append(&mut payload);
+        }
+        EndpointMessage::RelayData(p1_id, p2_id, relays, data) => {
+            bytes.append(&mut p1_id.to_bytes());
+            bytes.append(&mut p2_id.to_bytes());
+            // legacy layout (type 7) has no relays.
+            if !relays.is_empty() {
+                bytes.push(relays.len().min(MAX_RELAY_HOPS) as u8);
+                for relay in relays.iter().take(MAX_RELAY_HOPS) {
+                    bytes.append(&mut relay.to_bytes());
+                }
+            }
+            bytes.extend_from_slice(&data);
+        }
+    }
+
+    bytes
+}
+
+pub fn decode(bytes: Vec<u8>) -> Result<EndpointMessage> {
+    let t = *bytes.first().ok_or_else(failure)?;
+    match t {
+        0u8 => Ok(EndpointMessage::Close),
+        1u8 => {
+            let mut r = Reader(&bytes[1..]);
+            let peer_len = r.u32()? as usize;
+            let mut peer = decode_remote(r.take(peer_len)?).map_err(|_| failure())?;
+            (peer.3, peer.4) = r.version_payload();
+            Ok(EndpointMessage::Handshake(peer))
+        }
+        2u8 => Ok(EndpointMessage::DHT(DHT::from_bytes(&bytes[1..])?)),
+        3u8 => {
+            if bytes.len() != 2 {
+                return Err(failure());
+            }
+            Ok(EndpointMessage::Hole(Hole::from_byte(bytes[1])?))
+        }
+        4u8 => {
+            if bytes.len() != 1 + PEER_LENGTH {
+                return Err(failure());
+            }
+            Ok(EndpointMessage::HoleConnect(Peer::from_bytes(&bytes[1..])?))
+        }
+        // data messages are zero-copy slice of the received frame.
+        5u8 => Ok(EndpointMessage::Data(Bytes::from(bytes).slice(1..))),
+        6u8 => {
+            let mut r = Reader(&bytes[1..]);
+            let peer_len = r.u32()? as usize;
+            let mut peer = decode_remote(r.take(peer_len)?).map_err(|_| failure())?;
+            let p2 = PeerId::from_bytes(r.take(PEER_ID_LENGTH)?)?;
+            (peer.3, peer.4) = r.version_payload();
+            Ok(EndpointMessage::RelayHandshake(peer, p2))
+        }
+        7u8 | 9u8 => {
+            let mut r = Reader(&bytes[1..]);
+            let p1 = PeerId::from_bytes(r.take(PEER_ID_LENGTH)?)?;
+            let p2 = PeerId::from_bytes(r.take(PEER_ID_LENGTH)?)?;
+            let n = if t == 9u8 { r.take(1)?[0] as usize } else { 0 };
+            if n > MAX_RELAY_HOPS || (t == 9u8 && n == 0) {
+                return Err(failure());
+            }
+            let mut relays = Vec::with_capacity(n);
+            for _ in 0..n {
+                relays.push(PeerId::from_bytes(r.take(PEER_ID_LENGTH)?)?);
+            }
+            let head = bytes.len() - r.0.len();
+            Ok(EndpointMessage::RelayData(
+                p1,
+                p2,
+                relays,
+                Bytes::from(bytes).slice(head..),
+            ))
+        }
+        8u8 => {
+            if bytes.len() != 1 + PEER_ID_LENGTH {
+                return Err(failure());
+            }
+            Ok(EndpointMessage::HolePunch(PeerId::from_bytes(&bytes[1..])?))
+        }
+        _ => Err(failure()),
+    }
+}
+
+/// the remote's peer, public key, session key's dh bytes and the psk confirmation
+/// after them. (version & payload are in the handshake's envelope).
+pub fn encode_remote(mut remote: RemotePublic) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytes.append(&mut remote.1.to_bytes());
+    let mut keypair_bytes = remote.0.to_bytes();
+    bytes.extend(&(keypair_bytes.len() as u16).to_be_bytes()[..]);
+    bytes.append(&mut keypair_bytes);
+    bytes.append(&mut remote.2);
+    bytes.append(&mut remote.5);
+    bytes
+}
+
+pub fn decode_remote(bytes: &[u8]) -> Result<RemotePublic> {
+    let mut r = Reader(bytes);
+    let peer = Peer::from_bytes(r.take(PEER_LENGTH)?)?;
+    let mut keypair_len_bytes = [0u8; 2];
+    keypair_len_bytes.copy_from_slice(r.take(2)?);
+    let keypair_len = u16::from_be_bytes(keypair_len_bytes) as usize;
+    let keypair = Keypair::from_bytes(r.take(keypair_len)?)?;
+    // old version or no psk has no confirmation.
+    let (dh_bytes, confirm) = if r.0.len() == keypair.key.dh_bytes_len() + PSK_CONFIRM_LENGTH {
+        r.0.split_at(keypair.key.dh_bytes_len())
+    } else {
+        (r.0, &[][..])
+    };
+    Ok(RemotePublic(
+        keypair,
+        peer,
+        dh_bytes.to_vec(),
+        0,
+        vec![],
+        confirm.to_vec(),
+    ))
+}
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impls {
+    use arbitrary::{Arbitrary, Result, Unstructured};
+    use bytes::Bytes;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    use chamomile_types::{
+        peer::Peer,
+        types::{PeerId, TransportType},
+    };
+
+    use crate::hole_punching::{Hole, DHT};
+    use crate::keys::{KeyType, Keypair};
+    use crate::primitives::MAX_RELAY_HOPS;
+    use crate::transports::{EndpointMessage, RemotePublic};
+
+    /// max peers of arbitrary DHT message.
+    const ARBITRARY_DHT_MAX: usize = 8;
+
+    fn peer(u: &mut Unstructured) -> Result<Peer> {
+        // ipv6 which first byte is not zero, it will not decode as ipv4.
+        let ip = if u.arbitrary()? {
+            IpAddr::V4(Ipv4Addr::from(u.arbitrary::<[u8; 4]>()?))
+        } else {
+            let mut b: [u8; 16] = u.arbitrary()?;
+            b[0] |= 1;
+            IpAddr::V6(Ipv6Addr::from(b))
+        };
+        let transport = *u.choose(&[
+            TransportType::QUIC,
+            TransportType::TCP,
+            TransportType::RTP,
+            TransportType::UDT,
+        ])?;
+        Ok(Peer {
+            id: PeerId(u.arbitrary()?),
+            socket: SocketAddr::new(ip, u.arbitrary()?),
+            transport,
+            is_pub: u.arbitrary()?,
+        })
+    }
+
+    fn keypair(u: &mut Unstructured) -> Result<Keypair> {
+        if u.arbitrary()? {
+            Ok(Keypair {
+                key: KeyType::Ed25519,
+                sk: vec![],
+                pk: u.arbitrary::<[u8; 32]>()?.to_vec(),
+            })
+        } else {
+            Ok(Keypair::default())
+        }
+    }
+
+    impl<'a> Arbitrary<'a> for RemotePublic {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            Ok(RemotePublic(
+                keypair(u)?,
+                peer(u)?,
+                u.arbitrary()?,
+                u.arbitrary()?,
+                u.arbitrary()?,
+                u.arbitrary()?,
+            ))
+        }
+    }
+
+    impl<'a> Arbitrary<'a> for EndpointMessage {
+        fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+            Ok(match u.int_in_range(0u8..=8)? {
+                0 => EndpointMessage::Close,
+                1 => EndpointMessage::Handshake(u.arbitrary()?),
+                2 => {
+                    let n = u.int_in_range(0..=ARBITRARY_DHT_MAX)?;
+                    let mut peers = vec![];
+                    for _ in 0..n {
+                        peers.push(peer(u)?);
+                    }
+                    EndpointMessage::DHT(DHT(peers))
+                }
+                3 => EndpointMessage::Hole(match u.int_in_range(0u8..=2)? {
+                    0 => Hole::Help,
+                    1 => Hole::StunOne,
+                    _ => Hole::StunTwo,
+                }),
+                4 => EndpointMessage::HoleConnect(peer(u)?),
+                5 => EndpointMessage::Data(Bytes::from(u.arbitrary::<Vec<u8>>()?)),
+                6 => EndpointMessage::RelayHandshake(u.arbitrary()?, PeerId(u.arbitrary()?)),
+                7 => {
+                    let (p1, p2) = (PeerId(u.arbitrary()?), PeerId(u.arbitrary()?));
+                    let n = u.int_in_range(0..=MAX_RELAY_HOPS)?;
+                    let mut relays = vec![];
+                    for _ in 0..n {
+                        relays.push(PeerId(u.arbitrary()?));
+                    }
+                    let data = Bytes::from(u.arbitrary::<Vec<u8>>()?);
+                    EndpointMessage::RelayData(p1, p2, relays, data)
+                }
+                _ => EndpointMessage::HolePunch(PeerId(u.arbitrary()?)),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "arbitrary")]
+    use arbitrary::{Arbitrary, Unstructured};
+    #[cfg(feature = "arbitrary")]
+    use rand::RngCore;
+
+    use super::*;
+    use crate::keys::KeyType;
+
+    /// rounds of the random inputs.
+    #[cfg(feature = "arbitrary")]
+    const ROUNDS: usize = 2000;
+
+    fn peer_id(b: u8) -> PeerId {
+        PeerId([b; PEER_ID_LENGTH])
+    }
+
+    fn remote() -> RemotePublic {
+        let key = KeyType::Ed25519.generate_kepair();
+        let session_key = key.generate_session_key().unwrap();
+        RemotePublic(
+            key.public(),
+            Peer::default(),
+            session_key.out_bytes(),
+            5,
+            b"payload".to_vec(),
+            vec![],
+        )
+    }
+
+    #[test]
+    fn data_roundtrip() {
+        let bytes = encode(EndpointMessage::Data(Bytes::from_static(b"hello")));
+        assert_eq!(bytes[0], 5u8);
+        match decode(bytes).unwrap() {
+            EndpointMessage::Data(data) => assert_eq!(&data[..], b"hello"),
+            _ => panic!("not data"),
+        }
+    }
+
+    #[test]
+    fn relay_roundtrip() {
+        // legacy layout without relays.
+        let msg = EndpointMessage::RelayData(peer_id(1), peer_id(2), vec![], Bytes::new());
+        let bytes = encode(msg);
+        assert_eq!(bytes[0], 7u8);
+        assert_eq!(encode(decode(bytes.clone()).unwrap()), bytes);
+
+        let relays = vec![peer_id(3), peer_id(4)];
+        let data = Bytes::from_static(b"relay");
+        let msg = EndpointMessage::RelayData(peer_id(1), peer_id(2), relays.clone(), data);
+        let bytes = encode(msg);
+        assert_eq!(bytes[0], 9u8);
+        match decode(bytes).unwrap() {
+            EndpointMessage::RelayData(p1, p2, r, data) => {
+                assert_eq!((p1, p2, r), (peer_id(1), peer_id(2), relays));
+                assert_eq!(&data[..], b"relay");
+            }
+            _ => panic!("not relay data"),
+        }
+    }
+
+    #[test]
+    fn handshake_roundtrip() {
+        let bytes = encode(EndpointMessage::Handshake(remote()));
+        match decode(bytes.clone()).unwrap() {
+            EndpointMessage::Handshake(remote) => {
+                assert_eq!(remote.3, 5);
+                assert_eq!(remote.4, b"payload".to_vec());
+                assert_eq!(encode(EndpointMessage::Handshake(remote)), bytes);
+            }
+            _ => panic!("not handshake"),
+        }
+
+        let bytes = encode(EndpointMessage::RelayHandshake(remote(), peer_id(2)));
+        assert_eq!(encode(decode(bytes.clone()).unwrap()), bytes);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(decode(vec![]).is_err());
+        assert!(decode(vec![255u8]).is_err());
+        assert!(decode(vec![3u8]).is_err());
+        assert!(decode(vec![8u8, 1, 2, 3]).is_err());
+
+        // too many relays, or the type 9 without relays.
+        let mut bytes = vec![9u8];
+        bytes.extend(peer_id(1).to_bytes());
+        bytes.extend(peer_id(2).to_bytes());
+        bytes.push(MAX_RELAY_HOPS as u8 + 1);
+        assert!(decode(bytes.clone()).is_err());
+        bytes.pop();
+        bytes.push(0);
+        assert!(decode(bytes).is_err());
+
+        // every truncated handshake.
+        let bytes = encode(EndpointMessage::Handshake(remote()));
+        for len in 1..bytes.len() - 1 {
+            let _ = decode(bytes[..len].to_vec());
+        }
+        assert!(decode(bytes[..5].to_vec()).is_err());
+        assert!(decode_remote(&[0u8; 3]).is_err());
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn roundtrip() {
+        let mut rng = rand::thread_rng();
+        let mut raw = vec![0u8; 4096];
+        for _ in 0..ROUNDS {
+            rng.fill_bytes(&mut raw);
+            let mut u = Unstructured::new(&raw);
+
+            let msg = EndpointMessage::arbitrary(&mut u).unwrap();
+            let bytes = encode(msg);
+            let decoded = decode(bytes.clone()).expect("decode the encoded message");
+            assert_eq!(encode(decoded), bytes);
+
+            let remote = RemotePublic::arbitrary(&mut u).unwrap();
+            let bytes = encode_remote(remote);
+            let decoded = decode_remote(&bytes).expect("decode the encoded remote");
+            assert_eq!(encode_remote(decoded), bytes);
+
+            // malformed bytes never panic.
+            let len = (raw[0] as usize) * 8;
+            let _ = decode(raw[..len].to_vec());
+            let _ = decode_remote(&raw[..len]);
+        }
+    }
+}
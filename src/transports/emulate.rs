@@ -0,0 +1,96 @@
+use rand::Rng;
+use std::time::Duration;
+use tokio::{
+    sync::mpsc::{self, Sender},
+    time::{sleep_until, Instant},
+};
+
+use super::{new_endpoint_channel, EndpointMessage};
+
+/// Network condition emulation, it works on the sending side of every session,
+/// use it between in-process peers to test the bad network.
+#[derive(Debug, Clone, Copy)]
+pub struct Emulation {
+    /// base delay of every message.
+    pub latency: Duration,
+    /// random delay in `[0, jitter]` add to latency, messages order is kept.
+    pub jitter: Duration,
+    /// data messages lost rate, in `[0.0, 1.0]`. control messages will not lost.
+    pub loss: f64,
+    /// max bytes per second, 0 is unlimited.
+    pub bandwidth: u64,
+}
+
+impl Default for Emulation {
+    fn default() -> Self {
+        Emulation {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            loss: 0.0,
+            bandwidth: 0,
+        }
+    }
+}
+
+impl Emulation {
+    fn delay(&self) -> Duration {
+        let jitter = self.jitter.as_micros() as u64;
+        if jitter > 0 {
+            self.latency + Duration::from_micros(rand::thread_rng().gen_range(0, jitter + 1))
+        } else {
+            self.latency
+        }
+    }
+
+    fn is_lost(&self, msg: &EndpointMessage) -> bool {
+        match msg {
+            EndpointMessage::Data(_) | EndpointMessage::RelayData(..) => {
+                self.loss > 0.0 && rand::thread_rng().gen::<f64>() < self.loss
+            }
+            _ => false,
+        }
+    }
+}
+
+/// wrap the endpoint sender, all messages will go through the emulation.
+pub(crate) fn emulate(
+    sender: Sender<EndpointMessage>,
+    emulation: Emulation,
+) -> Sender<EndpointMessage> {
+    let (wrap_sender, mut wrap_receiver) = new_endpoint_channel();
+    let (delay_sender, mut delay_receiver) = mpsc::unbounded_channel();
+
+    // stamp the arrival time, so waiting messages will not add more delay.
+    tokio::spawn(async move {
+        while let Some(msg) = wrap_receiver.recv().await {
+            if emulation.is_lost(&msg) {
+                continue;
+            }
+            if delay_sender.send((Instant::now(), msg)).is_err() {
+                break;
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut last = Instant::now();
+        let mut next_free = Instant::now();
+        while let Some((arrival, msg)) = delay_receiver.recv().await {
+            let mut at = arrival + emulation.delay();
+            let bytes = msg.payload_len() as u64 * 1_000_000;
+            if let Some(cost) = bytes.checked_div(emulation.bandwidth) {
+                next_free = next_free.max(arrival) + Duration::from_micros(cost);
+                at = at.max(next_free);
+            }
+            // keep the order of messages.
+            at = at.max(last);
+            last = at;
+            sleep_until(at).await;
+            if sender.send(msg).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    wrap_sender
+}
@@ -1,19 +1,26 @@
 use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::{sync::Arc, time::Duration};
 use structopt::StructOpt;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::{io::Result, join, select};
 
+use chamomile_types::{
+    message::{DialReason, Misbehavior},
+    types::TransportType,
+};
+
 use crate::keys::SessionKey;
+use crate::stats;
 
 use super::{
-    new_endpoint_channel, EndpointMessage, RemotePublic, TransportRecvMessage, TransportSendMessage,
+    dial_reason, new_endpoint_channel, EndpointMessage, FrameCounter, RemotePublic,
+    TransportRecvMessage, TransportSendMessage,
 };
 
 const DOMAIN: &str = "chamomile.quic";
-const SIZE_LIMIT: usize = 67108864; // 64 * 1024 * 1024 = 64 MB
 
 /// Init and run a QuicEndpoint object.
 /// You need send a socketaddr str and quic send message's addr,
@@ -23,6 +30,9 @@ pub async fn start(
     send: Sender<TransportRecvMessage>,
     recv: Receiver<TransportSendMessage>,
     both: bool,
+    limit: usize,
+    retry: usize,
+    counter: Arc<FrameCounter>,
 ) -> tokio::io::Result<SocketAddr> {
     let config = InternalConfig::try_from_config(Default::default()).unwrap();
 
@@ -32,63 +42,153 @@ pub async fn start(
 
     // QUIC listen incoming.
     let out_send = send.clone();
+    let in_counter = counter.clone();
+    let retry = Arc::new(RetryGuard::new(
+        endpoint.clone(),
+        config.server.clone(),
+        retry,
+    ));
     tokio::spawn(async move {
-        loop {
-            match incoming.next().await {
-                Some(quinn_conn) => match quinn_conn.await {
+        while let Some(quinn_conn) = incoming.next().await {
+            retry.start();
+            let retry = retry.clone();
+            let out_send = out_send.clone();
+            let counter = in_counter.clone();
+            tokio::spawn(async move {
+                let result = quinn_conn.await;
+                retry.done();
+                match result {
                     Ok(conn) => {
                         if both {
                             let (self_sender, self_receiver) = new_endpoint_channel();
                             let (out_sender, out_receiver) = new_endpoint_channel();
 
-                            tokio::spawn(process_stream(
+                            process_stream(
                                 conn,
                                 out_sender,
                                 self_receiver,
-                                OutType::DHT(out_send.clone(), self_sender, out_receiver),
+                                OutType::DHT(out_send, self_sender, out_receiver),
                                 None,
-                            ));
+                                limit,
+                                counter,
+                            )
+                            .await
+                        } else {
+                            Ok(())
                         }
                     }
                     Err(err) => {
                         error!("An incoming failed because of an error: {:?}", err);
+                        Ok(())
                     }
-                },
-                None => {
-                    break;
                 }
-            }
+            });
         }
     });
 
     // QUIC listen from outside.
-    tokio::spawn(run_self_recv(endpoint, config.client, recv, send));
+    tokio::spawn(run_self_recv(
+        endpoint,
+        config.client,
+        recv,
+        send,
+        limit,
+        counter,
+    ));
 
     Ok(addr)
 }
 
+/// Stateless retry when under load, like QUIC Retry: when pending inbound handshakes
+/// are more than the threshold, new connections must echo the retry token (validated
+/// source address) before allocating the connection state and the TLS & DH works,
+/// so spoofed-source handshake floods cannot exhaust the CPU & memory.
+struct RetryGuard {
+    endpoint: quinn::Endpoint,
+    server: quinn::ServerConfig,
+    /// 0 is disable.
+    threshold: usize,
+    pending: AtomicUsize,
+    is_retry: AtomicBool,
+}
+
+impl RetryGuard {
+    fn new(endpoint: quinn::Endpoint, server: quinn::ServerConfig, threshold: usize) -> Self {
+        RetryGuard {
+            endpoint,
+            server,
+            threshold,
+            pending: AtomicUsize::new(0),
+            is_retry: AtomicBool::new(false),
+        }
+    }
+
+    /// a new inbound handshake, turn on retry if over threshold.
+    fn start(&self) {
+        let pending = self.pending.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.threshold > 0
+            && pending > self.threshold
+            && !self.is_retry.swap(true, Ordering::Relaxed)
+        {
+            warn!(
+                "CHAMOMILE: QUIC HANDSHAKES OVERLOAD ({}), USE RETRY.",
+                pending
+            );
+            let mut server = self.server.clone();
+            server.use_retry(true);
+            self.endpoint.set_server_config(Some(server));
+        }
+    }
+
+    /// the inbound handshake finished, turn off retry if load is down to half.
+    fn done(&self) {
+        let pending = self.pending.fetch_sub(1, Ordering::Relaxed) - 1;
+        if pending <= self.threshold / 2 && self.is_retry.swap(false, Ordering::Relaxed) {
+            info!("QUIC handshakes load is down, stop retry.");
+            self.endpoint.set_server_config(Some(self.server.clone()));
+        }
+    }
+}
+
 async fn connect_to(
     connect: std::result::Result<quinn::Connecting, quinn::ConnectError>,
     remote_pk: RemotePublic,
+    counter: &FrameCounter,
 ) -> Result<quinn::NewConnection> {
     let conn = connect
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "connecting failure."))?
+        .map_err(|_| std::io::Error::other("connecting failure."))?
         .await?;
     let mut stream = conn.connection.open_uni().await?;
-    stream
-        .write_all(&EndpointMessage::Handshake(remote_pk).to_bytes())
-        .await?;
+    let bytes = EndpointMessage::Handshake(remote_pk).to_bytes();
+    counter.add_sent(bytes.len());
+    stream.write_all(&bytes).await?;
     stream.finish().await?;
     Ok(conn)
 }
 
 async fn dht_connect_to(
     connect: std::result::Result<quinn::Connecting, quinn::ConnectError>,
+    addr: SocketAddr,
     out_send: Sender<TransportRecvMessage>,
     remote_pk: RemotePublic,
     session_key: SessionKey,
+    limit: usize,
+    counter: Arc<FrameCounter>,
 ) -> Result<()> {
-    let conn = connect_to(connect, remote_pk).await?;
+    let conn = match connect_to(connect, remote_pk, &counter).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            let _ = out_send
+                .send(TransportRecvMessage::ConnectFailure(
+                    addr,
+                    TransportType::QUIC,
+                    dial_reason(&e),
+                    Some(session_key.out_bytes()),
+                ))
+                .await;
+            return Err(e);
+        }
+    };
 
     let (self_sender, self_receiver) = new_endpoint_channel();
     let (out_sender, out_receiver) = new_endpoint_channel();
@@ -99,19 +199,45 @@ async fn dht_connect_to(
         self_receiver,
         OutType::DHT(out_send, self_sender, out_receiver),
         Some(session_key),
+        limit,
+        counter,
     )
     .await
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn stable_connect_to(
     connect: std::result::Result<quinn::Connecting, quinn::ConnectError>,
+    addr: SocketAddr,
+    out_send: Sender<TransportRecvMessage>,
     out_sender: Sender<EndpointMessage>,
     self_receiver: Receiver<EndpointMessage>,
     remote_pk: RemotePublic,
+    limit: usize,
+    counter: Arc<FrameCounter>,
 ) -> Result<()> {
-    match connect_to(connect, remote_pk).await {
-        Ok(conn) => process_stream(conn, out_sender, self_receiver, OutType::Stable, None).await,
-        Err(_) => {
+    match connect_to(connect, remote_pk, &counter).await {
+        Ok(conn) => {
+            process_stream(
+                conn,
+                out_sender,
+                self_receiver,
+                OutType::Stable(out_send),
+                None,
+                limit,
+                counter,
+            )
+            .await
+        }
+        Err(e) => {
+            let _ = out_send
+                .send(TransportRecvMessage::ConnectFailure(
+                    addr,
+                    TransportType::QUIC,
+                    dial_reason(&e),
+                    None,
+                ))
+                .await;
             let _ = out_sender.send(EndpointMessage::Close).await;
             Ok(())
         }
@@ -123,6 +249,8 @@ async fn run_self_recv(
     client_cfg: quinn::ClientConfig,
     mut recv: Receiver<TransportSendMessage>,
     out_send: Sender<TransportRecvMessage>,
+    limit: usize,
+    counter: Arc<FrameCounter>,
 ) -> Result<()> {
     while let Some(m) = recv.recv().await {
         match m {
@@ -131,19 +259,38 @@ async fn run_self_recv(
                 info!("QUIC dht connect to: {:?}", addr);
                 tokio::spawn(dht_connect_to(
                     connect,
+                    addr,
                     out_send.clone(),
                     remote_pk,
                     session_key,
+                    limit,
+                    counter.clone(),
                 ));
             }
+            TransportSendMessage::Rebind => {
+                // same address first, if it is still in use, a new port.
+                let rebind = endpoint.local_addr().and_then(|addr| {
+                    let socket = std::net::UdpSocket::bind(addr)
+                        .or_else(|_| std::net::UdpSocket::bind(SocketAddr::new(addr.ip(), 0)))?;
+                    endpoint.rebind(socket)
+                });
+                match rebind {
+                    Ok(()) => info!("QUIC rebind at: {:?}", endpoint.local_addr()),
+                    Err(e) => warn!("QUIC rebind failure: {:?}", e),
+                }
+            }
             TransportSendMessage::StableConnect(out_sender, self_receiver, addr, remote_pk) => {
                 let connect = endpoint.connect_with(client_cfg.clone(), addr, DOMAIN);
                 info!("QUIC stable connect to: {:?}", addr);
                 tokio::spawn(stable_connect_to(
                     connect,
+                    addr,
+                    out_send.clone(),
                     out_sender,
                     self_receiver,
                     remote_pk,
+                    limit,
+                    counter.clone(),
                 ));
             }
         }
@@ -152,13 +299,14 @@ async fn run_self_recv(
     Ok(())
 }
 
+#[allow(clippy::upper_case_acronyms)]
 enum OutType {
     DHT(
         Sender<TransportRecvMessage>,
         Sender<EndpointMessage>,
         Receiver<EndpointMessage>,
     ),
-    Stable,
+    Stable(Sender<TransportRecvMessage>),
 }
 
 async fn process_stream(
@@ -167,6 +315,8 @@ async fn process_stream(
     mut self_receiver: Receiver<EndpointMessage>,
     out_type: OutType,
     has_session: Option<SessionKey>,
+    limit: usize,
+    counter: Arc<FrameCounter>,
 ) -> tokio::io::Result<()> {
     let quinn::NewConnection {
         connection,
@@ -175,65 +325,82 @@ async fn process_stream(
     } = conn;
     let addr = connection.remote_address();
 
-    let handshake: std::result::Result<RemotePublic, ()> = select! {
+    let handshake: std::result::Result<RemotePublic, DialReason> = select! {
         v = async {
             if let Some(result) = uni_streams.next().await {
                 match result {
                     Err(quinn::ConnectionError::ApplicationClosed { .. }) => {
                         debug!("Connection terminated by peer {:?}.", addr);
-                        Err(())
+                        Err(DialReason::Handshake)
                     }
                     Err(err) => {
                         debug!(
                             "Failed to read incoming message on uni-stream for peer {:?} with error: {:?}",
                             addr, err
                         );
-                        Err(())
+                        Err(DialReason::Handshake)
                     }
                     Ok(recv) => {
-                        if let Ok(bytes) = recv.read_to_end(SIZE_LIMIT).await {
+                        if let Ok(bytes) = recv.read_to_end(limit).await {
+                            counter.add_received(bytes.len());
                             if let Ok(EndpointMessage::Handshake(remote_pk)) =
                                 EndpointMessage::from_bytes(bytes)
                             {
-                                return Ok(remote_pk);
+                                Ok(remote_pk)
                             } else {
-                                Err(())
+                                Err(DialReason::Handshake)
                             }
                         } else {
-                            Err(())
+                            Err(DialReason::Handshake)
                         }
                     }
                 }
             } else {
-                Err(())
+                Err(DialReason::Handshake)
             }
         } => v,
         v = async {
             tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-            Err(())
+            Err(DialReason::Timeout)
         } => v
     };
 
-    if handshake.is_err() {
-        // close it. if is_by_self, Better send outside not connect.
-        debug!("Transport: connect read publics timeout, close it.");
-        return Ok(());
-    }
-
-    let remote_pk = handshake.unwrap(); // safe. checked.
+    let remote_pk = match handshake {
+        Ok(remote_pk) => remote_pk,
+        Err(reason) => {
+            // close it. if is_by_self, send outside connect failure.
+            debug!("Transport: connect read publics failure, close it.");
+            let sender = match out_type {
+                OutType::Stable(sender) => Some(sender),
+                OutType::DHT(sender, ..) if has_session.is_some() => Some(sender),
+                _ => None,
+            };
+            if let Some(sender) = sender {
+                let _ = sender
+                    .send(TransportRecvMessage::ConnectFailure(
+                        addr,
+                        TransportType::QUIC,
+                        reason,
+                        has_session.as_ref().map(|key| key.out_bytes()),
+                    ))
+                    .await;
+            }
+            return Ok(());
+        }
+    };
 
-    match out_type {
-        OutType::Stable => {
+    // the server's channel, report the remote's misbehavior.
+    let trans_sender = match out_type {
+        OutType::Stable(sender) => {
             out_sender
                 .send(EndpointMessage::Handshake(remote_pk))
                 .await
-                .map_err(|_e| {
-                    std::io::Error::new(std::io::ErrorKind::Other, "endpoint channel missing")
-                })?;
+                .map_err(|_e| std::io::Error::other("endpoint channel missing"))?;
+            sender
         }
         OutType::DHT(sender, self_sender, out_receiver) => {
             sender
-                .send(TransportRecvMessage(
+                .send(TransportRecvMessage::Connected(
                     addr,
                     remote_pk,
                     has_session,
@@ -242,30 +409,24 @@ async fn process_stream(
                     self_sender,
                 ))
                 .await
-                .map_err(|_e| {
-                    std::io::Error::new(std::io::ErrorKind::Other, "server channel missing")
-                })?;
+                .map_err(|_e| std::io::Error::other("server channel missing"))?;
+            sender
         }
-    }
+    };
 
+    let write_counter = counter.clone();
     let a = async move {
-        loop {
-            match self_receiver.recv().await {
-                Some(msg) => {
-                    let mut writer = connection.open_uni().await.map_err(|_e| ())?;
-                    let is_close = match msg {
-                        EndpointMessage::Close => true,
-                        _ => false,
-                    };
-
-                    let _ = writer.write_all(&msg.to_bytes()).await;
-                    let _ = writer.finish().await;
-
-                    if is_close {
-                        break;
-                    }
-                }
-                None => break,
+        while let Some(msg) = self_receiver.recv().await {
+            let mut writer = connection.open_uni().await.map_err(|_e| ())?;
+            let is_close = matches!(msg, EndpointMessage::Close);
+
+            let bytes = msg.to_bytes();
+            write_counter.add_sent(bytes.len());
+            let _ = writer.write_all(&bytes).await;
+            let _ = writer.finish().await;
+
+            if is_close {
+                break;
             }
         }
 
@@ -273,29 +434,40 @@ async fn process_stream(
     };
 
     let b = async {
-        loop {
-            match uni_streams.next().await {
-                Some(result) => match result {
-                    Err(quinn::ConnectionError::ApplicationClosed { .. }) => {
-                        debug!("Connection terminated by peer {:?}.", addr);
-                        break;
+        while let Some(result) = uni_streams.next().await {
+            match result {
+                Err(quinn::ConnectionError::ApplicationClosed { .. }) => {
+                    debug!("Connection terminated by peer {:?}.", addr);
+                    break;
+                }
+                Err(err) => {
+                    debug!(
+                    "Failed to read incoming message on uni-stream for peer {:?} with error: {:?}",
+                    addr, err
+                );
+                    break;
+                }
+                Ok(recv) => match recv.read_to_end(limit).await {
+                    Ok(bytes) => {
+                        counter.add_received(bytes.len());
+                        if let Ok(msg) = EndpointMessage::from_bytes(bytes) {
+                            let _ = out_sender.send(msg).await;
+                        }
                     }
-                    Err(err) => {
-                        debug!(
-                            "Failed to read incoming message on uni-stream for peer {:?} with error: {:?}",
-                            addr, err
-                        );
+                    Err(quinn::ReadToEndError::TooLong) => {
+                        warn!("QUIC message from {:?} is too large, close it.", addr);
+                        stats::counter(stats::METRIC_OVERSIZE, 1);
+                        let _ = trans_sender
+                            .send(TransportRecvMessage::Misbehavior(
+                                addr,
+                                Misbehavior::InvalidFrame,
+                            ))
+                            .await;
+                        let _ = out_sender.send(EndpointMessage::Close).await;
                         break;
                     }
-                    Ok(recv) => {
-                        if let Ok(bytes) = recv.read_to_end(SIZE_LIMIT).await {
-                            if let Ok(msg) = EndpointMessage::from_bytes(bytes) {
-                                let _ = out_sender.send(msg).await;
-                            }
-                        }
-                    }
+                    Err(_) => {}
                 },
-                None => break,
             }
         }
 
@@ -365,9 +537,7 @@ impl InternalConfig {
     pub(crate) fn try_from_config(config: Config) -> Result<Self> {
         let idle_timeout =
             quinn::IdleTimeout::try_from(config.idle_timeout.unwrap_or(DEFAULT_IDLE_TIMEOUT))
-                .map_err(|_e| {
-                    std::io::Error::new(std::io::ErrorKind::Other, "rcgen generate failure.")
-                })?;
+                .map_err(|_e| std::io::Error::other("rcgen generate failure."))?;
 
         let mut tconfig = quinn::TransportConfig::default();
         let _ = tconfig.max_idle_timeout(Some(idle_timeout));
@@ -407,22 +577,19 @@ impl InternalConfig {
             .with_safe_defaults()
             .with_no_client_auth()
             .with_single_cert(vec![cert], key)
-            .map_err(|_e| {
-                std::io::Error::new(std::io::ErrorKind::Other, "server config failure.")
-            })?;
+            .map_err(|_e| std::io::Error::other("server config failure."))?;
         let mut config = quinn::ServerConfig::with_crypto(Arc::new(server_crypto));
         config.transport = transport;
         Ok(config)
     }
 
     fn generate_cert() -> Result<(rustls::Certificate, rustls::PrivateKey)> {
-        let cert = rcgen::generate_simple_self_signed(vec![DOMAIN.to_string()]).map_err(|_e| {
-            std::io::Error::new(std::io::ErrorKind::Other, "rcgen generate failure.")
-        })?;
+        let cert = rcgen::generate_simple_self_signed(vec![DOMAIN.to_string()])
+            .map_err(|_e| std::io::Error::other("rcgen generate failure."))?;
 
-        let cert_der = cert.serialize_der().map_err(|_e| {
-            std::io::Error::new(std::io::ErrorKind::Other, "cert serialize failure.")
-        })?;
+        let cert_der = cert
+            .serialize_der()
+            .map_err(|_e| std::io::Error::other("cert serialize failure."))?;
         let key_der = cert.serialize_private_key_der();
 
         Ok((rustls::Certificate(cert_der), rustls::PrivateKey(key_der)))
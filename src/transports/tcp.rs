@@ -1,4 +1,6 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt, Result},
     join,
@@ -7,108 +9,239 @@ use tokio::{
     sync::mpsc::{Receiver, Sender},
 };
 
+use chamomile_types::{
+    message::{DialReason, Misbehavior},
+    types::TransportType,
+};
+
 use crate::keys::SessionKey;
+use crate::stats;
 
 use super::{
-    new_endpoint_channel, EndpointMessage, RemotePublic, TransportRecvMessage, TransportSendMessage,
+    dial_reason, new_endpoint_channel, EndpointMessage, FrameCounter, RemotePublic,
+    TransportRecvMessage, TransportSendMessage,
 };
 
+/// PROXY protocol v2 header's signature.
+const PROXY_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+
+/// waiting time for the PROXY protocol header.
+const PROXY_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Init and run a TcpEndpoint object.
 /// You need send a socketaddr str and tcp send message's addr,
 /// and receiver outside message addr.
+/// the incoming connections from `proxies` (trusted) must start with PROXY protocol
+/// v2 header, it is not parsed from others.
 pub async fn start(
     bind_addr: SocketAddr,
     send: Sender<TransportRecvMessage>,
     recv: Receiver<TransportSendMessage>,
     both: bool,
+    limit: usize,
+    proxies: Vec<IpAddr>,
+    counter: Arc<FrameCounter>,
 ) -> Result<SocketAddr> {
     let addr = if both {
         let listener = TcpListener::bind(bind_addr).await.map_err(|e| {
             error!("TCP listen {:?}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, "TCP Listen")
+            std::io::Error::other("TCP Listen")
         })?;
         let addr = listener.local_addr()?;
         info!("TCP listening at: {:?}", addr);
 
         // TCP listen incoming.
-        tokio::spawn(run_listen(listener, send.clone()));
+        tokio::spawn(run_listen(
+            listener,
+            send.clone(),
+            limit,
+            Arc::new(proxies),
+            counter.clone(),
+        ));
         addr
     } else {
         bind_addr
     };
 
     // TCP listen from outside.
-    tokio::spawn(run_self_recv(recv, send));
+    tokio::spawn(run_self_recv(recv, send, limit, counter));
 
     Ok(addr)
 }
 
-async fn run_listen(listener: TcpListener, out_send: Sender<TransportRecvMessage>) -> Result<()> {
+async fn run_listen(
+    listener: TcpListener,
+    out_send: Sender<TransportRecvMessage>,
+    limit: usize,
+    proxies: Arc<Vec<IpAddr>>,
+    counter: Arc<FrameCounter>,
+) -> Result<()> {
     loop {
-        let (stream, _) = listener.accept().await?;
-        let (self_sender, self_receiver) = new_endpoint_channel();
-        let (out_sender, out_receiver) = new_endpoint_channel();
-
-        tokio::spawn(process_stream(
-            stream,
-            out_sender,
-            self_receiver,
-            OutType::DHT(out_send.clone(), self_sender, out_receiver),
-            None,
+        let (mut stream, addr) = listener.accept().await?;
+        let out_send = out_send.clone();
+        let counter = counter.clone();
+        let is_proxy = proxies.contains(&addr.ip());
+        tokio::spawn(async move {
+            let addr = if is_proxy {
+                let header = tokio::time::timeout(PROXY_TIMEOUT, read_proxy_header(&mut stream));
+                match header.await {
+                    Ok(Ok(Some(client))) => client,
+                    Ok(Ok(None)) => addr,
+                    _ => {
+                        warn!("TCP PROXY header from {:?} is invalid, close it.", addr);
+                        return Ok(());
+                    }
+                }
+            } else {
+                addr
+            };
+
+            let (self_sender, self_receiver) = new_endpoint_channel();
+            let (out_sender, out_receiver) = new_endpoint_channel();
+            process_stream(
+                stream,
+                addr,
+                out_sender,
+                self_receiver,
+                OutType::DHT(out_send, self_sender, out_receiver),
+                None,
+                limit,
+                counter,
+            )
+            .await
+        });
+    }
+}
+
+/// read the PROXY protocol v2 header, return the client address,
+/// None if it is a LOCAL command (e.g. the proxy's health check) or not TCP.
+async fn read_proxy_header<R: AsyncReadExt + Unpin>(stream: &mut R) -> Result<Option<SocketAddr>> {
+    let mut header = [0u8; 16];
+    stream.read_exact(&mut header).await?;
+    if header[..12] != PROXY_SIGNATURE || header[12] >> 4 != 2 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "PROXY header",
         ));
     }
+    let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+    let mut addrs = vec![0u8; len];
+    stream.read_exact(&mut addrs).await?;
+
+    // command LOCAL.
+    if header[12] & 0x0F == 0 {
+        return Ok(None);
+    }
+    let client = match header[13] {
+        // TCP over IPv4: src ip, dst ip, src port, dst port.
+        0x11 if len >= 12 => {
+            let ip = Ipv4Addr::new(addrs[0], addrs[1], addrs[2], addrs[3]);
+            let port = u16::from_be_bytes([addrs[8], addrs[9]]);
+            Some(SocketAddr::new(IpAddr::V4(ip), port))
+        }
+        // TCP over IPv6.
+        0x21 if len >= 36 => {
+            let mut ip = [0u8; 16];
+            ip.copy_from_slice(&addrs[..16]);
+            let port = u16::from_be_bytes([addrs[32], addrs[33]]);
+            Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), port))
+        }
+        _ => None,
+    };
+    Ok(client)
 }
 
 async fn run_self_recv(
     mut recv: Receiver<TransportSendMessage>,
     out_send: Sender<TransportRecvMessage>,
+    limit: usize,
+    counter: Arc<FrameCounter>,
 ) -> Result<()> {
     while let Some(m) = recv.recv().await {
         match m {
             TransportSendMessage::Connect(addr, remote_pk, session_key) => {
                 let server_send = out_send.clone();
+                let counter = counter.clone();
                 tokio::spawn(async move {
-                    if let Ok(mut stream) = TcpStream::connect(addr).await {
-                        info!("TCP connect to {:?}", addr);
-                        let bytes = EndpointMessage::Handshake(remote_pk).to_bytes();
-                        let _ = stream.write(&(bytes.len() as u32).to_be_bytes()).await;
-                        let _ = stream.write_all(&bytes[..]).await;
-
-                        let (self_sender, self_receiver) = new_endpoint_channel();
-                        let (out_sender, out_receiver) = new_endpoint_channel();
-
-                        let _ = process_stream(
-                            stream,
-                            out_sender,
-                            self_receiver,
-                            OutType::DHT(server_send, self_sender, out_receiver),
-                            Some(session_key),
-                        )
-                        .await;
-                    } else {
-                        info!("TCP cannot connect to {:?}", addr);
+                    match TcpStream::connect(addr).await {
+                        Ok(mut stream) => {
+                            info!("TCP connect to {:?}", addr);
+                            let bytes = EndpointMessage::Handshake(remote_pk).to_bytes();
+                            counter.add_sent(4 + bytes.len());
+                            let _ = stream.write(&(bytes.len() as u32).to_be_bytes()).await;
+                            let _ = stream.write_all(&bytes[..]).await;
+
+                            let (self_sender, self_receiver) = new_endpoint_channel();
+                            let (out_sender, out_receiver) = new_endpoint_channel();
+
+                            let _ = process_stream(
+                                stream,
+                                addr,
+                                out_sender,
+                                self_receiver,
+                                OutType::DHT(server_send, self_sender, out_receiver),
+                                Some(session_key),
+                                limit,
+                                counter,
+                            )
+                            .await;
+                        }
+                        Err(e) => {
+                            info!("TCP cannot connect to {:?}", addr);
+                            let _ = server_send
+                                .send(TransportRecvMessage::ConnectFailure(
+                                    addr,
+                                    TransportType::TCP,
+                                    dial_reason(&e),
+                                    Some(session_key.out_bytes()),
+                                ))
+                                .await;
+                        }
                     }
                 });
             }
+            TransportSendMessage::Rebind => {
+                // every connection has its own socket, only the listener is kept.
+                debug!("TCP rebind is not needed.");
+            }
             TransportSendMessage::StableConnect(out_sender, self_receiver, addr, remote_pk) => {
+                let server_send = out_send.clone();
+                let counter = counter.clone();
                 tokio::spawn(async move {
-                    if let Ok(mut stream) = TcpStream::connect(addr).await {
-                        info!("TCP stable connect to {:?}", addr);
-                        let bytes = EndpointMessage::Handshake(remote_pk).to_bytes();
-                        let _ = stream.write(&(bytes.len() as u32).to_be_bytes()).await;
-                        let _ = stream.write_all(&bytes[..]).await;
-
-                        let _ = process_stream(
-                            stream,
-                            out_sender,
-                            self_receiver,
-                            OutType::Stable,
-                            None,
-                        )
-                        .await;
-                    } else {
-                        info!("TCP cannot stable connect to {:?}", addr);
-                        let _ = out_sender.send(EndpointMessage::Close).await;
+                    match TcpStream::connect(addr).await {
+                        Ok(mut stream) => {
+                            info!("TCP stable connect to {:?}", addr);
+                            let bytes = EndpointMessage::Handshake(remote_pk).to_bytes();
+                            counter.add_sent(4 + bytes.len());
+                            let _ = stream.write(&(bytes.len() as u32).to_be_bytes()).await;
+                            let _ = stream.write_all(&bytes[..]).await;
+
+                            let _ = process_stream(
+                                stream,
+                                addr,
+                                out_sender,
+                                self_receiver,
+                                OutType::Stable(server_send),
+                                None,
+                                limit,
+                                counter,
+                            )
+                            .await;
+                        }
+                        Err(e) => {
+                            info!("TCP cannot stable connect to {:?}", addr);
+                            let _ = server_send
+                                .send(TransportRecvMessage::ConnectFailure(
+                                    addr,
+                                    TransportType::TCP,
+                                    dial_reason(&e),
+                                    None,
+                                ))
+                                .await;
+                            let _ = out_sender.send(EndpointMessage::Close).await;
+                        }
                     }
                 });
             }
@@ -118,35 +251,42 @@ async fn run_self_recv(
     Ok(())
 }
 
+#[allow(clippy::upper_case_acronyms)]
 enum OutType {
     DHT(
         Sender<TransportRecvMessage>,
         Sender<EndpointMessage>,
         Receiver<EndpointMessage>,
     ),
-    Stable,
+    Stable(Sender<TransportRecvMessage>),
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn process_stream(
     mut stream: TcpStream,
+    addr: SocketAddr,
     out_sender: Sender<EndpointMessage>,
     mut self_receiver: Receiver<EndpointMessage>,
     out_type: OutType,
     has_session: Option<SessionKey>,
+    limit: usize,
+    counter: Arc<FrameCounter>,
 ) -> Result<()> {
-    let addr = stream.peer_addr()?;
     let (mut reader, mut writer) = stream.split();
 
     let mut read_len = [0u8; 4];
-    let handshake: std::result::Result<RemotePublic, ()> = select! {
+    let handshake: std::result::Result<RemotePublic, DialReason> = select! {
         v = async {
             match reader.read(&mut read_len).await {
                 Ok(size) => {
                     if size != 4 {
-                        return Err(());
+                        return Err(DialReason::Handshake);
                     }
 
                     let len: usize = u32::from_be_bytes(read_len) as usize;
+                    if len > limit {
+                        return Err(DialReason::Handshake);
+                    }
                     let mut read_bytes = vec![0u8; len];
                     let mut received: usize = 0;
 
@@ -156,49 +296,66 @@ async fn process_stream(
                             continue;
                         }
 
+                        counter.add_received(4 + len);
                         if let Ok(EndpointMessage::Handshake(remote_pk)) =
                             EndpointMessage::from_bytes(read_bytes)
                         {
                             return Ok(remote_pk);
                         } else {
-                            return Err(());
+                            return Err(DialReason::Handshake);
                         }
                     }
 
-                    Err(())
+                    Err(DialReason::Handshake)
                 }
                 Err(e) => {
                     error!("TCP READ ERROR: {:?}", e);
-                    Err(())
+                    Err(DialReason::Handshake)
                 }
             }
         } => v,
         v = async {
             tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-            Err(())
+            Err(DialReason::Timeout)
         } => v
     };
 
-    if handshake.is_err() {
-        // close it. if is_by_self, Better send outside not connect.
-        debug!("Transport: connect read publics timeout, close it.");
-        return Ok(());
-    }
-
-    let remote_pk = handshake.unwrap(); // safe. checked.
+    let remote_pk = match handshake {
+        Ok(remote_pk) => remote_pk,
+        Err(reason) => {
+            // close it. if is_by_self, send outside connect failure.
+            debug!("Transport: connect read publics failure, close it.");
+            let sender = match out_type {
+                OutType::Stable(sender) => Some(sender),
+                OutType::DHT(sender, ..) if has_session.is_some() => Some(sender),
+                _ => None,
+            };
+            if let Some(sender) = sender {
+                let _ = sender
+                    .send(TransportRecvMessage::ConnectFailure(
+                        addr,
+                        TransportType::TCP,
+                        reason,
+                        has_session.as_ref().map(|key| key.out_bytes()),
+                    ))
+                    .await;
+            }
+            return Ok(());
+        }
+    };
 
-    match out_type {
-        OutType::Stable => {
+    // the server's channel, report the remote's misbehavior.
+    let trans_sender = match out_type {
+        OutType::Stable(sender) => {
             out_sender
                 .send(EndpointMessage::Handshake(remote_pk))
                 .await
-                .map_err(|_e| {
-                    std::io::Error::new(std::io::ErrorKind::Other, "endpoint channel missing")
-                })?;
+                .map_err(|_e| std::io::Error::other("endpoint channel missing"))?;
+            sender
         }
         OutType::DHT(sender, self_sender, out_receiver) => {
             sender
-                .send(TransportRecvMessage(
+                .send(TransportRecvMessage::Connected(
                     addr,
                     remote_pk,
                     has_session,
@@ -207,35 +364,28 @@ async fn process_stream(
                     self_sender,
                 ))
                 .await
-                .map_err(|_e| {
-                    std::io::Error::new(std::io::ErrorKind::Other, "server channel missing")
-                })?;
+                .map_err(|_e| std::io::Error::other("server channel missing"))?;
+            sender
         }
-    }
+    };
 
+    let write_counter = counter.clone();
     let a = async move {
-        loop {
-            match self_receiver.recv().await {
-                Some(msg) => {
-                    let is_close = match msg {
-                        EndpointMessage::Close => true,
-                        _ => false,
-                    };
-
-                    let bytes = msg.to_bytes();
-                    if writer
-                        .write(&(bytes.len() as u32).to_be_bytes())
-                        .await
-                        .is_ok()
-                    {
-                        let _ = writer.write_all(&bytes[..]).await;
-                    }
+        while let Some(msg) = self_receiver.recv().await {
+            let is_close = matches!(msg, EndpointMessage::Close);
 
-                    if is_close {
-                        break;
-                    }
-                }
-                None => break,
+            let bytes = msg.to_bytes();
+            write_counter.add_sent(4 + bytes.len());
+            if writer
+                .write(&(bytes.len() as u32).to_be_bytes())
+                .await
+                .is_ok()
+            {
+                let _ = writer.write_all(&bytes[..]).await;
+            }
+
+            if is_close {
+                break;
             }
         }
 
@@ -256,6 +406,18 @@ async fn process_stream(
                     }
 
                     let len: usize = u32::from_be_bytes(read_len) as usize;
+                    if len > limit {
+                        warn!("TCP message from {:?} is too large, close it.", addr);
+                        stats::counter(stats::METRIC_OVERSIZE, 1);
+                        let _ = trans_sender
+                            .send(TransportRecvMessage::Misbehavior(
+                                addr,
+                                Misbehavior::InvalidFrame,
+                            ))
+                            .await;
+                        let _ = out_sender.send(EndpointMessage::Close).await;
+                        break;
+                    }
                     let mut read_bytes = vec![0u8; len];
                     while let Ok(bytes_size) = reader.read(&mut read_bytes[received..]).await {
                         received += bytes_size;
@@ -267,6 +429,7 @@ async fn process_stream(
                             continue;
                         }
 
+                        counter.add_received(4 + len);
                         if let Ok(msg) = EndpointMessage::from_bytes(read_bytes) {
                             let _ = out_sender.send(msg).await;
                         }
@@ -292,3 +455,70 @@ async fn process_stream(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// PROXY v2 header of the command, family and the addresses.
+    fn header(command: u8, family: u8, addrs: &[u8]) -> Vec<u8> {
+        let mut bytes = PROXY_SIGNATURE.to_vec();
+        bytes.push(0x20 | command);
+        bytes.push(family);
+        bytes.extend((addrs.len() as u16).to_be_bytes());
+        bytes.extend(addrs);
+        bytes
+    }
+
+    #[tokio::test]
+    async fn proxy_ipv4() {
+        let mut addrs = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        addrs.extend(5678u16.to_be_bytes());
+        addrs.extend(7364u16.to_be_bytes());
+        let bytes = header(1, 0x11, &addrs);
+        let client = read_proxy_header(&mut &bytes[..]).await.unwrap();
+        assert_eq!(client, Some("1.2.3.4:5678".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn proxy_ipv6() {
+        let src: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let dst: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let mut addrs = src.octets().to_vec();
+        addrs.extend(dst.octets());
+        addrs.extend(5678u16.to_be_bytes());
+        addrs.extend(7364u16.to_be_bytes());
+        let bytes = header(1, 0x21, &addrs);
+        let client = read_proxy_header(&mut &bytes[..]).await.unwrap();
+        assert_eq!(client, Some(SocketAddr::new(IpAddr::V6(src), 5678)));
+    }
+
+    #[tokio::test]
+    async fn proxy_local_and_unknown() {
+        // LOCAL command, the addresses are skipped.
+        let bytes = header(0, 0x11, &[0u8; 12]);
+        assert_eq!(read_proxy_header(&mut &bytes[..]).await.unwrap(), None);
+        // UDP is not a client address.
+        let bytes = header(1, 0x12, &[0u8; 12]);
+        assert_eq!(read_proxy_header(&mut &bytes[..]).await.unwrap(), None);
+        // too short addresses of the family.
+        let bytes = header(1, 0x11, &[0u8; 4]);
+        assert_eq!(read_proxy_header(&mut &bytes[..]).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn proxy_malformed() {
+        // bad signature.
+        let mut bytes = header(1, 0x11, &[0u8; 12]);
+        bytes[0] = 0;
+        assert!(read_proxy_header(&mut &bytes[..]).await.is_err());
+        // not version 2.
+        let mut bytes = header(1, 0x11, &[0u8; 12]);
+        bytes[12] = 0x11;
+        assert!(read_proxy_header(&mut &bytes[..]).await.is_err());
+        // truncated header and addresses.
+        let bytes = header(1, 0x11, &[0u8; 12]);
+        assert!(read_proxy_header(&mut &bytes[..10]).await.is_err());
+        assert!(read_proxy_header(&mut &bytes[..20]).await.is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chamomile_types::message::{ReceiveMessage, RecoveryStage};
+
+use crate::global::Global;
+use crate::server::{bootstrap, stable_reboot};
+use crate::transports::TransportSendMessage;
+
+/// the watchdog is waiting network lost.
+const IDLE: u8 = 0;
+/// the watchdog is running the recovery.
+const RECOVERING: u8 = 1;
+/// all rounds finished, waiting network ready or reboot.
+const GAVE_UP: u8 = 2;
+
+/// Network watchdog, when network lost, run the staged recovery (seeds, stables,
+/// rebind) round by round, tell outside the progress, until the network ready
+/// or all rounds finished.
+pub(crate) struct Watchdog {
+    /// recovery rounds, 0 is disable (only tell outside `NetworkLost`).
+    rounds: usize,
+    /// waiting time after every stage.
+    wait: Duration,
+    state: AtomicU8,
+}
+
+impl Watchdog {
+    pub fn new(rounds: usize, wait: Duration) -> Self {
+        Watchdog {
+            rounds,
+            wait,
+            state: AtomicU8::new(IDLE),
+        }
+    }
+
+    pub fn is_enable(&self) -> bool {
+        self.rounds > 0
+    }
+
+    /// start the recovery, false if it is running or gave up.
+    pub fn start(&self) -> bool {
+        self.state
+            .compare_exchange(IDLE, RECOVERING, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+
+    /// network ready or rebooted by outside, waiting the next lost.
+    pub fn reset(&self) {
+        self.state.store(IDLE, Ordering::SeqCst);
+    }
+}
+
+/// run the recovery stages, stop when the network ready (by `Global::network_ready`).
+pub(crate) async fn recover(global: Arc<Global>, recv_data: bool) {
+    let watchdog = &global.watchdog;
+    let stages = [
+        RecoveryStage::Seeds,
+        RecoveryStage::Stables,
+        RecoveryStage::Rebind,
+    ];
+    for round in 1..=watchdog.rounds {
+        for stage in stages {
+            if watchdog.state.load(Ordering::SeqCst) != RECOVERING || global.out_sender.is_closed()
+            {
+                return;
+            }
+            info!("Network recovery round {}: {:?}.", round, stage);
+            let _ = global
+                .out_send(ReceiveMessage::NetworkRecovery(round, stage))
+                .await;
+            match stage {
+                RecoveryStage::Seeds => bootstrap(global.clone()).await,
+                RecoveryStage::Stables => stable_reboot(global.clone(), recv_data, false).await,
+                RecoveryStage::Rebind => {
+                    let transports: Vec<_> =
+                        global.transports.read().await.keys().copied().collect();
+                    for transport in transports {
+                        let _ = global
+                            .trans_send(&transport, TransportSendMessage::Rebind)
+                            .await;
+                    }
+                }
+                RecoveryStage::GaveUp => {}
+            }
+            tokio::time::sleep(watchdog.wait).await;
+        }
+    }
+
+    if watchdog
+        .state
+        .compare_exchange(RECOVERING, GAVE_UP, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok()
+    {
+        warn!("CHAMOMILE: NETWORK RECOVERY GAVE UP.");
+        global.audit.log("network_recovery gave_up".to_owned());
+        let _ = global
+            .out_send(ReceiveMessage::NetworkRecovery(
+                watchdog.rounds,
+                RecoveryStage::GaveUp,
+            ))
+            .await;
+    }
+}
@@ -2,7 +2,8 @@ pub mod message;
 pub mod peer;
 pub mod types;
 
-pub use peer::Peer;
+pub use bytes::Bytes;
+pub use peer::{Multiaddr, Peer};
 pub use types::PeerId;
 
 /// delivery data.
@@ -1,7 +1,13 @@
+use bytes::Bytes;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::Sender;
 
 use crate::peer::Peer;
-use crate::types::{Broadcast, PeerId, TransportStream};
+use crate::types::{
+    BlockTarget, Broadcast, DataPath, PeerId, PeerPolicy, PowerProfile, QosClass, TransportStream,
+    TransportType,
+};
 
 /// Custom apply for build a stream between nodes.
 #[derive(Debug, Eq, PartialEq)]
@@ -22,6 +28,72 @@ pub enum DeliveryType {
     StableResult,
 }
 
+/// dial failure reason.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DialReason {
+    /// connect or handshake timeout.
+    Timeout,
+    /// remote refused or reset the connection.
+    Refused,
+    /// connected, but remote info or session key is invalid.
+    Handshake,
+    /// remote address or peer is in blocklist.
+    Blocked,
+    /// other network error, e.g. no route to the address.
+    Unreachable,
+}
+
+/// the network watchdog's recovery stage, after `NetworkLost`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RecoveryStage {
+    /// retry the bootstrap peers and seeds.
+    Seeds,
+    /// retry the dropped stable peers.
+    Stables,
+    /// rebind the transports' sockets (e.g. the network interface changed).
+    Rebind,
+    /// all rounds finished without peers, the watchdog stopped until network
+    /// ready or `SendMessage::NetworkReboot`.
+    GaveUp,
+}
+
+/// the misbehavior of remote peer, scored for the automatic ban.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Misbehavior {
+    /// sent frame cannot be decoded.
+    InvalidFrame,
+    /// session key or frame's authentication is invalid.
+    BadSignature,
+    /// sent frames faster than `Config.ban_rate`.
+    RateViolation,
+}
+
+/// delivery failure reason.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DeliveryFailure {
+    /// peer is not connected, and cannot reach it (no DHT route, no mailbox,
+    /// or the session closed before sent).
+    PeerOffline,
+    /// not delivered before the deadline, or expired in buffer or outbox.
+    Timeout,
+    /// peer is in blocklist.
+    Blocked,
+    /// buffer or outbox is full, the message is rejected or dropped.
+    BufferFull,
+    /// cannot build the relay connection to the peer.
+    RelayRefused,
+    /// data is larger than `max_message_size`.
+    TooLarge,
+    /// remote not support it (e.g. protocol data to legacy peer).
+    Unsupported,
+    /// cancelled by `SendMessage::StableCancel`.
+    Cancelled,
+    /// the target is invalid, e.g. self, or the petname is not registered.
+    Invalid,
+    /// the recipient's key is unknown (never handshaked), cannot encrypt to it.
+    UnknownKey,
+}
+
 /// main received message for outside channel, send from chamomile to outside.
 #[derive(Debug)]
 pub enum ReceiveMessage {
@@ -45,11 +117,241 @@ pub enum ReceiveMessage {
     Data(PeerId, Vec<u8>),
     /// (Only stable connected) Apply for build a stream between nodes.
     /// params is `u32` stream symbol, and `StreamType`.
-    Stream(u32, StreamType, Vec<u8>),
+    Stream(u32, StreamType, Bytes),
     /// (Only stable connected) Delivery feedback. include StableConnect, StableResult, Data. `id(u32) != 0`.
-    Delivery(DeliveryType, u64, bool, Vec<u8>),
+    /// params is type, `tid`, result (failure with the reason) and the delivery data.
+    Delivery(DeliveryType, u64, Result<(), DeliveryFailure>, Vec<u8>),
     /// when network lost all DHT network and direct stables. will tell outside.
+    /// (once, if the watchdog is enabled, then `NetworkRecovery` progress).
+    NetworkLost,
+    /// when the first peer connected after start or `NetworkLost`. will tell outside.
+    NetworkReady,
+    /// response of `SendMessage::Ping`.
+    /// params is `ping_id`, `peer_id` and round-trip time (ms),
+    /// if peer is not connected or ping timeout, rtt is None.
+    Pong(u64, PeerId, Option<u32>),
+    /// (Only when `Config.dht_events` is true) when a DHT (not stable) peer connected.
+    /// params is `peer`.
+    PeerJoin(Peer),
+    /// (Only when `Config.dht_events` is true) when a DHT (not stable) peer
+    /// disconnected or upgraded to stable.
+    /// params is `peer_id`.
+    PeerLeave(PeerId),
+    /// when dial to a peer failure, include stable connect, bootstrap and `Connect`.
+    /// params is attempted `peer` (maybe only has socket) and failure `reason`.
+    DialFailure(Peer, DialReason),
+    /// when a dropped stable connection reconnected by chamomile (`Config.stable_reconnect_times`).
+    /// params is `peer`.
+    StableRestored(Peer),
+    /// when received a data with sub-protocol id from a trusted peer.
+    /// params is `peer_id`, `protocol` and `data_bytes`.
+    ProtocolData(PeerId, String, Vec<u8>),
+    /// when a peer joined the group which self joined, if the group is encrypted,
+    /// use `SendMessage::GroupAdmit` to give it self's sender key.
+    /// params is `group` and `peer_id`.
+    GroupJoin(String, PeerId),
+    /// when a peer left the group which self joined, or it disconnected.
+    /// params is `group` and `peer_id`.
+    GroupLeave(String, PeerId),
+    /// when received a group's data from a member.
+    /// params is `group`, `peer_id` and `data_bytes`.
+    GroupData(String, PeerId, Vec<u8>),
+    /// response of `SendMessage::FindService`, the providers near self.
+    /// params is `service` and providers.
+    Services(String, Vec<Peer>),
+    /// when fetched a mail from mailbox, it was sent when self offline.
+    /// params is `peer_id` (sender) and `data_bytes`.
+    Mail(PeerId, Vec<u8>),
+    /// when received a data from a trusted peer, with the arrived path,
+    /// instead of `Data` when enable `Config.data_path`.
+    /// params is `peer_id`, path and `data_bytes`.
+    PathData(PeerId, DataPath, Vec<u8>),
+    /// response of `SendMessage::ProbeAddr`.
+    /// params is `probe_id`, the remote peer (with peer_id if reachable) and
+    /// round-trip time (ms) of handshake, if unreachable or timeout, rtt is None.
+    Probe(u64, Peer, Option<u32>),
+    /// the aggregated delivery of `SendMessage::DataMulti`, when all recipients finished.
+    /// params is `tid`, delivered peers, failed peers and the delivery data.
+    DeliveryMulti(u64, Vec<PeerId>, Vec<PeerId>, Vec<u8>),
+    /// when a peer misbehaved more than `Config.ban_threshold`, it is disconnected
+    /// and temp-banned for `Config.ban_time`.
+    /// params is `peer` (the ip is banned too, only has socket if handshake failure)
+    /// and the last `misbehavior`.
+    PeerBanned(Peer, Misbehavior),
+    /// the application heartbeat payload of a stable peer, received with its
+    /// keepalive (see `SendMessage::Heartbeat`).
+    /// params is `peer_id` and the payload.
+    Heartbeat(PeerId, Vec<u8>),
+    /// the periodic stats of a stable session, every `Config.session_stats`.
+    /// params is `peer_id` and the stats.
+    SessionStats(PeerId, SessionStats),
+    /// the network watchdog's recovery progress, after `NetworkLost`.
+    /// params is the round (from 1) and the stage started.
+    NetworkRecovery(usize, RecoveryStage),
+    /// the periodic bandwidth summary, every `Config.bandwidth_summary`.
+    /// params is the number of sessions, and every transport's total (sent, received)
+    /// frame bytes, include handshakes, control messages and keepalives.
+    Bandwidth(usize, Vec<(TransportType, u64, u64)>),
+    /// the result of `SendMessage::ExportPeers`.
+    /// params is the file path, and the error message if failure.
+    PeersExported(PathBuf, Option<String>),
+}
+
+impl ReceiveMessage {
+    /// the kind of this message, used by `EventFilter`.
+    pub fn kind(&self) -> EventKind {
+        match self {
+            ReceiveMessage::StableConnect(..) => EventKind::StableConnect,
+            ReceiveMessage::StableResult(..) => EventKind::StableResult,
+            ReceiveMessage::ResultConnect(..) => EventKind::ResultConnect,
+            ReceiveMessage::StableLeave(..) => EventKind::StableLeave,
+            ReceiveMessage::Data(..) => EventKind::Data,
+            ReceiveMessage::Stream(..) => EventKind::Stream,
+            ReceiveMessage::Delivery(..) => EventKind::Delivery,
+            ReceiveMessage::NetworkLost => EventKind::NetworkLost,
+            ReceiveMessage::NetworkReady => EventKind::NetworkReady,
+            ReceiveMessage::Pong(..) => EventKind::Pong,
+            ReceiveMessage::PeerJoin(..) => EventKind::PeerJoin,
+            ReceiveMessage::PeerLeave(..) => EventKind::PeerLeave,
+            ReceiveMessage::DialFailure(..) => EventKind::DialFailure,
+            ReceiveMessage::StableRestored(..) => EventKind::StableRestored,
+            ReceiveMessage::ProtocolData(..) => EventKind::ProtocolData,
+            ReceiveMessage::GroupJoin(..) => EventKind::GroupJoin,
+            ReceiveMessage::GroupLeave(..) => EventKind::GroupLeave,
+            ReceiveMessage::GroupData(..) => EventKind::GroupData,
+            ReceiveMessage::Services(..) => EventKind::Services,
+            ReceiveMessage::Mail(..) => EventKind::Mail,
+            ReceiveMessage::PathData(..) => EventKind::Data,
+            ReceiveMessage::Probe(..) => EventKind::Probe,
+            ReceiveMessage::DeliveryMulti(..) => EventKind::Delivery,
+            ReceiveMessage::PeerBanned(..) => EventKind::PeerBanned,
+            ReceiveMessage::Heartbeat(..) => EventKind::Heartbeat,
+            ReceiveMessage::SessionStats(..) => EventKind::SessionStats,
+            ReceiveMessage::NetworkRecovery(..) => EventKind::NetworkRecovery,
+            ReceiveMessage::Bandwidth(..) => EventKind::Bandwidth,
+            ReceiveMessage::PeersExported(..) => EventKind::PeersExported,
+        }
+    }
+
+    /// the remote peer of this message, if it has.
+    pub fn peer_id(&self) -> Option<&PeerId> {
+        match self {
+            ReceiveMessage::StableConnect(peer, _)
+            | ReceiveMessage::StableResult(peer, ..)
+            | ReceiveMessage::ResultConnect(peer, _)
+            | ReceiveMessage::PeerJoin(peer)
+            | ReceiveMessage::DialFailure(peer, _)
+            | ReceiveMessage::Probe(_, peer, _)
+            | ReceiveMessage::PeerBanned(peer, _)
+            | ReceiveMessage::StableRestored(peer) => Some(&peer.id),
+            ReceiveMessage::StableLeave(peer_id)
+            | ReceiveMessage::Data(peer_id, _)
+            | ReceiveMessage::ProtocolData(peer_id, ..)
+            | ReceiveMessage::GroupJoin(_, peer_id)
+            | ReceiveMessage::GroupLeave(_, peer_id)
+            | ReceiveMessage::GroupData(_, peer_id, _)
+            | ReceiveMessage::Mail(peer_id, _)
+            | ReceiveMessage::PathData(peer_id, ..)
+            | ReceiveMessage::Pong(_, peer_id, _)
+            | ReceiveMessage::Heartbeat(peer_id, _)
+            | ReceiveMessage::SessionStats(peer_id, _)
+            | ReceiveMessage::PeerLeave(peer_id) => Some(peer_id),
+            _ => None,
+        }
+    }
+}
+
+/// the kind of `ReceiveMessage`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum EventKind {
+    StableConnect,
+    StableResult,
+    ResultConnect,
+    StableLeave,
+    Data,
+    Stream,
+    Delivery,
     NetworkLost,
+    NetworkReady,
+    Pong,
+    PeerJoin,
+    PeerLeave,
+    DialFailure,
+    StableRestored,
+    ProtocolData,
+    GroupJoin,
+    GroupLeave,
+    GroupData,
+    Services,
+    Mail,
+    Probe,
+    PeerBanned,
+    Heartbeat,
+    SessionStats,
+    NetworkRecovery,
+    Bandwidth,
+    PeersExported,
+}
+
+/// subscription filter, empty list is not filter on it.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    /// only these kinds of message.
+    pub kinds: Vec<EventKind>,
+    /// only messages about these peers, messages without peer will not match.
+    pub peers: Vec<PeerId>,
+    /// only `ProtocolData` of these sub-protocols, other messages will not match.
+    pub protocols: Vec<String>,
+}
+
+impl EventFilter {
+    /// filter only these kinds of message.
+    pub fn kinds(kinds: Vec<EventKind>) -> Self {
+        EventFilter {
+            kinds,
+            peers: vec![],
+            protocols: vec![],
+        }
+    }
+
+    /// filter only messages about these peers.
+    pub fn peers(peers: Vec<PeerId>) -> Self {
+        EventFilter {
+            kinds: vec![],
+            peers,
+            protocols: vec![],
+        }
+    }
+
+    /// filter only `ProtocolData` of these sub-protocols.
+    pub fn protocols(protocols: Vec<String>) -> Self {
+        EventFilter {
+            kinds: vec![],
+            peers: vec![],
+            protocols,
+        }
+    }
+
+    /// check the message is match this filter.
+    pub fn is_match(&self, msg: &ReceiveMessage) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(&msg.kind()) {
+            return false;
+        }
+        if !self.protocols.is_empty() {
+            match msg {
+                ReceiveMessage::ProtocolData(_, protocol, _)
+                    if self.protocols.contains(protocol) => {}
+                _ => return false,
+            }
+        }
+        if !self.peers.is_empty() {
+            return match msg.peer_id() {
+                Some(peer_id) => self.peers.contains(peer_id),
+                None => false,
+            };
+        }
+        true
+    }
 }
 
 /// main send message for outside channel, send from outside to chamomile.
@@ -74,6 +376,10 @@ pub enum SendMessage {
     /// when outside want to close a stable connectioned peer. use it force close.
     /// params is `peer_id`.
     StableDisconnect(PeerId),
+    /// cancel a pending `StableConnect`, it will remove from buffer, stop the connecting,
+    /// and return failure `Delivery`. if had stable connected, do nothing.
+    /// params is `delivery_feedback_id` and `peer_id`.
+    StableCancel(u64, PeerId),
     /// (DHT connected) when outside want to connect a peer. will try connect directly.
     /// if connected, chamomile will add to kad and bootstrap.
     /// params is `Peer`.
@@ -84,21 +390,155 @@ pub enum SendMessage {
     DisConnect(Peer),
     /// when need send a data to a peer, only need know the peer_id,
     /// the chamomile will help you send data to there.
+    /// in stable connection, data is received in sending order (see `Config.ordered_delivery`).
     /// params is `delivery_feedback_id`, `peer_id` and `data_bytes`.
     /// if `delivery_feedback_id = 0` will not feedback.
     Data(u64, PeerId, Vec<u8>),
+    /// send one data to many peers, the data is shared (not copied) in sending,
+    /// and returns one `ReceiveMessage::DeliveryMulti` when all peers finished.
+    /// params is `delivery_feedback_id`, peers and `data_bytes`.
+    /// if `delivery_feedback_id = 0` will not feedback.
+    DataMulti(u64, Vec<PeerId>, Vec<u8>),
+    /// send a critical data like `Data`, in direct stable connection, it is sent by
+    /// the direct path and a relay path at same time, remote deliver the first one
+    /// and drop the duplicate. it is not ordered with `Data`. if remote not support
+    /// it or no relay path, it is sent as `Data`.
+    /// params is `delivery_feedback_id`, `peer_id` and `data_bytes`.
+    /// if `delivery_feedback_id = 0` will not feedback.
+    RedundantData(u64, PeerId, Vec<u8>),
     /// when need broadcast a data to all network,
     /// chamomile support some common algorithm, use it, donnot worry.
-    /// params is `broadcast_type` and `data_bytes`
-    Broadcast(Broadcast, Vec<u8>),
+    /// params is `broadcast_type` and `data_bytes`, the bytes are shared by all
+    /// sessions, not copied.
+    Broadcast(Broadcast, Bytes),
     /// (Only Stable connected) Apply for build a stream between nodes.
     /// params is `u32` stream symbol, and `StreamType`.
-    Stream(u32, StreamType, Vec<u8>),
+    Stream(u32, StreamType, Bytes),
     /// Request for return the network current state info.
     /// params is request type, and return channel's sender (async).
     NetworkState(StateRequest, Sender<StateResponse>),
     /// When receive `ReceiveMessage::NetworkLost`, want to reboot network, it can use.
+    /// it will rebootstrap, and reconnect the dropped stable peers (and restart
+    /// the watchdog if it gave up).
     NetworkReboot,
+    /// change the keepalive & maintenance profile, e.g. `LowPower` when the mobile
+    /// app goes to background, and `Normal` when it comes back.
+    PowerProfile(PowerProfile),
+    /// assign the stable peer's uplink bandwidth class, idle classes' share is used
+    /// by others. only work when `Config::uplink` is set.
+    /// params is `peer_id` and class.
+    PeerQos(PeerId, QosClass),
+    /// set the peer's data permissions at runtime, default policy is remove it.
+    /// params is `peer_id` and policy.
+    PeerPolicy(PeerId, PeerPolicy),
+    /// start or stop record the peer's decrypted traffic to `db_dir/capture.jsonl`,
+    /// for debugging the protocol. need the `capture` feature, else ignored.
+    /// params is `peer_id` and is enable.
+    Capture(PeerId, bool),
+    /// start or stop record the peer's session events (inbound & outbound frames,
+    /// timers) to `db_dir/record.jsonl`, start it before connected for replay.
+    /// need the `capture` feature, else ignored.
+    /// params is `peer_id` and is enable.
+    Record(PeerId, bool),
+    /// replay the peer's last recorded session from the record file, through a new
+    /// session, and log the outbound frames which diverged from the recorded.
+    /// need the `capture` feature, else ignored.
+    /// params is record file path and `peer_id`.
+    Replay(PathBuf, PeerId),
+    /// check the connected peer is alive, will return `ReceiveMessage::Pong`.
+    /// params is `ping_id` and `peer_id`.
+    Ping(u64, PeerId),
+    /// set the application heartbeat payload (e.g. presence/status), it is
+    /// piggybacked on the keepalive of every stable session, and surfaced at the
+    /// remote as `ReceiveMessage::Heartbeat`. empty is stop, max 256 bytes.
+    Heartbeat(Vec<u8>),
+    /// block the PeerId or ip for the time, and share the signed entry with the
+    /// connected trusted peers (allowlist), they apply it if `Config.block_share`
+    /// enabled, and forward it to their trusted peers.
+    /// params is the target and the block time.
+    ShareBlock(BlockTarget, Duration),
+    /// local override of the shared blocks, if true, the target is never blocked
+    /// by shared entries (and the active one is removed), false is cancel it.
+    /// params is the target and is override.
+    BlockOverride(BlockTarget, bool),
+    /// check the address is reachable by a handshake, not join it to DHT,
+    /// will return `ReceiveMessage::Probe`.
+    /// params is `probe_id` and the peer (socket & transport).
+    ProbeAddr(u64, Peer),
+    /// export the known peers to the file, it can be imported by `Config::import_peers`.
+    /// the format is text lines: header `chamomile-peer-list <version>`, and
+    /// peer lines `/ip4/<ip>/<transport>/<port>/<is_pub>/<peer_id hex>`,
+    /// tag lines `tag <peer_id hex> <tag>`, stable lines `stable <peer line>`,
+    /// name lines `name <name> <peer_id hex>`. will return `ReceiveMessage::PeersExported`.
+    ExportPeers(PathBuf),
+    /// register a petname of the peer, then `PeerId::named(name)` and `Peer::named(name)`
+    /// can be used as the target of messages. it will be saved with peer list,
+    /// and returned by `StateRequest::Names`.
+    /// params is `name` (max `PEER_NAME_MAX` bytes, no whitespace) and `peer_id`.
+    NamePeer(String, PeerId),
+    /// remove the petname.
+    UnnamePeer(String),
+    /// attach a small tag to a peer, like "friend", "validator". it will be saved
+    /// with peer list, and returned by `StateRequest::Tags`.
+    /// params is `peer_id` and `tag`.
+    TagPeer(PeerId, String),
+    /// remove a tag from the peer.
+    /// params is `peer_id` and `tag`.
+    UntagPeer(PeerId, String),
+    /// register a new receiver with filter, the matched messages will send to this
+    /// receiver, not the main receiver. if more than one subscriber matched, the
+    /// first registered one will receive it. drop the receiver to unsubscribe.
+    /// params is `filter` and receiver channel's sender.
+    Subscribe(EventFilter, Sender<ReceiveMessage>),
+    /// when need send a data with sub-protocol id (e.g. "sync/1") to a connected peer,
+    /// remote will receive `ReceiveMessage::ProtocolData`.
+    /// params is `delivery_feedback_id`, `peer_id`, `protocol` (max 255 bytes) and `data_bytes`.
+    ProtocolData(u64, PeerId, String, Vec<u8>),
+    /// join a named group (max 255 bytes), tell it to connected peers, and will receive
+    /// `GroupJoin` and `GroupLeave` when members changed. (members are found in connected peers,
+    /// and by the topic records in DHT, see `Config::topic_replicas`).
+    GroupJoin(String),
+    /// join a named group like `GroupJoin`, and its data is end-to-end encrypted by
+    /// sender keys: self's data is sealed once by self's key, the key is only sent to
+    /// the allowlisted members and the members admitted by `GroupAdmit`, and renewed
+    /// when a member left. (members need support it, and join with `GroupJoinEncrypted`
+    /// to encrypt their data). it protects the data from the peers which only know the
+    /// group's name and from the forwarding peers, not from the admitted members, and
+    /// the group's name and members are not hidden.
+    GroupJoinEncrypted(String),
+    /// admit the member (when received `GroupJoin`) to the encrypted group, send self's
+    /// sender key to it, and the renewed keys until it left.
+    /// params is `group` and `peer_id`.
+    GroupAdmit(String, PeerId),
+    /// leave the group.
+    GroupLeave(String),
+    /// send a data to all connected members of the group.
+    /// params is `group` and `data_bytes`.
+    GroupData(String, Vec<u8>),
+    /// register a named service (max 255 bytes, e.g. "relay"), the connected peers
+    /// will keep the record, and others can find self by `FindService`.
+    RegisterService(String),
+    /// unregister the service.
+    UnregisterService(String),
+    /// find the service's providers in connected peers and their connected peers,
+    /// will return `ReceiveMessage::Services`.
+    FindService(String),
+    /// send data to an offline peer, store it at the connected mailboxes closest to it,
+    /// when it online, will fetch by `ReceiveMessage::Mail`. the mail is signed by self
+    /// and encrypted to the recipient's key (learned when handshaked with it, otherwise
+    /// fail with `UnknownKey`), mailbox only can read the id, sender and recipient.
+    /// `Delivery` is ok if sent to a mailbox.
+    /// params is `delivery_id`, `peer_id` and `data_bytes`.
+    MailboxSend(u64, PeerId, Vec<u8>),
+    /// issue a join token signed by self, for the peer (subject) to stable connect
+    /// the nodes which trust self (`Config.join_issuers`). attach it to `StableConnect`'s
+    /// data by `with_join_token`. params is `subject`, `audience` (None is any node),
+    /// valid time, and return channel's sender.
+    IssueJoinToken(PeerId, Option<PeerId>, Duration, Sender<Vec<u8>>),
+    /// send `Data` or `StableConnect` with a deadline, if it is still waiting in buffer
+    /// or outbox after the deadline, will drop it and return failure `Delivery`.
+    /// need `delivery_feedback_id` is not 0. params is `deadline` and the message.
+    Deadline(Instant, Box<SendMessage>),
 }
 
 /// Network state info response.
@@ -107,6 +547,17 @@ pub enum StateRequest {
     Stable,
     DHT,
     Seed,
+    Tags,
+    Names,
+    Latency,
+    Time,
+    Identity,
+    Bandwidth,
+    Pending,
+    Groups,
+    /// the connected peer's session info.
+    PeerInfo(PeerId),
+    Relays,
 }
 
 /// Network state info response.
@@ -116,6 +567,109 @@ pub enum StateResponse {
     Stable(Vec<(PeerId, bool)>),
     /// response is peer list.
     DHT(Vec<PeerId>),
-    /// response is socket list.
-    Seed(Vec<Peer>),
+    /// response is the bootstrap peers & configured seeds, and their health.
+    Seed(Vec<SeedInfo>),
+    /// response is tagged peer list and the peer's tags.
+    Tags(Vec<(PeerId, Vec<String>)>),
+    /// response is the petnames.
+    Names(Vec<(String, PeerId)>),
+    /// response is connected peer list and the round-trip time (ms).
+    Latency(Vec<(PeerId, u32)>),
+    /// response is the network median clock offset, and connected peer list and
+    /// the estimated clock offset (ms, remote's clock minus self's, by keepalive).
+    /// add the median to local time to get the loosely synchronized network time.
+    Time(i64, Vec<(PeerId, i64)>),
+    /// response is self's PeerId and the public key (key type + public key bytes),
+    /// for the identity documents, the PeerId is derived by `Config.peer_id_hash`.
+    Identity(PeerId, Vec<u8>),
+    /// response is connected peer's (sent, received) payload bytes, and every
+    /// transport's total (sent, received) frame bytes, include handshakes, control
+    /// messages and keepalives.
+    Bandwidth(Vec<(PeerId, u64, u64)>, Vec<(TransportType, u64, u64)>),
+    /// response is waiting tmp sessions (peer, is directly, waited time),
+    /// and queued stable connects and results (peer, tid, waited time).
+    Pending(
+        Vec<(PeerId, bool, Duration)>,
+        Vec<(PeerId, u64, Duration)>,
+        Vec<(PeerId, u64, Duration)>,
+    ),
+    /// response is joined groups and the connected members.
+    Groups(Vec<(String, Vec<PeerId>)>),
+    /// response is the peer's session info, None if not connected.
+    PeerInfo(Option<PeerInfo>),
+    /// response is the relay-connected stable peers and their relay circuits.
+    Relays(Vec<RelayInfo>),
+}
+
+/// The connected peer's session info, returned by `StateRequest::PeerInfo`.
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    /// the remote peer, with transport and socket.
+    pub peer: Peer,
+    /// stable or DHT session.
+    pub is_stable: bool,
+    /// directly or relay.
+    pub is_direct: bool,
+    /// the time since session established.
+    pub age: Duration,
+    /// round-trip time (ms), None if not measured yet.
+    pub rtt: Option<u32>,
+    /// sent bytes.
+    pub sent: u64,
+    /// received bytes.
+    pub received: u64,
+}
+
+/// The bootstrap peer or seed's health, returned by `StateRequest::Seed`.
+#[derive(Debug, Clone)]
+pub struct SeedInfo {
+    /// the seed, with transport and socket.
+    pub peer: Peer,
+    /// is connected now.
+    pub is_connected: bool,
+    /// continuous dial failures, reset when connected (only configured seeds).
+    pub failures: u32,
+    /// the time since last connected, None if not seen yet.
+    pub last_seen: Option<Duration>,
+    /// failed too many times, it is tried last when bootstrap.
+    pub is_dead: bool,
+}
+
+/// The stable session's counters, sent by `ReceiveMessage::SessionStats`.
+#[derive(Debug, Clone)]
+pub struct SessionStats {
+    /// directly or relay.
+    pub is_direct: bool,
+    /// the time since session established.
+    pub age: Duration,
+    /// round-trip time (ms) of last keepalive, None if not measured yet.
+    pub rtt: Option<u32>,
+    /// sent bytes.
+    pub sent: u64,
+    /// received bytes.
+    pub received: u64,
+    /// sent messages (not keepalive).
+    pub sent_messages: u64,
+    /// received messages (not keepalive).
+    pub received_messages: u64,
+    /// keepalives sent again without the last one answered, the transport's
+    /// own retransmits (e.g. QUIC) are not visible to session.
+    pub retransmits: u64,
+}
+
+/// The relay-connected stable peer's circuit, returned by `StateRequest::Relays`.
+#[derive(Debug, Clone)]
+pub struct RelayInfo {
+    /// the remote peer.
+    pub peer: PeerId,
+    /// the intermediate peer which is carrying the traffic.
+    pub relay: PeerId,
+    /// the time since circuit established.
+    pub age: Duration,
+    /// round-trip time (ms) through the relay, None if not measured yet.
+    pub rtt: Option<u32>,
+    /// sent bytes through the circuit.
+    pub sent: u64,
+    /// received bytes through the circuit.
+    pub received: u64,
 }
@@ -1,4 +1,4 @@
-use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 use std::io::Result;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
@@ -36,6 +36,109 @@ fn socket_addr_from_bytes(bytes: &[u8]) -> Result<SocketAddr> {
     }
 }
 
+/// multiaddr's host, ip address or dns name.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Host {
+    Ip(IpAddr),
+    /// dns name, params is `name` and ip version (4, 6, 0 is any).
+    Dns(String, u8),
+}
+
+/// Multiaddr style address with transport, the dns name will resolved when dial.
+/// example: "/ip4/1.2.3.4/udp/4433/quic", "/dns4/node.example.com/tcp/443".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Multiaddr {
+    pub host: Host,
+    pub port: u16,
+    pub transport: TransportType,
+}
+
+impl Multiaddr {
+    pub fn new(host: Host, port: u16, transport: TransportType) -> Self {
+        Self {
+            host,
+            port,
+            transport,
+        }
+    }
+
+    /// check if it need dns resolve.
+    pub fn is_dns(&self) -> bool {
+        matches!(self.host, Host::Dns(..))
+    }
+
+    /// check if the resolved ip address matched the dns version.
+    pub fn is_match(&self, ip: &IpAddr) -> bool {
+        match self.host {
+            Host::Ip(ref i) => i == ip,
+            Host::Dns(_, 4) => ip.is_ipv4(),
+            Host::Dns(_, 6) => ip.is_ipv6(),
+            Host::Dns(..) => true,
+        }
+    }
+
+    /// to peer, only when host is ip address.
+    pub fn to_peer(&self) -> Result<Peer> {
+        match self.host {
+            Host::Ip(ip) => Ok(Peer {
+                socket: SocketAddr::new(ip, self.port),
+                transport: self.transport,
+                id: Default::default(),
+                is_pub: true,
+            }),
+            Host::Dns(..) => Err(new_io_error("multiaddr need dns resolve.")),
+        }
+    }
+
+    /// parse multiaddr string, supported:
+    /// "/ip4/1.2.3.4/udp/4433/quic", "/ip6/::1/tcp/1234", "/dns/example.com/tcp/443",
+    /// and chamomile's "/ip4/127.0.0.1/quic/1234".
+    pub fn from_string(s: &str) -> Result<Self> {
+        let invalid = || new_io_error("multiaddr string is invalid.");
+        let mut ss = s.trim_start_matches('/').split('/');
+        let version = ss.next().ok_or_else(invalid)?;
+        let name = ss.next().ok_or_else(invalid)?;
+        let host = match version {
+            "ip4" | "ip6" => Host::Ip(name.parse().or(Err(invalid()))?),
+            "dns4" => Host::Dns(name.to_owned(), 4),
+            "dns6" => Host::Dns(name.to_owned(), 6),
+            "dns" => Host::Dns(name.to_owned(), 0),
+            _ => return Err(invalid()),
+        };
+        let protocol = ss.next().ok_or_else(invalid)?;
+        let port = ss.next().ok_or_else(invalid)?.parse().or(Err(invalid()))?;
+        let transport = match (protocol, ss.next()) {
+            ("udp", None) | ("udp", Some("quic")) | ("udp", Some("quic-v1")) => TransportType::QUIC,
+            ("tcp", None) => TransportType::TCP,
+            ("quic", None) | ("rtp", None) | ("udt", None) => TransportType::from_str(protocol),
+            _ => return Err(new_io_error("multiaddr transport not supported.")),
+        };
+
+        Ok(Self {
+            host,
+            port,
+            transport,
+        })
+    }
+}
+
+impl Display for Multiaddr {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let host = match &self.host {
+            Host::Ip(ip) if ip.is_ipv4() => format!("/ip4/{}", ip),
+            Host::Ip(ip) => format!("/ip6/{}", ip),
+            Host::Dns(name, 4) => format!("/dns4/{}", name),
+            Host::Dns(name, 6) => format!("/dns6/{}", name),
+            Host::Dns(name, _) => format!("/dns/{}", name),
+        };
+        match self.transport {
+            TransportType::QUIC => write!(f, "{}/udp/{}/quic", host, self.port),
+            TransportType::TCP => write!(f, "{}/tcp/{}", host, self.port),
+            _ => write!(f, "{}/{}/{}", host, self.transport.to_str(), self.port),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub struct Peer {
     pub id: PeerId,
@@ -68,6 +171,7 @@ impl Peer {
     }
 
     /// create peer by only peer id.
+    #[allow(clippy::self_named_constructors)]
     pub fn peer(id: PeerId) -> Self {
         Self {
             id,
@@ -77,6 +181,11 @@ impl Peer {
         }
     }
 
+    /// create peer by the petname, see `PeerId::named`.
+    pub fn named(name: &str) -> Result<Self> {
+        Ok(Self::peer(PeerId::named(name)?))
+    }
+
     pub fn effective(&self) -> bool {
         self.effective_socket() || self.effective_id()
     }
@@ -129,7 +238,8 @@ impl Peer {
     /// 5 is open or not,
     /// 6 is peer id hex encode.
     /// example: "/ip4/127.0.0.1/tcp/1234/false/xxxxxx"
-    pub fn to_string<'a>(&self) -> String {
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
         let v = if self.socket.is_ipv4() { "4" } else { "6" };
 
         format!(
@@ -145,14 +255,15 @@ impl Peer {
 
     /// from string exported to peer.
     pub fn from_string(s: &str) -> Result<Self> {
-        let mut ss = s.split("/");
+        let mut ss = s.trim_start_matches('/').split('/');
         let _ = ss.next(); // ipv4 / ipv6
         let ipaddr = ss
             .next()
             .ok_or(new_io_error("peer string is invalid."))?
             .parse()
             .or(Err(new_io_error("peer string is invalid.")))?; // safe
-        let transport = TransportType::from_str(ss.next().unwrap()); // safe
+        let transport =
+            TransportType::from_str(ss.next().ok_or(new_io_error("peer string is invalid."))?);
         let port = ss
             .next()
             .ok_or(new_io_error("peer string is invalid."))?
@@ -174,48 +285,26 @@ impl Peer {
         })
     }
 
-    /// only load this peer by socket and transport.
-    /// example: "/ip4/127.0.0.1/tcp/1234"
+    /// only load this peer by socket and transport, dns name is not supported,
+    /// use `Multiaddr` for it.
+    /// example: "/ip4/127.0.0.1/tcp/1234", "/ip4/127.0.0.1/udp/1234/quic"
     pub fn from_multiaddr_string(s: &str) -> Result<Self> {
-        let mut ss = s.split("/");
-        let _ = ss.next(); // ipv4 / ipv6
-        let ipaddr = ss
-            .next()
-            .ok_or(new_io_error("peer string is invalid."))?
-            .parse()
-            .or(Err(new_io_error("peer string is invalid.")))?; // safe
-        let transport = TransportType::from_str(ss.next().unwrap()); // safe
-        let port = ss
-            .next()
-            .ok_or(new_io_error("peer string is invalid."))?
-            .parse()
-            .or(Err(new_io_error("peer string is invalid.")))?; // safe
-        let socket = SocketAddr::new(ipaddr, port);
+        Multiaddr::from_string(s)?.to_peer()
+    }
 
-        Ok(Self {
-            socket,
-            transport,
-            id: Default::default(),
-            is_pub: true,
-        })
+    /// to multiaddr with socket and transport.
+    pub fn to_multiaddr(&self) -> Multiaddr {
+        Multiaddr::new(
+            Host::Ip(self.socket.ip()),
+            self.socket.port(),
+            self.transport,
+        )
     }
 
     /// Multiaddr, you can import/export it.
-    /// 1 is ip version,
-    /// 2 is bind ip address,
-    /// 3 is transport type,
-    /// 4 is bind port
-    /// example: "/ip4/127.0.0.1/tcp/1234"
+    /// example: "/ip4/127.0.0.1/tcp/1234", "/ip4/127.0.0.1/udp/1234/quic"
     pub fn to_multiaddr_string(&self) -> String {
-        let v = if self.socket.is_ipv4() { "4" } else { "6" };
-
-        format!(
-            "/ip{}/{}/{}/{}",
-            v,
-            self.socket.ip(),
-            self.transport.to_str(),
-            self.socket.port(),
-        )
+        self.to_multiaddr().to_string()
     }
 }
 
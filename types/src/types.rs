@@ -5,7 +5,7 @@ use tokio::sync::mpsc::{Receiver, Sender};
 
 #[inline]
 pub fn new_io_error(s: &str) -> std::io::Error {
-    std::io::Error::new(std::io::ErrorKind::Other, s)
+    std::io::Error::other(s)
 }
 
 /// peer's network id.
@@ -14,6 +14,15 @@ pub struct PeerId(pub [u8; 32]);
 
 pub const PEER_ID_LENGTH: usize = 32;
 
+/// max bytes of a petname, it is encoded in the placeholder `PeerId`.
+pub const PEER_NAME_MAX: usize = 24;
+
+/// the DID method prefix of `PeerId`.
+const DID_PREFIX: &str = "did:chamomile:";
+
+/// the prefix of petname's placeholder `PeerId`.
+const PEER_NAME_PREFIX: &[u8; 8] = b"\0petname";
+
 impl PeerId {
     pub fn short_show(&self) -> String {
         let mut hex = String::new();
@@ -65,6 +74,41 @@ impl PeerId {
         hex.extend(self.0.iter().map(|byte| format!("{:02x?}", byte)));
         hex
     }
+
+    /// the DID-style identifier `did:chamomile:<hex>`, a stable mapping of the PeerId.
+    pub fn to_did(&self) -> String {
+        format!("{}{}", DID_PREFIX, self.to_hex())
+    }
+
+    pub fn from_did(s: &str) -> Result<PeerId> {
+        match s.strip_prefix(DID_PREFIX) {
+            Some(hex) => PeerId::from_hex(hex),
+            None => Err(new_io_error("peer did failure.")),
+        }
+    }
+
+    /// placeholder id of the petname (max `PEER_NAME_MAX` bytes), when send,
+    /// it will be replaced by the id registered by `SendMessage::NamePeer`.
+    pub fn named(name: &str) -> Result<PeerId> {
+        let bytes = name.as_bytes();
+        if bytes.is_empty() || bytes.len() > PEER_NAME_MAX {
+            return Err(new_io_error("peer name length failure."));
+        }
+        let mut value = [0u8; 32];
+        value[..8].copy_from_slice(PEER_NAME_PREFIX);
+        value[8..8 + bytes.len()].copy_from_slice(bytes);
+        Ok(PeerId(value))
+    }
+
+    /// the petname, if it is a placeholder id.
+    pub fn petname(&self) -> Option<String> {
+        if &self.0[..8] != PEER_NAME_PREFIX {
+            return None;
+        }
+        let name = &self.0[8..];
+        let len = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        String::from_utf8(name[..len].to_vec()).ok()
+    }
 }
 
 impl Debug for PeerId {
@@ -82,6 +126,73 @@ pub enum Broadcast {
     StableAll,
 }
 
+/// The path which the received data arrived.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DataPath {
+    /// directly session, params is the transport.
+    Direct(TransportType),
+    /// relayed by other peer, params is the relay peer.
+    Relay(PeerId),
+    /// self send to self.
+    Local,
+}
+
+/// Keepalive & maintenance profile, use `LowPower` on mobile to save battery,
+/// it can be changed at runtime by `SendMessage::PowerProfile`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PowerProfile {
+    /// keepalive every 2s, maintenance jobs run by the configured intervals.
+    Normal,
+    /// keepalive every 30s (aligned in all sessions, so they wake up together),
+    /// maintenance intervals are 6 times and the close jobs run together.
+    LowPower,
+}
+
+/// Uplink bandwidth class of stable session, used when `Config::uplink` is set,
+/// assigned by `SendMessage::PeerQos`. default is `Bulk`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum QosClass {
+    /// latency-sensitive session, 60% share of the uplink.
+    Interactive,
+    /// normal session, 30% share of the uplink.
+    Bulk,
+    /// sync & prefetch session, 10% share of the uplink.
+    Background,
+}
+
+/// Per-peer data permissions, assigned by `SendMessage::PeerPolicy`, checked in
+/// the session receive path beyond `Config::only_stable_data`. default allows all.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PeerPolicy {
+    /// accept the peer's data (include protocol & group data).
+    /// `None` follows `only_stable_data`, `Some(true)` accepts from DHT peer too.
+    pub data: Option<bool>,
+    /// accept the peer's data which arrived by relay.
+    pub relayed_data: bool,
+    /// accept the peer's stream apply (reserved, streams are not supported yet).
+    pub stream: bool,
+    /// forward the relay data & connections from the peer (when self is a relay).
+    pub relay: bool,
+}
+
+impl Default for PeerPolicy {
+    fn default() -> Self {
+        PeerPolicy {
+            data: None,
+            relayed_data: true,
+            stream: true,
+            relay: true,
+        }
+    }
+}
+
+/// The target of a shared block entry (`SendMessage::ShareBlock`).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum BlockTarget {
+    Peer(PeerId),
+    Ip(std::net::IpAddr),
+}
+
 /// Transports types support by Endpoint.
 #[derive(Debug, Copy, Clone, Hash, Deserialize, Serialize, Eq, PartialEq)]
 pub enum TransportType {
@@ -93,6 +204,7 @@ pub enum TransportType {
 
 impl TransportType {
     /// transports from parse from str.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         match s {
             "quic" => TransportType::QUIC,